@@ -3,7 +3,7 @@
 //! `validate()` — check DSL syntax and semantics without producing bytes.
 //! `parse()`    — reverse-read raw binary bytes into named field values.
 
-use delbin::{generate, parse, validate, Value, WarningCode};
+use delbin::{diagnostic::Renderer, generate, parse, validate, Value, WarningCode};
 use std::collections::HashMap;
 
 fn main() {
@@ -20,7 +20,7 @@ fn main() {
 
     match validate(bad_dsl, &HashMap::new()) {
         Ok(_) => println!("Unexpected OK"),
-        Err(e) => println!("Caught error: [{:?}] {}", e.code, e.message),
+        Err(e) => print!("Caught error:\n{}", Renderer::new("example.dsl").render_error(&e)),
     }
 
     // validate() returns warnings (not errors) for non-fatal issues
@@ -35,7 +35,7 @@ fn main() {
         Ok(warnings) => {
             println!("\nvalidate() succeeded with {} warning(s):", warnings.len());
             for w in &warnings {
-                println!("  [{:?}] {}", w.code, w.message);
+                print!("{}", Renderer::new("example.dsl").render_warning(w));
             }
             assert!(warnings.iter().any(|w| w.code == WarningCode::W03002));
         }
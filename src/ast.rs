@@ -1,12 +1,164 @@
 //! Delbin AST definitions
 
+use crate::error::SourceLocation;
 use crate::types::{Endian, ScalarType};
 
 /// File (top-level)
 #[derive(Debug, Clone)]
 pub struct File {
     pub endian: Endian,
+    /// Env variable that selects the endianness (`@endian = ${VAR};`),
+    /// overriding `endian` at evaluation time
+    pub endian_var: Option<String>,
+    /// Address the header is placed at (`@base = 0x08000000;`)
+    pub base: Option<u64>,
+    /// Bytes reserved for the header (`@size = 0x200;`); the struct must fit
+    pub size: Option<u64>,
+    /// Gap fill byte for `assemble()` (`@fill = 0xFF;`)
+    pub fill: Option<u8>,
+    /// Flash slot the whole image must fit in (`@slot_size = 512K;`)
+    pub slot_size: Option<u64>,
+    /// Declared inputs (`@inputs { ... }`), in declaration order
+    pub inputs: Vec<InputDecl>,
+    /// Integrity requirements (`@policy { ... }`), in declaration order
+    pub policy: Vec<PolicyRule>,
+    /// Image layout (`@layout { ... }`), in declaration order
+    pub layout: Vec<LayoutEntry>,
+    /// Multi-image bundle (`@bundle { ... }`) built by `bundle()`, which
+    /// uses the second struct as the table entry
+    pub bundle: Option<Bundle>,
+    /// Derived sections (`@section name = @whiten(...);`), in declaration order
+    pub sections: Vec<SectionDef>,
+    /// `@assert(...)` checks, in declaration order
+    pub asserts: Vec<Assert>,
+    /// Struct templates (`struct record<T, LEN> { ... }`), in declaration
+    /// order; their instances are already expanded in the structs
+    pub templates: Vec<StructTemplate>,
     pub struct_def: StructDef,
+    /// Optional second struct, placed after the image by `merge()` and
+    /// `assemble()`
+    pub trailer: Option<StructDef>,
+    /// Source text between the header and trailer structs, verbatim
+    pub trailer_gap: String,
+    /// Source text before `struct` (directives, comments), verbatim;
+    /// `None` prints the directives canonically
+    pub prelude: Option<String>,
+    /// Source text after the last closing brace, verbatim
+    pub epilogue: String,
+    /// Every file `@include`d, nested ones too, in the order they were read
+    pub includes: Vec<String>,
+}
+
+/// Integrity requirement in a `@policy` block: `header_crc: required;`
+/// or `payload_hash: sha256;`
+///
+/// `check()`, generation and `verify()` reject a file whose structs don't
+/// meet its own policy (E02007).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyRule {
+    pub kind: PolicyKind,
+    /// Builtin the field must use (`sha256`, `crc32`, `ed25519`); `None`
+    /// for `required`, which any builtin of the kind meets
+    pub algorithm: Option<String>,
+}
+
+/// What a `@policy` rule asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyKind {
+    /// `header_crc`: a field checksums or hashes the header (`@self`)
+    HeaderCrc,
+    /// `payload_hash`: a field checksums or hashes a section
+    PayloadHash,
+    /// `signature`: a field holds a signature
+    Signature,
+}
+
+impl PolicyKind {
+    pub const ALL: [PolicyKind; 3] = [PolicyKind::HeaderCrc, PolicyKind::PayloadHash, PolicyKind::Signature];
+
+    /// DSL rule name
+    pub fn name(&self) -> &'static str {
+        match self {
+            PolicyKind::HeaderCrc => "header_crc",
+            PolicyKind::PayloadHash => "payload_hash",
+            PolicyKind::Signature => "signature",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// Placement in an `@layout` block: `name @ offset;`
+///
+/// `name` is a section, or the struct itself (by its name or as `header`).
+#[derive(Debug, Clone)]
+pub struct LayoutEntry {
+    pub name: String,
+    /// Byte offset from the start of the image
+    pub offset: u64,
+}
+
+/// Images of a multi-image bundle: `@bundle @align(4K) { boot; app; }`
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    /// Section names, in table and placement order
+    pub images: Vec<String>,
+    /// Alignment of each image from the start of the bundle
+    pub align: Option<u32>,
+}
+
+/// Derived section: `@section name = @transform(source, ...);`
+///
+/// Evaluated before the struct; fields, `@layout` and `merge()` can then use
+/// `name` like any other section.
+#[derive(Debug, Clone)]
+pub struct SectionDef {
+    pub name: String,
+    /// Transform call, e.g. `@whiten(image, 0x221, 0x1FF)`
+    pub transform: Expr,
+}
+
+/// Build-time check: `@assert(@sizeof(image) <= 480K, "image too big");`
+///
+/// Evaluated after the header; a zero condition fails generation.
+#[derive(Debug, Clone)]
+pub struct Assert {
+    pub cond: Expr,
+    pub message: Option<String>,
+}
+
+/// Declared input: `NAME: type required;` or `NAME: type optional = default;`
+#[derive(Debug, Clone)]
+pub struct InputDecl {
+    pub name: String,
+    pub ty: InputType,
+    pub required: bool,
+    /// Value used when an optional input is absent (number or string literal)
+    pub default: Option<Expr>,
+}
+
+/// Type of a declared input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputType {
+    /// Integer that must fit the scalar type
+    Int(ScalarType),
+    /// `str`: string value
+    Str,
+    /// `bytes`: byte string (or secret)
+    Bytes,
+}
+
+impl InputType {
+    /// DSL type name
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputType::Int(s) => s.name(),
+            InputType::Str => "str",
+            InputType::Bytes => "bytes",
+        }
+    }
 }
 
 /// Struct definition
@@ -16,14 +168,122 @@ pub struct StructDef {
     pub packed: bool,
     pub align: Option<u32>,
     pub fields: Vec<FieldDef>,
+    /// `@encrypt_range(...)` attributes, applied in order
+    pub encrypt: Vec<EncryptRange>,
+    /// Sections named by `@expect_encrypted(...)`, checked for plaintext
+    pub expect_encrypted: Vec<String>,
+    /// Source text from `struct` through `{`, verbatim; `None` prints canonically
+    pub head_source: Option<String>,
+    /// Whitespace and comments between the last field and `}`
+    pub closing: String,
+}
+
+/// `@encrypt_range(from..to, key, iv)` struct attribute
+///
+/// AES-CTR over part of the struct (key of 16, 24 or 32 bytes, 16-byte
+/// initial counter), applied after every checksum has been computed, so
+/// checksums cover the plaintext. The IV is required: a fixed counter block
+/// would reuse the keystream across images encrypted under the same key.
+#[derive(Debug, Clone)]
+pub struct EncryptRange {
+    /// Start of the range: field name or byte offset; `None` is offset 0
+    pub start: Option<Box<Expr>>,
+    /// Field the range ends before; `None` is the end of the struct
+    pub end: Option<String>,
+    pub key: Expr,
+    /// Initial counter block
+    pub iv: Expr,
+}
+
+/// Parametric struct: `struct record<T, LEN> { tag: u16 = T; len: u16 = LEN; }`
+///
+/// An instance `rec0: record<1, 4>;` expands to the fields with the instance
+/// name as prefix (`rec0_tag`, `rec0_len`) and the arguments in place of
+/// the parameters.
+#[derive(Debug, Clone)]
+pub struct StructTemplate {
+    pub name: String,
+    pub params: Vec<String>,
+    /// Fields as written, `@include`s expanded
+    pub fields: Vec<FieldDef>,
 }
 
 /// Field definition
 #[derive(Debug, Clone)]
 pub struct FieldDef {
     pub name: String,
+    /// Former names (`@alias("old")`), under which decoding reports the
+    /// field too
+    pub aliases: Vec<String>,
     pub ty: Type,
     pub init: Option<Expr>,
+    /// Comments and whitespace around the field
+    pub trivia: Trivia,
+    /// Source text of the field (name through `;`), verbatim;
+    /// `None` for new or edited fields, which print canonically
+    pub source: Option<String>,
+    /// Where the field starts in the DSL text; `None` for new fields
+    pub location: Option<SourceLocation>,
+    /// Source of the `@include`, template instance or `@for` block in this
+    /// file the field was expanded from, verbatim; unedited fields print as it
+    pub expanded_from: Option<String>,
+    /// The `@for` block the field is in, repeated once env is known
+    pub for_each: Option<ForEach>,
+    /// Length of a TLV record, generated by the parser; a length that
+    /// does not fit the field's type is an error rather than truncated
+    pub tlv_len: bool,
+}
+
+/// Loop of an `@for entry in ${PARTITIONS} { ... }` block
+///
+/// [`eval::unroll()`](crate::eval::unroll) repeats the block's fields once
+/// per list element, prefixed `entry_0_`, `entry_1_`, ..., with `${entry}`
+/// standing for `${PARTITIONS[0]}`, `${PARTITIONS[1]}`, ...
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ForEach {
+    /// Loop variable
+    pub var: String,
+    /// Env variable holding the list
+    pub list: String,
+}
+
+impl FieldDef {
+    /// New field without source text or trivia
+    pub fn new(name: impl Into<String>, ty: Type, init: Option<Expr>) -> Self {
+        Self {
+            name: name.into(),
+            aliases: Vec::new(),
+            ty,
+            init,
+            trivia: Trivia::default(),
+            source: None,
+            location: None,
+            expanded_from: None,
+            for_each: None,
+            tlv_len: false,
+        }
+    }
+}
+
+/// Whitespace and comments around a node, kept verbatim so tools can
+/// rewrite a file without losing its formatting
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trivia {
+    /// Text between the previous node (or its trailing comment) and this node
+    pub leading: String,
+    /// Comment after the node on the same line, including the spaces before it
+    pub trailing: String,
+}
+
+impl Trivia {
+    /// Comment lines (with the `//` marker), in source order
+    pub fn comments(&self) -> impl Iterator<Item = &str> {
+        self.leading
+            .lines()
+            .chain(self.trailing.lines())
+            .map(str::trim)
+            .filter(|line| line.starts_with("//"))
+    }
 }
 
 /// Type
@@ -34,6 +294,9 @@ pub enum Type {
         elem: ScalarType,
         len: Box<Expr>,
     },
+    /// `[u8; _]`: as many bytes as the initializer produces (`@uleb128()`,
+    /// `@bytes()`, ...)
+    Inferred,
 }
 
 impl Type {
@@ -42,6 +305,7 @@ impl Type {
         match self {
             Type::Scalar(s) => *s,
             Type::Array { elem, .. } => *elem,
+            Type::Inferred => ScalarType::U8,
         }
     }
 }
@@ -83,6 +347,8 @@ pub enum Expr {
     },
     /// Array literal: [val; N], [val; _], or [a, b, c]
     ArrayLiteral(ArrayLiteralKind),
+    /// Keyword argument of a builtin call: `block = 4096`
+    Named { name: String, value: Box<Expr> },
 }
 
 /// Array literal kind
@@ -117,6 +383,13 @@ pub enum BinOp {
     Shr,        // >>
     Add,        // +
     Sub,        // -
+    Mul,        // *
+    Lt,         // <
+    Le,         // <=
+    Gt,         // >
+    Ge,         // >=
+    Eq,         // ==
+    Ne,         // !=
 }
 
 /// Unary operator
@@ -1,34 +1,531 @@
 //! Delbin built-in function implementations
 
-use crc::{Crc, CRC_16_MODBUS, CRC_32_ISO_HDLC};
-use sha2::{Digest, Sha256};
+use crc::{
+    Crc, CRC_16_IBM_3740, CRC_16_MODBUS, CRC_16_USB, CRC_16_XMODEM, CRC_32_BZIP2, CRC_32_CKSUM,
+    CRC_32_ISCSI, CRC_32_ISO_HDLC, CRC_32_JAMCRC, CRC_32_MPEG_2,
+};
+#[cfg(feature = "encryption")]
+use ctr::cipher::{KeyIvInit, StreamCipher};
+#[cfg(feature = "encryption")]
+use ctr::Ctr128BE;
+#[cfg(feature = "hmac")]
+use hmac::Mac;
+use sha2::Digest;
+
+pub use sha2::Sha256;
 
 use crate::error::{DelbinError, DelbinWarning, ErrorCode, WarningCode};
 
+static CRC32_ISO_HDLC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+static CRC32_BZIP2: Crc<u32> = Crc::<u32>::new(&CRC_32_BZIP2);
+static CRC32_MPEG2: Crc<u32> = Crc::<u32>::new(&CRC_32_MPEG_2);
+static CRC32_POSIX: Crc<u32> = Crc::<u32>::new(&CRC_32_CKSUM);
+static CRC32_C: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+static CRC32_JAMCRC: Crc<u32> = Crc::<u32>::new(&CRC_32_JAMCRC);
+static CRC16_MODBUS: Crc<u16> = Crc::<u16>::new(&CRC_16_MODBUS);
+static CRC16_CCITT: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+static CRC16_XMODEM: Crc<u16> = Crc::<u16>::new(&CRC_16_XMODEM);
+static CRC16_USB: Crc<u16> = Crc::<u16>::new(&CRC_16_USB);
+
 /// CRC32 calculation (ISO-HDLC)
 pub fn crc32(data: &[u8]) -> u32 {
-    const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-    CRC.checksum(data)
+    CRC32_ISO_HDLC.checksum(data)
 }
 
 /// CRC16-MODBUS calculation
 pub fn crc16_modbus(data: &[u8]) -> u16 {
-    const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_MODBUS);
-    CRC.checksum(data)
+    CRC16_MODBUS.checksum(data)
 }
 
-/// Generic CRC dispatch by algorithm name
-pub fn crc_by_name(algorithm: &str, data: &[u8]) -> crate::error::Result<u64> {
-    match algorithm {
-        "crc32" | "crc32-iso-hdlc" => Ok(crc32(data) as u64),
-        "crc16-modbus" => Ok(crc16_modbus(data) as u64),
-        other => Err(DelbinError::new(
+/// Incremental CRC state, fed chunk by chunk so large inputs never need
+/// to be held in memory at once
+pub enum CrcDigest {
+    Crc32(crc::Digest<'static, u32>),
+    Crc16Modbus(crc::Digest<'static, u16>),
+    /// The other CRC16 variants
+    Crc16(crc::Digest<'static, u16>),
+    /// Parameterized CRC, see [`CrcParams`]
+    Custom(Box<CustomCrc>),
+}
+
+impl CrcDigest {
+    /// CRC32-ISO-HDLC digest
+    pub fn crc32() -> Self {
+        CrcDigest::Crc32(CRC32_ISO_HDLC.digest())
+    }
+
+    /// Digest for a named algorithm (as accepted by `@crc()`)
+    pub fn by_name(algorithm: &str) -> crate::error::Result<Self> {
+        match algorithm {
+            "crc32" | "crc32-iso-hdlc" => Ok(Self::crc32()),
+            // MPEG-2 matches the STM32 hardware CRC unit; C (Castagnoli) is iSCSI
+            "crc32-bzip2" => Ok(CrcDigest::Crc32(CRC32_BZIP2.digest())),
+            "crc32-mpeg2" => Ok(CrcDigest::Crc32(CRC32_MPEG2.digest())),
+            "crc32-posix" => Ok(CrcDigest::Crc32(CRC32_POSIX.digest())),
+            "crc32-c" => Ok(CrcDigest::Crc32(CRC32_C.digest())),
+            "crc32-jamcrc" => Ok(CrcDigest::Crc32(CRC32_JAMCRC.digest())),
+            "crc16-modbus" => Ok(CrcDigest::Crc16Modbus(CRC16_MODBUS.digest())),
+            // CCITT-FALSE: poly 0x1021, init 0xFFFF, not reflected
+            "crc16-ccitt" => Ok(CrcDigest::Crc16(CRC16_CCITT.digest())),
+            "crc16-xmodem" => Ok(CrcDigest::Crc16(CRC16_XMODEM.digest())),
+            "crc16-usb" => Ok(CrcDigest::Crc16(CRC16_USB.digest())),
+            other => Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!(
+                    "Unknown CRC algorithm: '{}'. Supported: crc32, crc32-bzip2, crc32-mpeg2, \
+                     crc32-posix, crc32-c, crc32-jamcrc, crc16-ccitt, crc16-modbus, crc16-xmodem, \
+                     crc16-usb",
+                    other
+                ),
+            )),
+        }
+    }
+
+    /// Digest for an arbitrary CRC definition
+    pub fn custom(params: CrcParams) -> crate::error::Result<Self> {
+        params.validate()?;
+        Ok(CrcDigest::Custom(Box::new(CustomCrc::new(params))))
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            CrcDigest::Crc32(d) => d.update(data),
+            CrcDigest::Crc16Modbus(d) | CrcDigest::Crc16(d) => d.update(data),
+            CrcDigest::Custom(c) => c.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> u64 {
+        match self {
+            CrcDigest::Crc32(d) => d.finalize() as u64,
+            CrcDigest::Crc16Modbus(d) | CrcDigest::Crc16(d) => d.finalize() as u64,
+            CrcDigest::Custom(c) => c.finalize(),
+        }
+    }
+}
+
+/// CRC definition in the Rocksoft model, as used by the CRC catalogues
+///
+/// `poly` is written without its top bit; `init` is the register value
+/// before the first byte; `refin`/`refout` reflect input bytes and the
+/// final register; `xorout` is XORed into the result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrcParams {
+    pub width: u8,
+    pub poly: u64,
+    pub init: u64,
+    pub refin: bool,
+    pub refout: bool,
+    pub xorout: u64,
+}
+
+impl CrcParams {
+    /// E04003 unless `width` is 1 to 64 and the other values fit in it
+    pub fn validate(&self) -> crate::error::Result<()> {
+        if !(1..=64).contains(&self.width) {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("CRC width must be 1 to 64 bits, got {}", self.width),
+            ));
+        }
+        for (name, value) in [("poly", self.poly), ("init", self.init), ("xorout", self.xorout)] {
+            if value & !self.mask() != 0 {
+                return Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    format!("CRC {} 0x{:X} doesn't fit in {} bits", name, value, self.width),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.width.clamp(1, 64))
+    }
+
+    /// Parameters from the `width, poly, init, refin, refout, xorout`
+    /// arguments of `@crc()`, validated
+    pub(crate) fn from_args(args: [u64; 6]) -> crate::error::Result<Self> {
+        let [width, poly, init, refin, refout, xorout] = args;
+        let params = Self {
+            width: crc_width(width)?,
+            poly,
+            init,
+            refin: crc_flag("refin", refin)?,
+            refout: crc_flag("refout", refout)?,
+            xorout,
+        };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+/// `width` argument of `@crc()`
+pub(crate) fn crc_width(width: u64) -> crate::error::Result<u8> {
+    u8::try_from(width).ok().filter(|width| (1..=64).contains(width)).ok_or_else(|| {
+        DelbinError::new(ErrorCode::E04003, format!("CRC width must be 1 to 64 bits, got {}", width))
+    })
+}
+
+/// `refin` or `refout` argument of `@crc()`
+pub(crate) fn crc_flag(name: &str, value: u64) -> crate::error::Result<bool> {
+    match value {
+        0 | 1 => Ok(value == 1),
+        _ => Err(DelbinError::new(
             ErrorCode::E04003,
-            format!("Unknown CRC algorithm: '{}'. Supported: crc32, crc16-modbus", other),
+            format!("@crc() {} must be 0 or 1, got {}", name, value),
         )),
     }
 }
 
+/// RevEng notation, e.g. `width=16 poly=0x1021 init=0xffff refin=false
+/// refout=false xorout=0x0000`
+impl std::fmt::Display for CrcParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = (self.width as usize).div_ceil(4);
+        write!(
+            f,
+            "width={} poly=0x{:0w$x} init=0x{:0w$x} refin={} refout={} xorout=0x{:0w$x}",
+            self.width,
+            self.poly,
+            self.init,
+            self.refin,
+            self.refout,
+            self.xorout,
+            w = digits
+        )
+    }
+}
+
+/// Incremental state of a [`CrcParams`] CRC
+///
+/// Table-driven a byte at a time for widths of 8 bits and more, bit by bit
+/// below that.
+pub struct CustomCrc {
+    params: CrcParams,
+    table: Option<Box<[u64; 256]>>,
+    register: u64,
+}
+
+impl CustomCrc {
+    fn new(params: CrcParams) -> Self {
+        let table = (params.width >= 8).then(|| {
+            let mut table = Box::new([0u64; 256]);
+            for (byte, entry) in table.iter_mut().enumerate() {
+                *entry = Self::shift_in(&params, (byte as u64) << (params.width - 8), 0);
+            }
+            table
+        });
+        Self {
+            params,
+            table,
+            register: params.init,
+        }
+    }
+
+    /// Clock the 8 bits of `byte`, most significant first, into `register`
+    fn shift_in(params: &CrcParams, mut register: u64, byte: u8) -> u64 {
+        let top = params.width - 1;
+        for i in (0..8).rev() {
+            let feedback = (register >> top) & 1 != ((byte >> i) & 1) as u64;
+            register = (register << 1) & params.mask();
+            if feedback {
+                register ^= params.poly;
+            }
+        }
+        register
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let p = &self.params;
+        for &byte in data {
+            let byte = if p.refin { byte.reverse_bits() } else { byte };
+            self.register = match &self.table {
+                Some(table) => {
+                    let index = ((self.register >> (p.width - 8)) as u8 ^ byte) as usize;
+                    ((self.register << 8) & p.mask()) ^ table[index]
+                }
+                None => Self::shift_in(p, self.register, byte),
+            };
+        }
+    }
+
+    pub fn finalize(self) -> u64 {
+        let p = &self.params;
+        let register = if p.refout {
+            self.register.reverse_bits() >> (64 - p.width)
+        } else {
+            self.register
+        };
+        register ^ p.xorout
+    }
+}
+
+/// Generic CRC dispatch by algorithm name
+pub fn crc_by_name(algorithm: &str, data: &[u8]) -> crate::error::Result<u64> {
+    let mut digest = CrcDigest::by_name(algorithm)?;
+    digest.update(data);
+    Ok(digest.finalize())
+}
+
+/// Additive, XOR, Fletcher and Adler checksums found in vendor header specs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimpleChecksum {
+    /// Sum of all bytes modulo 2^8
+    Sum8,
+    /// Sum of all bytes modulo 2^16
+    Sum16,
+    /// Sum of all bytes modulo 2^32
+    Sum32,
+    /// XOR of all bytes
+    Xor8,
+    /// Two's complement of `Sum8`, so that the data plus the checksum sums
+    /// to zero
+    Checksum8TwosComplement,
+    /// Fletcher-16: two sums of bytes modulo 255, the second in the high byte
+    Fletcher16,
+    /// Fletcher-32: two sums of little-endian 16-bit words modulo 65535, an
+    /// odd trailing byte padded with zero
+    Fletcher32,
+    /// Adler-32 (RFC 1950): Fletcher-style sums modulo 65521, starting at 1
+    Adler32,
+}
+
+impl SimpleChecksum {
+    /// Checksum for a builtin name: `sum8`, `sum16`, `sum32`, `xor8`,
+    /// `checksum8_2c`, `fletcher16`, `fletcher32` or `adler32`
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "sum8" => Some(Self::Sum8),
+            "sum16" => Some(Self::Sum16),
+            "sum32" => Some(Self::Sum32),
+            "xor8" => Some(Self::Xor8),
+            "checksum8_2c" => Some(Self::Checksum8TwosComplement),
+            "fletcher16" => Some(Self::Fletcher16),
+            "fletcher32" => Some(Self::Fletcher32),
+            "adler32" => Some(Self::Adler32),
+            _ => None,
+        }
+    }
+
+    /// Incremental state
+    pub fn digest(self) -> SimpleChecksumDigest {
+        SimpleChecksumDigest {
+            kind: self,
+            a: if self == Self::Adler32 { 1 } else { 0 },
+            b: 0,
+            odd: None,
+        }
+    }
+}
+
+/// Incremental state of a [`SimpleChecksum`]
+#[derive(Debug, Clone)]
+pub struct SimpleChecksumDigest {
+    kind: SimpleChecksum,
+    /// Running sum (or XOR)
+    a: u64,
+    /// Sum of the running sums, for Fletcher and Adler
+    b: u64,
+    /// Fletcher-32 byte waiting for the other half of its word
+    odd: Option<u8>,
+}
+
+impl SimpleChecksumDigest {
+    pub fn update(&mut self, data: &[u8]) {
+        match self.kind {
+            SimpleChecksum::Xor8 => self.a = data.iter().fold(self.a, |a, &byte| a ^ byte as u64),
+            SimpleChecksum::Fletcher16 => self.fletcher(data.iter().map(|&byte| byte as u64), 255),
+            SimpleChecksum::Adler32 => self.fletcher(data.iter().map(|&byte| byte as u64), 65521),
+            SimpleChecksum::Fletcher32 => {
+                let mut data = data;
+                if let (Some(low), Some((&high, rest))) = (self.odd, data.split_first()) {
+                    self.fletcher(std::iter::once(u16::from_le_bytes([low, high]) as u64), 65535);
+                    self.odd = None;
+                    data = rest;
+                }
+                let words = data.chunks_exact(2);
+                if let [last] = words.remainder() {
+                    self.odd = Some(*last);
+                }
+                self.fletcher(words.map(|w| u16::from_le_bytes([w[0], w[1]]) as u64), 65535);
+            }
+            _ => self.a = data.iter().fold(self.a, |a, &byte| a.wrapping_add(byte as u64)),
+        }
+    }
+
+    fn fletcher(&mut self, values: impl Iterator<Item = u64>, modulus: u64) {
+        for value in values {
+            self.a = (self.a + value) % modulus;
+            self.b = (self.b + self.a) % modulus;
+        }
+    }
+
+    pub fn finalize(&self) -> u64 {
+        match self.kind {
+            SimpleChecksum::Sum8 | SimpleChecksum::Xor8 => self.a & 0xFF,
+            SimpleChecksum::Sum16 => self.a & 0xFFFF,
+            SimpleChecksum::Sum32 => self.a & 0xFFFF_FFFF,
+            SimpleChecksum::Checksum8TwosComplement => (self.a as u8).wrapping_neg() as u64,
+            SimpleChecksum::Fletcher16 => self.b << 8 | self.a,
+            SimpleChecksum::Fletcher32 => {
+                let mut last = self.clone();
+                if let Some(low) = last.odd {
+                    last.fletcher(std::iter::once(low as u64), 65535);
+                }
+                last.b << 16 | last.a
+            }
+            SimpleChecksum::Adler32 => self.b << 16 | self.a,
+        }
+    }
+}
+
+/// Fast non-cryptographic hashes, `@xxh32()` and friends, all with seed 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FastHash {
+    /// XXH32; needs the `xxhash` feature
+    Xxh32,
+    /// XXH64; needs the `xxhash` feature
+    Xxh64,
+    /// XXH3, 64-bit; needs the `xxhash` feature
+    Xxh3,
+    /// MurmurHash3, x86 32-bit variant
+    Murmur3_32,
+}
+
+impl FastHash {
+    /// Hash for a builtin name: `xxh32`, `xxh64`, `xxh3` or `murmur3_32`
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "xxh32" => Some(Self::Xxh32),
+            "xxh64" => Some(Self::Xxh64),
+            "xxh3" => Some(Self::Xxh3),
+            "murmur3_32" => Some(Self::Murmur3_32),
+            _ => None,
+        }
+    }
+
+    /// Builtin name
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Xxh32 => "xxh32",
+            Self::Xxh64 => "xxh64",
+            Self::Xxh3 => "xxh3",
+            Self::Murmur3_32 => "murmur3_32",
+        }
+    }
+
+    /// Incremental state; E04003 for the xxHash family in builds without
+    /// the `xxhash` feature
+    pub fn hasher(self) -> crate::error::Result<FastHashDigest> {
+        let state = match self {
+            #[cfg(feature = "xxhash")]
+            Self::Xxh32 => FastHashState::Xxh32(xxhash_rust::xxh32::Xxh32::new(0)),
+            #[cfg(feature = "xxhash")]
+            Self::Xxh64 => FastHashState::Xxh64(xxhash_rust::xxh64::Xxh64::new(0)),
+            #[cfg(feature = "xxhash")]
+            Self::Xxh3 => FastHashState::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+            #[cfg(not(feature = "xxhash"))]
+            Self::Xxh32 | Self::Xxh64 | Self::Xxh3 => {
+                return Err(missing_feature(&format!("@{}()", self.name()), "xxhash"))
+            }
+            Self::Murmur3_32 => FastHashState::Murmur3 { h: 0, tail: Vec::with_capacity(4), len: 0 },
+        };
+        Ok(FastHashDigest(state))
+    }
+}
+
+/// Incremental fast hash state
+#[derive(Clone)]
+pub struct FastHashDigest(FastHashState);
+
+#[derive(Clone)]
+enum FastHashState {
+    #[cfg(feature = "xxhash")]
+    Xxh32(xxhash_rust::xxh32::Xxh32),
+    #[cfg(feature = "xxhash")]
+    Xxh64(xxhash_rust::xxh64::Xxh64),
+    #[cfg(feature = "xxhash")]
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    /// Hash of the full blocks so far, the bytes of an incomplete block and
+    /// the total length
+    Murmur3 { h: u32, tail: Vec<u8>, len: u32 },
+}
+
+impl std::fmt::Debug for FastHashDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FastHashDigest")
+    }
+}
+
+impl FastHashDigest {
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.0 {
+            #[cfg(feature = "xxhash")]
+            FastHashState::Xxh32(state) => state.update(data),
+            #[cfg(feature = "xxhash")]
+            FastHashState::Xxh64(state) => state.update(data),
+            #[cfg(feature = "xxhash")]
+            FastHashState::Xxh3(state) => state.update(data),
+            FastHashState::Murmur3 { h, tail, len } => {
+                *len = len.wrapping_add(data.len() as u32);
+                let mut data = data;
+                if !tail.is_empty() {
+                    let take = (4 - tail.len()).min(data.len());
+                    tail.extend_from_slice(&data[..take]);
+                    data = &data[take..];
+                    if tail.len() < 4 {
+                        return;
+                    }
+                    *h = murmur3_block(*h, [tail[0], tail[1], tail[2], tail[3]]);
+                    tail.clear();
+                }
+                let blocks = data.chunks_exact(4);
+                tail.extend_from_slice(blocks.remainder());
+                for block in blocks {
+                    *h = murmur3_block(*h, [block[0], block[1], block[2], block[3]]);
+                }
+            }
+        }
+    }
+
+    pub fn finalize(&self) -> u64 {
+        match &self.0 {
+            #[cfg(feature = "xxhash")]
+            FastHashState::Xxh32(state) => state.digest() as u64,
+            #[cfg(feature = "xxhash")]
+            FastHashState::Xxh64(state) => state.digest(),
+            #[cfg(feature = "xxhash")]
+            FastHashState::Xxh3(state) => state.digest(),
+            FastHashState::Murmur3 { h, tail, len } => {
+                let mut h = *h;
+                if !tail.is_empty() {
+                    let k = tail.iter().rev().fold(0u32, |k, &byte| k << 8 | byte as u32);
+                    h ^= murmur3_scramble(k);
+                }
+                h ^= len;
+                h ^= h >> 16;
+                h = h.wrapping_mul(0x85EB_CA6B);
+                h ^= h >> 13;
+                h = h.wrapping_mul(0xC2B2_AE35);
+                h ^= h >> 16;
+                h as u64
+            }
+        }
+    }
+}
+
+fn murmur3_scramble(k: u32) -> u32 {
+    k.wrapping_mul(0xCC9E_2D51).rotate_left(15).wrapping_mul(0x1B87_3593)
+}
+
+fn murmur3_block(h: u32, block: [u8; 4]) -> u32 {
+    (h ^ murmur3_scramble(u32::from_le_bytes(block)))
+        .rotate_left(13)
+        .wrapping_mul(5)
+        .wrapping_add(0xE654_6B64)
+}
+
 /// SHA256 calculation
 pub fn sha256(data: &[u8]) -> [u8; 32] {
     let mut hasher = Sha256::new();
@@ -36,6 +533,618 @@ pub fn sha256(data: &[u8]) -> [u8; 32] {
     hasher.finalize().into()
 }
 
+/// Digests of the array-producing hash builtins, `@sha256()` and friends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+    Sha3_256,
+    /// Needs the `legacy-digests` feature
+    Sha1,
+    /// Needs the `legacy-digests` feature
+    Md5,
+}
+
+impl DigestAlgorithm {
+    /// Algorithm for a builtin name: `sha256`, `sha384`, `sha512`,
+    /// `sha3_256`, `sha1` or `md5`
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(Self::Sha256),
+            "sha384" => Some(Self::Sha384),
+            "sha512" => Some(Self::Sha512),
+            "sha3_256" => Some(Self::Sha3_256),
+            "sha1" => Some(Self::Sha1),
+            "md5" => Some(Self::Md5),
+            _ => None,
+        }
+    }
+
+    /// Builtin name
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+            Self::Sha3_256 => "sha3_256",
+            Self::Sha1 => "sha1",
+            Self::Md5 => "md5",
+        }
+    }
+
+    /// Digest size in bytes
+    pub fn output_len(self) -> usize {
+        match self {
+            Self::Sha256 | Self::Sha3_256 => 32,
+            Self::Sha384 => 48,
+            Self::Sha512 => 64,
+            Self::Sha1 => 20,
+            Self::Md5 => 16,
+        }
+    }
+
+    /// Incremental state; E04003 in builds without the algorithm's feature
+    /// (`sha3` or `legacy-digests`)
+    pub fn hasher(self) -> crate::error::Result<Box<dyn sha2::digest::DynDigest>> {
+        match self {
+            Self::Sha256 => Ok(Box::new(Sha256::new())),
+            Self::Sha384 => Ok(Box::new(sha2::Sha384::new())),
+            Self::Sha512 => Ok(Box::new(sha2::Sha512::new())),
+            #[cfg(feature = "sha3")]
+            Self::Sha3_256 => Ok(Box::new(sha3::Sha3_256::new())),
+            #[cfg(not(feature = "sha3"))]
+            Self::Sha3_256 => Err(missing_feature(&format!("@{}()", self.name()), "sha3")),
+            #[cfg(feature = "legacy-digests")]
+            Self::Sha1 => Ok(Box::new(sha1::Sha1::new())),
+            #[cfg(feature = "legacy-digests")]
+            Self::Md5 => Ok(Box::new(md5::Md5::new())),
+            #[cfg(not(feature = "legacy-digests"))]
+            Self::Sha1 | Self::Md5 => Err(missing_feature(&format!("@{}()", self.name()), "legacy-digests")),
+        }
+    }
+}
+
+/// Incremental HMAC-SHA256 state
+#[cfg(feature = "hmac")]
+pub type HmacSha256 = hmac::Hmac<Sha256>;
+
+/// HMAC-SHA256 calculation (keys of any length)
+#[cfg(feature = "hmac")]
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Network-order bytes of an address literal: `"ipv4"` (4 bytes),
+/// `"ipv6"` (16) or `"mac"` (6, `:` or `-` separated)
+pub fn address(kind: &str, text: &str) -> crate::error::Result<Vec<u8>> {
+    let bytes = match kind {
+        "ipv4" => text.parse::<std::net::Ipv4Addr>().ok().map(|a| a.octets().to_vec()),
+        "ipv6" => text.parse::<std::net::Ipv6Addr>().ok().map(|a| a.octets().to_vec()),
+        _ => {
+            let parts: Vec<&str> = text.split([':', '-']).collect();
+            let octets = parts
+                .iter()
+                .filter(|p| p.len() == 2)
+                .filter_map(|p| u8::from_str_radix(p, 16).ok())
+                .collect::<Vec<u8>>();
+            (parts.len() == 6 && octets.len() == 6).then_some(octets)
+        }
+    };
+    bytes.ok_or_else(|| {
+        DelbinError::new(
+            ErrorCode::E04003,
+            format!("Invalid {} address: '{}'", kind.to_uppercase(), text),
+        )
+    })
+}
+
+/// `@uuid5()` namespaces by name (RFC 9562, section 6.6)
+pub const UUID_NAMESPACES: &[(&str, [u8; 16])] = &[
+    ("dns", uuid_const(0x6ba7b810_9dad_11d1_80b4_00c04fd430c8)),
+    ("url", uuid_const(0x6ba7b811_9dad_11d1_80b4_00c04fd430c8)),
+    ("oid", uuid_const(0x6ba7b812_9dad_11d1_80b4_00c04fd430c8)),
+    ("x500", uuid_const(0x6ba7b814_9dad_11d1_80b4_00c04fd430c8)),
+];
+
+const fn uuid_const(value: u128) -> [u8; 16] {
+    value.to_be_bytes()
+}
+
+/// Bytes of a UUID in its text form, `6ba7b810-9dad-11d1-80b4-00c04fd430c8`
+/// (hyphens optional), or of a well-known namespace name
+pub fn uuid_namespace(text: &str) -> crate::error::Result<[u8; 16]> {
+    if let Some((_, bytes)) = UUID_NAMESPACES.iter().find(|(name, _)| name.eq_ignore_ascii_case(text)) {
+        return Ok(*bytes);
+    }
+    let digits = text.replace('-', "");
+    let groups: Vec<usize> = text.split('-').map(str::len).collect();
+    let layout_ok = groups == [8, 4, 4, 4, 12] || groups == [32];
+    match u128::from_str_radix(&digits, 16) {
+        Ok(value) if layout_ok && digits.bytes().all(|b| b.is_ascii_hexdigit()) => Ok(value.to_be_bytes()),
+        _ => Err(DelbinError::new(
+            ErrorCode::E04003,
+            format!("Invalid UUID namespace: '{}'", text),
+        )
+        .with_hint("use dns, url, oid, x500 or a UUID such as 6ba7b810-9dad-11d1-80b4-00c04fd430c8")),
+    }
+}
+
+/// Random (version 4) UUID from 16 random bytes
+pub fn uuid_v4(mut bytes: [u8; 16]) -> [u8; 16] {
+    bytes[6] = bytes[6] & 0x0F | 0x40;
+    bytes[8] = bytes[8] & 0x3F | 0x80;
+    bytes
+}
+
+/// Name-based (version 5, SHA-1) UUID of `name` in `namespace`; E04003 in
+/// builds without the `uuid` feature
+pub fn uuid_v5(namespace: &[u8; 16], name: &[u8]) -> crate::error::Result<[u8; 16]> {
+    #[cfg(feature = "uuid")]
+    {
+        let hash = sha1::Sha1::new().chain_update(namespace).chain_update(name).finalize();
+        let mut bytes: [u8; 16] = hash[..16].try_into().expect("16 of 20 bytes");
+        bytes[6] = bytes[6] & 0x0F | 0x50;
+        bytes[8] = bytes[8] & 0x3F | 0x80;
+        Ok(bytes)
+    }
+    #[cfg(not(feature = "uuid"))]
+    {
+        let _ = (namespace, name);
+        Err(missing_feature("@uuid5()", "uuid"))
+    }
+}
+
+/// `len` bytes from the operating system's random source; E04003 in builds
+/// without the `random` feature, naming `what`
+pub fn random_bytes(what: &str, len: usize) -> crate::error::Result<Vec<u8>> {
+    #[cfg(feature = "random")]
+    {
+        let _ = what;
+        let mut bytes = vec![0u8; len];
+        getrandom::getrandom(&mut bytes).map_err(|e| {
+            DelbinError::new(ErrorCode::E04003, format!("System random source failed: {}", e))
+        })?;
+        Ok(bytes)
+    }
+    #[cfg(not(feature = "random"))]
+    {
+        let _ = len;
+        Err(missing_feature(what, "random").with_hint("or fix the output with GenerateOptions::with_rng_seed()"))
+    }
+}
+
+/// `len` pseudo-random bytes determined by `seed` and `label`
+///
+/// SHA-256 in counter mode, so each label (field) gets its own stream and
+/// output stays the same across runs and platforms.
+pub fn seeded_bytes(seed: u64, label: &str, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len.next_multiple_of(32));
+    let mut block = 0u32;
+    while out.len() < len {
+        let mut hasher = Sha256::new();
+        hasher.update(b"delbin-rng");
+        hasher.update(seed.to_le_bytes());
+        hasher.update(block.to_le_bytes());
+        hasher.update(label.as_bytes());
+        out.extend_from_slice(&hasher.finalize());
+        block += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+/// `value` rounded up to a multiple of `align` (`@align_up()`)
+///
+/// `align` may be any non-zero number, not only a power of two; E03003 if
+/// the result doesn't fit in 64 bits.
+pub fn align_up(value: u64, align: u64) -> crate::error::Result<u64> {
+    nonzero_align("align_up", align)?;
+    value
+        .div_ceil(align)
+        .checked_mul(align)
+        .ok_or_else(|| {
+            DelbinError::new(
+                ErrorCode::E03003,
+                format!("@align_up({}, {}) doesn't fit in 64 bits", value, align),
+            )
+        })
+}
+
+/// `value` rounded down to a multiple of `align` (`@align_down()`)
+pub fn align_down(value: u64, align: u64) -> crate::error::Result<u64> {
+    nonzero_align("align_down", align)?;
+    Ok(value - value % align)
+}
+
+/// Unsigned LEB128 encoding of `value` (`@uleb128()`): 7 bits per byte,
+/// least significant first, high bit set on all but the last byte
+pub fn uleb128(mut value: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(10);
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Signed LEB128 encoding of `value` (`@sleb128()`), two's complement
+pub fn sleb128(mut value: i64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(10);
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// LEB128 `encoded` stretched to `len` bytes with redundant continuation
+/// bytes, so decoders read the whole field; `None` if it is longer
+///
+/// `negative` is the sign of a signed encoding, whose padding is `0xFF`.
+pub fn pad_leb128(mut encoded: Vec<u8>, len: usize, negative: bool) -> Option<Vec<u8>> {
+    if encoded.len() > len {
+        return None;
+    }
+    let fill = if negative { 0x7F } else { 0x00 };
+    while encoded.len() < len {
+        *encoded.last_mut()? |= 0x80;
+        encoded.push(fill);
+    }
+    Some(encoded)
+}
+
+/// `x` limited to `lo..=hi` (`@clamp()`); E04003 if `lo > hi`
+pub fn clamp(x: u64, lo: u64, hi: u64) -> crate::error::Result<u64> {
+    if lo > hi {
+        return Err(DelbinError::new(
+            ErrorCode::E04003,
+            format!("@clamp() lower bound {} is above upper bound {}", lo, hi),
+        ));
+    }
+    Ok(x.clamp(lo, hi))
+}
+
+/// Builtins that only do arithmetic on their numeric arguments, so
+/// constant arguments can be folded ahead of evaluation
+pub const ARITHMETIC_BUILTINS: &[&str] = &["align_up", "align_down", "min", "max", "clamp"];
+
+/// Value of arithmetic builtin `name` on `args`; `None` if `name` isn't
+/// one of [`ARITHMETIC_BUILTINS`] or the argument count is wrong
+pub fn arithmetic(name: &str, args: &[u64]) -> Option<crate::error::Result<u64>> {
+    Some(match (name, args) {
+        ("align_up", &[value, align]) => align_up(value, align),
+        ("align_down", &[value, align]) => align_down(value, align),
+        ("min", &[a, b]) => Ok(a.min(b)),
+        ("max", &[a, b]) => Ok(a.max(b)),
+        ("clamp", &[x, lo, hi]) => clamp(x, lo, hi),
+        _ => return None,
+    })
+}
+
+fn nonzero_align(name: &str, align: u64) -> crate::error::Result<()> {
+    if align == 0 {
+        return Err(DelbinError::new(
+            ErrorCode::E04003,
+            format!("@{}() alignment must not be zero", name),
+        ));
+    }
+    Ok(())
+}
+
+/// DOS (FAT) `(date, time)` of a Unix timestamp, in UTC
+///
+/// Date: bits 15-9 years since 1980, 8-5 month, 4-0 day. Time: bits 15-11
+/// hours, 10-5 minutes, 4-0 seconds / 2, so odd seconds round down. Only
+/// 1980 through 2107 can be represented.
+pub fn dos_datetime(unix: u64) -> crate::error::Result<(u16, u16)> {
+    const DOS_EPOCH: u64 = 315_532_800; // 1980-01-01T00:00:00Z
+    const DOS_END: u64 = 4_354_819_200; // 2108-01-01T00:00:00Z
+    if !(DOS_EPOCH..DOS_END).contains(&unix) {
+        return Err(DelbinError::new(
+            ErrorCode::E04003,
+            format!("Timestamp {} is outside the DOS date range (1980-2107)", unix),
+        ));
+    }
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm,
+    // with eras starting on March 1st)
+    let (days, secs) = (unix / 86_400, unix % 86_400);
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    let date = ((year - 1980) << 9 | month << 5 | day) as u16;
+    let time = ((secs / 3600) << 11 | (secs / 60 % 60) << 5 | (secs % 60 / 2)) as u16;
+    Ok((date, time))
+}
+
+/// Bit widths of a `@semver()` packing scheme such as `"u8.u8.u16"`, most
+/// significant component first
+///
+/// Each component is `u1` through `u64`; together they fit in 64 bits.
+pub fn semver_scheme(scheme: &str) -> crate::error::Result<Vec<u32>> {
+    let invalid = || {
+        DelbinError::new(
+            ErrorCode::E04003,
+            format!("Invalid @semver() packing scheme: '{}'", scheme),
+        )
+        .with_hint("list a width per component, most significant first, e.g. \"u8.u8.u16\"")
+    };
+    let widths = scheme
+        .split('.')
+        .map(|part| {
+            part.strip_prefix('u')
+                .and_then(|bits| bits.parse::<u32>().ok())
+                .filter(|bits| (1..=64).contains(bits))
+                .ok_or_else(invalid)
+        })
+        .collect::<crate::error::Result<Vec<u32>>>()?;
+    if widths.iter().sum::<u32>() > 64 {
+        return Err(DelbinError::new(
+            ErrorCode::E04003,
+            format!("@semver() packing scheme '{}' needs more than 64 bits", scheme),
+        ));
+    }
+    Ok(widths)
+}
+
+/// `version` (`"1.2.345"`, optionally `v`-prefixed, `+build` metadata
+/// ignored) packed under `scheme`: the first component in the top bits
+///
+/// The version needs exactly one number per scheme component; a
+/// pre-release (`1.2.3-rc1`) is rejected rather than packed as the release.
+pub fn semver(version: &str, scheme: &str) -> crate::error::Result<u64> {
+    let widths = semver_scheme(scheme)?;
+    let core = version.strip_prefix('v').unwrap_or(version);
+    let core = core.split_once('+').map_or(core, |(core, _)| core);
+    let invalid = |why: &str| {
+        DelbinError::new(
+            ErrorCode::E04003,
+            format!("Invalid version '{}' for @semver(..., \"{}\"): {}", version, scheme, why),
+        )
+    };
+    if core.contains('-') {
+        return Err(invalid("pre-release versions can't be packed"));
+    }
+    let parts: Vec<&str> = core.split('.').collect();
+    if parts.len() != widths.len() {
+        return Err(invalid(&format!("expected {} components", widths.len())));
+    }
+
+    let mut packed = 0u64;
+    for (part, &bits) in parts.iter().zip(&widths) {
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid(&format!("'{}' is not a number", part)));
+        }
+        let max = u64::MAX >> (64 - bits);
+        let value = part.parse::<u64>().ok().filter(|&v| v <= max).ok_or_else(|| {
+            DelbinError::new(
+                ErrorCode::E03003,
+                format!("Version component {} of '{}' doesn't fit in u{}", part, version, bits),
+            )
+        })?;
+        packed = packed.checked_shl(bits).unwrap_or(0) | value;
+    }
+    Ok(packed)
+}
+
+/// Fibonacci LFSR keystream for `@whiten()`
+///
+/// `poly` includes the x^n term (PN9, x^9 + x^5 + 1, is `0x221`). Each step
+/// outputs the low state bit, then shifts right and feeds the parity of the
+/// tapped bits back in at the top. Bytes are assembled LSB first, so PN9 with
+/// seed `0x1FF` yields `FF E1 1D 9A ...`.
+#[derive(Debug, Clone)]
+pub struct Lfsr {
+    state: u64,
+    taps: u64,
+    degree: u32,
+}
+
+impl Lfsr {
+    pub fn new(poly: u64, seed: u64) -> crate::error::Result<Self> {
+        if poly < 2 {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("Invalid whitening polynomial 0x{:X}: degree must be at least 1", poly),
+            ));
+        }
+        let degree = 63 - poly.leading_zeros();
+        let mask = (1u64 << degree) - 1;
+        if seed & mask == 0 {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("Whitening seed must be non-zero in the low {} bits", degree),
+            ));
+        }
+        Ok(Self {
+            state: seed & mask,
+            taps: poly & mask,
+            degree,
+        })
+    }
+
+    /// Next 8 keystream bits
+    pub fn next_byte(&mut self) -> u8 {
+        let mut byte = 0;
+        for bit in 0..8 {
+            byte |= ((self.state & 1) as u8) << bit;
+            let feedback = (self.state & self.taps).count_ones() as u64 & 1;
+            self.state = (self.state >> 1) | (feedback << (self.degree - 1));
+        }
+        byte
+    }
+
+    /// XOR `data` with the keystream, continuing where the last call stopped
+    pub fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            *byte ^= self.next_byte();
+        }
+    }
+}
+
+/// @whiten() function: XOR data with an LFSR sequence (its own inverse)
+pub fn whiten(data: &mut [u8], poly: u64, seed: u64) -> crate::error::Result<()> {
+    Lfsr::new(poly, seed)?.apply(data);
+    Ok(())
+}
+
+/// SHA256 Merkle tree over fixed-size blocks, fed incrementally
+///
+/// Leaves are the hashes of each block (the last one may be short); each
+/// parent is the hash of its two children concatenated, and an odd node at
+/// the end of a level moves up unchanged. Empty data has the single leaf
+/// `sha256("")`.
+pub struct MerkleBuilder {
+    block: usize,
+    hasher: Sha256,
+    filled: usize,
+    leaves: Vec<[u8; 32]>,
+}
+
+impl MerkleBuilder {
+    pub fn new(block: usize) -> Self {
+        Self {
+            block,
+            hasher: Sha256::new(),
+            filled: 0,
+            leaves: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = data.len().min(self.block - self.filled);
+            self.hasher.update(&data[..take]);
+            self.filled += take;
+            data = &data[take..];
+            if self.filled == self.block {
+                self.leaves.push(self.hasher.finalize_reset().into());
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// Hash of each block; none for empty data
+    pub fn leaves(mut self) -> Vec<[u8; 32]> {
+        if self.filled > 0 {
+            self.leaves.push(self.hasher.finalize_reset().into());
+        }
+        self.leaves
+    }
+
+    /// Tree levels, leaves first; the last level holds only the root
+    pub fn finish(self) -> Vec<Vec<[u8; 32]>> {
+        let mut leaves = self.leaves();
+        if leaves.is_empty() {
+            leaves.push(sha256(b""));
+        }
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let next = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => sha256(&[left.as_slice(), right.as_slice()].concat()),
+                    _ => pair[0],
+                })
+                .collect();
+            levels.push(next);
+        }
+        levels
+    }
+
+    /// Root hash
+    pub fn root(self) -> [u8; 32] {
+        let levels = self.finish();
+        levels[levels.len() - 1][0]
+    }
+}
+
+/// E04003 for a builtin whose cargo feature is disabled
+pub(crate) fn missing_feature(what: &str, feature: &str) -> DelbinError {
+    DelbinError::new(ErrorCode::E04003, format!("{} requires delbin's {} feature", what, feature))
+}
+
+/// Error for AES-CTR without an IV: a fixed counter block would reuse the
+/// keystream across every image encrypted under the same key
+pub(crate) fn missing_iv(what: &str) -> DelbinError {
+    DelbinError::new(ErrorCode::E03001, format!("{} is required", what))
+        .with_hint("pass a unique 16-byte IV per image, e.g. a bytes env variable ${IV}")
+}
+
+/// AES-CTR keystream XOR (encryption and decryption alike)
+///
+/// The key length selects AES-128, AES-192 or AES-256; `iv` is the initial
+/// 128-bit big-endian counter block.
+#[cfg(feature = "encryption")]
+pub fn aes_ctr(key: &[u8], iv: &[u8; 16], data: &mut [u8]) -> crate::error::Result<()> {
+    fn apply<C: KeyIvInit + StreamCipher>(key: &[u8], iv: &[u8; 16], data: &mut [u8]) {
+        let mut cipher = C::new_from_slices(key, iv).expect("key and IV lengths are checked");
+        cipher.apply_keystream(data);
+    }
+    match key.len() {
+        16 => apply::<Ctr128BE<aes::Aes128>>(key, iv, data),
+        24 => apply::<Ctr128BE<aes::Aes192>>(key, iv, data),
+        32 => apply::<Ctr128BE<aes::Aes256>>(key, iv, data),
+        n => {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("AES key must be 16, 24 or 32 bytes, got {}", n),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// AES-CTR without the `encryption` feature: always E04003
+#[cfg(not(feature = "encryption"))]
+pub fn aes_ctr(_key: &[u8], _iv: &[u8; 16], _data: &mut [u8]) -> crate::error::Result<()> {
+    Err(missing_feature("AES-CTR encryption", "encryption"))
+}
+
+/// @compress() section transform
+///
+/// `lz4` is a raw LZ4 block without a size prefix (the header records the
+/// sizes), `zlib` is RFC 1950 at level 9 and `zstd` a single Zstandard frame.
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+pub fn compress(algorithm: &str, data: &[u8]) -> crate::error::Result<Vec<u8>> {
+    match algorithm {
+        #[cfg(not(feature = "compression"))]
+        "lz4" | "zlib" | "zstd" => Err(missing_feature("@compress()", "compression")),
+        #[cfg(feature = "compression")]
+        "lz4" => Ok(lz4_flex::block::compress(data)),
+        #[cfg(feature = "compression")]
+        "zlib" => Ok(miniz_oxide::deflate::compress_to_vec_zlib(data, 9)),
+        #[cfg(feature = "compression")]
+        "zstd" => Ok(ruzstd::encoding::compress_to_vec(
+            data,
+            ruzstd::encoding::CompressionLevel::Fastest,
+        )),
+        other => Err(DelbinError::new(
+            ErrorCode::E04003,
+            format!("Unknown compression algorithm: '{}'. Supported: lz4, zlib, zstd", other),
+        )),
+    }
+}
+
 /// @bytes() function: convert string to byte array
 pub fn bytes(s: &str, target_len: usize) -> (Vec<u8>, Option<DelbinWarning>) {
     let bytes = s.as_bytes();
@@ -63,6 +1172,123 @@ pub fn bytes(s: &str, target_len: usize) -> (Vec<u8>, Option<DelbinWarning>) {
     (result, warning)
 }
 
+/// Bytes spelled by a hex string such as `"DEADBEEF00"`
+///
+/// An optional `0x` prefix and whitespace between digit pairs are allowed;
+/// an odd number of digits or any other character is E04003.
+pub fn hex(s: &str) -> crate::error::Result<Vec<u8>> {
+    let invalid = |why: &str| {
+        DelbinError::new(ErrorCode::E04003, format!("Invalid hex string '{}': {}", s, why))
+    };
+    let text = s.trim();
+    let digits = text
+        .strip_prefix("0x")
+        .unwrap_or(text)
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| {
+            c.to_digit(16)
+                .map(|d| d as u8)
+                .ok_or_else(|| invalid(&format!("'{}' is not a hex digit", c)))
+        })
+        .collect::<crate::error::Result<Vec<u8>>>()?;
+    if digits.len() % 2 != 0 {
+        return Err(invalid("odd number of digits"));
+    }
+    Ok(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+/// Bytes encoded by a base64 string (RFC 4648, standard or URL-safe
+/// alphabet)
+///
+/// Padding is optional and whitespace is skipped, so wrapped PEM-style text
+/// decodes. Errors (E04003) give positions but not the text, which may be a
+/// secret.
+pub fn base64(s: &str) -> crate::error::Result<Vec<u8>> {
+    let invalid = |why: String| {
+        DelbinError::new(ErrorCode::E04003, format!("Invalid base64 data: {}", why))
+    };
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    let (mut acc, mut bits) = (0u32, 0u32);
+    let (mut symbols, mut padding) = (0usize, 0usize);
+    for (i, c) in s.chars().enumerate() {
+        let value = match c {
+            c if c.is_whitespace() => continue,
+            '=' => {
+                padding += 1;
+                continue;
+            }
+            _ if padding > 0 => return Err(invalid(format!("character {} follows padding", i + 1))),
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' | '-' => 62,
+            '/' | '_' => 63,
+            _ => return Err(invalid(format!("character {} is not in the base64 alphabet", i + 1))),
+        };
+        symbols += 1;
+        acc = acc << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    if symbols % 4 == 1 || (padding > 0 && (symbols + padding) % 4 != 0) {
+        return Err(invalid(format!("{} characters is not a whole number of bytes", symbols + padding)));
+    }
+    Ok(out)
+}
+
+/// Modes of `@file(path, mode)`; without one, data is zero-filled or
+/// truncated with a warning like `@bytes()`
+pub(crate) const FILE_MODES: &[&str] = &["exact", "pad", "truncate"];
+
+/// @file() function: fit file contents to a `target_len`-byte field
+///
+/// `"exact"` requires the file to be `target_len` bytes, `"pad"` allows it
+/// to be shorter (zero-filled) and `"truncate"` allows it to be longer (cut
+/// without a warning); anything else that doesn't fit is E04002.
+pub fn fit_file(
+    path: &str,
+    mut data: Vec<u8>,
+    target_len: usize,
+    mode: Option<&str>,
+) -> crate::error::Result<(Vec<u8>, Option<DelbinWarning>)> {
+    if let Some(mode) = mode.filter(|m| !FILE_MODES.contains(m)) {
+        return Err(DelbinError::new(
+            ErrorCode::E04003,
+            format!("Unknown @file() mode: '{}'. Supported: {}", mode, FILE_MODES.join(", ")),
+        ));
+    }
+    let fits = match mode {
+        Some("exact") => data.len() == target_len,
+        Some("pad") => data.len() <= target_len,
+        Some(_) => data.len() >= target_len,
+        None => true,
+    };
+    if !fits {
+        return Err(DelbinError::new(
+            ErrorCode::E04002,
+            format!(
+                "File '{}' is {} bytes but the field is {} (mode '{}')",
+                path,
+                data.len(),
+                target_len,
+                mode.unwrap_or_default()
+            ),
+        ));
+    }
+    let warning = (mode.is_none() && data.len() > target_len).then(|| DelbinWarning {
+        code: WarningCode::W03001,
+        message: format!("File '{}' truncated from {} to {} bytes", path, data.len(), target_len),
+        location: None,
+    });
+    data.resize(target_len, 0);
+    Ok((data, warning))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -74,6 +1300,171 @@ mod tests {
         assert_eq!(crc, 0x0D4A1185);
     }
 
+    #[test]
+    fn test_crc_variant_check_values() {
+        // Catalogue check values over "123456789"
+        for (algo, check) in [
+            ("crc16-ccitt", 0x29B1),
+            ("crc16-modbus", 0x4B37),
+            ("crc16-xmodem", 0x31C3),
+            ("crc16-usb", 0xB4C8),
+            ("crc32-bzip2", 0xFC891918),
+            ("crc32-mpeg2", 0x0376E6E7),
+            ("crc32-posix", 0x765E7680),
+            ("crc32-c", 0xE3069283),
+            ("crc32-jamcrc", 0x340BC6D9),
+        ] {
+            assert_eq!(crc_by_name(algo, b"123456789").unwrap(), check, "{algo}");
+        }
+    }
+
+    #[test]
+    fn test_simple_checksums() {
+        let check = |name| {
+            let mut digest = SimpleChecksum::by_name(name).unwrap().digest();
+            digest.update(b"1234");
+            digest.update(b"56789");
+            digest.finalize()
+        };
+        assert_eq!(check("sum8"), 0xDD);
+        assert_eq!(check("sum16"), 0x01DD);
+        assert_eq!(check("sum32"), 0x01DD);
+        assert_eq!(check("xor8"), 0x31);
+        assert_eq!(check("checksum8_2c"), 0x23);
+        assert_eq!(SimpleChecksum::by_name("sum64"), None);
+
+        let mut digest = SimpleChecksum::Sum16.digest();
+        digest.update(&[0xFF; 0x102]);
+        assert_eq!(digest.finalize(), (0xFF * 0x102) & 0xFFFF);
+    }
+
+    #[test]
+    fn test_fletcher_and_adler_check_values() {
+        let check = |name, data: &[u8]| {
+            let algorithm = SimpleChecksum::by_name(name).unwrap();
+            let mut whole = algorithm.digest();
+            whole.update(data);
+            // Byte-at-a-time must agree, odd Fletcher-32 chunks included
+            let mut split = algorithm.digest();
+            data.chunks(1).for_each(|chunk| split.update(chunk));
+            assert_eq!(whole.finalize(), split.finalize(), "{name}");
+            whole.finalize()
+        };
+        assert_eq!(check("fletcher16", b"abcde"), 0xC8F0);
+        assert_eq!(check("fletcher16", b"abcdef"), 0x2057);
+        assert_eq!(check("fletcher32", b"abcde"), 0xF04FC729);
+        assert_eq!(check("fletcher32", b"abcdef"), 0x56502D2A);
+        assert_eq!(check("fletcher32", b"abcdefgh"), 0xEBE19591);
+        assert_eq!(check("adler32", b"Wikipedia"), 0x11E60398);
+        assert_eq!(check("adler32", b""), 1);
+    }
+
+    #[test]
+    fn test_digest_algorithms() {
+        let hex = |algorithm: DigestAlgorithm| {
+            let mut hasher = algorithm.hasher().unwrap();
+            hasher.update(b"abc");
+            let digest = hasher.finalize();
+            assert_eq!(digest.len(), algorithm.output_len());
+            hex::encode(&digest[..8])
+        };
+        assert_eq!(hex(DigestAlgorithm::Sha256), "ba7816bf8f01cfea");
+        assert_eq!(hex(DigestAlgorithm::Sha384), "cb00753f45a35e8b");
+        assert_eq!(hex(DigestAlgorithm::Sha512), "ddaf35a193617aba");
+        #[cfg(feature = "sha3")]
+        assert_eq!(hex(DigestAlgorithm::Sha3_256), "3a985da74fe225b2");
+        #[cfg(feature = "legacy-digests")]
+        {
+            assert_eq!(hex(DigestAlgorithm::Sha1), "a9993e364706816a");
+            assert_eq!(hex(DigestAlgorithm::Md5), "900150983cd24fb0");
+        }
+    }
+
+    #[test]
+    fn test_disabled_features_name_themselves() {
+        let message = |result: crate::error::Result<()>| result.err().map(|e| e.message);
+        #[cfg(not(feature = "sha3"))]
+        assert_eq!(
+            message(DigestAlgorithm::Sha3_256.hasher().map(drop)).unwrap(),
+            "@sha3_256() requires delbin's sha3 feature"
+        );
+        #[cfg(not(feature = "legacy-digests"))]
+        assert!(message(DigestAlgorithm::Md5.hasher().map(drop)).unwrap().contains("legacy-digests"));
+        #[cfg(not(feature = "compression"))]
+        assert_eq!(
+            message(compress("zstd", b"data").map(drop)).unwrap(),
+            "@compress() requires delbin's compression feature"
+        );
+        assert!(message(compress("lzma", b"data").map(drop)).unwrap().starts_with("Unknown compression"));
+        #[cfg(not(feature = "encryption"))]
+        assert!(message(aes_ctr(&[0; 16], &[0; 16], &mut [0; 4])).unwrap().contains("encryption feature"));
+    }
+
+    #[test]
+    fn test_fast_hash_check_values() {
+        let check = |name, data: &[u8]| {
+            let algorithm = FastHash::by_name(name).unwrap();
+            let mut whole = algorithm.hasher().unwrap();
+            whole.update(data);
+            // Chunk boundaries must not matter
+            let mut split = algorithm.hasher().unwrap();
+            data.chunks(3).for_each(|chunk| split.update(chunk));
+            assert_eq!(whole.finalize(), split.finalize(), "{name}");
+            whole.finalize()
+        };
+        assert_eq!(check("murmur3_32", b""), 0);
+        assert_eq!(check("murmur3_32", b"hello"), 0x248B_FA47);
+        assert_eq!(check("murmur3_32", b"The quick brown fox jumps over the lazy dog"), 0x2E4F_F723);
+        #[cfg(feature = "xxhash")]
+        {
+            assert_eq!(check("xxh32", b""), 0x02CC_5D05);
+            assert_eq!(check("xxh64", b""), 0xEF46_DB37_51D8_E999);
+            assert_eq!(check("xxh3", b""), 0x2D06_8005_38D3_94C2);
+        }
+        #[cfg(not(feature = "xxhash"))]
+        assert_eq!(FastHash::Xxh3.hasher().unwrap_err().code, ErrorCode::E04003);
+    }
+
+    #[test]
+    fn test_custom_crc_check_values() {
+        let params = |width, poly, init, refin, xorout| CrcParams { width, poly, init, refin, refout: refin, xorout };
+        for (name, params, check) in [
+            ("CRC-3/GSM", params(3, 0x3, 0x0, false, 0x7), 0x4),
+            ("CRC-5/USB", params(5, 0x05, 0x1F, true, 0x1F), 0x19),
+            ("CRC-8/SMBUS", params(8, 0x07, 0x00, false, 0x00), 0xF4),
+            ("CRC-16/ARC", params(16, 0x8005, 0x0000, true, 0x0000), 0xBB3D),
+            ("CRC-16/IBM-3740", params(16, 0x1021, 0xFFFF, false, 0x0000), 0x29B1),
+            ("CRC-24/OPENPGP", params(24, 0x864CFB, 0xB704CE, false, 0x000000), 0x21CF02),
+            ("CRC-32/ISO-HDLC", params(32, 0x04C11DB7, 0xFFFF_FFFF, true, 0xFFFF_FFFF), 0xCBF43926),
+            ("CRC-32/MPEG-2", params(32, 0x04C11DB7, 0xFFFF_FFFF, false, 0x0000_0000), 0x0376E6E7),
+            ("CRC-64/XZ", params(64, 0x42F0E1EBA9EA3693, u64::MAX, true, u64::MAX), 0x995DC9BBDF1939FA),
+        ] {
+            let mut digest = CrcDigest::custom(params).unwrap();
+            for chunk in b"123456789".chunks(4) {
+                digest.update(chunk);
+            }
+            assert_eq!(digest.finalize(), check, "{name}");
+        }
+
+        let arc = params(16, 0x8005, 0, true, 0);
+        assert_eq!(arc.to_string(), "width=16 poly=0x8005 init=0x0000 refin=true refout=true xorout=0x0000");
+        assert!(CrcDigest::custom(CrcParams { width: 0, ..arc }).is_err());
+        assert!(CrcDigest::custom(CrcParams { width: 65, ..arc }).is_err());
+        assert!(CrcDigest::custom(CrcParams { width: 8, ..arc }).is_err());
+    }
+
+    #[test]
+    fn test_crc_digest_chunked_matches_oneshot() {
+        let data = b"hello world";
+        for algo in ["crc32", "crc16-modbus", "crc16-ccitt"] {
+            let mut digest = CrcDigest::by_name(algo).unwrap();
+            for chunk in data.chunks(3) {
+                digest.update(chunk);
+            }
+            assert_eq!(digest.finalize(), crc_by_name(algo, data).unwrap());
+        }
+    }
+
     #[test]
     fn test_sha256() {
         let data = b"hello world";
@@ -84,6 +1475,252 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "hmac")]
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_address() {
+        assert_eq!(address("ipv4", "192.168.1.10").unwrap(), [192, 168, 1, 10]);
+        let v6 = address("ipv6", "2001:db8::1").unwrap();
+        assert_eq!(v6[..4], [0x20, 0x01, 0x0D, 0xB8]);
+        assert_eq!(v6[15], 1);
+        assert_eq!(address("mac", "AA:BB:cc:DD:EE:0f").unwrap(), [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x0F]);
+        assert_eq!(address("mac", "02-00-00-00-00-01").unwrap(), [2, 0, 0, 0, 0, 1]);
+
+        for (kind, bad) in [("ipv4", "256.0.0.1"), ("ipv6", "1::2::3"), ("mac", "AA:BB:CC:DD:EE"), ("mac", "AAB:B:CC:DD:EE:FF")] {
+            assert!(address(kind, bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_uuid() {
+        let dns = uuid_namespace("dns").unwrap();
+        assert_eq!(uuid_namespace("6ba7b810-9dad-11d1-80b4-00c04fd430c8").unwrap(), dns);
+        assert_eq!(uuid_namespace("6BA7B8109DAD11D180B400C04FD430C8").unwrap(), dns);
+        for bad in ["6ba7b810-9dad-11d1-80b4", "6ba7b8109-dad-11d1-80b4-00c04fd430c8", "+ba7b810-9dad-11d1-80b4-00c04fd430c8"] {
+            assert!(uuid_namespace(bad).is_err(), "{bad}");
+        }
+
+        let v4 = uuid_v4([0xFF; 16]);
+        assert_eq!((v4[6], v4[8]), (0x4F, 0xBF));
+        #[cfg(feature = "uuid")]
+        assert_eq!(
+            hex::encode(uuid_v5(&dns, b"python.org").unwrap()),
+            "886313e13b8a53729b900c9aee199e5d"
+        );
+        #[cfg(not(feature = "uuid"))]
+        assert!(uuid_v5(&dns, b"python.org").unwrap_err().message.contains("uuid feature"));
+    }
+
+    #[test]
+    fn test_seeded_bytes() {
+        let a = seeded_bytes(7, "id", 40);
+        assert_eq!(a.len(), 40);
+        assert_eq!(a, seeded_bytes(7, "id", 40));
+        assert_eq!(a[..16], seeded_bytes(7, "id", 16));
+        assert_ne!(a[..16], seeded_bytes(8, "id", 16));
+        assert_ne!(a[..16], seeded_bytes(7, "id2", 16));
+    }
+
+    #[test]
+    fn test_dos_datetime() {
+        // 2023-11-14 22:13:20 UTC
+        assert_eq!(dos_datetime(1_700_000_000).unwrap(), (0x576E, 0xB1AA));
+        // 1980-01-01 00:00:01 and 2107-12-31 23:59:59
+        assert_eq!(dos_datetime(315_532_801).unwrap(), (0x0021, 0x0000));
+        assert_eq!(dos_datetime(4_354_819_199).unwrap(), (0xFF9F, 0xBF7D));
+        // Leap day
+        assert_eq!(dos_datetime(951_782_400).unwrap().0, 20 << 9 | 2 << 5 | 29);
+
+        assert!(dos_datetime(315_532_799).is_err());
+        assert!(dos_datetime(4_354_819_200).is_err());
+    }
+
+    #[test]
+    fn test_align_up_down() {
+        assert_eq!(align_up(1000, 512).unwrap(), 1024);
+        assert_eq!(align_up(1024, 512).unwrap(), 1024);
+        assert_eq!(align_up(0, 512).unwrap(), 0);
+        assert_eq!(align_up(7, 3).unwrap(), 9);
+        assert_eq!(align_down(1000, 512).unwrap(), 512);
+        assert_eq!(align_down(7, 3).unwrap(), 6);
+
+        assert_eq!(align_up(u64::MAX, 16).unwrap_err().code, ErrorCode::E03003);
+        assert_eq!(align_up(1, 0).unwrap_err().code, ErrorCode::E04003);
+        assert_eq!(align_down(1, 0).unwrap_err().code, ErrorCode::E04003);
+    }
+
+    #[test]
+    fn test_leb128() {
+        assert_eq!(uleb128(0), [0x00]);
+        assert_eq!(uleb128(127), [0x7F]);
+        assert_eq!(uleb128(128), [0x80, 0x01]);
+        assert_eq!(uleb128(624_485), [0xE5, 0x8E, 0x26]);
+        assert_eq!(uleb128(u64::MAX).len(), 10);
+        assert_eq!(sleb128(0), [0x00]);
+        assert_eq!(sleb128(-1), [0x7F]);
+        assert_eq!(sleb128(63), [0x3F]);
+        assert_eq!(sleb128(64), [0xC0, 0x00]);
+        assert_eq!(sleb128(-123_456), [0xC0, 0xBB, 0x78]);
+        assert_eq!(sleb128(i64::MIN).len(), 10);
+        assert_eq!(pad_leb128(uleb128(5), 3, false).unwrap(), [0x85, 0x80, 0x00]);
+        assert_eq!(pad_leb128(sleb128(-1), 3, true).unwrap(), [0xFF, 0xFF, 0x7F]);
+        assert_eq!(pad_leb128(uleb128(300), 2, false).unwrap(), [0xAC, 0x02]);
+        assert!(pad_leb128(uleb128(300), 1, false).is_none());
+    }
+
+    #[test]
+    fn test_min_max_clamp() {
+        assert_eq!(arithmetic("min", &[3, 7]).unwrap().unwrap(), 3);
+        assert_eq!(arithmetic("max", &[3, 7]).unwrap().unwrap(), 7);
+        assert_eq!(clamp(5, 1, 10).unwrap(), 5);
+        assert_eq!(clamp(0, 1, 10).unwrap(), 1);
+        assert_eq!(clamp(u64::MAX, 1, 10).unwrap(), 10);
+        assert_eq!(clamp(5, 5, 5).unwrap(), 5);
+        assert_eq!(clamp(5, 10, 1).unwrap_err().code, ErrorCode::E04003);
+        assert_eq!(arithmetic("align_up", &[1000, 512]).unwrap().unwrap(), 1024);
+        assert!(arithmetic("min", &[1]).is_none());
+        assert!(arithmetic("crc32", &[1, 2]).is_none());
+    }
+
+    #[test]
+    fn test_semver() {
+        assert_eq!(semver("1.2.345", "u8.u8.u16").unwrap(), 0x0102_0159);
+        assert_eq!(semver("v10.0.7+g1a2b3c", "u4.u4.u8").unwrap(), 0xA007);
+        assert_eq!(semver("255.255", "u8.u8").unwrap(), 0xFFFF);
+        assert_eq!(semver("7", "u64").unwrap(), 7);
+        assert_eq!(semver("1.2.3", "u16.u16.u32").unwrap(), 1 << 48 | 2 << 32 | 3);
+
+        assert_eq!(semver("1.256.0", "u8.u8.u16").unwrap_err().code, ErrorCode::E03003);
+        for (version, scheme) in [
+            ("1.2", "u8.u8.u16"),
+            ("1.2.3-rc1", "u8.u8.u16"),
+            ("1.x.3", "u8.u8.u16"),
+            ("1..3", "u8.u8.u16"),
+            ("1.2.3", "u8.u8.i16"),
+            ("1.2.3", "u8.u0.u16"),
+            ("1.2.3", "u32.u32.u8"),
+        ] {
+            assert_eq!(semver(version, scheme).unwrap_err().code, ErrorCode::E04003, "{version} {scheme}");
+        }
+    }
+
+    #[test]
+    fn test_whiten_pn9() {
+        let mut data = [0u8; 8];
+        whiten(&mut data, 0x221, 0x1FF).unwrap();
+        assert_eq!(data, [0xFF, 0xE1, 0x1D, 0x9A, 0xED, 0x85, 0x33, 0x24]);
+        whiten(&mut data, 0x221, 0x1FF).unwrap();
+        assert_eq!(data, [0; 8]);
+
+        assert!(whiten(&mut data, 0x221, 0x200).is_err());
+        assert!(whiten(&mut data, 1, 1).is_err());
+    }
+
+    #[test]
+    fn test_merkle_tree() {
+        let data = b"abcdefghij";
+        let mut tree = MerkleBuilder::new(4);
+        for chunk in data.chunks(3) {
+            tree.update(chunk);
+        }
+        let levels = tree.finish();
+        let leaves = [sha256(b"abcd"), sha256(b"efgh"), sha256(b"ij")];
+        assert_eq!(levels[0], leaves);
+        let ab = sha256(&[leaves[0], leaves[1]].concat());
+        assert_eq!(levels[1], [ab, leaves[2]]);
+        assert_eq!(levels[2], [sha256(&[ab, leaves[2]].concat())]);
+
+        assert_eq!(MerkleBuilder::new(4).root(), sha256(b""));
+        assert!(MerkleBuilder::new(4).leaves().is_empty());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_aes_ctr() {
+        // NIST SP 800-38A F.5.1, first block
+        let key = hex::decode("2b7e151628aed2a6abf7158809cf4f3c").unwrap();
+        let iv: [u8; 16] = hex::decode("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff").unwrap().try_into().unwrap();
+        let mut data = hex::decode("6bc1bee22e409f96e93d7e117393172a").unwrap();
+        aes_ctr(&key, &iv, &mut data).unwrap();
+        assert_eq!(hex::encode(&data), "874d6191b620e3261bef6864990db6ce");
+        assert!(aes_ctr(&key[..15], &iv, &mut data).is_err());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compress_round_trip() {
+        let data: Vec<u8> = b"firmware ".iter().cycle().take(4096).copied().collect();
+
+        let lz4 = compress("lz4", &data).unwrap();
+        assert!(lz4.len() < 100);
+        assert_eq!(lz4_flex::block::decompress(&lz4, data.len()).unwrap(), data);
+
+        let zlib = compress("zlib", &data).unwrap();
+        assert_eq!(&zlib[..2], [0x78, 0xDA]);
+        assert_eq!(miniz_oxide::inflate::decompress_to_vec_zlib(&zlib).unwrap(), data);
+
+        let zstd = compress("zstd", &data).unwrap();
+        assert_eq!(&zstd[..4], [0x28, 0xB5, 0x2F, 0xFD]);
+        let mut decoded = Vec::new();
+        std::io::Read::read_to_end(
+            &mut ruzstd::decoding::StreamingDecoder::new(zstd.as_slice()).unwrap(),
+            &mut decoded,
+        )
+        .unwrap();
+        assert_eq!(decoded, data);
+
+        assert_eq!(compress("lzma", &data).unwrap_err().code, ErrorCode::E04003);
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex("DEADbeef00").unwrap(), [0xDE, 0xAD, 0xBE, 0xEF, 0x00]);
+        assert_eq!(hex(" 0x01 02\n0a ").unwrap(), [0x01, 0x02, 0x0A]);
+        assert!(hex("").unwrap().is_empty());
+        assert_eq!(hex("ABC").unwrap_err().code, ErrorCode::E04003);
+        assert!(hex("0G").unwrap_err().message.contains("'G' is not a hex digit"));
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64("").unwrap(), b"");
+        assert_eq!(base64("Zg==").unwrap(), b"f");
+        assert_eq!(base64("Zm8").unwrap(), b"fo");
+        assert_eq!(base64("Zm9v\nYmFy").unwrap(), b"foobar");
+        assert_eq!(base64("+/8=").unwrap(), [0xFB, 0xFF]);
+        assert_eq!(base64("-_8").unwrap(), [0xFB, 0xFF]);
+        for bad in ["Z", "Zg=", "Zg==Zg==", "Zm9v!"] {
+            assert_eq!(base64(bad).unwrap_err().code, ErrorCode::E04003, "{bad}");
+        }
+        assert!(!base64("c2VjcmV0!").unwrap_err().message.contains("c2Vj"));
+    }
+
+    #[test]
+    fn test_fit_file() {
+        let fit = |len: usize, mode| fit_file("k.bin", b"KEY".to_vec(), len, mode);
+        let (data, warning) = fit(5, None).unwrap();
+        assert_eq!((data, warning.is_none()), (b"KEY\0\0".to_vec(), true));
+        let (data, warning) = fit(2, None).unwrap();
+        assert_eq!((data, warning.unwrap().code), (b"KE".to_vec(), WarningCode::W03001));
+
+        assert_eq!(fit(3, Some("exact")).unwrap().0, b"KEY");
+        assert_eq!(fit(4, Some("pad")).unwrap().0, b"KEY\0");
+        let (data, warning) = fit(2, Some("truncate")).unwrap();
+        assert_eq!((data, warning.is_none()), (b"KE".to_vec(), true));
+        for (len, mode) in [(4, "exact"), (2, "exact"), (2, "pad"), (4, "truncate")] {
+            assert_eq!(fit(len, Some(mode)).unwrap_err().code, ErrorCode::E04002, "{mode} {len}");
+        }
+        assert_eq!(fit(3, Some("fit")).unwrap_err().code, ErrorCode::E04003);
+    }
+
     #[test]
     fn test_bytes() {
         let (result, warning) = bytes("fpk", 4);
@@ -0,0 +1,1068 @@
+//! Delbin semantic checks
+//!
+//! [`compile()`](crate::compile) runs these before any env variables or
+//! sections are supplied. [`fold_constants()`] replaces arithmetic on
+//! literals with its result; [`check()`] rejects what would fail every
+//! generation of the file, whatever its inputs: unknown functions, wrong
+//! argument counts and kinds, strings and bytes used as numbers, and
+//! references to fields the layout doesn't have or hasn't placed yet.
+
+use std::collections::HashSet;
+
+use crate::ast::*;
+use crate::builtin::{self, CrcDigest, CrcParams, DigestAlgorithm, FastHash, SimpleChecksum};
+use crate::error::{DelbinError, ErrorCode, Result, SourceLocation};
+use crate::eval::{
+    self, arg_refers_to_self, bind_args, is_range_based_builtin, sign_args, FunctionRegistry,
+    ADDRESS_BUILTINS, SIGNATURE_BUILTINS,
+};
+use crate::parser::SECTION_TRANSFORMS;
+use crate::sign::{RsaScheme, SignatureFormat};
+use crate::types::ScalarType;
+
+/// Builtins callable as `@name(...)` besides the checksums, digests,
+/// signatures, address literals and section transforms
+pub(crate) const OTHER_BUILTINS: &[&str] = &[
+    "bytes", "hex", "base64", "file", "sizeof", "offsetof", "dostime", "dosdate", "crc32", "crc", "crc16",
+    "hmac_sha256", "merkle_root", "block_hashes", "numblocks", "compressed", "base", "slot_size", "index",
+    "random", "uuid", "uuid5", "timestamp", "semver",
+    "align_up", "align_down", "min", "max", "clamp", "strlen", "count", "uleb128", "sleb128",
+];
+
+/// Replace every operation on number literals in `file`, and arithmetic
+/// builtins (`@align_up()`, `@min()`, ...) of them, with its value
+///
+/// Shifts by 64 bits or more are left alone, so evaluation still warns
+/// about them.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::{ast::{Expr, Type}, check, parser};
+///
+/// let mut file = parser::parse("struct h { data: [u8; 4 * 16 + 8]; }").unwrap();
+/// check::fold_constants(&mut file);
+/// let Type::Array { len, .. } = &file.struct_def.fields[0].ty else { unreachable!() };
+/// assert!(matches!(**len, Expr::Number(72)));
+/// ```
+pub fn fold_constants(file: &mut File) {
+    for def in std::iter::once(&mut file.struct_def).chain(file.trailer.as_mut()) {
+        for field in &mut def.fields {
+            if let Type::Array { len, .. } = &mut field.ty {
+                fold(len);
+            }
+            if let Some(init) = &mut field.init {
+                fold(init);
+            }
+        }
+        for enc in &mut def.encrypt {
+            if let Some(start) = &mut enc.start {
+                fold(start);
+            }
+        }
+    }
+    for section in &mut file.sections {
+        fold(&mut section.transform);
+    }
+    for assert in &mut file.asserts {
+        fold(&mut assert.cond);
+    }
+}
+
+fn fold(expr: &mut Expr) {
+    let value = match expr {
+        Expr::BinaryOp { op, left, right } => {
+            fold(left);
+            fold(right);
+            match (left.as_ref(), right.as_ref()) {
+                (Expr::Number(l), Expr::Number(r)) => eval::binary_op(*op, *l, *r),
+                _ => None,
+            }
+        }
+        Expr::UnaryOp { op: UnaryOp::Not, operand } => {
+            fold(operand);
+            match operand.as_ref() {
+                Expr::Number(v) => Some(!v),
+                _ => None,
+            }
+        }
+        Expr::Call { name, args } => {
+            args.iter_mut().for_each(fold);
+            let values = args
+                .iter()
+                .map(|arg| match arg {
+                    Expr::Number(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<Option<Vec<u64>>>();
+            // Errors are left for check() to report
+            values.and_then(|values| builtin::arithmetic(name, &values)?.ok())
+        }
+        Expr::Named { value, .. } => {
+            fold(value);
+            None
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::Repeat { value, count }) => {
+            fold(value);
+            if let RepeatCount::Explicit(count) = count {
+                fold(count);
+            }
+            None
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::List { elements }) => {
+            elements.iter_mut().for_each(fold);
+            None
+        }
+        _ => None,
+    };
+    if let Some(value) = value {
+        *expr = Expr::Number(value);
+    }
+}
+
+/// Check `file` for errors that don't depend on its inputs
+///
+/// `functions` are the custom builtins generation will have. Errors inside
+/// a field carry the field's location.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::{check, parser, ErrorCode, FunctionRegistry};
+///
+/// let file = parser::parse("struct h { crc: u32 = @crc32(@self[..nope]); }").unwrap();
+/// let err = check::check(&file, &FunctionRegistry::new()).unwrap_err();
+/// assert_eq!(err.code, ErrorCode::E02002);
+/// ```
+pub fn check(file: &File, functions: &FunctionRegistry) -> Result<()> {
+    // `@for` blocks are checked as if their lists had one element
+    let file = &*eval::unroll_with(file, &|_| Ok(1))?;
+    for def in std::iter::once(&file.struct_def).chain(file.trailer.as_ref()) {
+        let mut names = HashSet::new();
+        for field in &def.fields {
+            if !names.insert(field.name.as_str()) {
+                let err = DelbinError::new(
+                    ErrorCode::E01003,
+                    format!("Duplicate field '{}' in struct '{}'", field.name, def.name),
+                );
+                return Err(located(err, field.location.as_ref()));
+            }
+        }
+        for (index, field) in def.fields.iter().enumerate() {
+            Checker::new(file, def, functions, index + 1)
+                .field(field)
+                .map_err(|e| located(e, field.location.as_ref()))?;
+        }
+        let checker = Checker::new(file, def, functions, def.fields.len());
+        for enc in &def.encrypt {
+            checker.encrypt_range(enc)?;
+        }
+    }
+    let checker = Checker::new(file, &file.struct_def, functions, file.struct_def.fields.len());
+    for section in &file.sections {
+        checker.section(section)?;
+    }
+    for assert in &file.asserts {
+        checker.numeric(&assert.cond)?;
+    }
+    check_policy(file)
+}
+
+/// Check that the header and trailer fields meet the file's `@policy`
+///
+/// Every unmet rule is listed in a single E02007 error.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::{check, parser, ErrorCode};
+///
+/// let dsl = "@policy { payload_hash: sha256; } struct h { crc: u32 = @crc32(image); }";
+/// let err = check::check_policy(&parser::parse(dsl).unwrap()).unwrap_err();
+/// assert_eq!(err.code, ErrorCode::E02007);
+/// ```
+pub fn check_policy(file: &File) -> Result<()> {
+    if file.policy.is_empty() {
+        return Ok(());
+    }
+    // (kind, algorithm) of every integrity field
+    let mut found = Vec::new();
+    for field in std::iter::once(&file.struct_def).chain(file.trailer.as_ref()).flat_map(|def| &def.fields) {
+        let Some(Expr::Call { name, args }) = &field.init else { continue };
+        if !is_range_based_builtin(name) {
+            continue;
+        }
+        let algorithm = policy_algorithm(name, args);
+        if SIGNATURE_BUILTINS.contains(&name.as_str()) {
+            found.push((PolicyKind::Signature, algorithm));
+            continue;
+        }
+        if args.iter().any(arg_refers_to_self) {
+            found.push((PolicyKind::HeaderCrc, algorithm));
+        }
+        if args.iter().any(|arg| matches!(arg, Expr::SectionRef(_))) {
+            found.push((PolicyKind::PayloadHash, algorithm));
+        }
+    }
+
+    let problems: Vec<String> = file
+        .policy
+        .iter()
+        .filter(|rule| {
+            !found.iter().any(|(kind, algorithm)| {
+                *kind == rule.kind && rule.algorithm.as_deref().is_none_or(|a| a == *algorithm)
+            })
+        })
+        .map(|rule| {
+            let what = match (rule.kind, &rule.algorithm) {
+                (PolicyKind::HeaderCrc, None) => "no field checksums the header (@self)".to_string(),
+                (PolicyKind::HeaderCrc, Some(a)) => format!("no field computes @{}() over the header (@self)", a),
+                (PolicyKind::PayloadHash, None) => "no field checksums or hashes a section".to_string(),
+                (PolicyKind::PayloadHash, Some(a)) => format!("no field computes @{}() over a section", a),
+                (PolicyKind::Signature, None) => "no field holds a signature".to_string(),
+                (PolicyKind::Signature, Some(a)) => format!("no field holds an @{}() signature", a),
+            };
+            format!("{}: {}", rule.kind.name(), what)
+        })
+        .collect();
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(DelbinError::new(
+        ErrorCode::E02007,
+        format!("The file doesn't meet its @policy:\n  - {}", problems.join("\n  - ")),
+    ))
+}
+
+/// Algorithm name a `@policy` rule matches a call against: the builtin,
+/// or for `@crc("crc16-modbus", ...)` the family (`crc16`)
+fn policy_algorithm<'e>(name: &'e str, args: &'e [Expr]) -> &'e str {
+    match (name, args.first()) {
+        ("crc", Some(Expr::String(algorithm))) => algorithm.split('-').next().unwrap_or(name),
+        _ => name,
+    }
+}
+
+/// Checks the expressions of one struct
+#[derive(Clone, Copy)]
+struct Checker<'f> {
+    file: &'f File,
+    def: &'f StructDef,
+    functions: &'f FunctionRegistry,
+    /// Fields whose offsets are known where the expression is evaluated:
+    /// the ones up to the current field, or all of them for deferred fields
+    known: usize,
+    /// Whether `@index` is defined: in the value of a repeat initializer
+    in_repeat: bool,
+}
+
+impl<'f> Checker<'f> {
+    fn new(file: &'f File, def: &'f StructDef, functions: &'f FunctionRegistry, known: usize) -> Self {
+        Self {
+            file,
+            def,
+            functions,
+            known,
+            in_repeat: false,
+        }
+    }
+
+    fn field(mut self, field: &FieldDef) -> Result<()> {
+        let len = match &field.ty {
+            Type::Scalar(_) | Type::Inferred => None,
+            Type::Array { len, .. } => {
+                check_array_length(self.def, self.known - 1)?;
+                self.numeric(len)?;
+                Some(len.as_ref())
+            }
+        };
+        let Some(init) = &field.init else {
+            return match field.ty {
+                Type::Inferred => Err(DelbinError::new(
+                    ErrorCode::E03001,
+                    format!("Field '{}' is [u8; _] and needs an initializer to take its length from", field.name),
+                )),
+                _ => Ok(()),
+            };
+        };
+        // Checksums over @self are computed once every field is in place
+        if let Expr::Call { name, args } = init {
+            let deferred = is_range_based_builtin(name) || self.is_custom(name);
+            if deferred && args.iter().any(arg_refers_to_self) {
+                self.known = self.def.fields.len();
+            }
+        }
+        match &field.ty {
+            Type::Scalar(_) => self.numeric(init),
+            Type::Array { elem, .. } => {
+                let size = match len {
+                    Some(Expr::Number(n)) => Some(*n as usize * elem.size()),
+                    _ => None,
+                };
+                self.array_init(*elem, size, init)
+            }
+            Type::Inferred => match init {
+                Expr::Call { name, args } if eval::has_natural_length(name) => self.call(name, args),
+                Expr::EnvVar(_) => Ok(()),
+                _ => Err(DelbinError::new(
+                    ErrorCode::E03001,
+                    "A [u8; _] field takes its length from @uleb128(), @sleb128(), @bytes(), @hex(), \
+                     @base64() or a bytes env variable",
+                )),
+            },
+        }
+    }
+
+    /// Expression evaluated as an integer
+    fn numeric(&self, expr: &Expr) -> Result<()> {
+        match expr {
+            Expr::Number(_) | Expr::EnvVar(_) | Expr::SectionRef(_) | Expr::SelfRef => Ok(()),
+            Expr::String(_) => Err(DelbinError::new(
+                ErrorCode::E03001,
+                "Cannot use string as numeric value",
+            )),
+            Expr::BinaryOp { left, right, .. } => {
+                self.numeric(left)?;
+                self.numeric(right)
+            }
+            Expr::UnaryOp { operand, .. } => self.numeric(operand),
+            Expr::Call { name, args } => match eval::non_numeric_builtin(name) {
+                Some(err) => Err(err),
+                None => self.call(name, args),
+            },
+            Expr::Range { .. } => Err(DelbinError::new(
+                ErrorCode::E03001,
+                "Range expression cannot be used as numeric value",
+            )),
+            Expr::ArrayLiteral(_) => Err(DelbinError::new(
+                ErrorCode::E03001,
+                "Array literal cannot be used as numeric value",
+            )),
+            Expr::Named { name, .. } => Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("Keyword argument '{}' is not accepted here", name),
+            )),
+        }
+    }
+
+    /// Initializer of an array field of `size` bytes (`None` until inputs
+    /// are known)
+    fn array_init(&self, elem: ScalarType, size: Option<usize>, init: &Expr) -> Result<()> {
+        let produced = match init {
+            Expr::String(_) => {
+                return Err(DelbinError::new(
+                    ErrorCode::E03001,
+                    "Cannot assign a string literal directly to an array field; use @bytes(\"...\") instead",
+                ))
+            }
+            Expr::ArrayLiteral(ArrayLiteralKind::Repeat { value, count }) => {
+                Checker { in_repeat: true, ..*self }.numeric(value)?;
+                if let RepeatCount::Explicit(count) = count {
+                    self.numeric(count)?;
+                }
+                return Ok(());
+            }
+            Expr::ArrayLiteral(ArrayLiteralKind::List { elements }) => {
+                return elements.iter().try_for_each(|e| self.numeric(e));
+            }
+            Expr::Call { name, args }
+                if matches!(name.as_str(), "bytes" | "hex" | "base64" | "file" | "uleb128" | "sleb128") =>
+            {
+                if elem != ScalarType::U8 {
+                    return Err(DelbinError::new(
+                        ErrorCode::E03001,
+                        format!("@{}() returns u8 data but field element type is {}", name, elem.name()),
+                    ));
+                }
+                return self.call(name, args);
+            }
+            Expr::Call { name, args } if name == "random" => {
+                self.call(name, args)?;
+                return match (args.as_slice(), size) {
+                    ([Expr::Number(count)], Some(size)) if *count > size as u64 => Err(DelbinError::new(
+                        ErrorCode::E03001,
+                        format!("@random({}) produces {} bytes but the field is {} bytes", count, count, size),
+                    )),
+                    _ => Ok(()),
+                };
+            }
+            Expr::Call { name, args } => {
+                self.call(name, args)?;
+                match name.as_str() {
+                    "ipv4" => Some(4),
+                    "ipv6" => Some(16),
+                    "mac" => Some(6),
+                    "uuid" | "uuid5" => Some(16),
+                    name => DigestAlgorithm::by_name(name).map(|algorithm| algorithm.output_len()),
+                }
+            }
+            _ => None,
+        };
+        match (produced, size) {
+            (Some(produced), Some(size)) if produced != size => {
+                let Expr::Call { name, .. } = init else { unreachable!() };
+                Err(DelbinError::new(
+                    ErrorCode::E03001,
+                    format!("@{}() produces {} bytes but the field is {} bytes", name, produced, size),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Arguments of a builtin or custom function call
+    fn call(&self, name: &str, args: &[Expr]) -> Result<()> {
+        match name {
+            "sizeof" => match exactly_one(name, args)? {
+                Expr::Call { name, args } if name == "compressed" => self.compressed(args),
+                // A section name may parse as a call
+                Expr::Call { name, .. } if !self.is_known(name) => Ok(()),
+                Expr::Call { name, args } if eval::has_natural_length(name) => self.call(name, args),
+                other => self.numeric(other),
+            },
+            "offsetof" => {
+                let field = field_name(exactly_one(name, args)?).ok_or_else(|| {
+                    DelbinError::new(ErrorCode::E04003, "Invalid argument for @offsetof()")
+                })?;
+                self.placed_field(field).map(|_| ())
+            }
+            "dostime" | "dosdate" => match exactly_one(name, args)? {
+                Expr::Number(unix) => builtin::dos_datetime(*unix).map(|_| ()),
+                other => self.numeric(other),
+            },
+            "bytes" | "strlen" => string_arg(exactly_one(name, args)?),
+            "count" => match exactly_one(name, args)? {
+                Expr::EnvVar(_) => Ok(()),
+                _ => Err(DelbinError::new(ErrorCode::E03001, "@count() takes a list variable, e.g. ${PARTITIONS}")),
+            },
+            "hex" => match exactly_one(name, args)? {
+                Expr::String(text) => builtin::hex(text).map(|_| ()),
+                other => string_arg(other),
+            },
+            "base64" => match exactly_one(name, args)? {
+                Expr::String(text) => builtin::base64(text).map(|_| ()),
+                other => string_arg(other),
+            },
+            "file" => match args {
+                [path] => string_arg(path),
+                [path, Expr::String(mode)] if builtin::FILE_MODES.contains(&mode.as_str()) => string_arg(path),
+                [_, Expr::String(mode)] => Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    format!("Unknown @file() mode: '{}'. Supported: {}", mode, builtin::FILE_MODES.join(", ")),
+                )),
+                [path, mode] => string_arg(path).and_then(|_| string_arg(mode)),
+                _ => Err(DelbinError::new(ErrorCode::E04004, "@file() requires 1 or 2 arguments")),
+            },
+            name if ADDRESS_BUILTINS.contains(&name) => match exactly_one(name, args)? {
+                Expr::String(text) => builtin::address(name, text).map(|_| ()),
+                other => string_arg(other),
+            },
+            "random" | "uleb128" | "sleb128" => self.numeric(exactly_one(name, args)?),
+            "uuid" | "timestamp" if args.is_empty() => Ok(()),
+            "uuid" | "timestamp" => Err(DelbinError::new(
+                ErrorCode::E04004,
+                format!("@{}() takes no arguments", name),
+            )),
+            "uuid5" => match args {
+                [Expr::String(namespace), value] => {
+                    builtin::uuid_namespace(namespace).and_then(|_| string_arg(value))
+                }
+                [namespace, value] => string_arg(namespace).and_then(|_| string_arg(value)),
+                _ => Err(DelbinError::new(
+                    ErrorCode::E04004,
+                    "@uuid5() requires 2 arguments: a namespace and a name",
+                )),
+            },
+            "align_up" | "align_down" => {
+                let [value, align] = args else {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        format!("@{}() requires 2 arguments: a value and an alignment", name),
+                    ));
+                };
+                self.numeric(value)?;
+                self.numeric(align)?;
+                // A zero alignment fails whatever the value; overflow needs both
+                let value = match value {
+                    Expr::Number(value) => *value,
+                    _ => 0,
+                };
+                match (name, align) {
+                    ("align_up", Expr::Number(align)) => builtin::align_up(value, *align).map(|_| ()),
+                    (_, Expr::Number(align)) => builtin::align_down(value, *align).map(|_| ()),
+                    _ => Ok(()),
+                }
+            }
+            "min" | "max" | "clamp" => {
+                let expected = if name == "clamp" { 3 } else { 2 };
+                if args.len() != expected {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        format!("@{}() requires exactly {} arguments", name, expected),
+                    ));
+                }
+                args.iter().try_for_each(|arg| self.numeric(arg))?;
+                match args {
+                    [_, Expr::Number(lo), Expr::Number(hi)] => builtin::clamp(0, *lo, *hi).map(|_| ()),
+                    _ => Ok(()),
+                }
+            }
+            "semver" => match args {
+                [Expr::String(version), Expr::String(scheme)] => builtin::semver(version, scheme).map(|_| ()),
+                [version, Expr::String(scheme)] => builtin::semver_scheme(scheme).and_then(|_| string_arg(version)),
+                [version, scheme] => string_arg(version).and_then(|_| string_arg(scheme)),
+                _ => Err(DelbinError::new(
+                    ErrorCode::E04004,
+                    "@semver() requires 2 arguments: a version and a packing scheme",
+                )),
+            },
+            "crc32" => {
+                let (data, variant) = match args {
+                    [data @ .., Expr::String(variant)] => (data, variant.as_str()),
+                    _ => (args, "iso-hdlc"),
+                };
+                if !matches!(variant, "iso-hdlc" | "bzip2" | "mpeg2" | "posix" | "c" | "jamcrc") {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04003,
+                        format!(
+                            "Unknown CRC32 variant: '{}'. Supported: iso-hdlc, bzip2, mpeg2, posix, c, jamcrc",
+                            variant
+                        ),
+                    ));
+                }
+                self.data(data)
+            }
+            "crc16" => {
+                let [data @ .., Expr::String(variant)] = args else {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        "@crc16() requires a data source and a variant, e.g. @crc16(image, \"ccitt\")",
+                    ));
+                };
+                if !matches!(variant.as_str(), "ccitt" | "modbus" | "xmodem" | "usb") {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04003,
+                        format!("Unknown CRC16 variant: '{}'. Supported: ccitt, modbus, xmodem, usb", variant),
+                    ));
+                }
+                self.data(data)
+            }
+            "crc" => self.crc(args),
+            name if SimpleChecksum::by_name(name).is_some() => self.data(args),
+            name if FastHash::by_name(name).is_some() => {
+                FastHash::by_name(name).expect("checked").hasher()?;
+                self.data(args)
+            }
+            name if DigestAlgorithm::by_name(name).is_some() => {
+                DigestAlgorithm::by_name(name).expect("checked").hasher()?;
+                self.data(args)
+            }
+            "hmac_sha256" => {
+                let args = sign_args(name, args, &[])?;
+                env_var_arg(args.key, "@hmac_sha256() key")?;
+                self.data(&args.data)
+            }
+            "sign" => self.data(args),
+            "ed25519" | "ecdsa_p256" | "rsa_sign" => self.signature(name, args),
+            "merkle_root" | "block_hashes" => self.merkle(name, args).map(|_| ()),
+            "numblocks" => {
+                let bound = bind_args(name, args, &["data", "block"])?;
+                let data = bound[0].ok_or_else(|| {
+                    DelbinError::new(ErrorCode::E04004, "@numblocks() requires a section")
+                })?;
+                if field_name(data).is_none() {
+                    return Err(DelbinError::new(ErrorCode::E04003, "@numblocks() takes a section name"));
+                }
+                self.block(name, bound[1])
+            }
+            "compressed" => self.compressed(args),
+            "index" if args.is_empty() && self.in_repeat => Ok(()),
+            "index" if args.is_empty() => Err(eval::index_outside_repeat()),
+            "base" if args.is_empty() => match self.file.base {
+                Some(_) => Ok(()),
+                None => Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    "@base is used but not set; add e.g. `@base = 0x08000000;`",
+                )),
+            },
+            "slot_size" if args.is_empty() => match self.file.slot_size {
+                Some(_) => Ok(()),
+                None => Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    "@slot_size is used but not set; add e.g. `@slot_size = 512K;`",
+                )),
+            },
+            name if SECTION_TRANSFORMS.contains(&name) => Ok(()),
+            name if self.is_custom(name) => args.iter().try_for_each(|arg| self.custom_arg(arg)),
+            _ => Err(DelbinError::new(
+                ErrorCode::E02004,
+                format!("Unknown function: @{}", name),
+            )),
+        }
+    }
+
+    /// Argument of a custom function: data, a string or an integer
+    fn custom_arg(&self, arg: &Expr) -> Result<()> {
+        match arg {
+            Expr::Range { .. } | Expr::SelfRef => self.data(std::slice::from_ref(arg)),
+            Expr::SectionRef(_) | Expr::String(_) | Expr::EnvVar(_) => Ok(()),
+            other => self.numeric(other),
+        }
+    }
+
+    /// Data sources of a checksum, digest or signature
+    fn data(&self, args: &[Expr]) -> Result<()> {
+        if args.is_empty() {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                "Function requires at least 1 argument",
+            ));
+        }
+        for arg in args {
+            match arg {
+                Expr::Range { start, end, .. } => {
+                    if let Some(start) = start {
+                        self.range_start(start)?;
+                    }
+                    if let Some(end) = end {
+                        self.placed_field(end)?;
+                    }
+                }
+                Expr::Call { name, args } if name == "compressed" => self.compressed(args)?,
+                // Sections may parse as env variables or calls
+                Expr::SelfRef | Expr::SectionRef(_) | Expr::EnvVar(_) | Expr::Call { .. } => {}
+                _ => {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04003,
+                        "Invalid argument for checksum function",
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Start of `@self[start..end]`: numbers, field names and `@offsetof()`
+    /// of fields, with operators
+    fn range_start(&self, expr: &Expr) -> Result<()> {
+        match expr {
+            Expr::Number(_) => Ok(()),
+            Expr::SectionRef(name) => self.placed_field(name).map(|_| ()).map_err(|_| {
+                DelbinError::new(
+                    ErrorCode::E02002,
+                    format!("Undefined field '{}' in range expression", name),
+                )
+            }),
+            Expr::Call { name, args } if name == "offsetof" => match args.as_slice() {
+                [field @ Expr::SectionRef(_)] => self.range_start(field),
+                _ => Err(DelbinError::new(
+                    ErrorCode::E04004,
+                    "@offsetof() requires exactly 1 argument",
+                )),
+            },
+            Expr::BinaryOp { left, right, .. } => {
+                self.range_start(left)?;
+                self.range_start(right)
+            }
+            Expr::UnaryOp { operand, .. } => self.range_start(operand),
+            _ => Err(DelbinError::new(
+                ErrorCode::E04003,
+                "Expected a numeric literal or field name in range expression",
+            )),
+        }
+    }
+
+    /// `@crc("algorithm", data...)` or `@crc(data, width, poly, ...)`
+    fn crc(&self, args: &[Expr]) -> Result<()> {
+        if let Some(Expr::String(algorithm)) = args.first() {
+            if args.len() < 2 {
+                return Err(DelbinError::new(
+                    ErrorCode::E04004,
+                    "@crc() requires 2 arguments: algorithm name and data source",
+                ));
+            }
+            CrcDigest::by_name(algorithm)?;
+            return self.data(&args[1..]);
+        }
+        if args.is_empty() {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                "@crc() requires 2 arguments: algorithm name and data source",
+            ));
+        }
+        const PARAMS: [&str; 7] = ["data", "width", "poly", "init", "refin", "refout", "xorout"];
+        let bound = bind_args("crc", args, &PARAMS)?;
+        for (arg, name) in bound[1..].iter().zip(&PARAMS[1..]) {
+            let arg = arg.ok_or_else(|| {
+                DelbinError::new(
+                    ErrorCode::E04004,
+                    format!("@crc() is missing '{}'; a custom CRC takes {}", name, PARAMS.join(", ")),
+                )
+            })?;
+            self.numeric(arg)?;
+        }
+        // Literal parameters that are invalid fail every generation
+        let literal = |i: usize| match bound[i] {
+            Some(Expr::Number(n)) => Some(*n),
+            _ => None,
+        };
+        if let Some(width) = literal(1) {
+            builtin::crc_width(width)?;
+        }
+        for (i, name) in [(4, "refin"), (5, "refout")] {
+            if let Some(value) = literal(i) {
+                builtin::crc_flag(name, value)?;
+            }
+        }
+        let values = [1, 2, 3, 4, 5, 6].map(literal);
+        if let [Some(width), Some(poly), Some(init), Some(refin), Some(refout), Some(xorout)] = values {
+            CrcParams::from_args([width, poly, init, refin, refout, xorout])?;
+        }
+        let data = bound[0].ok_or_else(|| DelbinError::new(ErrorCode::E04004, "@crc() is missing 'data'"))?;
+        self.data(std::slice::from_ref(data))
+    }
+
+    /// `@ed25519()`, `@ecdsa_p256()` or `@rsa_sign()`
+    fn signature(&self, func: &str, args: &[Expr]) -> Result<()> {
+        let (feature, enabled) = match func {
+            "rsa_sign" => ("crypto-rsa", cfg!(feature = "crypto-rsa")),
+            _ => ("crypto-sign", cfg!(feature = "crypto-sign")),
+        };
+        if !enabled {
+            return Err(builtin::missing_feature(&format!("@{}()", func), feature));
+        }
+        let options: &[&str] = match func {
+            "ecdsa_p256" => &["format"],
+            "rsa_sign" => &["scheme"],
+            _ => &[],
+        };
+        let args = sign_args(func, args, options)?;
+        env_var_arg(args.key, &format!("@{}() key", func))?;
+        match (func, args.options.first().copied().flatten()) {
+            (_, None) => {}
+            ("ecdsa_p256", Some(Expr::String(name))) => {
+                SignatureFormat::by_name(name)?;
+            }
+            ("rsa_sign", Some(Expr::String(name))) => {
+                RsaScheme::by_name(name)?;
+            }
+            ("ecdsa_p256", Some(_)) => {
+                return Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    "@ecdsa_p256() format must be \"raw\" or \"der\"",
+                ))
+            }
+            (_, Some(_)) => {
+                return Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    "@rsa_sign() scheme must be \"pkcs1\" or \"pss\"",
+                ))
+            }
+        }
+        self.data(&args.data)
+    }
+
+    /// `@merkle_root()`, `@block_hashes()` and `@merkle_tree()` arguments;
+    /// returns the data source
+    fn merkle<'e>(&self, func: &str, args: &'e [Expr]) -> Result<&'e Expr> {
+        let bound = bind_args(func, args, &["data", "block", "alg"])?;
+        let data = bound[0].ok_or_else(|| {
+            DelbinError::new(ErrorCode::E04004, format!("@{}() requires a data source", func))
+        })?;
+        self.block(func, bound[1])?;
+        match bound[2] {
+            None => {}
+            Some(Expr::String(alg)) if alg == "sha256" => {}
+            Some(other) => {
+                return Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    format!("Unsupported @{}() algorithm {}. Supported: \"sha256\"", func, other),
+                ))
+            }
+        }
+        Ok(data)
+    }
+
+    /// `block` argument of the block-hashing builtins
+    fn block(&self, func: &str, expr: Option<&Expr>) -> Result<()> {
+        match expr {
+            Some(Expr::Number(0)) => Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("@{}() block size must be non-zero", func),
+            )),
+            Some(expr) => self.numeric(expr),
+            None => Ok(()),
+        }
+    }
+
+    /// `@compressed(source)`: `source` must have exactly one `@compress` section
+    fn compressed(&self, args: &[Expr]) -> Result<()> {
+        let [Expr::SectionRef(source)] = args else {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                "@compressed() requires exactly 1 argument: a section name",
+            ));
+        };
+        let sections: Vec<&str> = self
+            .file
+            .sections
+            .iter()
+            .filter(|def| {
+                matches!(&def.transform, Expr::Call { name, args }
+                    if name == "compress" && matches!(args.first(), Some(Expr::SectionRef(s)) if s == source))
+            })
+            .map(|def| def.name.as_str())
+            .collect();
+        match sections.as_slice() {
+            [_] => Ok(()),
+            [] => Err(DelbinError::new(
+                ErrorCode::E02003,
+                format!("No compressed form of section '{}'", source),
+            )
+            .with_hint(format!("declare one with `@section {0}_lz4 = @compress({0}, lz4);`", source))),
+            sections => Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!(
+                    "@compressed({}) is ambiguous: {} all compress it",
+                    source,
+                    sections.join(", ")
+                ),
+            )),
+        }
+    }
+
+    /// `@section name = @transform(source, ...);`
+    fn section(&self, def: &SectionDef) -> Result<()> {
+        let Expr::Call { name, args } = &def.transform else {
+            return Ok(());
+        };
+        match name.as_str() {
+            "whiten" => {
+                let [_, poly, seed] = args.as_slice() else {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        "@whiten() requires 3 arguments: section, poly, seed",
+                    ));
+                };
+                self.numeric(poly)?;
+                self.numeric(seed)?;
+                // A literal polynomial or seed that is invalid fails every
+                // generation
+                match (poly, seed) {
+                    (Expr::Number(poly), Expr::Number(seed)) => builtin::whiten(&mut [], *poly, *seed),
+                    (Expr::Number(poly), _) => builtin::whiten(&mut [], *poly, 1),
+                    _ => Ok(()),
+                }
+            }
+            "merkle_tree" => self.merkle(name, args).map(|_| ()),
+            "encrypt" => {
+                let bound = bind_args(name, args, &["data", "cipher", "key", "iv"])?;
+                match bound[1] {
+                    Some(Expr::String(cipher)) if cipher == "aes_ctr" => {}
+                    Some(Expr::String(cipher)) => {
+                        return Err(DelbinError::new(
+                            ErrorCode::E04003,
+                            format!("Unknown cipher: '{}'. Supported: aes_ctr", cipher),
+                        ))
+                    }
+                    _ => {
+                        return Err(DelbinError::new(
+                            ErrorCode::E04004,
+                            "@encrypt() requires a cipher, e.g. @encrypt(image, aes_ctr, key = ${KEY})",
+                        ))
+                    }
+                }
+                let key = bound[2].ok_or_else(|| {
+                    DelbinError::new(ErrorCode::E04004, "@encrypt() requires key = ${KEY}")
+                })?;
+                env_var_arg(key, "@encrypt key")?;
+                env_var_arg(bound[3].ok_or_else(|| builtin::missing_iv("@encrypt IV"))?, "@encrypt IV")?;
+                builtin::aes_ctr(&[0; 16], &[0; 16], &mut [])
+            }
+            "compress" => {
+                let bound = bind_args(name, args, &["data", "algorithm"])?;
+                let Some(Expr::String(algorithm)) = bound[1] else {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        "@compress() requires an algorithm, e.g. @compress(image, lz4)",
+                    ));
+                };
+                builtin::compress(algorithm, &[]).map(|_| ())
+            }
+            other => Err(DelbinError::new(
+                ErrorCode::E02004,
+                format!("Unknown section transform: @{}", other),
+            )),
+        }
+    }
+
+    /// `@encrypt_range(from..to, key, iv)`
+    fn encrypt_range(&self, enc: &EncryptRange) -> Result<()> {
+        let range = Expr::Range {
+            base: Box::new(Expr::SelfRef),
+            start: enc.start.clone(),
+            end: enc.end.clone(),
+        };
+        self.data(std::slice::from_ref(&range))?;
+        env_var_arg(&enc.key, "@encrypt_range key")?;
+        env_var_arg(&enc.iv, "@encrypt_range IV")?;
+        builtin::aes_ctr(&[0; 16], &[0; 16], &mut [])
+    }
+
+    /// Index of field `name`, which must be placed where the expression is
+    /// evaluated
+    fn placed_field(&self, name: &str) -> Result<usize> {
+        self.def.fields[..self.known]
+            .iter()
+            .position(|field| field.name == name)
+            .ok_or_else(|| DelbinError::new(ErrorCode::E02002, format!("Undefined field: {}", name)))
+    }
+
+    fn is_custom(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Whether `@name` is a builtin or custom function
+    fn is_known(&self, name: &str) -> bool {
+        is_builtin(name) || self.is_custom(name)
+    }
+}
+
+/// Whether `name` is a built-in function
+pub(crate) fn is_builtin(name: &str) -> bool {
+    OTHER_BUILTINS.contains(&name)
+        || ADDRESS_BUILTINS.contains(&name)
+        || SIGNATURE_BUILTINS.contains(&name)
+        || SECTION_TRANSFORMS.contains(&name)
+        || SimpleChecksum::by_name(name).is_some()
+        || FastHash::by_name(name).is_some()
+        || DigestAlgorithm::by_name(name).is_some()
+}
+
+fn exactly_one<'e>(func: &str, args: &'e [Expr]) -> Result<&'e Expr> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(DelbinError::new(
+            ErrorCode::E04004,
+            format!("@{}() requires exactly 1 argument", func),
+        )),
+    }
+}
+
+/// Field or section name as the parser may produce it
+/// The length of array field `index` of `def` may only use what is known
+/// before the field is laid out: constants, env variables, sections, and
+/// the offsets of the fields up to and including it
+///
+/// Otherwise E03004, e.g. for `[u8; @offsetof(later)]` or a length that
+/// depends on `@sizeof(@self)`.
+pub(crate) fn check_array_length(def: &StructDef, index: usize) -> Result<()> {
+    let field = &def.fields[index];
+    let Type::Array { len, .. } = &field.ty else {
+        return Ok(());
+    };
+    match length_problem(def, index, len) {
+        Some(problem) => Err(DelbinError::new(
+            ErrorCode::E03004,
+            format!("Length of array '{}' {}", field.name, problem),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn length_problem(def: &StructDef, index: usize, expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Call { name, args } if name == "offsetof" => {
+            let target = args.first().and_then(field_name)?;
+            let position = def.fields.iter().position(|field| field.name == target)?;
+            (position > index).then(|| format!("refers to field '{}', which is laid out after it", target))
+        }
+        Expr::Call { name, args } if name == "sizeof" && matches!(args.as_slice(), [Expr::SelfRef]) => {
+            Some("depends on the struct size, which depends on it".to_string())
+        }
+        Expr::SelfRef | Expr::Range { .. } => Some("reads header bytes (@self), which aren't laid out yet".to_string()),
+        Expr::BinaryOp { left, right, .. } => {
+            length_problem(def, index, left).or_else(|| length_problem(def, index, right))
+        }
+        Expr::UnaryOp { operand, .. } | Expr::Named { value: operand, .. } => length_problem(def, index, operand),
+        Expr::Call { args, .. } => args.iter().find_map(|arg| length_problem(def, index, arg)),
+        _ => None,
+    }
+}
+
+fn field_name(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::EnvVar(name) | Expr::SectionRef(name) | Expr::Call { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+fn string_arg(expr: &Expr) -> Result<()> {
+    match expr {
+        Expr::String(_) | Expr::EnvVar(_) => Ok(()),
+        _ => Err(DelbinError::new(ErrorCode::E03001, "Expected string expression")),
+    }
+}
+
+/// Keys and IVs (`what`) come from env variables
+fn env_var_arg(expr: &Expr, what: &str) -> Result<()> {
+    match expr {
+        Expr::EnvVar(_) => Ok(()),
+        _ => Err(DelbinError::new(
+            ErrorCode::E03001,
+            format!("{} must be an env variable", what),
+        )),
+    }
+}
+
+/// Attach the field's `location` to `err` unless it has one
+fn located(err: DelbinError, location: Option<&SourceLocation>) -> DelbinError {
+    match (location, &err.location) {
+        (Some(location), None) => err.with_location(location.clone()),
+        _ => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn test_fold_constants() {
+        let mut file = parser::parse(
+            "struct h { a: u32 = (1 << 8) | ~0xFFFFFFFFFFFFFF00; b: u32 = 1 << 64; c: u32 = ${A} + 2 * 3; }",
+        )
+        .unwrap();
+        fold_constants(&mut file);
+        let inits: Vec<String> = file
+            .struct_def
+            .fields
+            .iter()
+            .map(|f| f.init.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(inits, ["0x1FF", "1 << 64", "${A} + 6"]);
+    }
+
+    #[test]
+    fn test_offsets_known_where_evaluated() {
+        let functions = FunctionRegistry::new();
+        let check_dsl = |dsl: &str| check(&parser::parse(dsl).unwrap(), &functions);
+
+        // Checksums over @self run last and see every field
+        check_dsl("struct h { crc: u32 = @crc32(@self[a..nope]); a: u32; }").unwrap_err();
+        check_dsl("struct h { crc: u32 = @crc32(@self[a..end]); a: u32 = @offsetof(crc); end: u8; }")
+            .unwrap();
+        // Array lengths and plain fields see the fields up to their own
+        check_dsl("struct h { pad: [u8; @offsetof(pad) + 4]; v: u32 = @offsetof(v); }").unwrap();
+        let err = check_dsl("struct h {\n v: u32 = @offsetof(w);\n w: u8;\n}").unwrap_err();
+        assert_eq!((err.code, err.location.unwrap().line), (ErrorCode::E02002, 2));
+        for dsl in [
+            "struct h { pad: [u8; @offsetof(v)]; v: u32; }",
+            "struct h { pad: [u8; 64 - @sizeof(@self)]; v: u32; }",
+            "struct h { pad: [u8; @crc32(@self[..pad]) & 7]; v: u32; }",
+        ] {
+            assert_eq!(check_dsl(dsl).unwrap_err().code, ErrorCode::E03004, "{dsl}");
+        }
+    }
+}
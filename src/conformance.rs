@@ -0,0 +1,273 @@
+//! Delbin conformance checking
+//!
+//! Compares delbin output with binaries produced by a reference (legacy)
+//! tool for the same inputs, reporting differing byte ranges and the DSL
+//! fields they fall in. Meant to de-risk migrating an existing packer.
+//!
+//! A case directory holds, for every case `NAME`:
+//!
+//! - `NAME.bin` — expected output of the reference tool
+//! - `NAME.env` — optional inputs, one per line: `KEY=VALUE` sets an
+//!   environment variable, typed as for `-D` by
+//!   [`infer_env_value()`](crate::infer_env_value); `section NAME=FILE`
+//!   loads a section from a file relative to the directory. Empty lines and lines starting with `#` are ignored.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::error::{DelbinError, ErrorCode, Result};
+use crate::eval::Evaluator;
+use crate::layout::Layout;
+use crate::parser;
+use crate::provider::{FileSections, SectionProvider};
+use crate::types::Value;
+use crate::utils::infer_env_value;
+
+/// Field whose bytes differ from the reference output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldMismatch {
+    pub field: String,
+    /// Field offset in the layout
+    pub offset: usize,
+    /// Reference bytes (shorter than the field if the reference is truncated)
+    pub expected: Vec<u8>,
+    /// Bytes generated by delbin
+    pub actual: Vec<u8>,
+}
+
+/// Comparison result for one case
+#[derive(Debug)]
+pub struct CaseReport {
+    pub name: String,
+    pub expected_len: usize,
+    pub actual_len: usize,
+    /// Differing byte ranges; a length difference shows up as a trailing range
+    pub byte_mismatches: Vec<Range<usize>>,
+    /// Fields overlapping a differing byte range, in layout order
+    pub field_mismatches: Vec<FieldMismatch>,
+    /// Set when delbin failed to generate output for the case
+    pub error: Option<DelbinError>,
+}
+
+impl CaseReport {
+    /// True when delbin produced exactly the reference bytes
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.byte_mismatches.is_empty()
+    }
+}
+
+/// Comparison results for a set of cases
+#[derive(Debug, Default)]
+pub struct ConformanceReport {
+    /// Cases in name order
+    pub cases: Vec<CaseReport>,
+}
+
+impl ConformanceReport {
+    /// True when every case passed
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(CaseReport::passed)
+    }
+
+    /// Cases that did not pass
+    pub fn failures(&self) -> impl Iterator<Item = &CaseReport> {
+        self.cases.iter().filter(|c| !c.passed())
+    }
+}
+
+/// Compare delbin output for one set of inputs with the reference bytes
+pub fn check_case(
+    name: &str,
+    dsl: &str,
+    env: &HashMap<String, Value>,
+    sections: &dyn SectionProvider,
+    expected: &[u8],
+) -> CaseReport {
+    let mut report = CaseReport {
+        name: name.to_string(),
+        expected_len: expected.len(),
+        actual_len: 0,
+        byte_mismatches: Vec::new(),
+        field_mismatches: Vec::new(),
+        error: None,
+    };
+
+    let (actual, layout) = match generate_with_layout(dsl, env, sections) {
+        Ok(r) => r,
+        Err(e) => {
+            report.error = Some(e);
+            return report;
+        }
+    };
+    report.actual_len = actual.len();
+    report.byte_mismatches = diff_ranges(expected, &actual);
+
+    for field in layout.fields {
+        let span = field.offset..field.offset + field.size;
+        if report
+            .byte_mismatches
+            .iter()
+            .any(|r| r.start < span.end && span.start < r.end)
+        {
+            report.field_mismatches.push(FieldMismatch {
+                field: field.name,
+                offset: span.start,
+                expected: clamp_slice(expected, &span).to_vec(),
+                actual: clamp_slice(&actual, &span).to_vec(),
+            });
+        }
+    }
+    report
+}
+
+/// Run every case in `dir` (see the module docs for the layout)
+pub fn check_dir(dsl: &str, dir: impl AsRef<Path>) -> Result<ConformanceReport> {
+    let dir = dir.as_ref();
+    let read_err = |path: &Path, e: std::io::Error| {
+        DelbinError::new(
+            ErrorCode::E05002,
+            format!("Error reading '{}': {}", path.display(), e),
+        )
+    };
+
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .map_err(|e| read_err(dir, e))?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "bin" {
+                return None;
+            }
+            path.file_stem()?.to_str().map(str::to_string)
+        })
+        .collect();
+    names.sort();
+
+    let mut report = ConformanceReport::default();
+    for name in names {
+        let bin_path = dir.join(format!("{}.bin", name));
+        let expected = std::fs::read(&bin_path).map_err(|e| read_err(&bin_path, e))?;
+
+        let env_path = dir.join(format!("{}.env", name));
+        let (env, sections) = if env_path.is_file() {
+            let text = std::fs::read_to_string(&env_path).map_err(|e| read_err(&env_path, e))?;
+            parse_inputs(&text, dir)
+        } else {
+            (HashMap::new(), FileSections::new())
+        };
+
+        report
+            .cases
+            .push(check_case(&name, dsl, &env, &sections, &expected));
+    }
+    Ok(report)
+}
+
+/// Generate output along with its field layout
+fn generate_with_layout(
+    dsl: &str,
+    env: &HashMap<String, Value>,
+    sections: &dyn SectionProvider,
+) -> Result<(Vec<u8>, Layout)> {
+    let file = parser::parse(dsl)?;
+    let data = Evaluator::new(env, sections).eval(&file)?;
+    let layout = Evaluator::new(env, sections).layout(&file)?;
+    Ok((data, layout))
+}
+
+/// Parse a case `.env` file
+fn parse_inputs(text: &str, dir: &Path) -> (HashMap<String, Value>, FileSections) {
+    let mut env = HashMap::new();
+    let mut sections = FileSections::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((name, file)) = line
+            .strip_prefix("section ")
+            .and_then(|rest| rest.trim().split_once('='))
+        {
+            sections.insert(name.trim(), dir.join(file.trim()));
+        } else if let Some((key, value)) = line.split_once('=') {
+            env.insert(key.trim().to_string(), infer_env_value(value.trim()));
+        }
+    }
+    (env, sections)
+}
+
+/// Differing byte ranges between two buffers
+fn diff_ranges(expected: &[u8], actual: &[u8]) -> Vec<Range<usize>> {
+    let common = expected.len().min(actual.len());
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for i in (0..common).filter(|&i| expected[i] != actual[i]) {
+        match ranges.last_mut() {
+            Some(last) if last.end == i => last.end = i + 1,
+            _ => ranges.push(i..i + 1),
+        }
+    }
+    if expected.len() != actual.len() {
+        ranges.push(common..expected.len().max(actual.len()));
+    }
+    ranges
+}
+
+fn clamp_slice<'d>(data: &'d [u8], span: &Range<usize>) -> &'d [u8] {
+    &data[span.start.min(data.len())..span.end.min(data.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DSL: &str = r#"
+        @endian = little;
+        struct h @packed {
+            magic:   [u8; 4] = @bytes("FPK");
+            version: u32     = ${VERSION};
+            size:    u32     = @sizeof(image);
+        }
+    "#;
+
+    #[test]
+    fn test_diff_ranges_merges_runs_and_length() {
+        assert_eq!(diff_ranges(b"abcdef", b"abXYef"), vec![2..4]);
+        assert_eq!(diff_ranges(b"abc", b"abcde"), vec![3..5]);
+        assert!(diff_ranges(b"same", b"same").is_empty());
+    }
+
+    #[test]
+    fn test_check_case_reports_field_mismatches() {
+        let mut env = HashMap::new();
+        env.insert("VERSION".to_string(), Value::U64(2));
+        let mut sections = HashMap::new();
+        sections.insert("image".to_string(), vec![0u8; 16]);
+
+        let expected = b"FPK\0\x01\0\0\0\x10\0\0\0";
+        let report = check_case("v2", DSL, &env, &sections, expected);
+        assert!(!report.passed());
+        assert_eq!(report.byte_mismatches, vec![4..5]);
+        assert_eq!(report.field_mismatches.len(), 1);
+        assert_eq!(report.field_mismatches[0].field, "version");
+        assert_eq!(report.field_mismatches[0].expected, vec![1, 0, 0, 0]);
+        assert_eq!(report.field_mismatches[0].actual, vec![2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_check_dir_reads_env_and_sections() {
+        let dir = std::env::temp_dir().join(format!("delbin-conformance-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("fw.img"), [0u8; 32]).unwrap();
+        std::fs::write(dir.join("ok.env"), "# release\nVERSION=0b0_111\nsection image=fw.img\n").unwrap();
+        std::fs::write(dir.join("ok.bin"), b"FPK\0\x07\0\0\0\x20\0\0\0").unwrap();
+        std::fs::write(dir.join("missing.bin"), b"FPK\0").unwrap();
+
+        let report = check_dir(DSL, &dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.cases.len(), 2);
+        let failures: Vec<_> = report.failures().map(|c| c.name.as_str()).collect();
+        assert_eq!(failures, vec!["missing"]);
+        assert!(report.cases[0].error.is_some()); // no VERSION / image for "missing"
+        assert!(report.cases[1].passed());
+    }
+}
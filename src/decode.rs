@@ -0,0 +1,65 @@
+//! Delbin decode result types
+
+use std::collections::HashMap;
+
+use crate::types::Value;
+
+/// Kind of problem found while validating a binary against the DSL layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IssueKind {
+    /// Data ends before the field is complete
+    Truncated,
+    /// Constant-initialized field (e.g. magic) does not match the DSL
+    ConstantMismatch,
+    /// Size/offset field doesn't match the layout or points outside the buffer
+    OutOfBounds,
+}
+
+/// Single validation finding
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValidationIssue {
+    pub kind: IssueKind,
+    /// Field the issue refers to
+    pub field: String,
+    pub message: String,
+}
+
+/// Result of decoding a binary with validation
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValidationReport {
+    /// Decoded field values (fields past a truncation point are absent)
+    pub fields: HashMap<String, Value>,
+    /// Problems found, in field order
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// True when no issues were found
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Result of decoding a possibly truncated prefix of a binary
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PartialDecode {
+    /// Fields fully contained in the data
+    pub fields: HashMap<String, Value>,
+    /// Fields that could not be decoded, in layout order
+    pub unresolved: Vec<String>,
+    /// Offset of the first unresolved field, `None` if every field was decoded
+    pub stopped_at: Option<usize>,
+    /// Struct size according to the layout (bytes needed for a full decode)
+    pub struct_size: usize,
+}
+
+impl PartialDecode {
+    /// True when every field was decoded
+    pub fn is_complete(&self) -> bool {
+        self.unresolved.is_empty()
+    }
+}
@@ -0,0 +1,214 @@
+//! Delbin terminal diagnostics
+//!
+//! Renders errors and warnings the way compilers do, with the offending DSL
+//! line, an underline and the hint:
+//!
+//! ```text
+//! error[E04003]: Unknown CRC32 variant: 'koopman'
+//!  --> header.dsl:3:5
+//!   |
+//! 3 |     crc: u32 = @crc32(app, "koopman");
+//!   |     ^^^ invalid argument
+//!   |
+//!   = help: ...
+//! ```
+//!
+//! Findings without a [`SourceLocation`] are rendered without the snippet.
+
+use std::fmt::Write;
+
+use crate::error::{DelbinError, DelbinWarning, SourceLocation};
+
+/// Renders diagnostics against one DSL file
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    path: String,
+    color: bool,
+}
+
+impl Renderer {
+    /// Renderer for the file at `path` (shown in the ` --> ` line), without
+    /// colors
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            color: false,
+        }
+    }
+
+    /// Use ANSI colors, e.g. when stderr is a terminal
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Render an error
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use delbin::{diagnostic::Renderer, parser};
+    ///
+    /// let err = parser::parse("struct h {\n    a: u8 = ;\n}").unwrap_err();
+    /// let text = Renderer::new("h.dsl").render_error(&err);
+    /// assert!(text.starts_with("error[E01003]: "));
+    /// assert!(text.contains(" --> h.dsl:2:13\n"));
+    /// assert!(text.contains("2 |     a: u8 = ;\n  |             ^ invalid syntax\n"));
+    /// ```
+    pub fn render_error(&self, error: &DelbinError) -> String {
+        self.render(
+            Severity::Error,
+            &error.code.to_string(),
+            &error.message,
+            error.code.title(),
+            error.location.as_ref(),
+            error.hint.as_deref(),
+        )
+    }
+
+    /// Render a warning
+    pub fn render_warning(&self, warning: &DelbinWarning) -> String {
+        self.render(
+            Severity::Warning,
+            &format!("{:?}", warning.code),
+            &warning.message,
+            warning.code.title(),
+            warning.location.as_ref(),
+            None,
+        )
+    }
+
+    fn render(
+        &self,
+        severity: Severity,
+        code: &str,
+        message: &str,
+        label: &str,
+        location: Option<&SourceLocation>,
+        hint: Option<&str>,
+    ) -> String {
+        let (name, accent) = match severity {
+            Severity::Error => ("error", "\x1b[1;31m"),
+            Severity::Warning => ("warning", "\x1b[1;33m"),
+        };
+        let paint = |text: &str, style: &str| {
+            if self.color {
+                format!("{}{}\x1b[0m", style, text)
+            } else {
+                text.to_string()
+            }
+        };
+        let gutter_style = "\x1b[1;34m";
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}{}",
+            paint(&format!("{}[{}]", name, code), accent),
+            paint(&format!(": {}", message), "\x1b[1m")
+        );
+        let Some(location) = location else {
+            if !self.path.is_empty() {
+                let _ = writeln!(out, " {} {}", paint("-->", gutter_style), self.path);
+            }
+            if let Some(hint) = hint {
+                let _ = writeln!(out, "  {} help: {}", paint("=", gutter_style), hint);
+            }
+            return out;
+        };
+
+        let line_no = location.line.to_string();
+        let pad = " ".repeat(line_no.len());
+        let bar = paint("|", gutter_style);
+        let _ = writeln!(
+            out,
+            "{}{} {}:{}:{}",
+            pad,
+            paint("-->", gutter_style),
+            self.path,
+            location.line,
+            location.column
+        );
+        let _ = writeln!(out, "{} {}", pad, bar);
+        let _ = writeln!(out, "{} {} {}", paint(&line_no, gutter_style), bar, location.context);
+
+        // Underline the word the location points at, or a single character
+        let column = location.column.saturating_sub(1);
+        let indent: String = location
+            .context
+            .chars()
+            .take(column)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = location
+            .context
+            .chars()
+            .skip(column)
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .count()
+            .max(1);
+        let _ = writeln!(
+            out,
+            "{} {} {}{}",
+            pad,
+            bar,
+            indent,
+            paint(&format!("{} {}", "^".repeat(width), label), accent)
+        );
+        if let Some(hint) = hint {
+            let _ = writeln!(out, "{} {}", pad, bar);
+            let _ = writeln!(out, "{} {} help: {}", pad, paint("=", gutter_style), hint);
+        }
+        out
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_render_field_diagnostics() {
+        let dsl = "struct h {\n\tvalue: u8 = 0x1FF;\n    c: u32 = @crc32(app);\n}";
+        let sections = HashMap::from([("image".to_string(), vec![0u8])]);
+        let err = crate::generate(dsl, &HashMap::new(), &sections).unwrap_err();
+        let warnings = crate::generate(&dsl.replace("app", "image"), &HashMap::new(), &sections)
+            .unwrap()
+            .warnings;
+
+        let renderer = Renderer::new("fw/h.dsl");
+        assert_eq!(
+            renderer.render_warning(&warnings[0]),
+            "warning[W03002]: Value 0x1FF truncated to fit 8-bit field (masked to 0xFF)\n \
+             --> fw/h.dsl:2:2\n  |\n2 | \tvalue: u8 = 0x1FF;\n  | \t^^^^^ value truncated\n"
+        );
+        assert_eq!(
+            renderer.render_error(&err),
+            "error[E02003]: Undefined section: app\n --> fw/h.dsl:3:5\n  |\n\
+             3 |     c: u32 = @crc32(app);\n  |     ^ undefined section\n"
+        );
+        let colored = renderer.clone().with_color(true).render_error(&err);
+        assert!(colored.starts_with("\x1b[1;31merror[E02003]\x1b[0m"), "{colored}");
+
+        let hinted = err.with_hint("pass --section app=FILE");
+        assert!(
+            renderer
+                .render_error(&hinted)
+                .ends_with("^ undefined section\n  |\n  = help: pass --section app=FILE\n")
+        );
+
+        // No location: message, file and hint only
+        let bare = DelbinError::new(crate::ErrorCode::E04007, "Too slow").with_hint("raise the budget");
+        assert_eq!(
+            renderer.render_error(&bare),
+            "error[E04007]: Too slow\n --> fw/h.dsl\n  = help: raise the budget\n"
+        );
+    }
+}
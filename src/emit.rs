@@ -0,0 +1,761 @@
+//! Delbin code emitters
+//!
+//! Generate artifacts for other toolchains from a DSL description, so
+//! layout constants aren't duplicated by hand.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::ast::{ArrayLiteralKind, Expr, File, RepeatCount, Type};
+use crate::error::{DelbinError, DelbinWarning, ErrorCode, Result};
+use crate::eval::Evaluator;
+use crate::model::{self, ChecksumSource, FieldValue};
+use crate::parser;
+use crate::types::{Endian, ScalarType, Value};
+
+/// Emit C preprocessor defines for the struct layout and constant fields
+///
+/// Produces `<PREFIX>_SIZE` (and `<PREFIX>_SLOT_SIZE` with `@slot_size`),
+/// `<PREFIX>_<FIELD>_OFFSET` and `<PREFIX>_<FIELD>_SIZE` for every field,
+/// plus `<PREFIX>_<FIELD>` for fields whose value is fixed by the DSL alone
+/// (magic numbers, format versions, offsets). Byte-array constants of up to
+/// 8 bytes are emitted as an integer in the file's byte order, so
+/// `@bytes("fpk")` in a little-endian file becomes `0x006B7066`, matching a
+/// `u32` read on the device. `prefix` defaults to the upper-cased struct
+/// name; `env` is only needed when array lengths depend on env variables.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::emit::c_defines;
+/// use std::collections::HashMap;
+///
+/// let dsl = r#"struct fw_header @packed { magic: [u8; 4] = @bytes("fpk"); size: u32; }"#;
+/// let header = c_defines(dsl, &HashMap::new(), Some("FW")).unwrap();
+/// assert!(header.contains("#define FW_SIZE 8\n"));
+/// assert!(header.contains("#define FW_MAGIC 0x006B7066\n"));
+/// ```
+pub fn c_defines(dsl: &str, env: &HashMap<String, Value>, prefix: Option<&str>) -> Result<String> {
+    let file = parser::parse(dsl)?;
+    let sections = HashMap::<String, Vec<u8>>::new();
+    let mut evaluator = Evaluator::new(env, &sections);
+    let layout = evaluator.layout(&file)?;
+    let constants: HashMap<String, Vec<u8>> = evaluator.constant_fields(&file)?.into_iter().collect();
+    let endian = evaluator.endian();
+
+    let prefix = prefix.map_or_else(|| c_ident(&layout.name), c_ident);
+    let guard = format!("{}_LAYOUT_H", prefix);
+
+    let mut out = String::new();
+    let _ = writeln!(out, "/* Generated by delbin from struct {}. Do not edit. */", layout.name);
+    let _ = writeln!(out, "#ifndef {}\n#define {}\n", guard, guard);
+    let mut defines = CDefines::default();
+    defines.define(&mut out, format!("{}_SIZE", prefix), layout.size.to_string());
+    if let Some(slot_size) = file.slot_size {
+        defines.define(&mut out, format!("{}_SLOT_SIZE", prefix), format!("0x{:X}", slot_size));
+    }
+    for field in &layout.fields {
+        let name = format!("{}_{}", prefix, c_ident(&field.name));
+        let _ = writeln!(out);
+        defines.define(&mut out, format!("{}_OFFSET", name), field.offset.to_string());
+        defines.define(&mut out, format!("{}_SIZE", name), field.size.to_string());
+        if let Some(bytes) = constants.get(&field.name).filter(|b| b.len() <= 8) {
+            defines.define(&mut out, name, c_integer(bytes, endian));
+        }
+    }
+    let _ = writeln!(out, "\n#endif /* {} */", guard);
+    Ok(out)
+}
+
+/// Emit a GNU ld snippet reserving the header region
+///
+/// Declares a `MEMORY` region at `@base` of `@size` bytes (the struct size
+/// when `@size` is absent) and a `NOLOAD` output section filling it, with
+/// `__<name>_start`/`__<name>_end` symbols for the firmware, plus
+/// `__<name>_slot_size` with `@slot_size`. Include it from the main linker
+/// script and start the application region at the end symbol's address.
+/// `@base` is required.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::emit::ld_script;
+/// use std::collections::HashMap;
+///
+/// let dsl = "@base = 0x08000000; @size = 0x200; struct fw_header { magic: u32; }";
+/// let script = ld_script(dsl, &HashMap::new()).unwrap();
+/// assert!(script.contains("FW_HEADER (r) : ORIGIN = 0x08000000, LENGTH = 0x200"));
+/// ```
+pub fn ld_script(dsl: &str, env: &HashMap<String, Value>) -> Result<String> {
+    let file = parser::parse(dsl)?;
+    let sections = HashMap::<String, Vec<u8>>::new();
+    let layout = Evaluator::new(env, &sections).layout(&file)?;
+    let base = file.base.ok_or_else(|| {
+        DelbinError::new(
+            ErrorCode::E04003,
+            "Linker script output needs an @base directive",
+        )
+    })?;
+    let size = file.size.unwrap_or(layout.size as u64);
+
+    let name = &layout.name;
+    let region = c_ident(name);
+    let mut out = String::new();
+    let _ = writeln!(out, "/* Generated by delbin from struct {}. Do not edit. */", name);
+    let _ = writeln!(out, "/* Header: {} bytes used of 0x{:X} reserved */\n", layout.size, size);
+    let _ = writeln!(out, "MEMORY\n{{");
+    let _ = writeln!(out, "    {} (r) : ORIGIN = 0x{:08X}, LENGTH = 0x{:X}", region, base, size);
+    let _ = writeln!(out, "}}\n\nSECTIONS\n{{");
+    if let Some(slot_size) = file.slot_size {
+        let _ = writeln!(out, "    __{}_slot_size = 0x{:X};", name, slot_size);
+    }
+    let _ = writeln!(out, "    .{} (NOLOAD) :\n    {{", name);
+    let _ = writeln!(out, "        __{}_start = .;", name);
+    let _ = writeln!(out, "        . += 0x{:X};", size);
+    let _ = writeln!(out, "        __{}_end = .;", name);
+    let _ = writeln!(out, "    }} > {}\n}}", region);
+    Ok(out)
+}
+
+/// Emit a Make dependency rule for a generated header
+///
+/// Lists `dsl_path` and the files providing every section and env variable
+/// the DSL reads as prerequisites of `target`, e.g.
+/// `header.bin: header.dsl app.bin version.env`. `files` maps section and
+/// env variable names to those files; inputs without an entry (env values
+/// passed on the command line) are left out, as are entries the DSL
+/// doesn't use. Files embedded with `@file("...")` are prerequisites too.
+/// The output is a plain depfile, so it works both with
+/// `-include` in a Makefile and with CMake's `add_custom_command(DEPFILE)`.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::emit::make_deps;
+/// use std::collections::HashMap;
+///
+/// let dsl = "struct h { version: u32 = ${VERSION}; size: u32 = @sizeof(app); }";
+/// let mut files = HashMap::new();
+/// files.insert("app".to_string(), "build/app.bin".to_string());
+/// files.insert("VERSION".to_string(), "version.env".to_string());
+/// let rule = make_deps(dsl, "header.dsl", "header.bin", &files).unwrap();
+/// assert_eq!(rule, "header.bin: header.dsl version.env build/app.bin\n");
+/// ```
+pub fn make_deps(
+    dsl: &str,
+    dsl_path: &str,
+    target: &str,
+    files: &HashMap<String, String>,
+) -> Result<String> {
+    make_deps_with_includes(dsl, dsl_path, target, files, &parser::IncludePath::default(), None)
+}
+
+/// [`make_deps()`] for a DSL that `@include`s files from `includes` and
+/// reads `@file()`s relative to `file_root`, which become prerequisites too
+///
+/// Without a `file_root`, `@file()` paths are listed as written, as they
+/// are read from the working directory.
+pub fn make_deps_with_includes(
+    dsl: &str,
+    dsl_path: &str,
+    target: &str,
+    files: &HashMap<String, String>,
+    includes: &parser::IncludePath,
+    file_root: Option<&Path>,
+) -> Result<String> {
+    let file = parser::parse_with_resolver(dsl, includes)?;
+    let mut prerequisites = vec![dsl_path.to_string()];
+    for include in &file.includes {
+        if let Some(path) = includes.locate(include)? {
+            let path = path.display().to_string();
+            if !prerequisites.contains(&path) {
+                prerequisites.push(path);
+            }
+        }
+    }
+    for path in embedded_files(&file) {
+        let path = match file_root {
+            Some(root) => root.join(path).display().to_string(),
+            None => path,
+        };
+        if !prerequisites.contains(&path) {
+            prerequisites.push(path);
+        }
+    }
+    for name in input_names(&file) {
+        if let Some(path) = files.get(&name) {
+            if !prerequisites.contains(path) {
+                prerequisites.push(path.clone());
+            }
+        }
+    }
+
+    let mut out = make_escape(target);
+    out.push(':');
+    for path in prerequisites.iter().filter(|p| !p.is_empty()) {
+        out.push(' ');
+        out.push_str(&make_escape(path));
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+/// Emit a SARIF 2.1.0 log of `errors` and `warnings` against the DSL file
+/// `dsl_path`, for code-scanning dashboards
+///
+/// Findings carrying a [`SourceLocation`](crate::error::SourceLocation)
+/// (parse errors, and problems in a field) point at that line and column;
+/// the rest point at the file. Hints are appended to the message.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::{emit::sarif, generate};
+/// use std::collections::HashMap;
+///
+/// let dsl = "struct h {\n    v: u8 = 0x1FF;\n}";
+/// let result = generate(dsl, &HashMap::new(), &HashMap::<String, Vec<u8>>::new()).unwrap();
+/// let log = sarif("header.dsl", &[], &result.warnings);
+/// assert!(log.contains(r#""ruleId": "W03002""#));
+/// assert!(log.contains(r#""startLine": 2"#));
+/// ```
+pub fn sarif(dsl_path: &str, errors: &[DelbinError], warnings: &[DelbinWarning]) -> String {
+    let findings = errors
+        .iter()
+        .map(|e| {
+            let message = match &e.hint {
+                Some(hint) => format!("{}\n{}", e.message, hint),
+                None => e.message.clone(),
+            };
+            (e.code.to_string(), "error", message, e.location.as_ref())
+        })
+        .chain(
+            warnings
+                .iter()
+                .map(|w| (format!("{:?}", w.code), "warning", w.message.clone(), w.location.as_ref())),
+        );
+
+    let mut results = Vec::new();
+    for (rule, level, message, location) in findings {
+        let mut region = String::new();
+        if let Some(location) = location {
+            region = format!(
+                r#", "region": {{"startLine": {}, "startColumn": {}, "snippet": {{"text": {}}}}}"#,
+                location.line,
+                location.column,
+                json_string(&location.context)
+            );
+        }
+        let mut result = String::new();
+        let _ = writeln!(result, "        {{");
+        let _ = writeln!(result, r#"          "ruleId": {},"#, json_string(&rule));
+        let _ = writeln!(result, r#"          "level": "{}","#, level);
+        let _ = writeln!(result, r#"          "message": {{"text": {}}},"#, json_string(&message));
+        let _ = writeln!(
+            result,
+            r#"          "locations": [{{"physicalLocation": {{"artifactLocation": {{"uri": {}}}{}}}}}]"#,
+            json_string(dsl_path),
+            region
+        );
+        result.push_str("        }");
+        results.push(result);
+    }
+
+    let mut out = String::new();
+    out.push_str("{\n");
+    out.push_str("  \"$schema\": \"https://json.schemastore.org/sarif-2.1.0.json\",\n");
+    out.push_str("  \"version\": \"2.1.0\",\n");
+    out.push_str("  \"runs\": [\n    {\n");
+    let _ = writeln!(
+        out,
+        r#"      "tool": {{"driver": {{"name": "delbin", "version": "{}", "informationUri": "{}"}}}},"#,
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_REPOSITORY")
+    );
+    if results.is_empty() {
+        out.push_str("      \"results\": []\n");
+    } else {
+        let _ = writeln!(out, "      \"results\": [\n{}\n      ]", results.join(",\n"));
+    }
+    out.push_str("    }\n  ]\n}\n");
+    out
+}
+
+/// Env variables and sections the file reads, in first-use order
+///
+/// Env variables from `@endian` and `@inputs` come first. Bare identifiers
+/// inside `@offsetof()` and `@self[..]` name fields, not sections.
+fn input_names(file: &File) -> Vec<String> {
+    let mut names: Vec<String> = file.endian_var.iter().cloned().collect();
+    names.extend(file.inputs.iter().map(|i| i.name.clone()));
+    for field in &file.struct_def.fields {
+        let start = names.len();
+        if let Type::Array { len, .. } = &field.ty {
+            collect_inputs(len, &mut names);
+        }
+        if let Some(init) = &field.init {
+            collect_inputs(init, &mut names);
+        }
+        // `${entry}` in an `@for` block reads the list
+        if let Some(for_each) = &field.for_each {
+            for name in &mut names[start..] {
+                let path = name.strip_prefix(for_each.var.as_str());
+                if path.is_some_and(|path| path.is_empty() || path.starts_with(['[', '.'])) {
+                    *name = for_each.list.clone();
+                }
+            }
+        }
+    }
+    for def in &file.sections {
+        collect_inputs(&def.transform, &mut names);
+    }
+    let mut seen = HashSet::new();
+    names.retain(|n| seen.insert(n.clone()));
+    names
+}
+
+/// Paths of the `@file("...")`s the file embeds, in first-use order; paths
+/// taken from env variables aren't known until generation
+fn embedded_files(file: &File) -> Vec<String> {
+    let mut paths = Vec::new();
+    for init in file.struct_def.fields.iter().filter_map(|f| f.init.as_ref()) {
+        collect_files(init, &mut paths);
+    }
+    let mut seen = HashSet::new();
+    paths.retain(|p| seen.insert(p.clone()));
+    paths
+}
+
+fn collect_files(expr: &Expr, paths: &mut Vec<String>) {
+    match expr {
+        Expr::Call { name, args } if name == "file" => {
+            if let Some(Expr::String(path)) = args.first() {
+                paths.push(path.clone());
+            }
+        }
+        Expr::Call { args, .. } => {
+            for arg in args {
+                collect_files(arg, paths);
+            }
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            collect_files(left, paths);
+            collect_files(right, paths);
+        }
+        Expr::UnaryOp { operand, .. } | Expr::Named { value: operand, .. } => collect_files(operand, paths),
+        _ => {}
+    }
+}
+
+fn collect_inputs(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::EnvVar(name) | Expr::SectionRef(name) => names.push(name.clone()),
+        Expr::BinaryOp { left, right, .. } => {
+            collect_inputs(left, names);
+            collect_inputs(right, names);
+        }
+        Expr::UnaryOp { operand, .. } | Expr::Named { value: operand, .. } => {
+            collect_inputs(operand, names)
+        }
+        Expr::Call { name, .. } if name == "offsetof" => {}
+        Expr::Call { args, .. } => {
+            for arg in args {
+                collect_inputs(arg, names);
+            }
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::Repeat { value, count }) => {
+            collect_inputs(value, names);
+            if let RepeatCount::Explicit(n) = count {
+                collect_inputs(n, names);
+            }
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::List { elements }) => {
+            for elem in elements {
+                collect_inputs(elem, names);
+            }
+        }
+        Expr::Number(_) | Expr::String(_) | Expr::SelfRef | Expr::Range { .. } => {}
+    }
+}
+
+/// Magic at the start of a binary schema
+pub const SCHEMA_MAGIC: [u8; 4] = *b"DLBS";
+/// Binary schema format version
+pub const SCHEMA_VERSION: u8 = 1;
+
+/// Emit a compact binary schema of the struct layout
+///
+/// Lets a small on-device interpreter parse and check any header generically
+/// instead of carrying per-format C code. All integers are little-endian.
+///
+/// ```text
+/// schema:   magic "DLBS" | version u8 | flags u8 | size u32 | count u16 | field*
+///           flags bit 0: the header is big-endian
+/// field:    id u16 | type u8 | kind u8 | offset u32 | count u32 | name str8
+///           id: declaration index; type: 1-4 u8..u64, 5-8 i8..i64,
+///           +0x10 for arrays; count: elements (1 for scalars)
+/// kind 0:   no value check (input-dependent or zero-filled)
+/// kind 1:   constant       | len u16 | bytes, as encoded in the header
+/// kind 2:   checksum       | algorithm str8 | n u8 | source*
+/// source:   0 u8 | start u32 | end u32     header bytes start..end
+///           1 u8 | name str8               a whole section
+/// str8:     len u8 | UTF-8 bytes
+/// ```
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::emit::{binary_schema, SCHEMA_MAGIC};
+/// use std::collections::HashMap;
+///
+/// let dsl = r#"struct h @packed { magic: u32 = 0x4B5046; crc: u32 = @crc32(@self[..crc]); }"#;
+/// let schema = binary_schema(dsl, &HashMap::new()).unwrap();
+/// assert_eq!(schema[..4], SCHEMA_MAGIC);
+/// assert_eq!(schema[10..12], [2, 0]); // field count
+/// ```
+pub fn binary_schema(dsl: &str, env: &HashMap<String, Value>) -> Result<Vec<u8>> {
+    let model = model::build(dsl, env)?;
+    let mut out = SCHEMA_MAGIC.to_vec();
+    out.push(SCHEMA_VERSION);
+    out.push(u8::from(model.endian == Endian::Big));
+    out.extend_from_slice(&schema_u32(model.size, "struct size")?.to_le_bytes());
+    let count = u16::try_from(model.fields.len())
+        .map_err(|_| schema_error(format!("{} fields don't fit a u16 count", model.fields.len())))?;
+    out.extend_from_slice(&count.to_le_bytes());
+
+    for (id, field) in model.fields.iter().enumerate() {
+        out.extend_from_slice(&(id as u16).to_le_bytes());
+        out.push(scalar_code(field.elem) | if field.count.is_some() { 0x10 } else { 0 });
+        out.push(match field.value {
+            FieldValue::Constant(_) => 1,
+            FieldValue::Checksum { .. } => 2,
+            _ => 0,
+        });
+        out.extend_from_slice(&schema_u32(field.offset, "field offset")?.to_le_bytes());
+        out.extend_from_slice(&schema_u32(field.count.unwrap_or(1), "element count")?.to_le_bytes());
+        push_str8(&mut out, &field.name)?;
+        match &field.value {
+            FieldValue::Constant(bytes) => {
+                let len = u16::try_from(bytes.len()).map_err(|_| {
+                    schema_error(format!("constant '{}' is longer than 65535 bytes", field.name))
+                })?;
+                out.extend_from_slice(&len.to_le_bytes());
+                out.extend_from_slice(bytes);
+            }
+            FieldValue::Checksum { algorithm, over } => {
+                push_str8(&mut out, algorithm)?;
+                let n = u8::try_from(over.len()).map_err(|_| {
+                    schema_error(format!("checksum '{}' has more than 255 sources", field.name))
+                })?;
+                out.push(n);
+                for source in over {
+                    match source {
+                        ChecksumSource::Header(range) => {
+                            out.push(0);
+                            out.extend_from_slice(&schema_u32(range.start, "range start")?.to_le_bytes());
+                            out.extend_from_slice(&schema_u32(range.end, "range end")?.to_le_bytes());
+                        }
+                        ChecksumSource::Section(name) => {
+                            out.push(1);
+                            push_str8(&mut out, name)?;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+fn scalar_code(ty: ScalarType) -> u8 {
+    match ty {
+        ScalarType::U8 => 1,
+        ScalarType::U16 => 2,
+        ScalarType::U32 => 3,
+        ScalarType::U64 => 4,
+        ScalarType::I8 => 5,
+        ScalarType::I16 => 6,
+        ScalarType::I32 => 7,
+        ScalarType::I64 => 8,
+    }
+}
+
+fn schema_u32(value: usize, what: &str) -> Result<u32> {
+    u32::try_from(value).map_err(|_| schema_error(format!("{} {} doesn't fit a u32", what, value)))
+}
+
+fn push_str8(out: &mut Vec<u8>, s: &str) -> Result<()> {
+    let len = u8::try_from(s.len())
+        .map_err(|_| schema_error(format!("name '{}' is longer than 255 bytes", s)))?;
+    out.push(len);
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn schema_error(message: String) -> DelbinError {
+    DelbinError::new(ErrorCode::E04003, format!("Binary schema: {}", message))
+}
+
+/// JSON string literal for `s`
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Escape a path for a Make rule (spaces, `#` and `$`)
+fn make_escape(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' | '#' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '$' => out.push_str("$$"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Macro names already written, so a field named e.g. `size` can't redefine
+/// the struct size macro
+#[derive(Default)]
+struct CDefines {
+    names: HashSet<String>,
+}
+
+impl CDefines {
+    fn define(&mut self, out: &mut String, name: String, value: String) {
+        if self.names.insert(name.clone()) {
+            let _ = writeln!(out, "#define {} {}", name, value);
+        } else {
+            let _ = writeln!(out, "/* {} omitted: name already defined */", name);
+        }
+    }
+}
+
+/// Upper-case a DSL identifier for use in a C macro name
+fn c_ident(name: &str) -> String {
+    name.trim_start_matches('_').to_ascii_uppercase()
+}
+
+/// Format encoded bytes as a C hex literal of the same width
+fn c_integer(bytes: &[u8], endian: Endian) -> String {
+    let value = match endian {
+        Endian::Little => bytes.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64),
+        Endian::Big => bytes.iter().fold(0u64, |acc, &b| acc << 8 | b as u64),
+    };
+    let suffix = if bytes.len() > 4 { "ULL" } else { "" };
+    format!("0x{:0width$X}{}", value, suffix, width = bytes.len() * 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_c_defines_layout_and_constants() {
+        let dsl = r#"
+            @endian = big;
+            struct fw_header @packed @align(256) {
+                magic:      [u8; 4] = @bytes("fpk");
+                hdr_ver:    u16     = 0x0102;
+                version:    u32     = ${VERSION};
+                body_at:    u32     = @offsetof(body);
+                total:      u64     = @sizeof(@self);
+                size:       u32     = @sizeof(image);
+                body:       [u8; 8];
+                crc:        u32     = @crc32(@self[..crc]);
+            }
+        "#;
+        let out = c_defines(dsl, &HashMap::new(), None).unwrap();
+
+        assert!(out.contains("#ifndef FW_HEADER_LAYOUT_H\n"), "{out}");
+        assert!(out.contains("#define FW_HEADER_SIZE 256\n"), "{out}");
+        assert!(out.contains("#define FW_HEADER_MAGIC 0x66706B00\n"), "{out}");
+        assert!(out.contains("#define FW_HEADER_HDR_VER 0x0102\n"), "{out}");
+        assert!(out.contains("#define FW_HEADER_BODY_AT 0x0000001A\n"), "{out}");
+        assert!(out.contains("#define FW_HEADER_TOTAL 0x0000000000000100ULL\n"), "{out}");
+        assert!(out.contains("#define FW_HEADER_CRC_OFFSET 34\n"), "{out}");
+
+        // Input- and data-dependent values only get offset/size
+        assert!(!out.contains("#define FW_HEADER_VERSION "), "{out}");
+        assert!(out.contains("#define FW_HEADER_SIZE_OFFSET 22\n"), "{out}");
+        assert!(!out.contains("#define FW_HEADER_CRC "), "{out}");
+    }
+
+    #[test]
+    fn test_ld_script_reserves_region() {
+        let dsl = "@base = 0x08000000; @size = 512; struct fw_header @align(64) { magic: u32; }";
+        let script = ld_script(dsl, &HashMap::new()).unwrap();
+        assert!(script.contains("/* Header: 64 bytes used of 0x200 reserved */"), "{script}");
+        assert!(script.contains("    FW_HEADER (r) : ORIGIN = 0x08000000, LENGTH = 0x200\n"), "{script}");
+        assert!(script.contains("    .fw_header (NOLOAD) :\n"), "{script}");
+        assert!(script.contains("        . += 0x200;\n        __fw_header_end = .;\n    } > FW_HEADER\n"), "{script}");
+
+        // Without @size the region is exactly the header
+        let script = ld_script("@base = 0x1000; struct h { a: u32; }", &HashMap::new()).unwrap();
+        assert!(script.contains("H (r) : ORIGIN = 0x00001000, LENGTH = 0x4\n"), "{script}");
+        assert!(!script.contains("slot_size"), "{script}");
+        let script = ld_script("@base = 0x1000; @slot_size = 512K; struct h { a: u32; }", &HashMap::new()).unwrap();
+        assert!(script.contains("SECTIONS\n{\n    __h_slot_size = 0x80000;\n"), "{script}");
+
+        let err = ld_script("struct h { a: u32; }", &HashMap::new()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::E04003);
+        let err = ld_script("@base = 0; @size = 2; struct h { a: u32; }", &HashMap::new()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::E04002);
+    }
+
+    #[test]
+    fn test_c_defines_skips_colliding_names() {
+        let dsl = "struct h @packed { size: u16 = 2; }";
+        let out = c_defines(dsl, &HashMap::new(), Some("HDR")).unwrap();
+        assert!(out.contains("#define HDR_SIZE 2\n"), "{out}");
+        assert!(out.contains("/* HDR_SIZE omitted: name already defined */"), "{out}");
+    }
+
+    #[test]
+    fn test_binary_schema() {
+        let dsl = r#"
+            @endian = big;
+            struct h @packed {
+                magic:  [u8; 2] = [0xAB, 0xCD];
+                size:   u32     = @sizeof(image);
+                delta:  i16;
+                crc:    u32     = @crc("crc16-modbus", @self[..crc], image);
+            }
+        "#;
+        let schema = binary_schema(dsl, &HashMap::new()).unwrap();
+        let mut expected = b"DLBS\x01\x01".to_vec();
+        expected.extend([12, 0, 0, 0, 4, 0]);
+        expected.extend([0, 0, 0x11, 1, 0, 0, 0, 0, 2, 0, 0, 0, 5]);
+        expected.extend(b"magic\x02\x00\xAB\xCD");
+        expected.extend([1, 0, 3, 0, 2, 0, 0, 0, 1, 0, 0, 0, 4]);
+        expected.extend(b"size");
+        expected.extend([2, 0, 6, 0, 6, 0, 0, 0, 1, 0, 0, 0, 5]);
+        expected.extend(b"delta");
+        expected.extend([3, 0, 3, 2, 8, 0, 0, 0, 1, 0, 0, 0, 3]);
+        expected.extend(b"crc\x0Ccrc16-modbus\x02");
+        expected.extend([0, 0, 0, 0, 0, 8, 0, 0, 0, 1, 5]);
+        expected.extend(b"image");
+        assert_eq!(schema, expected);
+    }
+
+    #[test]
+    fn test_make_deps_lists_used_inputs() {
+        let dsl = r#"
+            @endian = ${ENDIAN};
+            struct h @packed {
+                size:   u32      = @sizeof(app);
+                ver:    [u8; 16] = @bytes(${VERSION});
+                at:     u32      = @offsetof(crc);
+                crc:    u32      = @crc32(@self[..crc], app, boot);
+            }
+        "#;
+        let mut files = HashMap::new();
+        files.insert("app".to_string(), "out/my app.bin".to_string());
+        files.insert("boot".to_string(), "boot.bin".to_string());
+        files.insert("VERSION".to_string(), "version.env".to_string());
+        files.insert("ENDIAN".to_string(), "version.env".to_string());
+        files.insert("unused".to_string(), "unused.bin".to_string());
+        files.insert("crc".to_string(), "field-not-a-file".to_string());
+
+        let rule = make_deps(dsl, "hdr.dsl", "$(OUT)/header.bin", &files).unwrap();
+        assert_eq!(
+            rule,
+            "$$(OUT)/header.bin: hdr.dsl version.env out/my\\ app.bin boot.bin\n"
+        );
+
+        // Reading the DSL from stdin: no DSL prerequisite
+        let rule = make_deps(dsl, "", "h.bin", &HashMap::new()).unwrap();
+        assert_eq!(rule, "h.bin:\n");
+    }
+
+    #[test]
+    fn test_make_deps_lists_embedded_files() {
+        let dsl = r#"
+            struct h @packed {
+                key:  [u8; 32] = @file("keys/pub.bin");
+                cal:  [u8; 16] = @file("cal.bin", "pad");
+                copy: [u8; 32] = @file("keys/pub.bin");
+                env:  [u8; 4]  = @file(${BLOB});
+            }
+        "#;
+        let rule = make_deps(dsl, "hdr.dsl", "h.bin", &HashMap::new()).unwrap();
+        assert_eq!(rule, "h.bin: hdr.dsl keys/pub.bin cal.bin\n");
+
+        let includes = parser::IncludePath::default();
+        let root = Some(Path::new("assets"));
+        let rule = make_deps_with_includes(dsl, "hdr.dsl", "h.bin", &HashMap::new(), &includes, root).unwrap();
+        assert_eq!(rule, "h.bin: hdr.dsl assets/keys/pub.bin assets/cal.bin\n");
+    }
+
+    #[test]
+    fn test_sarif_locations() {
+        let sections = HashMap::<String, Vec<u8>>::new();
+        let dsl = "struct h {\n    name: [u8; 2] = @bytes(\"a\\\"bc\");\n    crc: u8 = @crc16(@self[..crc], \"nope\");\n}";
+        let err = crate::generate(dsl, &HashMap::new(), &sections).unwrap_err();
+        let syntax = parser::parse("struct h {\n    a: u8 = ;\n}").unwrap_err();
+        let warnings = crate::generate(&dsl.replace("\"nope\"", "\"usb\""), &HashMap::new(), &sections)
+            .unwrap()
+            .warnings;
+        let log = sarif("fw/hdr.dsl", &[err, syntax], &warnings);
+
+        let doc: serde_yaml::Value = serde_yaml::from_str(&log).unwrap();
+        assert_eq!(doc["version"], "2.1.0");
+        let results = doc["runs"][0]["results"].as_sequence().unwrap();
+        let summary: Vec<(&str, &str, u64, u64)> = results
+            .iter()
+            .map(|r| {
+                let region = &r["locations"][0]["physicalLocation"]["region"];
+                (
+                    r["ruleId"].as_str().unwrap(),
+                    r["level"].as_str().unwrap(),
+                    region["startLine"].as_u64().unwrap(),
+                    region["startColumn"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("E04003", "error", 3, 5),
+                ("E01003", "error", 2, 13),
+                ("W03001", "warning", 2, 5),
+                // Deferred checksums are located too
+                ("W03002", "warning", 3, 5),
+            ]
+        );
+        assert_eq!(results[2]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "fw/hdr.dsl");
+        assert_eq!(
+            results[2]["locations"][0]["physicalLocation"]["region"]["snippet"]["text"],
+            "    name: [u8; 2] = @bytes(\"a\\\"bc\");"
+        );
+        assert!(results[2]["message"]["text"].as_str().unwrap().contains("a\"bc"));
+
+        let log = sarif("h.dsl", &[], &[]);
+        let doc: serde_yaml::Value = serde_yaml::from_str(&log).unwrap();
+        assert!(doc["runs"][0]["results"].as_sequence().unwrap().is_empty());
+
+        let err = crate::generate("struct h { a: u8 = @sizeof(app); }", &HashMap::new(), &sections).unwrap_err();
+        assert!(err.location.is_some());
+    }
+}
@@ -4,6 +4,7 @@ use thiserror::Error;
 
 /// Error codes
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ErrorCode {
     // Parse errors (01)
     E01001, // UnexpectedToken
@@ -17,6 +18,9 @@ pub enum ErrorCode {
     E02002, // UndefinedField
     E02003, // UndefinedSection
     E02004, // UndefinedFunction
+    E02005, // InvalidInputs (env doesn't satisfy the @inputs schema)
+    E02006, // DuplicateInput (env variable or section set by two sources)
+    E02007, // PolicyViolation (the structs don't meet the file's @policy)
 
     // Type errors (03)
     E03001, // TypeMismatch
@@ -32,6 +36,8 @@ pub enum ErrorCode {
     E04004, // ArgumentCountMismatch
     E04005, // ComputationFailed
     E04006, // ShiftOverflow
+    E04007, // Timeout (evaluation exceeded its time budget)
+    E04008, // AssertionFailed (an @assert condition is false)
 
     // IO errors (05)
     E05001, // FileNotFound
@@ -39,6 +45,42 @@ pub enum ErrorCode {
     E05003, // FileWriteError
 }
 
+impl ErrorCode {
+    /// Short description, e.g. "invalid argument"
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorCode::E01001 => "unexpected token",
+            ErrorCode::E01002 => "unexpected end of input",
+            ErrorCode::E01003 => "invalid syntax",
+            ErrorCode::E01004 => "invalid number",
+            ErrorCode::E01005 => "invalid string",
+            ErrorCode::E02001 => "undefined variable",
+            ErrorCode::E02002 => "undefined field",
+            ErrorCode::E02003 => "undefined section",
+            ErrorCode::E02004 => "undefined function",
+            ErrorCode::E02005 => "invalid inputs",
+            ErrorCode::E02006 => "duplicate input",
+            ErrorCode::E02007 => "policy violation",
+            ErrorCode::E03001 => "type mismatch",
+            ErrorCode::E03002 => "array size mismatch",
+            ErrorCode::E03003 => "integer overflow",
+            ErrorCode::E03004 => "invalid array size",
+            ErrorCode::E03005 => "string too long",
+            ErrorCode::E04001 => "division by zero",
+            ErrorCode::E04002 => "invalid range",
+            ErrorCode::E04003 => "invalid argument",
+            ErrorCode::E04004 => "argument count mismatch",
+            ErrorCode::E04005 => "computation failed",
+            ErrorCode::E04006 => "shift overflow",
+            ErrorCode::E04007 => "timeout",
+            ErrorCode::E04008 => "assertion failed",
+            ErrorCode::E05001 => "file not found",
+            ErrorCode::E05002 => "file read error",
+            ErrorCode::E05003 => "file write error",
+        }
+    }
+}
+
 impl std::fmt::Display for ErrorCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
@@ -47,6 +89,7 @@ impl std::fmt::Display for ErrorCode {
 
 /// Source code location
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SourceLocation {
     pub line: usize,
     pub column: usize,
@@ -55,6 +98,7 @@ pub struct SourceLocation {
 
 /// Delbin error
 #[derive(Debug, Error)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[error("[{code}] {message}")]
 pub struct DelbinError {
     pub code: ErrorCode,
@@ -86,6 +130,7 @@ impl DelbinError {
 
 /// Delbin warning
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DelbinWarning {
     pub code: WarningCode,
     pub message: String,
@@ -94,10 +139,26 @@ pub struct DelbinWarning {
 
 /// Warning codes
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum WarningCode {
+    W02001, // InputOverridden (a later source replaced an env variable or section)
     W03001, // StringTruncated
     W03002, // ValueTruncated
     W04001, // ShiftOverflow (shift amount >= operand bit-width)
+    W05001, // PlaintextSuspected (@expect_encrypted section looks unencrypted)
+}
+
+impl WarningCode {
+    /// Short description, e.g. "value truncated"
+    pub fn title(&self) -> &'static str {
+        match self {
+            WarningCode::W02001 => "input overridden",
+            WarningCode::W03001 => "string truncated",
+            WarningCode::W03002 => "value truncated",
+            WarningCode::W04001 => "shift overflow",
+            WarningCode::W05001 => "plaintext suspected",
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, DelbinError>;
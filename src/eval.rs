@@ -1,11 +1,45 @@
 //! Delbin evaluator
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
 use crate::ast::*;
-use crate::builtin;
-use crate::error::{DelbinError, DelbinWarning, ErrorCode, Result};
-use crate::types::{Endian, ScalarType, Value};
+#[cfg(feature = "hmac")]
+use hmac::Mac;
+
+use crate::builtin::{self, CrcDigest, CrcParams, DigestAlgorithm, FastHash, MerkleBuilder, SimpleChecksum};
+use crate::decode::{IssueKind, PartialDecode, ValidationIssue, ValidationReport};
+use crate::error::{DelbinError, DelbinWarning, ErrorCode, Result, SourceLocation, WarningCode};
+use crate::layout::{FieldLayout, Layout};
+use crate::parser::SECTION_TRANSFORMS;
+use crate::provider::{undefined_section, HashAlgorithm, Overlay, SectionProvider};
+use crate::runtime::{Clock, RandomSource, SystemClock};
+use crate::sign::Signer;
+use crate::types::{ArgValue, Endian, ScalarType, Secret, Value};
+use crate::utils::{to_hex_string, ByteStats};
+use crate::verify::{CheckKind, FieldCheck, VerifyReport};
+
+/// Custom builtin, see `Evaluator::register_fn()`
+pub type CustomFn = dyn Fn(&[ArgValue]) -> Result<Value> + Send + Sync;
+
+/// Custom builtins by name
+pub type FunctionRegistry = HashMap<String, Arc<CustomFn>>;
+
+/// Address literal builtins, bytes in network order
+pub(crate) const ADDRESS_BUILTINS: &[&str] = &["ipv4", "ipv6", "mac"];
+
+/// Builtins that produce signatures into an array field
+pub(crate) const SIGNATURE_BUILTINS: &[&str] = &["sign", "ed25519", "ecdsa_p256", "rsa_sign"];
+
+/// Array elements evaluated between deadline checks
+const DEADLINE_STRIDE: usize = 4096;
+
+/// Section bytes streamed between deadline checks
+const DEADLINE_SLICE: u64 = 1 << 20;
 
 /// Pending field (for two-phase evaluation)
 #[derive(Debug)]
@@ -16,14 +50,18 @@ struct PendingField {
     size: usize,
     expr: Expr,
     ty: Type,
+    location: Option<SourceLocation>,
 }
 
 /// Evaluation context
-pub struct Evaluator {
+///
+/// Environment variables and section data are borrowed from the caller, so
+/// large section images are never copied into the evaluator.
+pub struct Evaluator<'a> {
     /// Environment variables
-    env: HashMap<String, Value>,
+    env: &'a HashMap<String, Value>,
     /// External section data
-    sections: HashMap<String, Vec<u8>>,
+    sections: &'a dyn SectionProvider,
     /// Endianness
     endian: Endian,
     /// Current offset
@@ -40,12 +78,40 @@ pub struct Evaluator {
     warnings: Vec<DelbinWarning>,
     /// Struct total size (for @sizeof(@self))
     struct_size: Option<usize>,
+    /// Fields written by the last eval(), in layout order
+    field_map: Vec<FieldLayout>,
+    /// Defaults of optional `@inputs` missing from `env`
+    defaults: HashMap<String, Value>,
+    /// `@section` transforms, materialized by `prepare()`
+    derived: HashMap<String, Vec<u8>>,
+    /// Value of the `@base` directive
+    base: Option<u64>,
+    /// Value of the `@slot_size` directive
+    slot_size: Option<u64>,
+    /// `@compress` sections of each source section, for `@compressed(source)`
+    compressed: HashMap<String, Vec<String>>,
+    /// Custom builtins consulted for names the crate doesn't define
+    functions: FunctionRegistry,
+    /// Backend for `@sign(...)`
+    signer: Option<Arc<dyn Signer>>,
+    /// Evaluation fails with E04007 once this passes
+    deadline: Option<Instant>,
+    /// Reserved field the metadata record is written into, and the record
+    metadata: Option<(String, Vec<u8>)>,
+    /// Directory `@file(...)` paths are confined to
+    file_root: Option<PathBuf>,
+    /// Source of `@random()` and `@uuid()` bytes; the OS if unset
+    random: Option<Arc<dyn RandomSource>>,
+    /// Source of `@timestamp()`; the system time if unset
+    clock: Option<Arc<dyn Clock>>,
+    /// `@index`: the element a repeat initializer is evaluating
+    index: Option<u64>,
 }
 
-impl Evaluator {
+impl<'a> Evaluator<'a> {
     pub fn new(
-        env: HashMap<String, Value>,
-        sections: HashMap<String, Vec<u8>>,
+        env: &'a HashMap<String, Value>,
+        sections: &'a dyn SectionProvider,
     ) -> Self {
         Self {
             env,
@@ -58,27 +124,369 @@ impl Evaluator {
             pending: Vec::new(),
             warnings: Vec::new(),
             struct_size: None,
+            field_map: Vec::new(),
+            defaults: HashMap::new(),
+            derived: HashMap::new(),
+            base: None,
+            slot_size: None,
+            compressed: HashMap::new(),
+            functions: FunctionRegistry::new(),
+            signer: None,
+            deadline: None,
+            metadata: None,
+            file_root: None,
+            random: None,
+            clock: None,
+            index: None,
+        }
+    }
+
+    /// Register a custom builtin, callable from the DSL as `@name(...)`
+    ///
+    /// Sections and `@self` ranges are passed as `ArgValue::Bytes`. A
+    /// numeric result can initialize a scalar field and a bytes result an
+    /// array field of the same length. Built-in names take precedence.
+    ///
+    /// ```rust
+    /// use delbin::{eval::Evaluator, parser, ArgValue, Value};
+    /// use std::collections::HashMap;
+    ///
+    /// let env = HashMap::new();
+    /// let mut sections = HashMap::new();
+    /// sections.insert("image".to_string(), vec![1u8, 2, 3]);
+    ///
+    /// let mut evaluator = Evaluator::new(&env, &sections);
+    /// evaluator.register_fn("byte_sum", |args: &[ArgValue]| {
+    ///     let data = args[0].as_bytes().unwrap_or_default();
+    ///     Ok(Value::U8(data.iter().fold(0u8, |a, b| a.wrapping_add(*b))))
+    /// });
+    /// let file = parser::parse("struct h @packed { sum: u8 = @byte_sum(image); }").unwrap();
+    /// assert_eq!(evaluator.eval(&file).unwrap(), [6]);
+    /// ```
+    pub fn register_fn(
+        &mut self,
+        name: impl Into<String>,
+        f: impl Fn(&[ArgValue]) -> Result<Value> + Send + Sync + 'static,
+    ) {
+        self.functions.insert(name.into(), Arc::new(f));
+    }
+
+    /// Attach the signer `@sign(...)` fields are produced with
+    pub fn with_signer(mut self, signer: Arc<dyn Signer>) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Fail with E04007 instead of running past `deadline`
+    ///
+    /// Checked between fields, between array elements and while streaming
+    /// sections, so a pathological DSL can't stall the caller.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Write `record` into the reserved field `field` (a `[u8; N]` without
+    /// initializer), zero-padded, before checksums are computed
+    pub fn with_metadata_field(mut self, field: impl Into<String>, record: Vec<u8>) -> Self {
+        self.metadata = Some((field.into(), record));
+        self
+    }
+
+    /// Resolve `@file(...)` paths against `root` and reject those that
+    /// leave it
+    ///
+    /// Without a root, paths are resolved against the current directory
+    /// and may point anywhere.
+    pub fn with_file_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.file_root = Some(root.into());
+        self
+    }
+
+    /// Draw `@random()` and `@uuid()` bytes from `random` instead of the OS
+    pub fn with_random_source(mut self, random: Arc<dyn RandomSource>) -> Self {
+        self.random = Some(random);
+        self
+    }
+
+    /// Read `@timestamp()` from `clock` instead of the system time
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Register every function in `functions`
+    pub fn with_functions(mut self, functions: &FunctionRegistry) -> Self {
+        self.functions
+            .extend(functions.iter().map(|(name, f)| (name.clone(), f.clone())));
+        self
+    }
+
+    /// Clear the state of the last evaluation (warnings, field map, offsets)
+    ///
+    /// Registered functions, the signer, the deadline and the metadata
+    /// field are kept, and so are the allocations, so one evaluator can
+    /// produce header after header without reallocating.
+    pub fn reset(&mut self) {
+        self.current_offset = 0;
+        self.field_offsets.clear();
+        self.current_field = None;
+        self.output.clear();
+        self.pending.clear();
+        self.warnings.clear();
+        self.struct_size = None;
+        self.field_map.clear();
+    }
+
+    /// Evaluate the next run against `env`, e.g. the next device's serial
+    /// number in a provisioning loop
+    pub fn set_env(&mut self, env: &'a HashMap<String, Value>) {
+        self.env = env;
+    }
+
+    /// [`eval()`](Self::eval) into `out`, reusing its allocation
+    ///
+    /// `out` is cleared first. Together with [`reset()`](Self::reset) this
+    /// lets a loop generate many headers with no per-header allocation for
+    /// the output.
+    ///
+    /// ```rust
+    /// use delbin::{eval::Evaluator, parser, Value};
+    /// use std::collections::HashMap;
+    ///
+    /// let file = parser::parse("struct h @packed { serial: u32 = ${SERIAL}; }").unwrap();
+    /// let envs: Vec<HashMap<String, Value>> = (1..=3)
+    ///     .map(|n| HashMap::from([("SERIAL".to_string(), Value::U32(n))]))
+    ///     .collect();
+    /// let sections = HashMap::<String, Vec<u8>>::new();
+    ///
+    /// let mut evaluator = Evaluator::new(&envs[0], &sections);
+    /// let mut out = Vec::with_capacity(64);
+    /// for env in &envs {
+    ///     evaluator.reset();
+    ///     evaluator.set_env(env);
+    ///     evaluator.eval_into(&file, &mut out).unwrap();
+    ///     assert_eq!(out.len(), 4);
+    /// }
+    /// assert_eq!(out, [3, 0, 0, 0]);
+    /// ```
+    pub fn eval_into(&mut self, file: &File, out: &mut Vec<u8>) -> Result<()> {
+        out.clear();
+        self.output = std::mem::take(out);
+        match self.eval(file) {
+            Ok(data) => {
+                *out = data;
+                Ok(())
+            }
+            Err(e) => {
+                // Hand the buffer back for the next attempt
+                *out = std::mem::take(&mut self.output);
+                out.clear();
+                Err(e)
+            }
         }
     }
 
     /// Execute evaluation
     pub fn eval(&mut self, file: &File) -> Result<Vec<u8>> {
-        self.endian = file.endian;
+        let file = &*unroll(file, self.env)?;
+        crate::check::check_policy(file)?;
+        self.check_inputs(file)?;
+        self.prepare(file)?;
+        self.check_expect_encrypted(&file.struct_def)?;
+        self.check_metadata_field(&file.struct_def)?;
 
         // First pass: calculate raw struct size
         let raw_size = self.calculate_struct_size(&file.struct_def)?;
 
         // Apply alignment: if @align(n) is specified, round up to n-byte boundary
-        let aligned_size = if let Some(align) = file.struct_def.align {
-            let n = align as usize;
-            raw_size.div_ceil(n) * n
-        } else {
-            raw_size
+        let aligned_size = aligned_struct_size(&file.struct_def, raw_size);
+        check_reserved_size(file, aligned_size)?;
+        check_slot_size(file, "Struct", aligned_size as u64)?;
+        let data = self.eval_sized(&file.struct_def, aligned_size)?;
+        self.check_asserts(&file.asserts)?;
+        Ok(data)
+    }
+
+    /// E04008 for the first `@assert` whose condition is zero
+    fn check_asserts<'f>(&mut self, asserts: impl IntoIterator<Item = &'f Assert>) -> Result<()> {
+        for assert in asserts {
+            if self.eval_expr(&assert.cond)? != 0 {
+                continue;
+            }
+            let mut message = match &assert.message {
+                Some(message) => format!("Assertion failed: {} ({})", message, assert.cond),
+                None => format!("Assertion failed: {}", assert.cond),
+            };
+            // Show both sides of a failed comparison
+            if let Expr::BinaryOp { op, left, right } = &assert.cond {
+                if matches!(op, BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::Eq | BinOp::Ne) {
+                    let (l, r) = (self.eval_expr(left)?, self.eval_expr(right)?);
+                    message.push_str(&format!("; {} is {}, {} is {}", left, l, right, r));
+                }
+            }
+            return Err(DelbinError::new(ErrorCode::E04008, message));
+        }
+        Ok(())
+    }
+
+    /// Evaluate what `file` fixes before any env variables or sections are
+    /// supplied
+    ///
+    /// When no array length depends on inputs, the struct size is checked
+    /// against `@size` and `@slot_size` and every constant field and
+    /// `@assert` is evaluated, so their errors surface at compile time.
+    pub(crate) fn check_constants(&mut self, file: &File) -> Result<()> {
+        let struct_def = &file.struct_def;
+        let fixed = struct_def.fields.iter().all(|field| field.for_each.is_none() && match &field.ty {
+            Type::Scalar(_) => true,
+            Type::Array { len, .. } => !depends_on_inputs(len),
+            Type::Inferred => field.init.as_ref().is_some_and(|init| !depends_on_inputs(init)),
+        });
+        if !fixed {
+            return Ok(());
+        }
+        self.endian = file.endian;
+        self.base = file.base;
+        self.slot_size = file.slot_size;
+        self.check_metadata_field(struct_def)?;
+
+        let raw_size = self.compute_field_layout(struct_def)?;
+        let size = aligned_struct_size(struct_def, raw_size);
+        check_reserved_size(file, size)?;
+        check_slot_size(file, "Struct", size as u64)?;
+        self.struct_size = Some(size);
+
+        for field in &struct_def.fields {
+            let Some(init) = field.init.as_ref().filter(|init| !depends_on_inputs(init)) else {
+                continue;
+            };
+            self.current_field = Some(field.name.clone());
+            self.current_offset = self.field_offsets[&field.name];
+            let first_warning = self.warnings.len();
+            let result = self.eval_init(field, init);
+            self.locate(field.location.as_ref(), first_warning, result)?;
+        }
+        self.current_field = None;
+        self.current_offset = 0;
+        self.check_asserts(file.asserts.iter().filter(|assert| !depends_on_inputs(&assert.cond)))
+    }
+
+    /// The metadata field must be a reserved `[u8; N]` the record fits in
+    fn check_metadata_field(&mut self, struct_def: &StructDef) -> Result<()> {
+        let Some((name, record)) = &self.metadata else {
+            return Ok(());
+        };
+        let field = struct_def.fields.iter().find(|f| &f.name == name).ok_or_else(|| {
+            DelbinError::new(ErrorCode::E04003, format!("Metadata field '{}' not found", name))
+        })?;
+        let (name, record_len) = (name.clone(), record.len());
+        if field.init.is_some() || !matches!(field.ty, Type::Array { elem: ScalarType::U8, .. }) {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("Metadata field '{}' must be a [u8; N] without initializer", name),
+            ));
+        }
+        let size = self.get_field_size(field)?;
+        if size < record_len {
+            return Err(DelbinError::new(
+                ErrorCode::E04002,
+                format!("Metadata field '{}' is {} bytes, the record needs {}", name, size, record_len),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Evaluate the trailer struct, if the file has one
+    ///
+    /// The header and image are read through the section provider, so the
+    /// caller exposes them as sections (the header under its struct name).
+    pub fn eval_trailer(&mut self, file: &File) -> Result<Option<Vec<u8>>> {
+        let file = &*unroll(file, self.env)?;
+        let Some(trailer) = &file.trailer else {
+            return Ok(None);
         };
+        self.check_inputs(file)?;
+        self.prepare(file)?;
+        let raw_size = self.calculate_struct_size(trailer)?;
+        let aligned_size = aligned_struct_size(trailer, raw_size);
+        self.eval_sized(trailer, aligned_size).map(Some)
+    }
+
+    /// Size of the trailer struct, alignment included, without evaluating it
+    pub(crate) fn trailer_size(&mut self, file: &File) -> Result<Option<usize>> {
+        let file = &*unroll(file, self.env)?;
+        let Some(trailer) = &file.trailer else {
+            return Ok(None);
+        };
+        self.prepare(file)?;
+        let raw_size = self.calculate_struct_size(trailer)?;
+        Ok(Some(aligned_struct_size(trailer, raw_size)))
+    }
+
+    /// Warn about `@expect_encrypted` sections that look like plaintext
+    ///
+    /// Flags sections of at least `MIN_ENTROPY_LEN` bytes below
+    /// `MIN_ENTROPY` bits per byte, and zero runs of `MAX_ZERO_RUN` bytes or
+    /// more. Sections that aren't provided are skipped.
+    fn check_expect_encrypted(&mut self, struct_def: &StructDef) -> Result<()> {
+        const MIN_ENTROPY: f64 = 7.0;
+        const MIN_ENTROPY_LEN: u64 = 1024;
+        const MAX_ZERO_RUN: u64 = 64;
+
+        for name in &struct_def.expect_encrypted {
+            if self.section_data().len(name).is_none() {
+                continue;
+            }
+            let mut stats = ByteStats::default();
+            self.feed_section(name, &mut |chunk| stats.update(chunk))?;
+
+            let entropy = stats.entropy();
+            if stats.len() >= MIN_ENTROPY_LEN && entropy < MIN_ENTROPY {
+                self.warnings.push(DelbinWarning {
+                    code: WarningCode::W05001,
+                    message: format!(
+                        "Section '{}' is marked @expect_encrypted but has {:.2} bits of entropy per byte",
+                        name, entropy
+                    ),
+                    location: None,
+                });
+            }
+            if stats.max_zero_run() >= MAX_ZERO_RUN {
+                self.warnings.push(DelbinWarning {
+                    code: WarningCode::W05001,
+                    message: format!(
+                        "Section '{}' is marked @expect_encrypted but contains a run of {} zero bytes",
+                        name,
+                        stats.max_zero_run()
+                    ),
+                    location: None,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// E04007 once the deadline has passed
+    fn check_deadline(&self) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(DelbinError::new(
+                ErrorCode::E04007,
+                "Evaluation exceeded its time budget",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Generate a struct whose aligned size is already known
+    fn eval_sized(&mut self, struct_def: &StructDef, aligned_size: usize) -> Result<Vec<u8>> {
+        self.output.clear();
+        self.pending.clear();
+        self.current_offset = 0;
         self.struct_size = Some(aligned_size);
 
         // Second pass: generate data
-        self.eval_struct(&file.struct_def)?;
+        self.eval_struct(struct_def)?;
 
         // Pad to aligned size
         while self.output.len() < aligned_size {
@@ -88,14 +496,204 @@ impl Evaluator {
         // Process pending fields
         self.process_pending()?;
 
+        // Encrypt last, so checksums cover the plaintext
+        self.apply_encryption(struct_def.encrypt.iter())?;
+
         Ok(std::mem::take(&mut self.output))
     }
 
+    /// Apply file-level settings: `@inputs` defaults and endianness
+    fn prepare(&mut self, file: &File) -> Result<()> {
+        self.defaults.clear();
+        for input in &file.inputs {
+            if let (Some(default), false) = (&input.default, self.env.contains_key(&input.name)) {
+                let value = match (default, input.ty) {
+                    (Expr::String(s), InputType::Bytes) => Value::Bytes(s.as_bytes().to_vec()),
+                    (Expr::String(s), _) => Value::String(s.clone()),
+                    (Expr::Number(n), _) => Value::U64(*n),
+                    _ => continue,
+                };
+                self.defaults.insert(input.name.clone(), value);
+            }
+        }
+        self.endian = self.resolve_endian(file)?;
+        self.base = file.base;
+        self.slot_size = file.slot_size;
+        self.derive_sections(file)
+    }
+
+    /// Compute the `@section` transforms whose source section is available
+    ///
+    /// Later definitions may use earlier ones as their source.
+    fn derive_sections(&mut self, file: &File) -> Result<()> {
+        self.derived.clear();
+        self.compressed.clear();
+        for def in &file.sections {
+            self.check_deadline()?;
+            let Expr::Call { name, args } = &def.transform else { continue };
+            let Some(Expr::SectionRef(source)) = args.first() else { continue };
+            let Some(len) = self.section_data().len(source) else { continue };
+            let mut data = Vec::with_capacity(len as usize);
+            self.feed_section(source, &mut |chunk| data.extend_from_slice(chunk))?;
+            match name.as_str() {
+                "whiten" => {
+                    let [_, poly, seed] = args.as_slice() else {
+                        return Err(DelbinError::new(
+                            ErrorCode::E04004,
+                            "@whiten() requires 3 arguments: section, poly, seed",
+                        ));
+                    };
+                    let poly = self.eval_expr(poly)?;
+                    let seed = self.eval_expr(seed)?;
+                    builtin::whiten(&mut data, poly, seed)?;
+                }
+                "merkle_tree" => {
+                    let (_, block) = self.merkle_params(name, args)?;
+                    let mut tree = MerkleBuilder::new(block);
+                    tree.update(&data);
+                    data = tree.finish().into_iter().flatten().flatten().collect();
+                }
+                "encrypt" => {
+                    let bound = bind_args(name, args, &["data", "cipher", "key", "iv"])?;
+                    match bound[1] {
+                        Some(Expr::String(cipher)) if cipher == "aes_ctr" => {}
+                        Some(Expr::String(cipher)) => {
+                            return Err(DelbinError::new(
+                                ErrorCode::E04003,
+                                format!("Unknown cipher: '{}'. Supported: aes_ctr", cipher),
+                            ))
+                        }
+                        _ => {
+                            return Err(DelbinError::new(
+                                ErrorCode::E04004,
+                                "@encrypt() requires a cipher, e.g. @encrypt(image, aes_ctr, key = ${KEY})",
+                            ))
+                        }
+                    }
+                    let key = bound[2].ok_or_else(|| {
+                        DelbinError::new(ErrorCode::E04004, "@encrypt() requires key = ${KEY}")
+                    })?;
+                    let key = self.secret_var(key, "@encrypt key")?;
+                    let iv = bound[3].ok_or_else(|| builtin::missing_iv("@encrypt IV"))?;
+                    let iv = self.aes_iv(iv, "@encrypt IV")?;
+                    builtin::aes_ctr(key.expose(), &iv, &mut data)?;
+                }
+                "compress" => {
+                    let bound = bind_args(name, args, &["data", "algorithm"])?;
+                    let Some(Expr::String(algorithm)) = bound[1] else {
+                        return Err(DelbinError::new(
+                            ErrorCode::E04004,
+                            "@compress() requires an algorithm, e.g. @compress(image, lz4)",
+                        ));
+                    };
+                    data = builtin::compress(algorithm, &data)?;
+                    self.compressed.entry(source.clone()).or_default().push(def.name.clone());
+                }
+                other => {
+                    return Err(DelbinError::new(
+                        ErrorCode::E02004,
+                        format!("Unknown section transform: @{}", other),
+                    ))
+                }
+            }
+            self.derived.insert(def.name.clone(), data);
+        }
+        Ok(())
+    }
+
+    /// Caller-provided sections plus the derived ones
+    pub(crate) fn section_data(&self) -> Overlay<'_> {
+        Overlay::new(&self.derived, self.sections)
+    }
+
+    /// Check `env` against the `@inputs` schema, reporting every problem at once
+    fn check_inputs(&self, file: &File) -> Result<()> {
+        let mut problems = Vec::new();
+        for input in &file.inputs {
+            let Some(value) = self.env.get(&input.name) else {
+                let default_ok = match (&input.default, input.ty) {
+                    (None, _) => true,
+                    (Some(Expr::Number(n)), InputType::Int(scalar)) => int_input_fits(scalar, &Value::U64(*n)),
+                    (Some(Expr::String(_)), InputType::Str | InputType::Bytes) => true,
+                    _ => false,
+                };
+                if input.required {
+                    problems.push(format!("{}: required input is missing", input.name));
+                } else if !default_ok {
+                    problems.push(format!(
+                        "{}: default does not match type {}",
+                        input.name,
+                        input.ty.name()
+                    ));
+                }
+                continue;
+            };
+            let ok = match input.ty {
+                InputType::Int(scalar) => int_input_fits(scalar, value),
+                InputType::Str => value.as_string().is_some(),
+                InputType::Bytes => value.as_bytes().is_some() || value.as_secret().is_some(),
+            };
+            if !ok {
+                problems.push(format!(
+                    "{}: expected {}, got {}",
+                    input.name,
+                    input.ty.name(),
+                    describe_value(value)
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(DelbinError::new(
+            ErrorCode::E02005,
+            format!("Invalid inputs:\n  - {}", problems.join("\n  - ")),
+        ))
+    }
+
+    /// Look up an env variable, falling back to `@inputs` defaults
+    ///
+    /// `name` may index into lists: `PARTITIONS[1][0]`.
+    fn env_var(&self, name: &str) -> Result<&Value> {
+        lookup_var(self.env, name)
+            .or_else(|| lookup_var(&self.defaults, name))
+            .ok_or_else(|| {
+                DelbinError::new(ErrorCode::E02001, format!("Undefined variable: {}", name))
+            })
+    }
+
+    /// Endianness of `file`, reading `@endian = ${VAR}` from the environment
+    fn resolve_endian(&self, file: &File) -> Result<Endian> {
+        let Some(name) = &file.endian_var else {
+            return Ok(file.endian);
+        };
+        let value = self.env_var(name)?;
+        match value.as_string() {
+            Some("little") => Ok(Endian::Little),
+            Some("big") => Ok(Endian::Big),
+            _ => Err(DelbinError::new(
+                ErrorCode::E03001,
+                format!("Variable '{}' must be \"little\" or \"big\" for @endian", name),
+            )),
+        }
+    }
+
     /// Get warnings
     pub fn warnings(&self) -> &[DelbinWarning] {
         &self.warnings
     }
 
+    /// Byte order in effect (after `@endian = ${VAR}` is resolved)
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Offsets and sizes of the fields written by the last `eval()`
+    pub fn field_map(&self) -> &[FieldLayout] {
+        &self.field_map
+    }
+
     /// Parse raw binary bytes according to the struct layout.
     ///
     /// Returns a map of field name → typed `Value`.
@@ -104,7 +702,8 @@ impl Evaluator {
         file: &File,
         data: &[u8],
     ) -> Result<HashMap<String, Value>> {
-        self.endian = file.endian;
+        let file = &*unroll(file, self.env)?;
+        self.prepare(file)?;
         // Populate field_offsets without clearing them at the end
         self.compute_field_layout(&file.struct_def)?;
 
@@ -112,42 +711,474 @@ impl Evaluator {
         let mut offset = 0usize;
 
         for field in &file.struct_def.fields {
-            let size = self.field_size_for_parse(&field.ty)?;
-            let value = self.extract_field_bytes(&field.ty, data, offset)?;
-            result.insert(field.name.clone(), value);
+            let size = self.field_size_for_parse(field)?;
+            let value = self.extract_field_bytes(field, data, offset)?;
+            insert_decoded(&mut result, field, value);
             offset += size;
         }
         Ok(result)
     }
 
+    /// Decode a single field, reading only its bytes
+    ///
+    /// `data` needs to extend only to the end of the field. `name` may be
+    /// one of the field's aliases.
+    pub fn extract_field(&mut self, file: &File, data: &[u8], name: &str) -> Result<Value> {
+        let file = &*unroll(file, self.env)?;
+        self.prepare(file)?;
+        self.compute_field_layout(&file.struct_def)?;
+        let field = file
+            .struct_def
+            .fields
+            .iter()
+            .find(|f| f.name == name || f.aliases.iter().any(|a| a == name))
+            .ok_or_else(|| {
+                DelbinError::new(ErrorCode::E02002, format!("Undefined field: {}", name))
+            })?;
+        let offset = self.field_offsets[&field.name];
+        self.extract_field_bytes(field, data, offset)
+    }
+
+    /// Parse as many fields as fit in `data`, which may be a truncated prefix.
+    ///
+    /// Decoding stops at the first field that extends past the end of `data`;
+    /// that field and all following ones are reported as unresolved.
+    pub fn parse_prefix(&mut self, file: &File, data: &[u8]) -> Result<PartialDecode> {
+        let file = &*unroll(file, self.env)?;
+        self.prepare(file)?;
+        let raw_size = self.compute_field_layout(&file.struct_def)?;
+
+        let mut result = PartialDecode {
+            struct_size: aligned_struct_size(&file.struct_def, raw_size),
+            ..Default::default()
+        };
+        for field in &file.struct_def.fields {
+            let offset = self.field_offsets[&field.name];
+            if result.stopped_at.is_none() {
+                let size = self.field_size_for_parse(field)?;
+                if offset + size <= data.len() {
+                    let value = self.extract_field_bytes(field, data, offset)?;
+                    insert_decoded(&mut result.fields, field, value);
+                    continue;
+                }
+                result.stopped_at = Some(offset);
+            }
+            result.unresolved.push(field.name.clone());
+        }
+        Ok(result)
+    }
+
+    /// Decode raw binary bytes and validate them against the struct definition.
+    ///
+    /// Constant-initialized fields (magic values, literals) must match, size and
+    /// offset fields must agree with the layout, and `@sizeof(section)` fields
+    /// must fit in the bytes following the header. Problems are collected into
+    /// the report instead of failing, so untrusted input can be screened.
+    pub fn validate_bytes(&mut self, file: &File, data: &[u8]) -> Result<ValidationReport> {
+        let file = &*unroll(file, self.env)?;
+        self.prepare(file)?;
+        let raw_size = self.compute_field_layout(&file.struct_def)?;
+        let struct_size = aligned_struct_size(&file.struct_def, raw_size);
+        self.struct_size = Some(struct_size);
+        let payload_len = data.len().saturating_sub(struct_size);
+
+        let mut report = ValidationReport::default();
+        for field in &file.struct_def.fields {
+            let offset = self.field_offsets[&field.name];
+            let size = self.field_size_for_parse(field)?;
+            if offset + size > data.len() {
+                report.issues.push(ValidationIssue {
+                    kind: IssueKind::Truncated,
+                    field: field.name.clone(),
+                    message: format!(
+                        "Data too short: field '{}' at offset {} needs {} bytes, only {} remain",
+                        field.name,
+                        offset,
+                        size,
+                        data.len().saturating_sub(offset)
+                    ),
+                });
+                break;
+            }
+
+            let value = self.extract_field_bytes(field, data, offset)?;
+            if let Some(init) = &field.init {
+                let raw = &data[offset..offset + size];
+                if let Some(issue) = self.check_decoded_field(field, init, offset, raw, &value, payload_len) {
+                    report.issues.push(issue);
+                }
+            }
+            insert_decoded(&mut report.fields, field, value);
+        }
+        Ok(report)
+    }
+
+    /// Check one decoded field against its initializer
+    fn check_decoded_field(
+        &mut self,
+        field: &FieldDef,
+        init: &Expr,
+        offset: usize,
+        raw: &[u8],
+        value: &Value,
+        payload_len: usize,
+    ) -> Option<ValidationIssue> {
+        // Checksums are recomputed by verification, not here
+        if self.is_self_referencing(init, &field.name) {
+            return None;
+        }
+
+        let is_layout_field = matches!(
+            init,
+            Expr::Call { name, .. } if name == "sizeof" || name == "offsetof"
+        );
+
+        // Section size fields must fit in the payload that follows the header
+        if let Expr::Call { name, args } = init {
+            if let (true, [Expr::SectionRef(section)]) = (name == "sizeof", args.as_slice()) {
+                let size = value.as_u64().unwrap_or(0);
+                if size > payload_len as u64 {
+                    return Some(ValidationIssue {
+                        kind: IssueKind::OutOfBounds,
+                        field: field.name.clone(),
+                        message: format!(
+                            "Field '{}' claims section '{}' is {} bytes, but only {} bytes follow the header",
+                            field.name, section, size, payload_len
+                        ),
+                    });
+                }
+                return None;
+            }
+        }
+
+        // Anything that evaluates without env or sections is a constant
+        self.current_field = Some(field.name.clone());
+        self.current_offset = offset;
+        let expected = self.eval_init(field, init);
+        self.current_field = None;
+
+        match expected {
+            Ok(expected) if expected != raw => Some(ValidationIssue {
+                kind: if is_layout_field {
+                    IssueKind::OutOfBounds
+                } else {
+                    IssueKind::ConstantMismatch
+                },
+                field: field.name.clone(),
+                message: format!(
+                    "Field '{}' is {} but the DSL expects {}",
+                    field.name,
+                    to_hex_string(raw),
+                    to_hex_string(&expected)
+                ),
+            }),
+            _ => None,
+        }
+    }
+
     /// Compute field offsets, keeping them in `field_offsets` after the scan.
-    fn compute_field_layout(&mut self, struct_def: &StructDef) -> Result<()> {
+    ///
+    /// Returns the raw (unaligned) struct size.
+    fn compute_field_layout(&mut self, struct_def: &StructDef) -> Result<usize> {
         let mut offset = 0usize;
-        for field in &struct_def.fields {
+        for (index, field) in struct_def.fields.iter().enumerate() {
+            crate::check::check_array_length(struct_def, index)?;
             self.current_field = Some(field.name.clone());
             self.field_offsets.insert(field.name.clone(), offset);
-            let size = self.calculate_field_size(&field.ty)?;
+            let size = self.calculate_field_size(field)?;
             offset += size;
         }
         self.current_field = None;
         self.current_offset = 0;
+        Ok(offset)
+    }
+
+    /// Compute field offsets and sizes without generating data
+    ///
+    /// Needs only what array lengths refer to (env variables, and sections
+    /// if a length uses `@sizeof(section)`).
+    pub fn layout(&mut self, file: &File) -> Result<Layout> {
+        let file = &*unroll(file, self.env)?;
+        self.prepare(file)?;
+        let raw_size = self.compute_field_layout(&file.struct_def)?;
+        let mut fields = Vec::with_capacity(file.struct_def.fields.len());
+        for field in &file.struct_def.fields {
+            let size = self.field_size_for_parse(field)?;
+            fields.push(field_layout(field, self.field_offsets[&field.name], size));
+        }
+        let size = aligned_struct_size(&file.struct_def, raw_size);
+        check_reserved_size(file, size)?;
+        Ok(Layout {
+            name: file.struct_def.name.clone(),
+            fields,
+            size,
+        })
+    }
+
+    /// Encoded bytes of every field whose value doesn't depend on env
+    /// variables, sections or checksums (magic numbers, format versions,
+    /// offsets), in layout order
+    pub fn constant_fields(&mut self, file: &File) -> Result<Vec<(String, Vec<u8>)>> {
+        let file = &*unroll(file, self.env)?;
+        self.prepare(file)?;
+        let raw_size = self.compute_field_layout(&file.struct_def)?;
+        self.struct_size = Some(aligned_struct_size(&file.struct_def, raw_size));
+
+        let mut constants = Vec::new();
+        for field in &file.struct_def.fields {
+            let Some(init) = &field.init else { continue };
+            if depends_on_inputs(init) {
+                continue;
+            }
+            self.current_field = Some(field.name.clone());
+            self.current_offset = self.field_offsets[&field.name];
+            let bytes = self.eval_init(field, init)?;
+            constants.push((field.name.clone(), bytes));
+        }
+        self.current_field = None;
+        self.current_offset = 0;
+        Ok(constants)
+    }
+
+    /// Recompute checksums and constant fields of an existing header
+    ///
+    /// Checksums over `@self` are recomputed from `header` the way `eval()`
+    /// computes them: with every deferred field zeroed until it is filled in.
+    /// Fields that read env variables are checked against the env when it
+    /// holds every variable they read (defaults of `@inputs` don't count);
+    /// other fields that depend on the env or section sizes are not checked.
+    /// `@encrypt_range` parts are decrypted first, with the key from the env.
+    /// A file that doesn't meet its own `@policy` is an E02007 error.
+    pub fn verify(&mut self, file: &File, header: &[u8]) -> Result<VerifyReport> {
+        let file = &*unroll(file, self.env)?;
+        crate::check::check_policy(file)?;
+        self.prepare(file)?;
+        let raw_size = self.compute_field_layout(&file.struct_def)?;
+        let struct_size = aligned_struct_size(&file.struct_def, raw_size);
+        self.struct_size = Some(struct_size);
+        if header.len() < struct_size {
+            return Err(DelbinError::new(
+                ErrorCode::E04002,
+                format!(
+                    "Data too short: header needs {} bytes, only {} given",
+                    struct_size,
+                    header.len()
+                ),
+            ));
+        }
+        self.output = header[..struct_size].to_vec();
+        self.apply_encryption(file.struct_def.encrypt.iter().rev())?;
+        let header = self.output.clone();
+
+        let mut report = VerifyReport::default();
+        let deferred = self.deferred_fields(&file.struct_def)?;
+        for pending in &deferred {
+            self.output[pending.offset..pending.offset + pending.size].fill(0);
+        }
+        for field in &file.struct_def.fields {
+            let Some(init) = &field.init else { continue };
+            let kind = match init {
+                _ if self.is_self_referencing(init, &field.name) || is_signature(init) => continue,
+                Expr::Call { name, .. } if is_range_based_builtin(name) => CheckKind::Checksum,
+                _ if depends_on_inputs(init) => {
+                    let mut vars = Vec::new();
+                    env_vars(init, &mut vars);
+                    if vars.is_empty() || !vars.iter().all(|var| lookup_var(self.env, var).is_some()) {
+                        continue;
+                    }
+                    CheckKind::Input
+                }
+                _ => CheckKind::Constant,
+            };
+            let offset = self.field_offsets[&field.name];
+            let size = self.field_size_for_parse(field)?;
+            self.current_field = Some(field.name.clone());
+            self.current_offset = offset;
+            let expected = self.eval_init(field, init)?;
+            report.checks.push(FieldCheck {
+                field: field.name.clone(),
+                kind,
+                expected,
+                actual: header[offset..offset + size].to_vec(),
+            });
+        }
+        self.current_field = None;
+        self.current_offset = 0;
+
+        for pending in deferred {
+            let range = pending.offset..pending.offset + pending.size;
+            if is_signature(&pending.expr) {
+                self.output[range.clone()].copy_from_slice(&header[range]);
+                continue;
+            }
+            let expected = self.eval_pending_field(&pending)?;
+            // Later checksums see this field as eval() wrote it
+            self.output[range.clone()].copy_from_slice(&header[range.clone()]);
+            report.checks.push(FieldCheck {
+                field: pending.name,
+                kind: CheckKind::Checksum,
+                expected,
+                actual: header[range].to_vec(),
+            });
+        }
+        self.output.clear();
+        Ok(report)
+    }
+
+    /// Overwrite fields of an existing header and re-derive its checksums
+    ///
+    /// Integers must fit the field type (negative values are sign-extended);
+    /// byte and string values may be shorter than an array field and are
+    /// zero-padded. Afterwards every checksum over `@self` is recomputed in
+    /// the order `eval()` computes them. Checksums over sections are left
+    /// as they are.
+    pub fn patch(
+        &mut self,
+        file: &File,
+        data: &mut [u8],
+        updates: &HashMap<String, Value>,
+    ) -> Result<()> {
+        let file = &*unroll(file, self.env)?;
+        self.prepare(file)?;
+        let raw_size = self.compute_field_layout(&file.struct_def)?;
+        let struct_size = aligned_struct_size(&file.struct_def, raw_size);
+        self.struct_size = Some(struct_size);
+        if data.len() < struct_size {
+            return Err(DelbinError::new(
+                ErrorCode::E04002,
+                format!(
+                    "Data too short: header needs {} bytes, only {} given",
+                    struct_size,
+                    data.len()
+                ),
+            ));
+        }
+        if let Some(name) = updates.keys().find(|n| !self.field_offsets.contains_key(*n)) {
+            return Err(DelbinError::new(
+                ErrorCode::E02002,
+                format!("Undefined field: {}", name),
+            ));
+        }
+        self.output = data[..struct_size].to_vec();
+
+        // Fields are written in layout order so errors don't depend on map order
+        for field in &file.struct_def.fields {
+            let Some(value) = updates.get(&field.name) else { continue };
+            let offset = self.field_offsets[&field.name];
+            let size = self.field_size_for_parse(field)?;
+            let bytes = self.encode_update(field, value, size)?;
+            self.output[offset..offset + size].copy_from_slice(&bytes);
+        }
+
+        let deferred = self.deferred_fields(&file.struct_def)?;
+        for pending in &deferred {
+            self.output[pending.offset..pending.offset + pending.size].fill(0);
+        }
+        for pending in &deferred {
+            let bytes = self.eval_pending_field(pending)?;
+            self.output[pending.offset..pending.offset + bytes.len()].copy_from_slice(&bytes);
+        }
+
+        data[..struct_size].copy_from_slice(&self.output);
+        self.output.clear();
         Ok(())
     }
 
+    /// Encode a patch value for `field`, which is `size` bytes long
+    fn encode_update(&self, field: &FieldDef, value: &Value, size: usize) -> Result<Vec<u8>> {
+        let mismatch = |expected: &str| {
+            DelbinError::new(
+                ErrorCode::E03001,
+                format!(
+                    "Field '{}' expects {}, got {}",
+                    field.name,
+                    expected,
+                    describe_value(value)
+                ),
+            )
+        };
+        match field.ty {
+            Type::Scalar(scalar) => {
+                let v = value.as_u64().ok_or_else(|| mismatch("an integer"))?;
+                let mask = scalar.bit_mask();
+                let bits = scalar.size() * 8;
+                let sign_extended = v | mask == u64::MAX && (v >> (bits - 1)) & 1 == 1;
+                if v & !mask != 0 && !sign_extended {
+                    return Err(DelbinError::new(
+                        ErrorCode::E03003,
+                        format!("Value 0x{:X} does not fit {} field '{}'", v, scalar.name(), field.name),
+                    ));
+                }
+                Ok(self.scalar_to_bytes(scalar, v))
+            }
+            Type::Array { .. } | Type::Inferred => {
+                let bytes = value
+                    .as_bytes()
+                    .or_else(|| value.as_string().map(str::as_bytes))
+                    .ok_or_else(|| mismatch("bytes or a string"))?;
+                if bytes.len() > size {
+                    return Err(DelbinError::new(
+                        ErrorCode::E03005,
+                        format!(
+                            "Value is {} bytes but field '{}' holds {}",
+                            bytes.len(),
+                            field.name,
+                            size
+                        ),
+                    ));
+                }
+                let mut out = bytes.to_vec();
+                out.resize(size, 0);
+                Ok(out)
+            }
+        }
+    }
+
+    /// Checksum fields over `@self`, in the order `eval()` fills them in
+    fn deferred_fields(&mut self, struct_def: &StructDef) -> Result<Vec<PendingField>> {
+        let mut deferred = Vec::new();
+        for field in &struct_def.fields {
+            let Some(init) = &field.init else { continue };
+            if self.is_self_referencing(init, &field.name) {
+                deferred.push(PendingField {
+                    name: field.name.clone(),
+                    offset: self.field_offsets[&field.name],
+                    size: self.field_size_for_parse(field)?,
+                    expr: init.clone(),
+                    ty: field.ty.clone(),
+                    location: field.location.clone(),
+                });
+            }
+        }
+        Ok(deferred)
+    }
+
     /// Get the byte size of a field type for parsing (uses eval_expr for dynamic lengths)
-    fn field_size_for_parse(&mut self, ty: &Type) -> Result<usize> {
-        match ty {
+    fn field_size_for_parse(&mut self, field: &FieldDef) -> Result<usize> {
+        match &field.ty {
             Type::Scalar(s) => Ok(s.size()),
             Type::Array { elem, len } => {
-                let n = self.eval_expr(len)? as usize;
-                Ok(elem.size() * n)
+                let n = self.eval_expr(len)?;
+                array_bytes(field, *elem, n)
             }
+            Type::Inferred => self.inferred_size(field),
         }
     }
 
+    /// Length of a `[u8; _]` field: that of the bytes its initializer produces
+    fn inferred_size(&mut self, field: &FieldDef) -> Result<usize> {
+        let init = field.init.as_ref().ok_or_else(|| {
+            DelbinError::new(
+                ErrorCode::E03001,
+                format!("Field '{}' is [u8; _] and needs an initializer to take its length from", field.name),
+            )
+        })?;
+        Ok(self.natural_bytes(init)?.len())
+    }
+
     /// Extract a field value from binary data at the given offset
-    fn extract_field_bytes(&mut self, ty: &Type, data: &[u8], offset: usize) -> Result<Value> {
-        match ty {
+    fn extract_field_bytes(&mut self, field: &FieldDef, data: &[u8], offset: usize) -> Result<Value> {
+        match &field.ty {
             Type::Scalar(scalar) => {
                 let size = scalar.size();
                 if offset + size > data.len() {
@@ -163,9 +1194,8 @@ impl Evaluator {
                 }
                 Ok(self.scalar_bytes_to_value(*scalar, &data[offset..offset + size]))
             }
-            Type::Array { elem, len } => {
-                let n = self.eval_expr(len)? as usize;
-                let size = elem.size() * n;
+            Type::Array { .. } | Type::Inferred => {
+                let size = self.field_size_for_parse(field)?;
                 if offset + size > data.len() {
                     return Err(DelbinError::new(
                         ErrorCode::E04002,
@@ -233,11 +1263,13 @@ impl Evaluator {
     fn calculate_struct_size(&mut self, struct_def: &StructDef) -> Result<usize> {
         let mut offset = 0;
 
-        for field in &struct_def.fields {
+        for (index, field) in struct_def.fields.iter().enumerate() {
+            self.check_deadline()?;
+            crate::check::check_array_length(struct_def, index)?;
             self.current_field = Some(field.name.clone());
             self.field_offsets.insert(field.name.clone(), offset);
 
-            let size = self.calculate_field_size(&field.ty)?;
+            let size = self.calculate_field_size(field)?;
             offset += size;
         }
 
@@ -249,20 +1281,25 @@ impl Evaluator {
     }
 
     /// Calculate field size
-    fn calculate_field_size(&mut self, ty: &Type) -> Result<usize> {
-        match ty {
+    fn calculate_field_size(&mut self, field: &FieldDef) -> Result<usize> {
+        match &field.ty {
             Type::Scalar(scalar) => Ok(scalar.size()),
             Type::Array { elem, len } => {
                 // Temporarily set current_offset for @offsetof self-reference
                 self.current_offset = *self.field_offsets.get(self.current_field.as_ref().unwrap()).unwrap();
                 let len_val = self.eval_expr(len)?;
-                Ok(elem.size() * len_val as usize)
+                array_bytes(field, *elem, len_val)
+            }
+            Type::Inferred => {
+                self.current_offset = self.field_offsets[&field.name];
+                self.inferred_size(field)
             }
         }
     }
 
     /// Evaluate struct
     fn eval_struct(&mut self, struct_def: &StructDef) -> Result<()> {
+        self.field_map.clear();
         for field in &struct_def.fields {
             self.eval_field(field)?;
         }
@@ -271,10 +1308,32 @@ impl Evaluator {
 
     /// Evaluate field
     fn eval_field(&mut self, field: &FieldDef) -> Result<()> {
+        let first_warning = self.warnings.len();
+        let result = self.eval_field_at(field);
+        self.locate(field.location.as_ref(), first_warning, result)
+    }
+
+    /// Attach `location` to the warnings from `first_warning` on and to the
+    /// error in `result`, where they have none
+    fn locate<T>(&mut self, location: Option<&SourceLocation>, first_warning: usize, result: Result<T>) -> Result<T> {
+        let Some(location) = location else {
+            return result;
+        };
+        for warning in &mut self.warnings[first_warning..] {
+            warning.location.get_or_insert_with(|| location.clone());
+        }
+        result.map_err(|e| match e.location {
+            None => e.with_location(location.clone()),
+            Some(_) => e,
+        })
+    }
+
+    fn eval_field_at(&mut self, field: &FieldDef) -> Result<()> {
+        self.check_deadline()?;
         self.current_field = Some(field.name.clone());
         self.field_offsets.insert(field.name.clone(), self.current_offset);
 
-        let size = self.get_field_size(&field.ty)?;
+        let size = self.get_field_size(field)?;
 
         if let Some(init) = &field.init {
             if self.is_self_referencing(init, &field.name) {
@@ -287,18 +1346,24 @@ impl Evaluator {
                     size,
                     expr: init.clone(),
                     ty: field.ty.clone(),
+                    location: field.location.clone(),
                 });
             } else {
                 // Normal field, evaluate directly
-                let bytes = self.eval_field_value(&field.ty, init)?;
+                let bytes = self.eval_init(field, init)?;
                 self.output.extend_from_slice(&bytes);
             }
         } else {
-            // No initialization, fill with 0
-            let zeros = vec![0u8; size];
-            self.output.extend_from_slice(&zeros);
+            // No initialization, fill with 0 (or the metadata record)
+            let mut bytes = vec![0u8; size];
+            if let Some((_, record)) = self.metadata.as_ref().filter(|(name, r)| *name == field.name && r.len() <= size) {
+                bytes[..record.len()].copy_from_slice(record);
+            }
+            self.output.extend_from_slice(&bytes);
         }
 
+        self.field_map
+            .push(field_layout(field, self.current_offset, size));
         self.current_offset += size;
         self.current_field = None;
 
@@ -306,27 +1371,84 @@ impl Evaluator {
     }
 
     /// Get field size
-    fn get_field_size(&mut self, ty: &Type) -> Result<usize> {
-        match ty {
+    fn get_field_size(&mut self, field: &FieldDef) -> Result<usize> {
+        match &field.ty {
             Type::Scalar(scalar) => Ok(scalar.size()),
             Type::Array { elem, len } => {
                 let len_val = self.eval_expr(len)?;
-                Ok(elem.size() * len_val as usize)
+                array_bytes(field, *elem, len_val)
             }
+            Type::Inferred => self.inferred_size(field),
         }
     }
 
     /// Check if expression must be deferred to the pending phase.
-    /// Deferred when a range-based builtin (@crc32, @sha256) references @self data.
+    /// Deferred when a range-based builtin (@crc32, @sha256) or a custom
+    /// function references @self data.
     fn is_self_referencing(&self, expr: &Expr, _field_name: &str) -> bool {
         match expr {
-            Expr::Call { name, args } if is_range_based_builtin(name) => {
+            Expr::Call { name, args }
+                if is_range_based_builtin(name) || self.functions.contains_key(name) =>
+            {
                 args.iter().any(arg_refers_to_self)
             }
             _ => false,
         }
     }
 
+    /// Contents of the file `@file(path)` names
+    ///
+    /// With a file root, `path` must be relative without `..` and stay
+    /// inside the root once symlinks are resolved.
+    fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let read_err = |e: std::io::Error| {
+            let code = match e.kind() {
+                std::io::ErrorKind::NotFound => ErrorCode::E05001,
+                _ => ErrorCode::E05002,
+            };
+            DelbinError::new(code, format!("Cannot read @file(\"{}\"): {}", path, e))
+        };
+        let Some(root) = &self.file_root else {
+            return std::fs::read(path).map_err(read_err);
+        };
+        let outside = || {
+            DelbinError::new(
+                ErrorCode::E04003,
+                format!("@file(\"{}\") is outside the file root", path),
+            )
+            .with_hint("use a path relative to the directory passed to with_file_root()")
+        };
+        let confined = Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !confined {
+            return Err(outside());
+        }
+        let root = root.canonicalize().map_err(read_err)?;
+        let full = root.join(path).canonicalize().map_err(read_err)?;
+        if !full.starts_with(&root) {
+            return Err(outside());
+        }
+        std::fs::read(full).map_err(read_err)
+    }
+
+    /// Evaluate `field`'s value from `init`; a TLV length that does not fit
+    /// its type is an error, as the record could not be read back
+    fn eval_init(&mut self, field: &FieldDef, init: &Expr) -> Result<Vec<u8>> {
+        let (true, &Type::Scalar(scalar)) = (field.tlv_len, &field.ty) else {
+            return self.eval_field_value(&field.ty, init);
+        };
+        let len = self.eval_expr(init)?;
+        if len & !scalar.bit_mask() != 0 {
+            return Err(DelbinError::new(
+                ErrorCode::E03001,
+                format!("TLV value is {} bytes, too long for {} length field '{}'", len, scalar.name(), field.name),
+            )
+            .with_hint(format!("use a wider length type than {}", scalar.name())));
+        }
+        Ok(self.scalar_to_bytes(scalar, len))
+    }
+
     /// Evaluate field value
     fn eval_field_value(&mut self, ty: &Type, init: &Expr) -> Result<Vec<u8>> {
         match ty {
@@ -354,28 +1476,216 @@ impl Evaluator {
                             return Err(DelbinError::new(
                                 ErrorCode::E03001,
                                 format!(
-                                    "@bytes() returns u8 data but field element type is {}",
-                                    format!("{:?}", elem).to_lowercase()
+                                    "@bytes() returns u8 data but field element type is {}",
+                                    format!("{:?}", elem).to_lowercase()
+                                ),
+                            ));
+                        }
+                        if args.len() != 1 {
+                            return Err(DelbinError::new(
+                                ErrorCode::E04004,
+                                "@bytes() requires exactly 1 argument",
+                            ));
+                        }
+                        let s = self.eval_string(&args[0])?;
+                        let (bytes, warning) = builtin::bytes(&s, len_val * elem.size());
+                        if let Some(w) = warning {
+                            self.warnings.push(w);
+                        }
+                        Ok(bytes)
+                    }
+                    Expr::Call { name, .. } if name == "uleb128" || name == "sleb128" => {
+                        // Padded with redundant continuation bytes to fill the field
+                        if *elem != crate::types::ScalarType::U8 {
+                            return Err(DelbinError::new(
+                                ErrorCode::E03001,
+                                format!("@{}() returns u8 data but field element type is {}", name, elem.name()),
+                            ));
+                        }
+                        let bytes = self.natural_bytes(init)?;
+                        let negative = bytes.last().is_some_and(|last| name == "sleb128" && last & 0x40 != 0);
+                        let produced = bytes.len();
+                        builtin::pad_leb128(bytes, len_val, negative).ok_or_else(|| {
+                            DelbinError::new(
+                                ErrorCode::E03001,
+                                format!("@{}() produces {} bytes but the field is {} bytes", name, produced, len_val),
+                            )
+                        })
+                    }
+                    Expr::Call { name, args } if name == "hex" || name == "base64" => {
+                        // u8 arrays only; hex spells out every byte, so it must fill the
+                        // field exactly, base64 is zero-filled or truncated like @bytes()
+                        if *elem != crate::types::ScalarType::U8 {
+                            return Err(DelbinError::new(
+                                ErrorCode::E03001,
+                                format!("@{}() returns u8 data but field element type is {}", name, elem.name()),
+                            ));
+                        }
+                        if args.len() != 1 {
+                            return Err(DelbinError::new(
+                                ErrorCode::E04004,
+                                format!("@{}() requires exactly 1 argument", name),
+                            ));
+                        }
+                        let text = self.eval_string(&args[0])?;
+                        let mut bytes = match name.as_str() {
+                            "hex" => builtin::hex(&text)?,
+                            _ => builtin::base64(&text)?,
+                        };
+                        if name == "hex" && bytes.len() != len_val {
+                            return Err(DelbinError::new(
+                                ErrorCode::E03001,
+                                format!("@hex() is {} bytes but the field is {} bytes", bytes.len(), len_val),
+                            ));
+                        }
+                        if bytes.len() > len_val {
+                            self.warnings.push(DelbinWarning {
+                                code: crate::error::WarningCode::W03001,
+                                message: format!(
+                                    "@{}() data truncated from {} to {} bytes",
+                                    name,
+                                    bytes.len(),
+                                    len_val
+                                ),
+                                location: None,
+                            });
+                        }
+                        bytes.resize(len_val, 0);
+                        Ok(bytes)
+                    }
+                    Expr::Call { name, args } if name == "file" => {
+                        if *elem != crate::types::ScalarType::U8 {
+                            return Err(DelbinError::new(
+                                ErrorCode::E03001,
+                                format!("@file() returns u8 data but field element type is {}", elem.name()),
+                            ));
+                        }
+                        let (path, mode) = match args.as_slice() {
+                            [path] => (self.eval_string(path)?, None),
+                            [path, mode] => (self.eval_string(path)?, Some(self.eval_string(mode)?)),
+                            _ => {
+                                return Err(DelbinError::new(
+                                    ErrorCode::E04004,
+                                    "@file() requires 1 or 2 arguments",
+                                ))
+                            }
+                        };
+                        let data = self.read_file(&path)?;
+                        let (bytes, warning) = builtin::fit_file(&path, data, len_val, mode.as_deref())?;
+                        self.warnings.extend(warning);
+                        Ok(bytes)
+                    }
+                    Expr::Call { name, args } if ADDRESS_BUILTINS.contains(&name.as_str()) => {
+                        let bytes = self.address_of(name, args)?;
+                        if bytes.len() != len_val * elem.size() {
+                            return Err(DelbinError::new(
+                                ErrorCode::E03001,
+                                format!(
+                                    "@{}() produces {} bytes but the field is {} bytes",
+                                    name,
+                                    bytes.len(),
+                                    len_val * elem.size()
+                                ),
+                            ));
+                        }
+                        Ok(bytes)
+                    }
+                    Expr::Call { name, args } if name == "random" => {
+                        let [count] = args.as_slice() else {
+                            return Err(DelbinError::new(
+                                ErrorCode::E04004,
+                                "@random() requires exactly 1 argument",
+                            ));
+                        };
+                        let count = self.eval_expr(count)? as usize;
+                        let field_len = len_val * elem.size();
+                        if count > field_len {
+                            return Err(DelbinError::new(
+                                ErrorCode::E03001,
+                                format!("@random({}) produces {} bytes but the field is {} bytes", count, count, field_len),
+                            ));
+                        }
+                        let mut bytes = vec![0u8; field_len];
+                        self.random_fill("@random()", &mut bytes[..count])?;
+                        Ok(bytes)
+                    }
+                    Expr::Call { name, args } if name == "uuid" || name == "uuid5" => {
+                        let bytes = self.uuid_of(name, args)?;
+                        if bytes.len() != len_val * elem.size() {
+                            return Err(DelbinError::new(
+                                ErrorCode::E03001,
+                                format!("@{}() produces 16 bytes but the field is {} bytes", name, len_val * elem.size()),
+                            ));
+                        }
+                        Ok(bytes.to_vec())
+                    }
+                    Expr::Call { name, args } if DigestAlgorithm::by_name(name).is_some() => {
+                        self.digest_of(name, args, len_val * elem.size())
+                    }
+                    Expr::Call { name, args } if name == "hmac_sha256" => self.hmac_sha256_of(args),
+                    Expr::Call { name, args } if name == "merkle_root" => {
+                        let (data, block) = self.merkle_params(name, args)?;
+                        let mut tree = MerkleBuilder::new(block);
+                        self.feed_range_data(std::slice::from_ref(data), &mut |chunk| tree.update(chunk))?;
+                        Ok(tree.root().to_vec())
+                    }
+                    Expr::Call { name, args } if SIGNATURE_BUILTINS.contains(&name.as_str()) => {
+                        self.signature_field(name, args, len_val * elem.size())
+                    }
+                    Expr::Call { name, args } if name == "block_hashes" => {
+                        let (data, block) = self.merkle_params(name, args)?;
+                        let mut tree = MerkleBuilder::new(block);
+                        self.feed_range_data(std::slice::from_ref(data), &mut |chunk| tree.update(chunk))?;
+                        let hashes = tree.leaves().concat();
+                        if hashes.len() != len_val * elem.size() {
+                            return Err(DelbinError::new(
+                                ErrorCode::E03001,
+                                format!(
+                                    "@block_hashes() produced {} bytes but the field is {} bytes; \
+                                     size it as [u8; 32 * @numblocks(...)]",
+                                    hashes.len(),
+                                    len_val * elem.size()
+                                ),
+                            ));
+                        }
+                        Ok(hashes)
+                    }
+                    Expr::Call { name, args } if self.is_custom(name) => {
+                        let value = self.call_custom(name, args)?;
+                        let bytes = value.as_bytes().ok_or_else(|| {
+                            DelbinError::new(
+                                ErrorCode::E03001,
+                                format!("@{}() must return bytes to initialize an array field", name),
+                            )
+                        })?;
+                        if bytes.len() != len_val * elem.size() {
+                            return Err(DelbinError::new(
+                                ErrorCode::E03001,
+                                format!(
+                                    "@{}() returned {} bytes but the field is {} bytes",
+                                    name,
+                                    bytes.len(),
+                                    len_val * elem.size()
                                 ),
                             ));
                         }
-                        if args.len() != 1 {
+                        Ok(bytes.to_vec())
+                    }
+                    Expr::EnvVar(name) => {
+                        // Precomputed data such as a signature or UUID
+                        let bytes = self.env_bytes(name)?;
+                        if bytes.len() != len_val * elem.size() {
                             return Err(DelbinError::new(
-                                ErrorCode::E04004,
-                                "@bytes() requires exactly 1 argument",
+                                ErrorCode::E03001,
+                                format!(
+                                    "Variable '{}' holds {} bytes but the field is {} bytes",
+                                    name,
+                                    bytes.len(),
+                                    len_val * elem.size()
+                                ),
                             ));
                         }
-                        let s = self.eval_string(&args[0])?;
-                        let (bytes, warning) = builtin::bytes(&s, len_val * elem.size());
-                        if let Some(w) = warning {
-                            self.warnings.push(w);
-                        }
-                        Ok(bytes)
-                    }
-                    Expr::Call { name, args } if name == "sha256" => {
-                        let data = self.collect_range_data(args)?;
-                        let hash = builtin::sha256(&data);
-                        Ok(hash.to_vec())
+                        Ok(bytes.to_vec())
                     }
                     _ => {
                         // Default zero fill for unrecognised init forms
@@ -383,9 +1693,55 @@ impl Evaluator {
                     }
                 }
             }
+            Type::Inferred => self.natural_bytes(init),
+        }
+    }
+
+    /// Bytes of the initializer of a `[u8; _]` field, which also give its
+    /// length
+    fn natural_bytes(&mut self, init: &Expr) -> Result<Vec<u8>> {
+        match init {
+            Expr::Call { name, args } if has_natural_length(name) => {
+                let [arg] = args.as_slice() else {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        format!("@{}() requires exactly 1 argument", name),
+                    ));
+                };
+                match name.as_str() {
+                    "uleb128" => Ok(builtin::uleb128(self.eval_expr(arg)?)),
+                    "sleb128" => Ok(builtin::sleb128(self.eval_expr(arg)? as i64)),
+                    "bytes" => Ok(self.eval_string(arg)?.into_bytes()),
+                    "hex" => builtin::hex(&self.eval_string(arg)?),
+                    _ => builtin::base64(&self.eval_string(arg)?),
+                }
+            }
+            Expr::EnvVar(name) => self.env_bytes(name).map(<[u8]>::to_vec),
+            _ => Err(DelbinError::new(
+                ErrorCode::E03001,
+                "A [u8; _] field takes its length from @uleb128(), @sleb128(), @bytes(), @hex(), \
+                 @base64() or a bytes env variable",
+            )),
         }
     }
 
+    /// Env variable `name`, which must hold bytes to initialize an array
+    fn env_bytes(&self, name: &str) -> Result<&[u8]> {
+        let value = self.env_var(name)?;
+        value.as_bytes().ok_or_else(|| {
+            let hint = match value {
+                Value::String(_) => format!("use @bytes(${{{}}}) to write a string", name),
+                Value::Secret(_) => "secrets are only accepted as keys".to_string(),
+                _ => "pass the value as Value::Bytes".to_string(),
+            };
+            DelbinError::new(
+                ErrorCode::E03001,
+                format!("Variable '{}' must be bytes to initialize an array field", name),
+            )
+            .with_hint(hint)
+        })
+    }
+
     /// Evaluate array literal
     fn eval_array_literal(
         &mut self,
@@ -398,8 +1754,8 @@ impl Evaluator {
 
         match array_lit {
             ArrayLiteralKind::Repeat { value, count } => {
-                // Get the fill value
-                let fill_value = self.eval_expr(value)?;
+                // Get the fill value, or evaluate it per element if it reads @index
+                let fill_value = if uses_index(value) { None } else { Some(self.eval_expr(value)?) };
 
                 // Determine actual count
                 let actual_count = match count {
@@ -431,7 +1787,19 @@ impl Evaluator {
                 // Generate bytes
                 let mut result = Vec::with_capacity(total_bytes);
                 // Fill with specified value
-                for _ in 0..actual_count {
+                for i in 0..actual_count {
+                    if i % DEADLINE_STRIDE == 0 {
+                        self.check_deadline()?;
+                    }
+                    let fill_value = match fill_value {
+                        Some(fill_value) => fill_value,
+                        None => {
+                            self.index = Some(i as u64);
+                            let element = self.eval_expr(value);
+                            self.index = None;
+                            element?
+                        }
+                    };
                     result.extend_from_slice(&self.write_scalar_value(elem_type, fill_value));
                 }
                 // Fill remaining with zeros
@@ -458,6 +1826,9 @@ impl Evaluator {
                         });
                         break;
                     }
+                    if idx % DEADLINE_STRIDE == 0 {
+                        self.check_deadline()?;
+                    }
                     let value = self.eval_expr(elem_expr)?;
                     result.extend_from_slice(&self.write_scalar_value(elem_type, value));
                 }
@@ -483,9 +1854,7 @@ impl Evaluator {
             )),
 
             Expr::EnvVar(name) => {
-                let value = self.env.get(name).ok_or_else(|| {
-                    DelbinError::new(ErrorCode::E02001, format!("Undefined variable: {}", name))
-                })?;
+                let value = self.env_var(name)?;
                 value.as_u64().ok_or_else(|| {
                     DelbinError::new(
                         ErrorCode::E03001,
@@ -497,36 +1866,16 @@ impl Evaluator {
             Expr::BinaryOp { op, left, right } => {
                 let l = self.eval_expr(left)?;
                 let r = self.eval_expr(right)?;
-                match op {
-                    BinOp::Or => Ok(l | r),
-                    BinOp::And => Ok(l & r),
-                    BinOp::Shl => {
-                        if r >= 64 {
-                            self.warnings.push(DelbinWarning {
-                                code: crate::error::WarningCode::W04001,
-                                message: format!("Shift left by {} bits overflows u64; result is 0", r),
-                                location: None,
-                            });
-                            Ok(0)
-                        } else {
-                            Ok(l << r)
-                        }
-                    }
-                    BinOp::Shr => {
-                        if r >= 64 {
-                            self.warnings.push(DelbinWarning {
-                                code: crate::error::WarningCode::W04001,
-                                message: format!("Shift right by {} bits overflows u64; result is 0", r),
-                                location: None,
-                            });
-                            Ok(0)
-                        } else {
-                            Ok(l >> r)
-                        }
-                    }
-                    BinOp::Add => Ok(l.wrapping_add(r)),
-                    BinOp::Sub => Ok(l.wrapping_sub(r)),
+                if let Some(value) = binary_op(*op, l, r) {
+                    return Ok(value);
                 }
+                let direction = if *op == BinOp::Shl { "left" } else { "right" };
+                self.warnings.push(DelbinWarning {
+                    code: crate::error::WarningCode::W04001,
+                    message: format!("Shift {} by {} bits overflows u64; result is 0", direction, r),
+                    location: None,
+                });
+                Ok(0)
             }
 
             Expr::UnaryOp { op, operand } => {
@@ -540,10 +1889,7 @@ impl Evaluator {
 
             Expr::SectionRef(name) => {
                 // Return section size
-                let section = self.sections.get(name).ok_or_else(|| {
-                    DelbinError::new(ErrorCode::E02003, format!("Undefined section: {}", name))
-                })?;
-                Ok(section.len() as u64)
+                self.section_data().len(name).ok_or_else(|| undefined_section(name))
             }
 
             Expr::SelfRef => {
@@ -560,6 +1906,11 @@ impl Evaluator {
                 ErrorCode::E03001,
                 "Array literal cannot be used as numeric value",
             )),
+
+            Expr::Named { name, .. } => Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("Keyword argument '{}' is not accepted here", name),
+            )),
         }
     }
 
@@ -568,9 +1919,7 @@ impl Evaluator {
         match expr {
             Expr::String(s) => Ok(s.clone()),
             Expr::EnvVar(name) => {
-                let value = self.env.get(name).ok_or_else(|| {
-                    DelbinError::new(ErrorCode::E02001, format!("Undefined variable: {}", name))
-                })?;
+                let value = self.env_var(name)?;
                 value.as_string().map(|s| s.to_string()).ok_or_else(|| {
                     DelbinError::new(
                         ErrorCode::E03001,
@@ -587,6 +1936,9 @@ impl Evaluator {
 
     /// Evaluate built-in function call
     fn eval_builtin_call(&mut self, name: &str, args: &[Expr]) -> Result<u64> {
+        if let Some(err) = non_numeric_builtin(name) {
+            return Err(err);
+        }
         match name {
             "sizeof" => {
                 if args.len() != 1 {
@@ -597,16 +1949,27 @@ impl Evaluator {
                 }
                 match &args[0] {
                     Expr::SelfRef => Ok(self.struct_size.unwrap_or(0) as u64),
+                    Expr::Call { name, args } if name == "compressed" => {
+                        let section = self.compressed_section(args)?;
+                        Ok(self.section_data().len(section).unwrap_or(0))
+                    }
                     Expr::SectionRef(section) | Expr::Call { name: section, .. }
-                        if self.sections.contains_key(section) =>
+                        if self.section_data().len(section).is_some() =>
                     {
-                        Ok(self.sections[section].len() as u64)
+                        Ok(self.section_data().len(section).unwrap_or(0))
+                    }
+                    // Bytes with a length of their own: @sizeof(@bytes("..."))
+                    Expr::Call { name, .. } if has_natural_length(name) => {
+                        Ok(self.natural_bytes(&args[0])?.len() as u64)
                     }
                     // Handle simple identifier as section name
                     other => {
                         if let Expr::EnvVar(section) = other {
-                            if let Some(data) = self.sections.get(section) {
-                                return Ok(data.len() as u64);
+                            if let Some(len) = self.section_data().len(section) {
+                                return Ok(len);
+                            }
+                            if let Ok(bytes) = self.env_bytes(section) {
+                                return Ok(bytes.len() as u64);
                             }
                         }
                         // Try to evaluate as expression (may be section reference)
@@ -644,136 +2007,725 @@ impl Evaluator {
                     })
             }
 
-            "crc32" => {
-                let data = self.collect_range_data(args)?;
-                Ok(builtin::crc32(&data) as u64)
+            "crc32" => Ok(self.crc32_of(args)? as u64),
+
+            "crc" => self.crc_named_of(args),
+
+            "crc16" => Ok(self.crc16_of(args)? as u64),
+
+            name if SimpleChecksum::by_name(name).is_some() => self.simple_checksum_of(name, args),
+
+            name if FastHash::by_name(name).is_some() => self.fast_hash_of(name, args),
+
+            "base" if args.is_empty() => self.base.ok_or_else(|| {
+                DelbinError::new(
+                    ErrorCode::E04003,
+                    "@base is used but not set; add e.g. `@base = 0x08000000;`",
+                )
+            }),
+
+            "slot_size" if args.is_empty() => self.slot_size.ok_or_else(|| {
+                DelbinError::new(
+                    ErrorCode::E04003,
+                    "@slot_size is used but not set; add e.g. `@slot_size = 512K;`",
+                )
+            }),
+
+            "index" if args.is_empty() => self.index.ok_or_else(index_outside_repeat),
+
+            "numblocks" => {
+                let bound = bind_args(name, args, &["data", "block"])?;
+                let data = bound[0].ok_or_else(|| {
+                    DelbinError::new(ErrorCode::E04004, "@numblocks() requires a section")
+                })?;
+                let block = self.block_size(name, bound[1])?;
+                let len = self
+                    .extract_field_name(data)
+                    .ok()
+                    .and_then(|section| self.section_data().len(&section))
+                    .ok_or_else(|| {
+                        DelbinError::new(ErrorCode::E04003, "@numblocks() takes a section name")
+                    })?;
+                Ok(len.div_ceil(block as u64))
+            }
+
+            "ipv4" | "mac" => {
+                let bytes = self.address_of(name, args)?;
+                Ok(bytes.iter().fold(0u64, |acc, &b| acc << 8 | b as u64))
+            }
+
+            "timestamp" if args.is_empty() => {
+                Ok(self.clock.as_ref().map_or_else(|| SystemClock.now(), |clock| clock.now()))
+            }
+
+            "timestamp" => Err(DelbinError::new(ErrorCode::E04004, "@timestamp() takes no arguments")),
+
+            "dostime" | "dosdate" => {
+                if args.len() != 1 {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        format!("@{}() requires exactly 1 argument", name),
+                    ));
+                }
+                let (date, time) = builtin::dos_datetime(self.eval_expr(&args[0])?)?;
+                Ok(if name == "dosdate" { date } else { time } as u64)
+            }
+
+            "align_up" | "align_down" => {
+                let [value, align] = args else {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        format!("@{}() requires 2 arguments: a value and an alignment", name),
+                    ));
+                };
+                let (value, align) = (self.eval_expr(value)?, self.eval_expr(align)?);
+                if name == "align_up" {
+                    builtin::align_up(value, align)
+                } else {
+                    builtin::align_down(value, align)
+                }
+            }
+
+            "min" | "max" | "clamp" => {
+                let expected = if name == "clamp" { 3 } else { 2 };
+                if args.len() != expected {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        format!("@{}() requires exactly {} arguments", name, expected),
+                    ));
+                }
+                let values = args.iter().map(|arg| self.eval_expr(arg)).collect::<Result<Vec<u64>>>()?;
+                builtin::arithmetic(name, &values).expect("argument count checked")
+            }
+
+            "count" => match args {
+                [Expr::EnvVar(name)] => {
+                    let value = self.env_var(name)?;
+                    value.as_list().map(|items| items.len() as u64).ok_or_else(|| {
+                        DelbinError::new(
+                            ErrorCode::E03001,
+                            format!("Variable '{}' is not a list", name),
+                        )
+                    })
+                }
+                _ => Err(DelbinError::new(
+                    ErrorCode::E04004,
+                    "@count() requires 1 argument: a list variable, e.g. @count(${PARTITIONS})",
+                )),
+            },
+
+            "strlen" => {
+                if args.len() != 1 {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        "@strlen() requires exactly 1 argument",
+                    ));
+                }
+                Ok(self.eval_string(&args[0])?.len() as u64)
+            }
+
+            "semver" => {
+                let [version, scheme] = args else {
+                    return Err(DelbinError::new(
+                        ErrorCode::E04004,
+                        "@semver() requires 2 arguments: a version and a packing scheme",
+                    ));
+                };
+                let version = self.eval_string(version)?;
+                builtin::semver(&version, &self.eval_string(scheme)?)
+            }
+
+            name if self.is_custom(name) => {
+                let value = self.call_custom(name, args)?;
+                value.as_u64().ok_or_else(|| {
+                    DelbinError::new(
+                        ErrorCode::E03001,
+                        format!("@{}() returned bytes, not a number", name),
+                    )
+                })
+            }
+
+            _ => Err(DelbinError::new(
+                ErrorCode::E02004,
+                format!("Unknown function: @{}", name),
+            )),
+        }
+    }
+
+    /// Network-order bytes of `@ipv4("...")`, `@ipv6("...")` or `@mac("...")`
+    fn address_of(&mut self, name: &str, args: &[Expr]) -> Result<Vec<u8>> {
+        if args.len() != 1 {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                format!("@{}() requires exactly 1 argument", name),
+            ));
+        }
+        let text = self.eval_string(&args[0])?;
+        builtin::address(name, &text)
+    }
+
+    /// Fill `buf` from the random source for the current field; `what`
+    /// names the builtin if the OS source isn't built in
+    fn random_fill(&self, what: &str, buf: &mut [u8]) -> Result<()> {
+        match &self.random {
+            Some(source) => source.fill(self.current_field.as_deref().unwrap_or(""), buf),
+            None => {
+                buf.copy_from_slice(&builtin::random_bytes(what, buf.len())?);
+                Ok(())
+            }
+        }
+    }
+
+    /// Bytes of `@uuid()` or `@uuid5(namespace, name)`, in RFC 9562 order
+    fn uuid_of(&mut self, name: &str, args: &[Expr]) -> Result<[u8; 16]> {
+        match (name, args) {
+            ("uuid", []) => {
+                let mut random = [0u8; 16];
+                self.random_fill("@uuid()", &mut random)?;
+                Ok(builtin::uuid_v4(random))
+            }
+            ("uuid", _) => Err(DelbinError::new(ErrorCode::E04004, "@uuid() takes no arguments")),
+            (_, [namespace, value]) => {
+                let namespace = builtin::uuid_namespace(&self.eval_string(namespace)?)?;
+                let value = self.eval_string(value)?;
+                builtin::uuid_v5(&namespace, value.as_bytes())
+            }
+            _ => Err(DelbinError::new(
+                ErrorCode::E04004,
+                "@uuid5() requires 2 arguments: a namespace and a name",
+            )),
+        }
+    }
+
+    /// Whether `name` resolves to a registered custom builtin
+    fn is_custom(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Evaluate the arguments of a custom builtin and call it
+    fn call_custom(&mut self, name: &str, args: &[Expr]) -> Result<Value> {
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(self.custom_arg(arg)?);
+        }
+        (self.functions[name])(&values)
+    }
+
+    /// Evaluate one custom builtin argument
+    fn custom_arg(&mut self, arg: &Expr) -> Result<ArgValue> {
+        match arg {
+            Expr::Range { .. } | Expr::SelfRef | Expr::SectionRef(_) => {
+                let mut data = Vec::new();
+                self.feed_range_data(std::slice::from_ref(arg), &mut |chunk| {
+                    data.extend_from_slice(chunk)
+                })?;
+                Ok(ArgValue::Bytes(data))
+            }
+            Expr::String(s) => Ok(ArgValue::Str(s.clone())),
+            Expr::EnvVar(name) => Ok(match self.env_var(name)? {
+                Value::String(s) => ArgValue::Str(s.clone()),
+                Value::Bytes(b) => ArgValue::Bytes(b.clone()),
+                Value::Secret(s) => ArgValue::Secret(s.clone()),
+                Value::List(_) => {
+                    return Err(DelbinError::new(
+                        ErrorCode::E03001,
+                        format!("Variable '{}' is a list; pass one element, e.g. ${{{}[0]}}", name, name),
+                    ))
+                }
+                Value::Map(_) => {
+                    return Err(DelbinError::new(
+                        ErrorCode::E03001,
+                        format!("Variable '{}' is a map; pass one entry, e.g. ${{{}.key}}", name, name),
+                    ))
+                }
+                other => ArgValue::Int(other.as_u64().unwrap_or_default()),
+            }),
+            other => self.eval_expr(other).map(ArgValue::Int),
+        }
+    }
+
+    /// Extract field name from expression
+    fn extract_field_name(&self, expr: &Expr) -> Result<String> {
+        match expr {
+            // When parsing directly, offsetof arguments may be parsed as different forms
+            // Try to extract from various forms
+            Expr::EnvVar(name) => Ok(name.clone()),
+            Expr::SectionRef(name) => Ok(name.clone()),
+            Expr::Call { name, .. } => Ok(name.clone()),
+            _ => Err(DelbinError::new(
+                ErrorCode::E04003,
+                "Invalid argument for @offsetof()",
+            )),
+        }
+    }
+
+    /// Stream the data selected by checksum arguments to `f`
+    ///
+    /// Ranges over `@self` are fed straight from the output buffer and sections
+    /// are streamed from the provider in chunks, so nothing is copied up front.
+    fn feed_range_data(&self, args: &[Expr], f: &mut dyn FnMut(&[u8])) -> Result<()> {
+        if args.is_empty() {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                "Function requires at least 1 argument",
+            ));
+        }
+
+        for arg in args {
+            match arg {
+                Expr::Range { start, end, .. } => {
+                    let range = self.self_range(start.as_deref(), end.as_deref())?;
+                    f(&self.output[range]);
+                }
+
+                Expr::SelfRef => {
+                    f(&self.output);
+                }
+
+                Expr::SectionRef(name) => {
+                    self.feed_section(name, f)?;
+                }
+
+                Expr::Call { name, args } if name == "compressed" => {
+                    self.feed_section(self.compressed_section(args)?, f)?;
+                }
+
+                // Section name may be parsed as other forms
+                other => {
+                    if let Ok(section_name) = self.extract_field_name(other) {
+                        if self.section_data().len(&section_name).is_some() {
+                            self.feed_section(&section_name, f)?;
+                            continue;
+                        }
+                    }
+                    return Err(DelbinError::new(
+                        ErrorCode::E04003,
+                        "Invalid argument for checksum function",
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Bytes of the output selected by `@self[start..end]`
+    fn self_range(&self, start: Option<&Expr>, end: Option<&str>) -> Result<Range<usize>> {
+        let start_offset = match start {
+            Some(expr) => self.eval_expr_const(expr)? as usize,
+            None => 0,
+        };
+
+        let end_offset = match end {
+            Some(field_name) => *self.field_offsets.get(field_name).ok_or_else(|| {
+                DelbinError::new(ErrorCode::E02002, format!("Undefined field: {}", field_name))
+            })?,
+            None => self.output.len(),
+        };
+
+        if start_offset <= end_offset && end_offset <= self.output.len() {
+            Ok(start_offset..end_offset)
+        } else {
+            Err(DelbinError::new(
+                ErrorCode::E04002,
+                format!("Invalid range: {}..{}", start_offset, end_offset),
+            ))
+        }
+    }
+
+    /// Run the struct's `@encrypt_range` attributes over the output
+    ///
+    /// AES-CTR is its own inverse, so this also decrypts.
+    fn apply_encryption<'s>(&mut self, ranges: impl Iterator<Item = &'s EncryptRange>) -> Result<()> {
+        for enc in ranges {
+            let range = self.self_range(enc.start.as_deref(), enc.end.as_deref())?;
+            let key = self.secret_var(&enc.key, "@encrypt_range key")?;
+            let iv = self.aes_iv(&enc.iv, "@encrypt_range IV")?;
+            builtin::aes_ctr(key.expose(), &iv, &mut self.output[range])?;
+        }
+        Ok(())
+    }
+
+    /// AES-CTR initial counter block from a bytes or secret env variable
+    fn aes_iv(&self, expr: &Expr, what: &str) -> Result<[u8; 16]> {
+        let iv = self.secret_var(expr, what)?;
+        <[u8; 16]>::try_from(iv.expose()).map_err(|_| {
+            DelbinError::new(
+                ErrorCode::E04003,
+                format!("{} must be 16 bytes, got {}", what, iv.len()),
+            )
+        })
+    }
+
+    /// Bytes of a key or IV (`what`), which must be a bytes or secret env
+    /// variable
+    fn secret_var(&self, expr: &Expr, what: &str) -> Result<Secret> {
+        let Expr::EnvVar(name) = expr else {
+            return Err(DelbinError::new(
+                ErrorCode::E03001,
+                format!("{} must be an env variable", what),
+            ));
+        };
+        let value = self.env_var(name)?;
+        value
+            .as_secret()
+            .or_else(|| value.as_bytes())
+            .map(Secret::new)
+            .ok_or_else(|| {
+                DelbinError::new(
+                    ErrorCode::E03001,
+                    format!("Variable '{}' must be bytes or a secret for the {}", name, what),
+                )
+            })
+    }
+
+    /// Stream a whole section from the provider
+    ///
+    /// Read in slices of `DEADLINE_SLICE` bytes, checking the deadline
+    /// before each.
+    fn feed_section(&self, name: &str, f: &mut dyn FnMut(&[u8])) -> Result<()> {
+        let len = self.section_data().len(name).ok_or_else(|| undefined_section(name))?;
+        let mut start = 0;
+        loop {
+            self.check_deadline()?;
+            let end = len.min(start + DEADLINE_SLICE);
+            self.section_data().read_chunks(name, start..end, f)?;
+            if end == len {
+                return Ok(());
+            }
+            start = end;
+        }
+    }
+
+    /// Name of the `@compress` section behind `@compressed(source)`
+    fn compressed_section(&self, args: &[Expr]) -> Result<&str> {
+        let [Expr::SectionRef(source)] = args else {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                "@compressed() requires exactly 1 argument: a section name",
+            ));
+        };
+        match self.compressed.get(source).map(Vec::as_slice) {
+            Some([section]) => Ok(section),
+            Some(sections) => Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!(
+                    "@compressed({}) is ambiguous: {} all compress it",
+                    source,
+                    sections.join(", ")
+                ),
+            )),
+            None => Err(DelbinError::new(
+                ErrorCode::E02003,
+                format!("No compressed form of section '{}'", source),
+            )
+            .with_hint(format!("declare one with `@section {0}_lz4 = @compress({0}, lz4);`", source))),
+        }
+    }
+
+    /// Digest of a checksum argument list that names exactly one whole section.
+    ///
+    /// Lets the provider supply (or cache) the digest without the data being read
+    /// into the evaluator; returns `None` for anything else.
+    fn whole_section_hash(&self, args: &[Expr], algo: HashAlgorithm) -> Result<Option<Vec<u8>>> {
+        match args {
+            [Expr::SectionRef(name)] if self.section_data().len(name).is_some() => {
+                self.section_data().hash(name, algo).map(Some)
             }
+            _ => Ok(None),
+        }
+    }
 
-            "crc" => {
-                if args.len() < 2 {
-                    return Err(DelbinError::new(
-                        ErrorCode::E04004,
-                        "@crc() requires 2 arguments: algorithm name and data source",
-                    ));
+    /// CRC32 over checksum arguments
+    ///
+    /// ISO-HDLC unless a trailing string names another variant.
+    fn crc32_of(&self, args: &[Expr]) -> Result<u32> {
+        let (data, variant) = match args {
+            [data @ .., Expr::String(variant)] => (data, variant.as_str()),
+            _ => (args, "iso-hdlc"),
+        };
+        if !matches!(variant, "iso-hdlc" | "bzip2" | "mpeg2" | "posix" | "c" | "jamcrc") {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!(
+                    "Unknown CRC32 variant: '{}'. Supported: iso-hdlc, bzip2, mpeg2, posix, c, jamcrc",
+                    variant
+                ),
+            ));
+        }
+        if variant == "iso-hdlc" {
+            if let Some(digest) = self.whole_section_hash(data, HashAlgorithm::Crc32)? {
+                if let Ok(bytes) = <[u8; 4]>::try_from(digest.as_slice()) {
+                    return Ok(u32::from_be_bytes(bytes));
                 }
-                let algo = match &args[0] {
-                    Expr::String(s) => s.clone(),
-                    _ => return Err(DelbinError::new(
-                        ErrorCode::E04003,
-                        "@crc() first argument must be a string literal (algorithm name)",
-                    )),
-                };
-                let data = self.collect_range_data(&args[1..])?;
-                builtin::crc_by_name(&algo, &data)
-            }
-
-            "sha256" => {
-                // sha256 returns byte array, not a number
-                Err(DelbinError::new(
-                    ErrorCode::E03001,
-                    "@sha256() returns bytes, not a number",
-                ))
-            }
-
-            "bytes" => {
-                // bytes returns byte array, not a number
-                Err(DelbinError::new(
-                    ErrorCode::E03001,
-                    "@bytes() returns bytes, not a number",
-                ))
             }
-
-            _ => Err(DelbinError::new(
-                ErrorCode::E02004,
-                format!("Unknown function: @{}", name),
-            )),
         }
+        let mut digest = CrcDigest::by_name(&format!("crc32-{}", variant))?;
+        self.feed_range_data(data, &mut |chunk| digest.update(chunk))?;
+        Ok(digest.finalize() as u32)
     }
 
-    /// Extract field name from expression
-    fn extract_field_name(&self, expr: &Expr) -> Result<String> {
-        match expr {
-            // When parsing directly, offsetof arguments may be parsed as different forms
-            // Try to extract from various forms
-            Expr::EnvVar(name) => Ok(name.clone()),
-            Expr::SectionRef(name) => Ok(name.clone()),
-            Expr::Call { name, .. } => Ok(name.clone()),
-            _ => Err(DelbinError::new(
+    /// Named-algorithm CRC over `@crc("algo", ...)` arguments
+    fn crc_named_of(&mut self, args: &[Expr]) -> Result<u64> {
+        if !matches!(args.first(), None | Some(Expr::String(_))) {
+            return self.crc_custom_of(args);
+        }
+        if args.len() < 2 {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                "@crc() requires 2 arguments: algorithm name and data source",
+            ));
+        }
+        let mut digest = match &args[0] {
+            Expr::String(algo) => CrcDigest::by_name(algo)?,
+            _ => return Err(DelbinError::new(
                 ErrorCode::E04003,
-                "Invalid argument for @offsetof()",
+                "@crc() first argument must be a string literal (algorithm name)",
             )),
+        };
+        self.feed_range_data(&args[1..], &mut |chunk| digest.update(chunk))?;
+        Ok(digest.finalize())
+    }
+
+    /// `@crc(data, width, poly, init, refin, refout, xorout)`
+    fn crc_custom_of(&mut self, args: &[Expr]) -> Result<u64> {
+        const PARAMS: [&str; 7] = ["data", "width", "poly", "init", "refin", "refout", "xorout"];
+        let bound = bind_args("crc", args, &PARAMS)?;
+        let mut values = [0u64; 6];
+        for ((value, arg), name) in values.iter_mut().zip(&bound[1..]).zip(&PARAMS[1..]) {
+            let arg = arg.ok_or_else(|| {
+                DelbinError::new(
+                    ErrorCode::E04004,
+                    format!("@crc() is missing '{}'; a custom CRC takes {}", name, PARAMS.join(", ")),
+                )
+            })?;
+            *value = self.eval_expr(arg)?;
         }
+        let mut digest = CrcDigest::custom(CrcParams::from_args(values)?)?;
+        let data = bound[0].ok_or_else(|| DelbinError::new(ErrorCode::E04004, "@crc() is missing 'data'"))?;
+        self.feed_range_data(std::slice::from_ref(data), &mut |chunk| digest.update(chunk))?;
+        Ok(digest.finalize())
     }
 
-    /// Collect range data for CRC/Hash calculation
-    fn collect_range_data(&self, args: &[Expr]) -> Result<Vec<u8>> {
-        if args.is_empty() {
+    /// CRC16 over `@crc16(data, ..., "variant")` arguments
+    fn crc16_of(&self, args: &[Expr]) -> Result<u16> {
+        let [data @ .., Expr::String(variant)] = args else {
             return Err(DelbinError::new(
                 ErrorCode::E04004,
-                "Function requires at least 1 argument",
+                "@crc16() requires a data source and a variant, e.g. @crc16(image, \"ccitt\")",
+            ));
+        };
+        if !matches!(variant.as_str(), "ccitt" | "modbus" | "xmodem" | "usb") {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("Unknown CRC16 variant: '{}'. Supported: ccitt, modbus, xmodem, usb", variant),
+            ));
+        }
+        let mut digest = CrcDigest::by_name(&format!("crc16-{}", variant))?;
+        self.feed_range_data(data, &mut |chunk| digest.update(chunk))?;
+        Ok(digest.finalize() as u16)
+    }
+
+    /// Byte sum or XOR over `@sum8(data, ...)` and the other simple checksums
+    fn simple_checksum_of(&self, name: &str, args: &[Expr]) -> Result<u64> {
+        let mut digest = SimpleChecksum::by_name(name)
+            .ok_or_else(|| DelbinError::new(ErrorCode::E02004, format!("Unknown function: @{}", name)))?
+            .digest();
+        self.feed_range_data(args, &mut |chunk| digest.update(chunk))?;
+        Ok(digest.finalize())
+    }
+
+    /// `@xxh32()`, `@xxh64()`, `@xxh3()` or `@murmur3_32()` over the data arguments
+    fn fast_hash_of(&self, name: &str, args: &[Expr]) -> Result<u64> {
+        let mut digest = FastHash::by_name(name)
+            .ok_or_else(|| DelbinError::new(ErrorCode::E02004, format!("Unknown function: @{}", name)))?
+            .hasher()?;
+        self.feed_range_data(args, &mut |chunk| digest.update(chunk))?;
+        Ok(digest.finalize())
+    }
+
+    /// Data source and block size of `@merkle_root()`, `@merkle_tree()` and `@block_hashes()`
+    fn merkle_params<'e>(&mut self, func: &str, args: &'e [Expr]) -> Result<(&'e Expr, usize)> {
+        let bound = bind_args(func, args, &["data", "block", "alg"])?;
+        let data = bound[0].ok_or_else(|| {
+            DelbinError::new(ErrorCode::E04004, format!("@{}() requires a data source", func))
+        })?;
+        let block = self.block_size(func, bound[1])?;
+        match bound[2] {
+            None => {}
+            Some(Expr::String(alg)) if alg == "sha256" => {}
+            Some(other) => {
+                return Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    format!("Unsupported @{}() algorithm {}. Supported: \"sha256\"", func, other),
+                ))
+            }
+        }
+        Ok((data, block))
+    }
+
+    /// `block` argument of the block-hashing builtins, 4096 if omitted
+    fn block_size(&mut self, func: &str, expr: Option<&Expr>) -> Result<usize> {
+        let block = match expr {
+            Some(expr) => self.eval_expr(expr)? as usize,
+            None => 4096,
+        };
+        if block == 0 {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("@{}() block size must be non-zero", func),
             ));
         }
+        Ok(block)
+    }
 
+    /// Signature over checksum arguments from the attached signer
+    fn sign_of(&self, args: &[Expr]) -> Result<Vec<u8>> {
+        let signer = self.signer.as_ref().ok_or_else(|| {
+            DelbinError::new(ErrorCode::E04003, "@sign() needs a signer")
+                .with_hint("attach one with GenerateOptions::with_signer()")
+        })?;
         let mut data = Vec::new();
+        self.feed_range_data(args, &mut |chunk| data.extend_from_slice(chunk))?;
+        signer.sign(&data)
+    }
 
-        for arg in args {
-            match arg {
-                Expr::Range { start, end, .. } => {
-                    let start_offset = match start {
-                        Some(expr) => self.eval_expr_const(expr)? as usize,
-                        None => 0,
-                    };
+    /// Output of a signature builtin, sized to an array field of
+    /// `field_len` bytes
+    ///
+    /// Variable-length (DER) signatures are zero-padded; anything else must
+    /// fill the field exactly.
+    fn signature_field(&self, func: &str, args: &[Expr], field_len: usize) -> Result<Vec<u8>> {
+        let (mut signature, variable) = match func {
+            "sign" => (self.sign_of(args)?, false),
+            _ => self.builtin_signature(func, args)?,
+        };
+        if signature.len() > field_len || (!variable && signature.len() != field_len) {
+            return Err(DelbinError::new(
+                ErrorCode::E03001,
+                format!(
+                    "@{}() produced {} bytes but the field is {} bytes",
+                    func,
+                    signature.len(),
+                    field_len
+                ),
+            ));
+        }
+        signature.resize(field_len, 0);
+        Ok(signature)
+    }
 
-                    let end_offset = match end {
-                        Some(field_name) => {
-                            *self.field_offsets.get(field_name).ok_or_else(|| {
-                                DelbinError::new(
-                                    ErrorCode::E02002,
-                                    format!("Undefined field: {}", field_name),
-                                )
-                            })?
-                        }
-                        None => self.output.len(),
-                    };
+    /// Output of a key-holding signature builtin; the flag is set for
+    /// variable-length output
+    #[cfg_attr(not(any(feature = "crypto-sign", feature = "crypto-rsa")), allow(unused_variables))]
+    fn builtin_signature(&self, func: &str, args: &[Expr]) -> Result<(Vec<u8>, bool)> {
+        match func {
+            #[cfg(feature = "crypto-sign")]
+            "ed25519" | "ecdsa_p256" => self.curve_signature(func, args),
+            #[cfg(feature = "crypto-rsa")]
+            "rsa_sign" => self.rsa_signature(args).map(|signature| (signature, false)),
+            _ => {
+                let feature = if func == "rsa_sign" { "crypto-rsa" } else { "crypto-sign" };
+                Err(builtin::missing_feature(&format!("@{}()", func), feature))
+            }
+        }
+    }
 
-                    if start_offset <= end_offset && end_offset <= self.output.len() {
-                        data.extend_from_slice(&self.output[start_offset..end_offset]);
-                    } else {
+    /// `@ed25519(key = ${KEY}, data...)` or
+    /// `@ecdsa_p256(key = ${KEY}, data..., format = "raw")`
+    #[cfg(feature = "crypto-sign")]
+    fn curve_signature(&self, func: &str, args: &[Expr]) -> Result<(Vec<u8>, bool)> {
+        use crate::sign::{EcdsaP256Signer, Ed25519Signer, SignatureFormat};
+
+        let options: &[&str] = if func == "ecdsa_p256" { &["format"] } else { &[] };
+        let args = sign_args(func, args, options)?;
+        let key = self.secret_var(args.key, &format!("@{}() key", func))?;
+        let (signer, variable): (Box<dyn Signer>, bool) = match func {
+            "ed25519" => (Box::new(Ed25519Signer::from_bytes(key.expose())?), false),
+            _ => {
+                let format = match args.options[0] {
+                    Some(Expr::String(name)) => SignatureFormat::by_name(name)?,
+                    Some(_) => {
                         return Err(DelbinError::new(
-                            ErrorCode::E04002,
-                            format!("Invalid range: {}..{}", start_offset, end_offset),
-                        ));
+                            ErrorCode::E04003,
+                            "@ecdsa_p256() format must be \"raw\" or \"der\"",
+                        ))
                     }
-                }
+                    None => SignatureFormat::Raw,
+                };
+                let signer = EcdsaP256Signer::from_bytes(key.expose(), format)?;
+                (Box::new(signer), format == SignatureFormat::Der)
+            }
+        };
+        let mut data = Vec::new();
+        self.feed_range_data(&args.data, &mut |chunk| data.extend_from_slice(chunk))?;
+        Ok((signer.sign(&data)?, variable))
+    }
 
-                Expr::SelfRef => {
-                    data.extend_from_slice(&self.output);
-                }
+    /// `@rsa_sign(key = ${KEY}, data..., scheme = "pkcs1")`
+    #[cfg(feature = "crypto-rsa")]
+    fn rsa_signature(&self, args: &[Expr]) -> Result<Vec<u8>> {
+        use crate::sign::{RsaScheme, RsaSigner};
 
-                Expr::SectionRef(name) => {
-                    let section = self.sections.get(name).ok_or_else(|| {
-                        DelbinError::new(ErrorCode::E02003, format!("Undefined section: {}", name))
-                    })?;
-                    data.extend_from_slice(section);
-                }
+        let args = sign_args("rsa_sign", args, &["scheme"])?;
+        let scheme = match args.options[0] {
+            Some(Expr::String(name)) => RsaScheme::by_name(name)?,
+            Some(_) => {
+                return Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    "@rsa_sign() scheme must be \"pkcs1\" or \"pss\"",
+                ))
+            }
+            None => RsaScheme::Pkcs1v15,
+        };
+        let key = self.secret_var(args.key, "@rsa_sign() key")?;
+        let signer = RsaSigner::from_bytes(key.expose(), scheme)?;
+        let mut data = Vec::new();
+        self.feed_range_data(&args.data, &mut |chunk| data.extend_from_slice(chunk))?;
+        signer.sign(&data)
+    }
 
-                // Section name may be parsed as other forms
-                other => {
-                    if let Ok(section_name) = self.extract_field_name(other) {
-                        if let Some(section) = self.sections.get(&section_name) {
-                            data.extend_from_slice(section);
-                            continue;
-                        }
-                    }
-                    return Err(DelbinError::new(
-                        ErrorCode::E04003,
-                        "Invalid argument for checksum function",
-                    ));
-                }
+    /// `@hmac_sha256(key = ${KEY}, data...)`: HMAC-SHA256 over checksum
+    /// arguments, keyed from a bytes or secret env variable
+    #[cfg(feature = "hmac")]
+    fn hmac_sha256_of(&self, args: &[Expr]) -> Result<Vec<u8>> {
+        let args = sign_args("hmac_sha256", args, &[])?;
+        let key = self.secret_var(args.key, "@hmac_sha256() key")?;
+        let mut mac =
+            builtin::HmacSha256::new_from_slice(key.expose()).expect("HMAC accepts keys of any length");
+        self.feed_range_data(&args.data, &mut |chunk| mac.update(chunk))?;
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+
+    #[cfg(not(feature = "hmac"))]
+    fn hmac_sha256_of(&self, _args: &[Expr]) -> Result<Vec<u8>> {
+        Err(builtin::missing_feature("@hmac_sha256()", "hmac"))
+    }
+
+    /// `@sha256()`, `@sha512()` etc. over checksum arguments, into a
+    /// `field_len`-byte field
+    fn digest_of(&self, name: &str, args: &[Expr], field_len: usize) -> Result<Vec<u8>> {
+        let algorithm = DigestAlgorithm::by_name(name)
+            .ok_or_else(|| DelbinError::new(ErrorCode::E02004, format!("Unknown function: @{}", name)))?;
+        if algorithm.output_len() != field_len {
+            return Err(DelbinError::new(
+                ErrorCode::E03001,
+                format!(
+                    "@{}() produces {} bytes but the field is {} bytes",
+                    name,
+                    algorithm.output_len(),
+                    field_len
+                ),
+            ));
+        }
+        if algorithm == DigestAlgorithm::Sha256 {
+            if let Some(digest) = self.whole_section_hash(args, HashAlgorithm::Sha256)? {
+                return Ok(digest);
             }
         }
-
-        Ok(data)
+        let mut hasher = algorithm.hasher()?;
+        self.feed_range_data(args, &mut |chunk| hasher.update(chunk))?;
+        Ok(hasher.finalize().to_vec())
     }
 
     /// Constant expression evaluation: resolves numbers and field names to offsets
@@ -791,6 +2743,20 @@ impl Evaluator {
                         )
                     })
             }
+            Expr::Call { name, args } if name == "offsetof" => match args.as_slice() {
+                [field @ Expr::SectionRef(_)] => self.eval_expr_const(field),
+                _ => Err(DelbinError::new(
+                    ErrorCode::E04004,
+                    "@offsetof() requires exactly 1 argument",
+                )),
+            },
+            Expr::BinaryOp { op, left, right } => {
+                let (l, r) = (self.eval_expr_const(left)?, self.eval_expr_const(right)?);
+                binary_op(*op, l, r).ok_or_else(|| {
+                    DelbinError::new(ErrorCode::E04002, "Shift amount too large in range expression")
+                })
+            }
+            Expr::UnaryOp { op: UnaryOp::Not, operand } => Ok(!self.eval_expr_const(operand)?),
             _ => Err(DelbinError::new(
                 ErrorCode::E04003,
                 "Expected a numeric literal or field name in range expression",
@@ -801,7 +2767,10 @@ impl Evaluator {
     /// Process pending fields
     fn process_pending(&mut self) -> Result<()> {
         for pending in std::mem::take(&mut self.pending) {
-            let bytes = self.eval_pending_field(&pending)?;
+            self.check_deadline()?;
+            let first_warning = self.warnings.len();
+            let bytes = self.eval_pending_field(&pending);
+            let bytes = self.locate(pending.location.as_ref(), first_warning, bytes)?;
 
             // Backfill data
             let end = pending.offset + bytes.len();
@@ -828,21 +2797,8 @@ impl Evaluator {
         match &pending.ty {
             Type::Scalar(scalar) => {
                 let value = match &pending.expr {
-                    Expr::Call { name, args } if name == "crc32" => {
-                        let data = self.collect_range_data(args)?;
-                        builtin::crc32(&data) as u64
-                    }
-                    Expr::Call { name, args } if name == "crc" => {
-                        let algo = match args.first() {
-                            Some(Expr::String(s)) => s.clone(),
-                            _ => return Err(DelbinError::new(
-                                ErrorCode::E04003,
-                                "@crc() first argument must be a string literal (algorithm name)",
-                            )),
-                        };
-                        let data = self.collect_range_data(&args[1..])?;
-                        builtin::crc_by_name(&algo, &data)?
-                    }
+                    Expr::Call { name, args } if name == "crc32" => self.crc32_of(args)? as u64,
+                    Expr::Call { name, args } if name == "crc" => self.crc_named_of(args)?,
                     _ => self.eval_expr(&pending.expr)?,
                 };
                 Ok(self.write_scalar_value(*scalar, value))
@@ -850,14 +2806,14 @@ impl Evaluator {
             Type::Array { elem, len } => {
                 let len_val = self.eval_expr(len)? as usize;
                 match &pending.expr {
-                    Expr::Call { name, args } if name == "sha256" => {
-                        let data = self.collect_range_data(args)?;
-                        let hash = builtin::sha256(&data);
-                        Ok(hash.to_vec())
+                    Expr::Call { name, args } if DigestAlgorithm::by_name(name).is_some() => {
+                        self.digest_of(name, args, len_val * elem.size())
                     }
+                    Expr::Call { .. } => self.eval_field_value(&pending.ty, &pending.expr),
                     _ => Ok(vec![0u8; len_val * elem.size()]),
                 }
             }
+            Type::Inferred => self.natural_bytes(&pending.expr),
         }
     }
 
@@ -908,16 +2864,481 @@ impl Evaluator {
     }
 }
 
+/// `l op r` as `eval_expr()` computes it; `None` for a shift by 64 bits or
+/// more, which is 0 with a warning
+pub(crate) fn binary_op(op: BinOp, l: u64, r: u64) -> Option<u64> {
+    Some(match op {
+        BinOp::Shl | BinOp::Shr if r >= 64 => return None,
+        BinOp::Or => l | r,
+        BinOp::And => l & r,
+        BinOp::Shl => l << r,
+        BinOp::Shr => l >> r,
+        BinOp::Add => l.wrapping_add(r),
+        BinOp::Sub => l.wrapping_sub(r),
+        BinOp::Mul => l.wrapping_mul(r),
+        BinOp::Lt => (l < r) as u64,
+        BinOp::Le => (l <= r) as u64,
+        BinOp::Gt => (l > r) as u64,
+        BinOp::Ge => (l >= r) as u64,
+        BinOp::Eq => (l == r) as u64,
+        BinOp::Ne => (l != r) as u64,
+    })
+}
+
+/// Record a decoded field under its name and each of its aliases
+fn insert_decoded(fields: &mut HashMap<String, Value>, field: &FieldDef, value: Value) {
+    for alias in &field.aliases {
+        fields.insert(alias.clone(), value.clone());
+    }
+    fields.insert(field.name.clone(), value);
+}
+
+/// Whether builtin `name` produces bytes of a length of its own, so it can
+/// initialize a `[u8; _]` field
+pub(crate) fn has_natural_length(name: &str) -> bool {
+    matches!(name, "uleb128" | "sleb128" | "bytes" | "hex" | "base64")
+}
+
+/// Error for using builtin `name` as a number, if it returns something else
+pub(crate) fn non_numeric_builtin(name: &str) -> Option<DelbinError> {
+    let message = match name {
+        "bytes" | "hex" | "base64" | "file" | "merkle_root" | "block_hashes" | "hmac_sha256" | "uleb128"
+        | "sleb128" => {
+            format!("@{}() returns bytes, not a number", name)
+        }
+        name if DigestAlgorithm::by_name(name).is_some() => {
+            format!("@{}() returns bytes, not a number", name)
+        }
+        name if SECTION_TRANSFORMS.contains(&name) => format!(
+            "@{0}() transforms a section; declare it with `@section name = @{0}(...);`",
+            name
+        ),
+        name if SIGNATURE_BUILTINS.contains(&name) => {
+            format!("@{}() returns a signature, not a number", name)
+        }
+        "compressed" => "@compressed() names a section; use it in @sizeof() or a checksum".to_string(),
+        "ipv6" => "@ipv6() returns 16 bytes, not a number; use a [u8; 16] field".to_string(),
+        "random" => "@random() returns bytes, not a number; use a [u8; N] field".to_string(),
+        "uuid" | "uuid5" => format!("@{}() returns 16 bytes, not a number; use a [u8; 16] field", name),
+        _ => return None,
+    };
+    Some(DelbinError::new(ErrorCode::E03001, message))
+}
+
+/// True if an initializer reads env variables, sections or checksummed data
+pub(crate) fn depends_on_inputs(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::String(_) => false,
+        Expr::EnvVar(_) | Expr::SectionRef(_) | Expr::SelfRef | Expr::Range { .. } => true,
+        Expr::BinaryOp { left, right, .. } => depends_on_inputs(left) || depends_on_inputs(right),
+        Expr::UnaryOp { operand, .. } | Expr::Named { value: operand, .. } => {
+            depends_on_inputs(operand)
+        }
+        // Field offsets and the struct size are fixed by the layout
+        Expr::Call { name, .. } if name == "offsetof" => false,
+        Expr::Call { name, args } if name == "sizeof" => !matches!(args.as_slice(), [Expr::SelfRef]),
+        // Fresh on every run unless pinned with GenerateOptions
+        Expr::Call { name, .. } if matches!(name.as_str(), "random" | "uuid" | "timestamp") => true,
+        Expr::Call { name, args } => {
+            is_range_based_builtin(name) || args.iter().any(depends_on_inputs)
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::Repeat { value, count }) => {
+            depends_on_inputs(value)
+                || matches!(count, RepeatCount::Explicit(n) if depends_on_inputs(n))
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::List { elements }) => {
+            elements.iter().any(depends_on_inputs)
+        }
+    }
+}
+
+/// Whether `expr` reads `@index`, so a repeat initializer is evaluated per
+/// element
+fn uses_index(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call { name, args } => (name == "index" && args.is_empty()) || args.iter().any(uses_index),
+        Expr::BinaryOp { left, right, .. } => uses_index(left) || uses_index(right),
+        Expr::UnaryOp { operand, .. } | Expr::Named { value: operand, .. } => uses_index(operand),
+        _ => false,
+    }
+}
+
+pub(crate) fn index_outside_repeat() -> DelbinError {
+    DelbinError::new(
+        ErrorCode::E04003,
+        "@index is only defined in the value of a repeat initializer: `[@index * 4; _]`",
+    )
+}
+
+/// Env variables `expr` reads
+fn env_vars<'e>(expr: &'e Expr, vars: &mut Vec<&'e str>) {
+    match expr {
+        Expr::EnvVar(name) => vars.push(name),
+        Expr::BinaryOp { left, right, .. } => {
+            env_vars(left, vars);
+            env_vars(right, vars);
+        }
+        Expr::UnaryOp { operand, .. } | Expr::Named { value: operand, .. } => env_vars(operand, vars),
+        Expr::Call { args, .. } => args.iter().for_each(|arg| env_vars(arg, vars)),
+        Expr::ArrayLiteral(ArrayLiteralKind::Repeat { value, count }) => {
+            env_vars(value, vars);
+            if let RepeatCount::Explicit(count) = count {
+                env_vars(count, vars);
+            }
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::List { elements }) => {
+            elements.iter().for_each(|element| env_vars(element, vars))
+        }
+        Expr::Number(_) | Expr::String(_) | Expr::SectionRef(_) | Expr::SelfRef | Expr::Range { .. } => {}
+    }
+}
+
+/// Match call arguments to `params`: positional ones in order, then
+/// `name = value` keyword arguments; missing parameters are `None`
+pub(crate) fn bind_args<'e>(func: &str, args: &'e [Expr], params: &[&str]) -> Result<Vec<Option<&'e Expr>>> {
+    let mut bound = vec![None; params.len()];
+    let mut positional = 0;
+    for arg in args {
+        let (slot, value) = match arg {
+            Expr::Named { name, value } => {
+                let slot = params.iter().position(|p| p == name).ok_or_else(|| {
+                    DelbinError::new(
+                        ErrorCode::E04003,
+                        format!("@{}() has no parameter '{}'", func, name),
+                    )
+                })?;
+                (slot, value.as_ref())
+            }
+            other => {
+                positional += 1;
+                (positional - 1, other)
+            }
+        };
+        if slot >= params.len() {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                format!("@{}() takes at most {} arguments", func, params.len()),
+            ));
+        }
+        if bound[slot].replace(value).is_some() {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                format!("@{}() argument '{}' given twice", func, params[slot]),
+            ));
+        }
+    }
+    Ok(bound)
+}
+
+/// `file` with each `@for` block repeated once per element of its list
+///
+/// The fields of element `n` are prefixed `{var}_{n}_`, and so are the
+/// references between them; `${var}` in them reads `${LIST[n]}`. Borrows
+/// `file` when it has no `@for` blocks.
+///
+/// ```rust
+/// use delbin::{eval, parser, Value};
+/// use std::collections::HashMap;
+///
+/// let file = parser::parse("struct h { @for p in ${PARTS} { size: u32 = ${p}; } }").unwrap();
+/// let env = HashMap::from([("PARTS".to_string(), Value::List(vec![Value::U32(1), Value::U32(2)]))]);
+/// let unrolled = eval::unroll(&file, &env).unwrap();
+/// let names: Vec<&str> = unrolled.struct_def.fields.iter().map(|f| f.name.as_str()).collect();
+/// assert_eq!(names, ["p_0_size", "p_1_size"]);
+/// ```
+pub fn unroll<'f>(file: &'f File, env: &HashMap<String, Value>) -> Result<Cow<'f, File>> {
+    unroll_with(file, &|for_each| {
+        let list = lookup_var(env, &for_each.list).ok_or_else(|| {
+            DelbinError::new(ErrorCode::E02001, format!("Undefined variable: {}", for_each.list))
+        })?;
+        let items = list.as_list().ok_or_else(|| {
+            DelbinError::new(
+                ErrorCode::E03001,
+                format!("@for {} in ${{{}}}: expected a list, got {}", for_each.var, for_each.list, describe_value(list)),
+            )
+        })?;
+        Ok(items.len())
+    })
+}
+
+/// [`unroll()`] with the length of each loop's list from `len`
+pub(crate) fn unroll_with<'f>(file: &'f File, len: &dyn Fn(&ForEach) -> Result<usize>) -> Result<Cow<'f, File>> {
+    let has_loops = |def: &StructDef| def.fields.iter().any(|field| field.for_each.is_some());
+    if !has_loops(&file.struct_def) && !file.trailer.as_ref().is_some_and(has_loops) {
+        return Ok(Cow::Borrowed(file));
+    }
+    let mut file = file.clone();
+    for def in std::iter::once(&mut file.struct_def).chain(file.trailer.as_mut()) {
+        def.fields = unroll_fields(&def.fields, len)?;
+        check_unrolled_names(def)?;
+    }
+    Ok(Cow::Owned(file))
+}
+
+/// Reject a name `unroll_fields()` gave an `@for` field that another field
+/// or alias of the struct already has, e.g. `part_0_size` next to a
+/// declared `part_0_size`
+fn check_unrolled_names(def: &StructDef) -> Result<()> {
+    let mut names = HashSet::new();
+    for field in &def.fields {
+        for name in std::iter::once(&field.name).chain(&field.aliases) {
+            if !names.insert(name.as_str()) {
+                let err = DelbinError::new(
+                    ErrorCode::E01003,
+                    format!("Duplicate field '{}' in struct '{}'", name, def.name),
+                );
+                return Err(match &field.location {
+                    Some(location) => err.with_location(location.clone()),
+                    None => err,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn unroll_fields(fields: &[FieldDef], len: &dyn Fn(&ForEach) -> Result<usize>) -> Result<Vec<FieldDef>> {
+    let mut unrolled = Vec::with_capacity(fields.len());
+    let mut i = 0;
+    while i < fields.len() {
+        let Some(for_each) = &fields[i].for_each else {
+            unrolled.push(fields[i].clone());
+            i += 1;
+            continue;
+        };
+        let body_len = fields[i..]
+            .iter()
+            .take_while(|field| field.for_each.as_ref() == Some(for_each) && field.expanded_from == fields[i].expanded_from)
+            .count();
+        let body = &fields[i..i + body_len];
+        for n in 0..len(for_each)? {
+            let prefixed = |name: &str| format!("{}_{}_{}", for_each.var, n, name);
+            let element = format!("{}[{}]", for_each.list, n);
+            for field in body {
+                let mut field = field.clone();
+                field.name = prefixed(&field.name);
+                field.aliases = field.aliases.iter().map(|alias| prefixed(alias)).collect();
+                for own in body {
+                    let renamed = prefixed(&own.name);
+                    crate::rewrite::visit_field_exprs(&mut field, &mut |expr| {
+                        crate::rewrite::rename_reference(expr, &own.name, &renamed)
+                    });
+                }
+                crate::rewrite::visit_field_exprs(&mut field, &mut |expr| {
+                    let Expr::EnvVar(name) = expr else { return false };
+                    match name.strip_prefix(for_each.var.as_str()) {
+                        Some(path) if path.is_empty() || path.starts_with(['[', '.']) => {
+                            *name = format!("{}{}", element, path);
+                            true
+                        }
+                        _ => false,
+                    }
+                });
+                field.for_each = None;
+                field.expanded_from = None;
+                field.source = None;
+                unrolled.push(field);
+            }
+        }
+        i += body_len;
+    }
+    Ok(unrolled)
+}
+
+/// Env variable `name` of `vars`, following `[i]` indexes into lists and
+/// `.key`s into maps
+pub(crate) fn lookup_var<'v>(vars: &'v HashMap<String, Value>, name: &str) -> Option<&'v Value> {
+    let (root, mut path) = name.split_at(name.find(['[', '.']).unwrap_or(name.len()));
+    let mut value = vars.get(root)?;
+    while !path.is_empty() {
+        if let Some(rest) = path.strip_prefix('[') {
+            let (index, rest) = rest.split_once(']')?;
+            value = value.at(index.parse().ok()?)?;
+            path = rest;
+        } else {
+            let rest = path.strip_prefix('.')?;
+            let end = rest.find(['[', '.']).unwrap_or(rest.len());
+            value = value.get(&rest[..end])?;
+            path = &rest[end..];
+        }
+    }
+    Some(value)
+}
+
+/// Whether an integer `@inputs` value fits its declared type: signed types
+/// by their `iN::MIN..=iN::MAX` range, unsigned ones by their bit width
+fn int_input_fits(scalar: ScalarType, value: &Value) -> bool {
+    match scalar.signed_range() {
+        Some(range) => value.as_i128().is_some_and(|v| range.contains(&v)),
+        None => value.as_u64().is_some_and(|v| v & !scalar.bit_mask() == 0),
+    }
+}
+
+/// Short description of a value for error messages (never its contents)
+fn describe_value(value: &Value) -> String {
+    match value {
+        Value::String(_) => "a string".to_string(),
+        Value::Bytes(b) => format!("{} bytes", b.len()),
+        Value::Secret(_) => "a secret".to_string(),
+        Value::List(items) => format!("a list of {} values", items.len()),
+        Value::Map(entries) => format!("a map of {} values", entries.len()),
+        other => match other.as_u64() {
+            Some(v) => format!("the integer {}", v),
+            None => "an unsupported value".to_string(),
+        },
+    }
+}
+
+/// Layout entry for a field of `size` bytes at `offset`
+fn field_layout(field: &FieldDef, offset: usize, size: usize) -> FieldLayout {
+    FieldLayout {
+        name: field.name.clone(),
+        offset,
+        size,
+        elem: field.ty.elem_type(),
+        count: match field.ty {
+            Type::Scalar(_) => None,
+            Type::Array { elem, .. } => Some(size / elem.size()),
+            Type::Inferred => Some(size),
+        },
+    }
+}
+
+/// Largest array field, so a wild length from the env fails instead of
+/// exhausting memory
+const MAX_ARRAY_BYTES: u64 = 1 << 32;
+
+/// Byte size of array field `field`, `len` elements of `elem`
+fn array_bytes(field: &FieldDef, elem: ScalarType, len: u64) -> Result<usize> {
+    match len.checked_mul(elem.size() as u64) {
+        Some(bytes) if bytes <= MAX_ARRAY_BYTES => Ok(bytes as usize),
+        _ => Err(DelbinError::new(
+            ErrorCode::E03004,
+            format!(
+                "Array '{}' has {} {} elements; array fields are limited to 4 GiB",
+                field.name,
+                len,
+                elem.name()
+            ),
+        )),
+    }
+}
+
+/// Round the raw struct size up to the `@align(n)` boundary, if any
+fn aligned_struct_size(struct_def: &StructDef, raw_size: usize) -> usize {
+    match struct_def.align {
+        Some(align) => {
+            let n = align as usize;
+            raw_size.div_ceil(n) * n
+        }
+        None => raw_size,
+    }
+}
+
+/// Fail if the struct doesn't fit in the region reserved by `@size`
+fn check_reserved_size(file: &File, struct_size: usize) -> Result<()> {
+    match file.size {
+        Some(reserved) if struct_size as u64 > reserved => Err(DelbinError::new(
+            ErrorCode::E04002,
+            format!(
+                "Struct '{}' is {} bytes but @size reserves only {}",
+                file.struct_def.name, struct_size, reserved
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Fail if `what` (the struct or the whole image) outgrows `@slot_size`
+pub(crate) fn check_slot_size(file: &File, what: &str, size: u64) -> Result<()> {
+    match file.slot_size {
+        Some(slot) if size > slot => Err(DelbinError::new(
+            ErrorCode::E04002,
+            format!("{} is {} bytes but @slot_size is only {}", what, size, slot),
+        )),
+        _ => Ok(()),
+    }
+}
+
 /// Returns true if the builtin function operates on data ranges (@self / sections)
 /// and therefore may need two-phase (deferred) evaluation.
-fn is_range_based_builtin(name: &str) -> bool {
-    matches!(name, "crc32" | "sha256" | "crc")
+pub(crate) fn is_range_based_builtin(name: &str) -> bool {
+    matches!(
+        name,
+        "crc32" | "sha256" | "crc" | "crc16" | "merkle_root" | "block_hashes" | "hmac_sha256"
+    )
+        || SimpleChecksum::by_name(name).is_some()
+        || FastHash::by_name(name).is_some()
+        || DigestAlgorithm::by_name(name).is_some()
+        || SIGNATURE_BUILTINS.contains(&name)
+}
+
+/// Signatures can't be recomputed without the key, so `verify()` skips them
+fn is_signature(expr: &Expr) -> bool {
+    matches!(expr, Expr::Call { name, .. } if SIGNATURE_BUILTINS.contains(&name.as_str()))
+}
+
+/// Arguments of a keyed builtin (signatures, HMAC)
+pub(crate) struct SignArgs<'e> {
+    pub(crate) key: &'e Expr,
+    pub(crate) data: Vec<Expr>,
+    /// Named options, in the order requested
+    pub(crate) options: Vec<Option<&'e Expr>>,
+}
+
+/// Split keyed builtin arguments into the key (`key = ...`, else the
+/// first positional argument), the data sources and the named `options`
+pub(crate) fn sign_args<'e>(func: &str, args: &'e [Expr], options: &[&str]) -> Result<SignArgs<'e>> {
+    let mut key = None;
+    let mut positional = Vec::new();
+    let mut bound = vec![None; options.len()];
+    for arg in args {
+        let Expr::Named { name, value } = arg else {
+            positional.push(arg);
+            continue;
+        };
+        let slot = if name == "key" {
+            &mut key
+        } else {
+            let index = options.iter().position(|o| o == name).ok_or_else(|| {
+                DelbinError::new(
+                    ErrorCode::E04003,
+                    format!("@{}() has no parameter '{}'", func, name),
+                )
+            })?;
+            &mut bound[index]
+        };
+        if slot.replace(value.as_ref()).is_some() {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                format!("@{}() argument '{}' given twice", func, name),
+            ));
+        }
+    }
+    if key.is_none() && !positional.is_empty() {
+        key = Some(positional.remove(0));
+    }
+    let key = key.ok_or_else(|| {
+        DelbinError::new(ErrorCode::E04004, format!("@{}() requires a key", func))
+    })?;
+    if positional.is_empty() {
+        return Err(DelbinError::new(
+            ErrorCode::E04004,
+            format!("@{}() requires data to sign", func),
+        ));
+    }
+    Ok(SignArgs {
+        key,
+        data: positional.into_iter().cloned().collect(),
+        options: bound,
+    })
 }
 
 /// Returns true if an argument expression references @self data.
-fn arg_refers_to_self(arg: &Expr) -> bool {
+pub(crate) fn arg_refers_to_self(arg: &Expr) -> bool {
     match arg {
         Expr::SelfRef => true,
+        Expr::Named { value, .. } => arg_refers_to_self(value),
         Expr::Range { base, .. } => matches!(base.as_ref(), Expr::SelfRef),
         _ => false,
     }
@@ -0,0 +1,220 @@
+//! Delbin grammar, published for external tooling
+//!
+//! Editors, linters and code generators can highlight, complete and check
+//! Delbin files from [`RULES`] and [`BUILTINS`] instead of vendoring
+//! `grammar.pest`. [`SOURCE`] is the pest grammar the parser is built from;
+//! [`VERSION`] changes whenever the language does.
+//!
+//! # Example
+//!
+//! ```rust
+//! use delbin::grammar::{self, RuleKind};
+//!
+//! let ident = grammar::rule("ident").unwrap();
+//! assert_eq!(ident.kind, RuleKind::Atomic);
+//! assert!(grammar::SOURCE.contains("ident  = @{"));
+//! assert!(grammar::BUILTINS.contains(&"crc32"));
+//! ```
+
+use RuleKind::{Atomic, CompoundAtomic, Normal, Silent};
+
+/// Language version, raised whenever a rule is added, removed or changed
+///
+/// Tools can compare it with the version they were written against.
+pub const VERSION: u32 = 9;
+
+/// The pest grammar the parser is generated from, verbatim
+pub const SOURCE: &str = include_str!("grammar.pest");
+
+/// How a rule matches, as its pest modifier says
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    /// `{ ... }`: a node with children; whitespace and comments allowed
+    /// between its parts
+    Normal,
+    /// `_{ ... }`: matched but produces no node (whitespace, comments)
+    Silent,
+    /// `@{ ... }`: a single token; no whitespace inside, no children
+    Atomic,
+    /// `${ ... }`: no whitespace inside, but keeps its children
+    CompoundAtomic,
+}
+
+/// One grammar rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleInfo {
+    /// Rule name in [`SOURCE`]
+    pub name: &'static str,
+    pub kind: RuleKind,
+    /// What the rule matches
+    pub doc: &'static str,
+}
+
+const fn rule_info(name: &'static str, kind: RuleKind, doc: &'static str) -> RuleInfo {
+    RuleInfo { name, kind, doc }
+}
+
+/// Every rule of [`SOURCE`], in source order
+pub const RULES: &[RuleInfo] = &[
+    rule_info("WHITESPACE", Silent, "Spaces, tabs and newlines between tokens"),
+    rule_info("COMMENT", Silent, "`//` comment up to the end of the line"),
+    rule_info("file", Normal, "Whole file: directives, blocks and struct templates, the header struct and an optional trailer struct"),
+    rule_info("directive", Normal, "Global setting: `@endian = little;`"),
+    rule_info("directive_name", Normal, "`endian`, `base`, `size`, `fill` or `slot_size`"),
+    rule_info("directive_value", Normal, "`little`, `big` or a constant expression (an env variable for `@endian`)"),
+    rule_info("inputs_block", Normal, "Input schema: `@inputs { ... }`"),
+    rule_info("input_decl", Normal, "Declared input: `NAME: u8 required;` or `NAME: str optional = \"dev\";`"),
+    rule_info("input_type", Atomic, "Input type: `u8` to `i64`, `str` or `bytes`"),
+    rule_info("input_presence", Atomic, "`required` or `optional`"),
+    rule_info("input_default", Normal, "Default of an optional input: a string or constant expression"),
+    rule_info("policy_block", Normal, "Integrity policy: `@policy { ... }`"),
+    rule_info("policy_rule", Normal, "Policy rule: `header_crc: required;` or `payload_hash: sha256;`"),
+    rule_info("layout_block", Normal, "Image layout for `assemble()`: `@layout { ... }`"),
+    rule_info("layout_entry", Normal, "Placement: `app @ 0x1000;`"),
+    rule_info("bundle_block", Normal, "Multi-image bundle: `@bundle @align(4K) { boot; app; }`"),
+    rule_info("section_def", Normal, "Derived section: `@section app_w = @whiten(app, 0x221, 0x1FF);`"),
+    rule_info("assert_stmt", Normal, "Build-time check: `@assert(cond, \"message\");`"),
+    rule_info("struct_def", Normal, "Struct with attributes, fields, template instances, TLV lists, `@include`s and `@for` blocks"),
+    rule_info("struct_attr", Normal, "Struct attribute: `@packed`, `@align(n)`, `@encrypt_range(...)`, `@expect_encrypted(...)`"),
+    rule_info("align_attr", Normal, "`align(n)`"),
+    rule_info("encrypt_attr", Normal, "`encrypt_range(from..to, key, iv)`"),
+    rule_info("expect_attr", Normal, "`expect_encrypted(section, ...)`"),
+    rule_info("field_def", Normal, "Field: `name @alias(\"old\"): type = init;`"),
+    rule_info("field_attr", Normal, "Field attribute: `@alias(\"old\", ...)`"),
+    rule_info("include_stmt", Normal, "Fields from another file: `@include \"common.dbin\";`"),
+    rule_info("fragment", Normal, "Whole included file: fields, template instances, TLV lists and `@include`s"),
+    rule_info("template_def", Normal, "Struct template: `struct record<T, LEN> { tag: u16 = T; len: u16 = LEN; }`"),
+    rule_info("instance_def", Normal, "Template instance: `rec0: record<1, 4>;`, fields `rec0_tag` and `rec0_len`"),
+    rule_info("tlv_def", Normal, "TLV list with its tag and length types: `opts: tlv<u8, u16> { ... }`"),
+    rule_info("tlv_record", Normal, "TLV record: `tlv { tag: 0x01; value: @bytes(${SERIAL}); }`"),
+    rule_info("tlv_value", Normal, "TLV value: an expression with a byte length (`@bytes(...)`), or `u32 = 5`"),
+    rule_info("for_block", Normal, "Fields once per list element: `@for entry in ${PARTITIONS} { ... }`"),
+    rule_info("type_spec", Normal, "Scalar or array type"),
+    rule_info("scalar_type", Atomic, "`u8`, `u16`, `u32`, `u64`, `i8`, `i16`, `i32` or `i64`"),
+    rule_info("array_type", Normal, "Array type: `[u8; N]`, or `[u8; _]` as long as its initializer"),
+    rule_info("expr", Normal, "Expression"),
+    rule_info("cmp_expr", Normal, "Comparison, lowest precedence"),
+    rule_info("or_expr", Normal, "Bitwise OR"),
+    rule_info("and_expr", Normal, "Bitwise AND"),
+    rule_info("shift_expr", Normal, "Shifts"),
+    rule_info("add_expr", Normal, "Addition and subtraction"),
+    rule_info("mul_expr", Normal, "Multiplication"),
+    rule_info("unary_expr", Normal, "Bitwise NOT"),
+    rule_info("cmp_op", Normal, "`<=`, `>=`, `==`, `!=`, `<` or `>`"),
+    rule_info("shift_op", Normal, "`<<` or `>>`"),
+    rule_info("add_op", Normal, "`+` or `-`"),
+    rule_info("mul_op", Normal, "`*`"),
+    rule_info("unary_op", Normal, "`~`"),
+    rule_info("primary_expr", Normal, "Operand: call, setting, env variable, number, string, name or parenthesized expression"),
+    rule_info("builtin_call", Normal, "Builtin or custom function call: `@name(args)`"),
+    rule_info("builtin_name", Atomic, "Function name; see `BUILTINS`, others resolve to custom functions"),
+    rule_info("arg_list", Normal, "Comma-separated call arguments"),
+    rule_info("arg", Normal, "Call argument: range, keyword argument or expression"),
+    rule_info("named_arg", Normal, "Keyword argument: `block = 4096`"),
+    rule_info("range_expr", Normal, "`@self` or a range of it: `@self[start..end]`"),
+    rule_info("range_spec", Normal, "`start..end`, both optional"),
+    rule_info("range_start", Normal, "Range start: a field name or offset expression"),
+    rule_info("range_end", Normal, "Range end: the field the range stops before"),
+    rule_info("array_literal", Normal, "Array initializer in brackets"),
+    rule_info("array_content", Normal, "Repeat or list form"),
+    rule_info("repeat_form", Normal, "`[value; N]` or `[value; _]`"),
+    rule_info("list_form", Normal, "`[a, b, c]`"),
+    rule_info("array_elem", Normal, "Array element expression"),
+    rule_info("infer_marker", Normal, "`_`: repeat count taken from the field type, or array length from the initializer"),
+    rule_info("hex_number", Atomic, "Hexadecimal literal: `0x1F`"),
+    rule_info("bin_number", Atomic, "Binary literal: `0b1010`"),
+    rule_info("size_number", Atomic, "Size literal in binary multiples: `512K`, `4M`, `1G`"),
+    rule_info("dec_number", Atomic, "Decimal literal"),
+    rule_info("string", CompoundAtomic, "Double-quoted string"),
+    rule_info("string_inner", Atomic, "String contents"),
+    rule_info("escape_seq", Atomic, "`\\n`, `\\r`, `\\t`, `\\\\`, `\\\"`, `\\0` or `\\xHH`"),
+    rule_info("env_var", Normal, "Env variable: `${NAME}`, or a list element or map entry: `${NAME[0].key}`"),
+    rule_info("env_index", Normal, "List index `[0]` or map key `.key` of an env variable"),
+    rule_info("setting_ref", Atomic, "`@slot_size` or `@base` used as a value, or `@index` in a repeat initializer"),
+    rule_info("ident", Atomic, "Identifier: letter or `_`, then letters, digits or `_`"),
+];
+
+/// Look up a rule by name
+pub fn rule(name: &str) -> Option<&'static RuleInfo> {
+    RULES.iter().find(|rule| rule.name == name)
+}
+
+/// Names of the built-in functions (`@name(...)`), whether or not their
+/// cargo feature is enabled in this build
+pub const BUILTINS: &[&str] = &[
+    // Data
+    "bytes", "strlen", "hex", "base64", "file", "random", "uuid", "uuid5", "uleb128", "sleb128",
+    // Sizes and numbers
+    "sizeof", "offsetof", "count", "numblocks", "compressed", "align_up", "align_down", "min", "max", "clamp", "semver",
+    "timestamp", "dostime", "dosdate", "ipv4", "ipv6", "mac",
+    // Settings used as values: `@base`, `@slot_size`; `@index` of a repeat initializer
+    "base", "slot_size", "index",
+    // CRCs and checksums
+    "crc32", "crc", "crc16", "sum8", "sum16", "sum32", "xor8", "checksum8_2c", "fletcher16", "fletcher32",
+    "adler32", "xxh32", "xxh64", "xxh3", "murmur3_32",
+    // Digests and MACs
+    "sha256", "sha384", "sha512", "sha3_256", "sha1", "md5", "hmac_sha256", "merkle_root", "block_hashes",
+    // Signatures
+    "sign", "ed25519", "ecdsa_p256", "rsa_sign",
+    // Section transforms (`@section name = ...;`)
+    "whiten", "merkle_tree", "encrypt", "compress",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(name, modifier)` of every rule definition in the pest source
+    fn declared_rules() -> Vec<(String, String)> {
+        SOURCE
+            .lines()
+            .filter(|line| line.starts_with(|c: char| c.is_ascii_alphabetic()))
+            .filter_map(|line| {
+                let (name, rest) = line.split_once('=')?;
+                let modifier = rest.trim_start().split('{').next()?.trim();
+                Some((name.trim().to_string(), modifier.to_string()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rules_match_source() {
+        let declared = declared_rules();
+        let listed: Vec<(String, String)> = RULES
+            .iter()
+            .map(|rule| {
+                let modifier = match rule.kind {
+                    Normal => "",
+                    Silent => "_",
+                    Atomic => "@",
+                    CompoundAtomic => "$",
+                };
+                (rule.name.to_string(), modifier.to_string())
+            })
+            .collect();
+        assert_eq!(listed, declared, "update RULES (and VERSION) with grammar.pest");
+        assert!(RULES.iter().all(|rule| !rule.doc.is_empty()));
+    }
+
+    #[test]
+    fn test_builtins_are_known() {
+        for name in BUILTINS {
+            assert!(crate::check::is_builtin(name), "{name}");
+        }
+        let mut sorted = BUILTINS.to_vec();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(sorted.len(), BUILTINS.len());
+        for list in [
+            crate::check::OTHER_BUILTINS,
+            crate::builtin::ARITHMETIC_BUILTINS,
+            crate::eval::ADDRESS_BUILTINS,
+            crate::eval::SIGNATURE_BUILTINS,
+            crate::parser::SECTION_TRANSFORMS,
+        ] {
+            for name in list {
+                assert!(BUILTINS.contains(name), "{name} missing from BUILTINS");
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Delbin layout inference (experimental)
+//!
+//! Proposes a draft DSL from several sample binaries of the same format:
+//! bytes that never change become constant fields, changing bytes become
+//! integer fields, and 32-bit fields that hold the CRC32 of everything before
+//! them become `@crc32(@self[..field])`. The result is a starting point for
+//! reverse-engineering, not a finished format definition.
+
+use crate::builtin;
+use crate::error::{DelbinError, ErrorCode, Result};
+
+/// Inferred field
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Region {
+    /// Identical in every sample
+    Constant { offset: usize, bytes: Vec<u8> },
+    /// Differs between samples
+    Varying { offset: usize, size: usize },
+    /// Little-endian CRC32 of all bytes before it
+    Crc32 { offset: usize },
+}
+
+/// Propose a DSL skeleton from sample binaries of the same format
+///
+/// Only the common prefix (the length of the shortest sample) is analysed.
+/// Field names are placeholders derived from offsets.
+pub fn infer_dsl(samples: &[&[u8]]) -> Result<String> {
+    let len = samples.iter().map(|s| s.len()).min().unwrap_or(0);
+    if len == 0 {
+        return Err(DelbinError::new(
+            ErrorCode::E04003,
+            "Layout inference needs at least one non-empty sample",
+        ));
+    }
+
+    let regions = split_regions(samples, len);
+    Ok(render(&regions, samples.len(), len))
+}
+
+/// Split the common prefix into constant runs and integer-sized varying fields
+fn split_regions(samples: &[&[u8]], len: usize) -> Vec<Region> {
+    // A changing byte marks its whole aligned 32-bit word as varying, so small
+    // counters stored in a u32 aren't split into u8 + constant zero bytes
+    let mut varying = vec![false; len];
+    for i in 0..len {
+        if samples.iter().any(|s| s[i] != samples[0][i]) {
+            let word = i & !3;
+            varying[word..(word + 4).min(len)].fill(true);
+        }
+    }
+    let constant_at = |i: usize| !varying[i];
+
+    let mut regions = Vec::new();
+    let mut pos = 0;
+    while pos < len {
+        let is_const = constant_at(pos);
+        let mut end = pos + 1;
+        while end < len && constant_at(end) == is_const {
+            end += 1;
+        }
+
+        if is_const {
+            regions.push(Region::Constant {
+                offset: pos,
+                bytes: samples[0][pos..end].to_vec(),
+            });
+        } else {
+            // Split changing bytes into naturally aligned u32/u16/u8 fields
+            let mut p = pos;
+            while p < end {
+                let size = [4, 2, 1]
+                    .into_iter()
+                    .find(|&n| p % n == 0 && p + n <= end)
+                    .unwrap_or(1);
+                if size == 4 && is_crc32_of_prefix(samples, p) {
+                    regions.push(Region::Crc32 { offset: p });
+                } else {
+                    regions.push(Region::Varying { offset: p, size });
+                }
+                p += size;
+            }
+        }
+        pos = end;
+    }
+    regions
+}
+
+/// True when the u32 at `offset` is the CRC32 of the preceding bytes in every sample
+fn is_crc32_of_prefix(samples: &[&[u8]], offset: usize) -> bool {
+    samples.iter().all(|s| {
+        let stored = u32::from_le_bytes(s[offset..offset + 4].try_into().unwrap());
+        stored == builtin::crc32(&s[..offset])
+    })
+}
+
+/// Render regions as DSL text
+fn render(regions: &[Region], sample_count: usize, len: usize) -> String {
+    let mut out = String::new();
+    out.push_str(&format!(
+        "// Draft layout inferred from {} sample(s), {} bytes (experimental).\n",
+        sample_count, len
+    ));
+    out.push_str("// Field names are placeholders; review every type and initializer.\n");
+    out.push_str("@endian = little;\n\nstruct inferred @packed {\n");
+
+    for region in regions {
+        let line = match region {
+            Region::Constant { offset, bytes } => render_constant(*offset, bytes),
+            Region::Varying { offset, size } => {
+                let ty = match size {
+                    4 => "u32",
+                    2 => "u16",
+                    _ => "u8",
+                };
+                format!("field_{:04x}: {};", offset, ty)
+            }
+            Region::Crc32 { offset } => {
+                format!("crc_{0:04x}: u32 = @crc32(@self[..crc_{0:04x}]);", offset)
+            }
+        };
+        out.push_str("    ");
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Render a constant run as `@bytes("...")`, an element list, or zero fill
+fn render_constant(offset: usize, bytes: &[u8]) -> String {
+    let len = bytes.len();
+    if bytes.iter().all(|&b| b == 0) {
+        return format!("reserved_{:04x}: [u8; {}];", offset, len);
+    }
+
+    // Printable text followed by zero padding: @bytes() zero-fills the rest
+    let text_len = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    let text = &bytes[..text_len];
+    if text.iter().all(|&b| (0x20..0x7F).contains(&b) && b != b'"' && b != b'\\') {
+        return format!(
+            "const_{:04x}: [u8; {}] = @bytes(\"{}\");",
+            offset,
+            len,
+            String::from_utf8_lossy(text)
+        );
+    }
+
+    let elems: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+    format!("const_{:04x}: [u8; {}] = [{}];", offset, len, elems.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn sample(version: u32) -> Vec<u8> {
+        let dsl = r#"
+            @endian = little;
+            struct h @packed {
+                magic:   [u8; 8] = @bytes("FPK");
+                version: u32     = ${VERSION};
+                flags:   u32     = 0x01020304;
+                crc:     u32     = @crc32(@self[..crc]);
+                _pad:    [u8; 4];
+            }
+        "#;
+        let mut env = HashMap::new();
+        env.insert("VERSION".to_string(), crate::Value::U64(version as u64));
+        crate::generate(dsl, &env, &HashMap::new()).unwrap().data
+    }
+
+    #[test]
+    fn test_infer_finds_constants_fields_and_crc() {
+        let a = sample(1);
+        let b = sample(0x0203);
+        let dsl = infer_dsl(&[&a, &b]).unwrap();
+
+        assert!(dsl.contains(r#"const_0000: [u8; 8] = @bytes("FPK");"#), "{dsl}");
+        assert!(dsl.contains("field_0008: u32;"), "{dsl}");
+        assert!(dsl.contains("const_000c: [u8; 4] = [0x04, 0x03, 0x02, 0x01];"), "{dsl}");
+        assert!(dsl.contains("crc_0010: u32 = @crc32(@self[..crc_0010]);"), "{dsl}");
+        assert!(dsl.contains("reserved_0014: [u8; 4];"), "{dsl}");
+
+        // The draft must be valid DSL with the same total size
+        let file = crate::parser::parse(&dsl).unwrap();
+        let layout_len: usize = crate::generate(&dsl, &HashMap::new(), &HashMap::new())
+            .unwrap()
+            .data
+            .len();
+        assert_eq!(layout_len, a.len());
+        assert_eq!(file.struct_def.name, "inferred");
+    }
+
+    #[test]
+    fn test_infer_rejects_empty_input() {
+        assert!(infer_dsl(&[]).is_err());
+        assert!(infer_dsl(&[b"".as_slice()]).is_err());
+    }
+}
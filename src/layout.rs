@@ -0,0 +1,37 @@
+//! Delbin layout types
+
+use crate::types::ScalarType;
+
+/// Position and size of one field
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldLayout {
+    pub name: String,
+    /// Byte offset from the start of the struct
+    pub offset: usize,
+    /// Size in bytes
+    pub size: usize,
+    /// Scalar type, or element type for arrays
+    pub elem: ScalarType,
+    /// Element count for arrays, `None` for scalars
+    pub count: Option<usize>,
+}
+
+/// Struct layout computed without generating data
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Layout {
+    /// Struct name
+    pub name: String,
+    /// Fields in declaration order
+    pub fields: Vec<FieldLayout>,
+    /// Total struct size, including `@align` padding
+    pub size: usize,
+}
+
+impl Layout {
+    /// Look up a field by name
+    pub fn field(&self, name: &str) -> Option<&FieldLayout> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+}
@@ -1,133 +1,858 @@
 use std::collections::HashMap;
-use std::io::Read;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Read};
 
-use clap::Parser;
-use delbin::{generate, to_hex_string, Value};
+use clap::{Parser, Subcommand};
+use delbin::diagnostic::Renderer;
+use delbin::emit::{make_deps_with_includes, sarif};
+use delbin::infer::infer_dsl;
+use delbin::parser::IncludePath;
+use delbin::{
+    assemble_with_options, env_from_toml, generate_with_options, infer_env_value, layout,
+    layout_with_includes, merge_to, parse_partial, to_hex_string, verify_with_includes, CheckKind,
+    DelbinError, DelbinWarning, DuplicatePolicy, FieldLayout, FileSections, GenerateOptions,
+    HeaderPosition, InputSet, MergeOptions, Value,
+};
 
 #[derive(Parser, Debug)]
 #[command(
     author,
     version,
-    about = "Delbin: Descriptive Language for Binary Object\nGenerates binary firmware headers from a DSL description."
+    about = "Delbin: Descriptive Language for Binary Object\nGenerates binary firmware headers from a DSL description.",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a header (the same as giving INPUT without a subcommand,
+    /// but --output writes raw bytes unless --format says otherwise)
+    Gen(Box<Args>),
+
+    /// Generate a header and combine it with an image (prepended by default)
+    Merge {
+        /// DSL file path. Use '-' to read from stdin.
+        input: String,
+
+        /// Image file, exposed to the DSL as the --section-name section
+        image: String,
+
+        /// Write the merged image to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+
+        /// Section name the image is exposed as in the DSL
+        #[arg(long, default_value = "image", value_name = "NAME")]
+        section_name: String,
+
+        /// Write the header after the image (a footer)
+        #[arg(long, conflicts_with = "at")]
+        append: bool,
+
+        /// Write the header over the image at byte OFFSET
+        #[arg(long, value_name = "OFFSET", value_parser = parse_number)]
+        at: Option<u64>,
+
+        /// Pad the header to N bytes (its reserved slot)
+        #[arg(long, value_name = "N", value_parser = parse_number)]
+        pad_to: Option<u64>,
+
+        /// Padding byte
+        #[arg(long, default_value = "0xFF", value_name = "BYTE", value_parser = parse_byte)]
+        fill: u8,
+
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+
+    /// Build a complete flash image from the DSL's @layout block
+    Assemble {
+        /// DSL file path. Use '-' to read from stdin.
+        input: String,
+
+        /// Write the image to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+
+    /// Decode an existing image: each field's offset, raw bytes and value
+    Inspect {
+        /// DSL file path. Use '-' to read from stdin.
+        input: String,
+
+        /// Binary holding the header at offset 0 (a header or a merged image)
+        image: String,
+
+        /// Print a JSON array of fields instead of a table
+        #[arg(long)]
+        json: bool,
+
+        /// Set environment variable, for arrays sized by ${VAR} (may be repeated)
+        #[arg(short = 'D', long = "env", value_name = "KEY=VALUE", action = clap::ArgAction::Append)]
+        env_vars: Vec<String>,
+    },
+
+    /// Check an image's checksums, constants and env-derived fields against
+    /// the DSL; exits with 1 on any mismatch
+    Verify {
+        /// DSL file path. Use '-' to read from stdin.
+        input: String,
+
+        /// Binary holding the header at offset 0 (a header or a merged image)
+        image: String,
+
+        /// Section name the bytes after the header are exposed as, unless a
+        /// --section of that name is given
+        #[arg(long, default_value = "image", value_name = "NAME")]
+        section_name: String,
+
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+
+    /// (Experimental) Propose a draft DSL from sample binaries of the same format
+    Infer {
+        /// Sample binary files (two or more give better results)
+        #[arg(required = true, value_name = "SAMPLE")]
+        samples: Vec<String>,
+
+        /// Write the draft DSL to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
-    /// DSL input file path. Use '-' to read from stdin.
-    input: String,
+    /// DSL input file path. Use '-' to read from stdin. Defaults to
+    /// `input` in the config file.
+    input: Option<String>,
 
     /// Write output to FILE instead of stdout
     #[arg(short, long, value_name = "FILE")]
     output: Option<String>,
 
-    /// Output format: 'hex' (uppercase hex string) or 'bin' (raw bytes)
-    #[arg(long, default_value = "hex", value_name = "FORMAT")]
-    format: String,
+    /// Output format: 'hex' (uppercase hex string, the default), 'bin' (raw
+    /// bytes) or 'map' (hex dump split and labeled by field, for review)
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<String>,
+
+    #[command(flatten)]
+    inputs: Inputs,
+
+    /// Write a Make dependency rule for the output to FILE (needs --output)
+    #[arg(long, value_name = "FILE")]
+    depfile: Option<String>,
+
+    /// Also write a detached signature of the generated binary to FILE
+    #[arg(long, value_name = "FILE", requires = "signing_key")]
+    signature: Option<String>,
+
+    /// Private key for --signature: a raw 32-byte seed (ed25519) or
+    /// scalar (ecdsa-p256*), or a PKCS#8/PKCS#1 DER or PEM key (rsa-*)
+    #[arg(long, value_name = "FILE", requires = "signature")]
+    signing_key: Option<String>,
+
+    /// Signature algorithm: ed25519, ecdsa-p256 (raw r||s), ecdsa-p256-der,
+    /// rsa-pkcs1 or rsa-pss
+    #[arg(long, default_value = "ed25519", value_name = "ALG")]
+    signature_alg: String,
+
+    /// Derive @random() and @uuid() fields from N instead of the OS random source, for
+    /// reproducible builds
+    #[arg(long, value_name = "N")]
+    rng_seed: Option<u64>,
+
+    /// Write warnings and errors as a SARIF log to FILE
+    #[arg(long, value_name = "FILE")]
+    sarif: Option<String>,
+}
+
+/// Env, sections and include paths, shared by the generating commands
+#[derive(clap::Args, Debug)]
+struct Inputs {
+    /// Read settings from FILE (default: ./delbin.toml, if it exists); the
+    /// command line adds to and overrides them
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<String>,
 
     /// Set environment variable (may be repeated)
-    #[arg(long = "env", value_name = "KEY=VALUE", action = clap::ArgAction::Append)]
+    #[arg(short = 'D', long = "env", value_name = "KEY=VALUE", action = clap::ArgAction::Append)]
     env_vars: Vec<String>,
 
     /// Load section data from file (may be repeated)
     #[arg(long = "section", value_name = "NAME=FILE", action = clap::ArgAction::Append)]
     sections: Vec<String>,
 
+    /// Search DIR for @include files (may be repeated; searched in order)
+    #[arg(short = 'I', long = "include-path", value_name = "DIR", action = clap::ArgAction::Append)]
+    include_path: Vec<String>,
+
+    /// A KEY or NAME given twice: 'error', or 'last-wins' (the default;
+    /// warns with --verbose)
+    #[arg(long, value_name = "POLICY")]
+    on_duplicate: Option<String>,
+
     /// Print warnings to stderr
     #[arg(long)]
     verbose: bool,
 }
 
-fn main() {
-    let args = Args::parse();
+/// Config file read when --config isn't given
+const CONFIG_FILE: &str = "delbin.toml";
 
-    // Read DSL source
-    let dsl = if args.input == "-" {
-        let mut s = String::new();
-        if let Err(e) = std::io::stdin().read_to_string(&mut s) {
-            eprintln!("Error reading stdin: {e}");
-            std::process::exit(1);
+/// Settings from the config file
+///
+/// ```toml
+/// input = "header.dbin"
+/// output = "build/header.bin"
+/// include_path = ["shared"]
+///
+/// [env]
+/// VERSION_MAJOR = 1
+/// VERSION_STRING = "1.2.3"
+///
+/// [sections]
+/// image = "build/app.bin"
+/// ```
+///
+/// Paths are relative to the file's directory. `input`, `output`, `format`
+/// and `rng_seed` are for `gen`; the rest apply to every command taking -D.
+#[derive(Debug, Default)]
+struct Config {
+    /// The file the settings came from, named as the source of duplicates
+    path: String,
+    input: Option<String>,
+    output: Option<String>,
+    format: Option<String>,
+    rng_seed: Option<u64>,
+    on_duplicate: Option<String>,
+    verbose: bool,
+    include_path: Vec<String>,
+    env: Vec<(String, Value)>,
+    sections: Vec<(String, String)>,
+}
+
+/// Env variables, section files and the warnings from reading them
+struct LoadedInputs {
+    env: HashMap<String, Value>,
+    section_files: HashMap<String, String>,
+    include_path: Vec<String>,
+    verbose: bool,
+    warnings: Vec<DelbinWarning>,
+}
+
+impl LoadedInputs {
+    fn sections(&self) -> FileSections {
+        let mut sections = FileSections::new();
+        for (name, path) in &self.section_files {
+            sections.insert(name, path);
         }
-        s
-    } else {
-        match std::fs::read_to_string(&args.input) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Error reading '{}': {e}", args.input);
-                std::process::exit(1);
+        sections
+    }
+
+    /// Options searching the -I directories for @include files
+    fn include_options(&self) -> GenerateOptions {
+        let mut options = GenerateOptions::default();
+        for dir in &self.include_path {
+            options = options.with_include_path(dir);
+        }
+        options
+    }
+
+    /// Print warnings if --verbose
+    fn print_warnings(&self, renderer: &Renderer, warnings: &[DelbinWarning]) {
+        if self.verbose {
+            for w in warnings {
+                eprint!("{}", renderer.render_warning(w));
             }
         }
-    };
+    }
+}
 
-    // Parse --env KEY=VALUE pairs
-    let mut env: HashMap<String, Value> = HashMap::new();
-    for kv in &args.env_vars {
-        if let Some((k, v)) = kv.split_once('=') {
-            let value = if let Ok(n) = v.parse::<u64>() {
-                Value::U64(n)
-            } else {
-                Value::String(v.to_string())
+fn main() {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Gen(args)) => run_generate(*args, true),
+        Some(Command::Merge { input, image, output, section_name, append, at, pad_to, fill, inputs }) => {
+            let header_position = match (append, at) {
+                (true, _) => HeaderPosition::Append,
+                (false, Some(offset)) => HeaderPosition::AtOffset(offset),
+                (false, None) => HeaderPosition::Prepend,
             };
-            env.insert(k.to_string(), value);
-        } else {
-            eprintln!("Warning: ignoring malformed --env value (expected KEY=VALUE): {kv}");
+            let options = MergeOptions { section_name, header_position, pad_to, fill, payload: None, include_path: Vec::new() };
+            run_merge(&input, &image, output.as_deref(), &options, &inputs)
         }
+        Some(Command::Assemble { input, output, inputs }) => run_assemble(&input, output.as_deref(), &inputs),
+        Some(Command::Inspect { input, image, json, env_vars }) => run_inspect(&input, &image, json, &env_vars),
+        Some(Command::Verify { input, image, section_name, inputs }) => run_verify(&input, &image, &section_name, &inputs),
+        Some(Command::Infer { samples, output }) => run_infer(&samples, output.as_deref()),
+        None => run_generate(cli.args, false),
     }
+}
 
-    // Parse --section NAME=FILE pairs
-    let mut sections: HashMap<String, Vec<u8>> = HashMap::new();
-    for nf in &args.sections {
-        if let Some((name, path)) = nf.split_once('=') {
-            match std::fs::read(path) {
-                Ok(data) => {
-                    sections.insert(name.to_string(), data);
-                }
-                Err(e) => {
-                    eprintln!("Error reading section '{name}' from '{path}': {e}");
-                    std::process::exit(1);
-                }
+fn run_infer(samples: &[String], output: Option<&str>) {
+    let mut blobs = Vec::new();
+    for path in samples {
+        match std::fs::read(path) {
+            Ok(data) => blobs.push(data),
+            Err(e) => {
+                eprintln!("Error reading '{path}': {e}");
+                std::process::exit(1);
             }
-        } else {
-            eprintln!("Warning: ignoring malformed --section value (expected NAME=FILE): {nf}");
         }
     }
+    let refs: Vec<&[u8]> = blobs.iter().map(Vec::as_slice).collect();
 
-    // Generate
-    let result = match generate(&dsl, &env, &sections) {
-        Ok(r) => r,
+    let dsl = match infer_dsl(&refs) {
+        Ok(d) => d,
         Err(e) => {
             eprintln!("Error: {e}");
             std::process::exit(1);
         }
     };
+    write_output(output, dsl.as_bytes());
+}
+
+/// `delbin gen` (`gen` true) or the bare `delbin INPUT`, which prints hex
+/// even with --output
+fn run_generate(args: Args, gen: bool) {
+    let config = load_config(&args.inputs);
+    let Some(input) = args.input.or(config.input.clone()) else {
+        eprintln!("error: no INPUT given, and no `input` in {CONFIG_FILE}");
+        std::process::exit(2);
+    };
+    let output = args.output.or(config.output.clone());
+    if args.depfile.is_some() && output.is_none() {
+        eprintln!("error: --depfile needs --output");
+        std::process::exit(2);
+    }
+    let default_format = if gen && output.is_some() { "bin" } else { "hex" };
+    let format = args.format.or(config.format.clone());
+
+    let dsl = read_dsl(&input);
+    let renderer = renderer(&input);
+    let loaded = load_inputs(&args.inputs, &config, &[], &renderer);
+    let sections = loaded.sections();
+
+    let mut options = loaded.include_options();
+    if let Some(seed) = args.rng_seed.or(config.rng_seed) {
+        options = options.with_rng_seed(seed);
+    }
 
-    // Print warnings if verbose
-    if args.verbose {
-        for w in &result.warnings {
-            eprintln!("[{:?}] {}", w.code, w.message);
+    // Signing key for the detached signature
+    if let Some(path) = &args.signing_key {
+        let key = match std::fs::read(path) {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("Error reading '{path}': {e}");
+                std::process::exit(1);
+            }
+        };
+        options = match with_signing_key(options, &args.signature_alg, &key) {
+            Ok(options) => options,
+            Err(e) => {
+                eprint!("{}", Renderer::new(path.as_str()).render_error(&e));
+                std::process::exit(1);
+            }
+        };
+    }
+
+    // Generate
+    let result = match generate_with_options(&dsl, &loaded.env, &sections, &options) {
+        Ok(r) => r,
+        Err(e) => {
+            eprint!("{}", renderer.render_error(&e));
+            if let Some(path) = &args.sarif {
+                write_output(Some(path), sarif(&input, &[e], &[]).as_bytes());
+            }
+            std::process::exit(1);
         }
+    };
+    let mut warnings = loaded.warnings.clone();
+    warnings.extend(result.warnings.iter().cloned());
+    if let Some(path) = &args.sarif {
+        write_output(Some(path), sarif(&input, &[], &warnings).as_bytes());
+    }
+    loaded.print_warnings(&renderer, &warnings);
+
+    if let (Some(path), Some(signature)) = (&args.signature, &result.signature) {
+        write_output(Some(path), signature);
     }
 
     // Format and write output
-    let output_bytes: Vec<u8> = match args.format.as_str() {
+    let output_bytes: Vec<u8> = match format.as_deref().unwrap_or(default_format) {
         "hex" => {
             let hex = to_hex_string(&result.data);
             format!("{hex}\n").into_bytes()
         }
         "bin" => result.data,
+        "map" => result.field_map().into_bytes(),
         other => {
-            eprintln!("Unknown --format '{other}'. Use 'hex' or 'bin'.");
+            eprintln!("Unknown --format '{other}'. Use 'hex', 'bin' or 'map'.");
             std::process::exit(1);
         }
     };
 
-    match &args.output {
+    write_output(output.as_deref(), &output_bytes);
+
+    if let (Some(depfile), Some(target)) = (&args.depfile, &output) {
+        let dsl_path = if input == "-" { "" } else { input.as_str() };
+        let includes = IncludePath::new(&loaded.include_path);
+        match make_deps_with_includes(&dsl, dsl_path, target, &loaded.section_files, &includes, None) {
+            Ok(rule) => write_output(Some(depfile), rule.as_bytes()),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn run_merge(input: &str, image: &str, output: Option<&str>, options: &MergeOptions, inputs: &Inputs) {
+    let config = load_config(inputs);
+    let dsl = read_dsl(input);
+    let renderer = renderer(input);
+    let loaded = load_inputs(inputs, &config, &[("IMAGE", &options.section_name, image)], &renderer);
+    let options = MergeOptions { include_path: loaded.include_path.iter().map(Into::into).collect(), ..options.clone() };
+
+    let result = with_output(output, |mut w| merge_to(&dsl, &loaded.env, &loaded.sections(), &options, &mut w));
+    let result = result.unwrap_or_else(|e| {
+        // Don't leave a half-written image behind for the next build step
+        if let Some(path) = output {
+            let _ = std::fs::remove_file(path);
+        }
+        exit_with(&renderer, &e)
+    });
+    let mut warnings = loaded.warnings.clone();
+    warnings.extend(result.warnings);
+    loaded.print_warnings(&renderer, &warnings);
+}
+
+fn run_assemble(input: &str, output: Option<&str>, inputs: &Inputs) {
+    let config = load_config(inputs);
+    let dsl = read_dsl(input);
+    let renderer = renderer(input);
+    let loaded = load_inputs(inputs, &config, &[], &renderer);
+
+    let result = assemble_with_options(&dsl, &loaded.env, &loaded.sections(), &loaded.include_options())
+        .unwrap_or_else(|e| exit_with(&renderer, &e));
+    write_output(output, &result.data);
+    let mut warnings = loaded.warnings.clone();
+    warnings.extend(result.warnings);
+    loaded.print_warnings(&renderer, &warnings);
+}
+
+fn run_inspect(input: &str, image: &str, json: bool, env_vars: &[String]) {
+    let dsl = read_dsl(input);
+    let renderer = renderer(input);
+    let data = std::fs::read(image).unwrap_or_else(|e| {
+        eprintln!("Error reading '{image}': {e}");
+        std::process::exit(1);
+    });
+    let mut env = InputSet::env(DuplicatePolicy::LastWins);
+    insert_env_args(&mut env, env_vars, &renderer);
+    let (env, _) = env.into_parts();
+
+    let layout = layout(&dsl, &env).unwrap_or_else(|e| exit_with(&renderer, &e));
+    let decoded = parse_partial(&dsl, &env, &data).unwrap_or_else(|e| exit_with(&renderer, &e));
+    let raw = |f: &FieldLayout| &data[f.offset.min(data.len())..(f.offset + f.size).min(data.len())];
+
+    let mut out = String::new();
+    if json {
+        let rows: Vec<String> = layout
+            .fields
+            .iter()
+            .map(|f| {
+                let value = match decoded.fields.get(&f.name) {
+                    Some(Value::String(s)) => json_string(s),
+                    Some(v) if v.as_u64().is_some() || matches!(v, Value::I64(_)) => v.to_string(),
+                    Some(v) => json_string(&v.to_string()),
+                    None => "null".to_string(),
+                };
+                format!(
+                    "  {{\"name\": {}, \"offset\": {}, \"size\": {}, \"raw\": \"{}\", \"value\": {}}}",
+                    json_string(&f.name),
+                    f.offset,
+                    f.size,
+                    to_hex_string(raw(f)),
+                    value
+                )
+            })
+            .collect();
+        out = format!("[\n{}\n]\n", rows.join(",\n"));
+    } else {
+        let name_width = layout.fields.iter().map(|f| f.name.len()).max().unwrap_or(0).max(5);
+        let _ = writeln!(out, "{:name_width$}  {:>8}  {:<32}  VALUE", "FIELD", "OFFSET", "RAW");
+        for f in &layout.fields {
+            let bytes = raw(f);
+            let mut hex = bytes.iter().take(15).map(|b| format!("{b:02X}")).collect::<Vec<_>>().join("");
+            if bytes.len() > 15 {
+                hex.push_str("..");
+            }
+            let value = match decoded.fields.get(&f.name) {
+                Some(v) => display_value(v),
+                None => "<truncated>".to_string(),
+            };
+            let _ = writeln!(out, "{:name_width$}  {:>#8X}  {hex:<32}  {value}", f.name, f.offset);
+        }
+        if !decoded.is_complete() {
+            let _ = writeln!(out, "({} of {} header bytes present)", data.len(), decoded.struct_size);
+        }
+    }
+    write_output(None, out.as_bytes());
+}
+
+fn run_verify(input: &str, image: &str, section_name: &str, inputs: &Inputs) {
+    let config = load_config(inputs);
+    let dsl = read_dsl(input);
+    let renderer = renderer(input);
+    let data = std::fs::read(image).unwrap_or_else(|e| {
+        eprintln!("Error reading '{image}': {e}");
+        std::process::exit(1);
+    });
+    let loaded = load_inputs(inputs, &config, &[], &renderer);
+    let includes = IncludePath::new(&loaded.include_path);
+    loaded.print_warnings(&renderer, &loaded.warnings);
+
+    let mut sections = HashMap::new();
+    for (name, path) in &loaded.section_files {
+        let bytes = std::fs::read(path).unwrap_or_else(|e| {
+            eprintln!("Error reading section '{name}' from '{path}': {e}");
+            std::process::exit(1);
+        });
+        sections.insert(name.clone(), bytes);
+    }
+    if !sections.contains_key(section_name) {
+        let header_size = layout_with_includes(&dsl, &loaded.env, &includes).unwrap_or_else(|e| exit_with(&renderer, &e)).size;
+        sections.insert(section_name.to_string(), data.get(header_size..).unwrap_or_default().to_vec());
+    }
+
+    let report =
+        verify_with_includes(&dsl, &data, &sections, &loaded.env, &includes).unwrap_or_else(|e| exit_with(&renderer, &e));
+    let name_width = report.checks.iter().map(|c| c.field.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for check in &report.checks {
+        let kind = match check.kind {
+            CheckKind::Checksum => "checksum",
+            CheckKind::Constant => "constant",
+            CheckKind::Input => "input",
+        };
+        if check.passed() {
+            let _ = writeln!(out, "ok    {:name_width$}  {kind}", check.field);
+        } else {
+            let _ = writeln!(
+                out,
+                "FAIL  {:name_width$}  {kind:8}  expected {}, found {}",
+                check.field,
+                to_hex_string(&check.expected),
+                to_hex_string(&check.actual)
+            );
+        }
+    }
+    let failed = report.failures().count();
+    if failed == 0 {
+        let _ = writeln!(out, "{} checks passed", report.checks.len());
+    } else {
+        let _ = writeln!(out, "{failed} of {} checks failed", report.checks.len());
+    }
+    write_output(None, out.as_bytes());
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// A decoded value for the inspect table: byte arrays holding NUL-padded
+/// text are shown as the text, long integers in hex too
+fn display_value(value: &Value) -> String {
+    if let Some(bytes) = value.as_bytes() {
+        let text = bytes.split(|&b| b == 0).next().unwrap_or_default();
+        let padding_only = bytes[text.len()..].iter().all(|&b| b == 0);
+        if !text.is_empty() && padding_only && text.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+            return format!("{:?}", String::from_utf8_lossy(text));
+        }
+    }
+    match value.as_u64() {
+        Some(n) if n > 9 => format!("{n} ({n:#X})"),
+        _ => value.to_string(),
+    }
+}
+
+/// JSON string literal for `s`
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// DSL source from INPUT, or stdin for '-'
+fn read_dsl(input: &str) -> String {
+    if input == "-" {
+        let mut s = String::new();
+        if let Err(e) = std::io::stdin().read_to_string(&mut s) {
+            eprintln!("Error reading stdin: {e}");
+            std::process::exit(1);
+        }
+        s
+    } else {
+        match std::fs::read_to_string(input) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Error reading '{input}': {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Diagnostics renderer for INPUT, colored on a terminal unless NO_COLOR is set
+fn renderer(input: &str) -> Renderer {
+    Renderer::new(if input == "-" { "<stdin>" } else { input })
+        .with_color(std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none())
+}
+
+fn exit_with(renderer: &Renderer, e: &DelbinError) -> ! {
+    eprint!("{}", renderer.render_error(e));
+    std::process::exit(1);
+}
+
+/// The config file named by --config, or ./delbin.toml if it exists
+fn load_config(inputs: &Inputs) -> Config {
+    let path = match &inputs.config {
+        Some(path) => path.clone(),
+        None if std::path::Path::new(CONFIG_FILE).is_file() => CONFIG_FILE.to_string(),
+        None => return Config::default(),
+    };
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("Error reading '{path}': {e}");
+        std::process::exit(1);
+    });
+    let fail = |message: String| -> ! {
+        eprintln!("Error in '{path}': {message}");
+        std::process::exit(1);
+    };
+    let settings = env_from_toml(&text).unwrap_or_else(|e| exit_with(&Renderer::new(path.as_str()), &e));
+
+    let dir = std::path::Path::new(&path).parent().unwrap_or(std::path::Path::new(""));
+    let resolve = |file: String| dir.join(file).display().to_string();
+    let string = |key: &str, value: Value| match value {
+        Value::String(s) => s,
+        other => fail(format!("`{key}` must be a string, not {other}")),
+    };
+    let table = |key: &str, value: Value| -> Vec<(String, Value)> {
+        let Value::Map(map) = value else {
+            fail(format!("`{key}` must be a table"));
+        };
+        let mut entries: Vec<_> = map.into_iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    };
+
+    let mut config = Config { path: path.clone(), ..Config::default() };
+    for (key, value) in settings {
+        match key.as_str() {
+            "input" => config.input = Some(resolve(string(&key, value))),
+            "output" => config.output = Some(resolve(string(&key, value))),
+            "format" => config.format = Some(string(&key, value)),
+            "on_duplicate" => config.on_duplicate = Some(string(&key, value)),
+            "rng_seed" => match value {
+                Value::U64(seed) => config.rng_seed = Some(seed),
+                other => fail(format!("`rng_seed` must be an integer, not {other}")),
+            },
+            "verbose" => match value {
+                Value::U8(flag) => config.verbose = flag != 0,
+                other => fail(format!("`verbose` must be true or false, not {other}")),
+            },
+            "include_path" => match value {
+                Value::List(dirs) => {
+                    config.include_path = dirs.into_iter().map(|dir| resolve(string("include_path", dir))).collect()
+                }
+                other => fail(format!("`include_path` must be a list of directories, not {other}")),
+            },
+            "env" => config.env = table(&key, value),
+            "sections" => {
+                config.sections = table(&key, value)
+                    .into_iter()
+                    .map(|(name, file)| {
+                        let file = resolve(string(&format!("sections.{name}"), file));
+                        (name, file)
+                    })
+                    .collect()
+            }
+            other => fail(format!(
+                "unknown setting `{other}` (expected input, output, format, rng_seed, on_duplicate, verbose, \
+                 include_path, env or sections)"
+            )),
+        }
+    }
+    config
+}
+
+/// Merge the config file with the --env and --section pairs; the command
+/// line comes last, so with 'last-wins' it overrides the config. `extra`
+/// sections (source, name, path) go between the two.
+fn load_inputs(inputs: &Inputs, config: &Config, extra: &[(&str, &str, &str)], renderer: &Renderer) -> LoadedInputs {
+    let policy = match inputs.on_duplicate.as_deref().or(config.on_duplicate.as_deref()).unwrap_or("last-wins") {
+        "error" => DuplicatePolicy::Error,
+        "last-wins" => DuplicatePolicy::LastWins,
+        other => {
+            eprintln!("Unknown --on-duplicate '{other}'. Use 'error' or 'last-wins'.");
+            std::process::exit(1);
+        }
+    };
+
+    let mut env = InputSet::env(policy);
+    for (key, value) in &config.env {
+        env.insert(&config.path, key, value.clone()).unwrap_or_else(|e| exit_with(renderer, &e));
+    }
+    insert_env_args(&mut env, &inputs.env_vars, renderer);
+    let (env, mut warnings) = env.into_parts();
+
+    // Parse --section NAME=FILE pairs (files are read on demand)
+    let mut pairs: Vec<(&str, &str, &str)> =
+        config.sections.iter().map(|(name, path)| (config.path.as_str(), name.as_str(), path.as_str())).collect();
+    pairs.extend_from_slice(extra);
+    for nf in &inputs.sections {
+        if let Some((name, path)) = nf.split_once('=') {
+            pairs.push(("--section", name, path));
+        } else {
+            eprintln!("Warning: ignoring malformed --section value (expected NAME=FILE): {nf}");
+        }
+    }
+    let mut section_set = InputSet::sections(policy);
+    for (source, name, path) in pairs {
+        if let Err(e) = std::fs::metadata(path) {
+            eprintln!("Error reading section '{name}' from '{path}': {e}");
+            std::process::exit(1);
+        }
+        section_set.insert(source, name, path.to_string()).unwrap_or_else(|e| exit_with(renderer, &e));
+    }
+    let (section_files, section_warnings) = section_set.into_parts();
+    warnings.extend(section_warnings);
+
+    let mut include_path = config.include_path.clone();
+    include_path.extend(inputs.include_path.iter().cloned());
+    LoadedInputs { env, section_files, include_path, verbose: inputs.verbose || config.verbose, warnings }
+}
+
+/// Parse --env KEY=VALUE pairs into `env`
+fn insert_env_args(env: &mut InputSet<Value>, env_vars: &[String], renderer: &Renderer) {
+    for kv in env_vars {
+        if let Some((k, v)) = kv.split_once('=') {
+            env.insert("--env", k, infer_env_value(v)).unwrap_or_else(|e| exit_with(renderer, &e));
+        } else {
+            eprintln!("Warning: ignoring malformed --env value (expected KEY=VALUE): {kv}");
+        }
+    }
+}
+
+/// Integer argument in decimal, 0x hex or 0b binary
+fn parse_number(text: &str) -> Result<u64, String> {
+    match infer_env_value(text) {
+        Value::U64(n) => Ok(n),
+        _ => Err(format!("'{text}' is not a non-negative integer")),
+    }
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    u8::try_from(parse_number(text)?).map_err(|_| format!("'{text}' doesn't fit in a byte"))
+}
+
+/// `options` signing the output with `key` as `--signature-alg` says
+fn with_signing_key(options: GenerateOptions, alg: &str, key: &[u8]) -> delbin::Result<GenerateOptions> {
+    #[cfg(feature = "crypto-sign")]
+    use delbin::sign::{EcdsaP256Signer, Ed25519Signer, SignatureFormat};
+    #[cfg(feature = "crypto-rsa")]
+    use delbin::sign::{RsaScheme, RsaSigner};
+
+    const ALGORITHMS: &[&str] = &["ed25519", "ecdsa-p256", "ecdsa-p256-der", "rsa-pkcs1", "rsa-pss"];
+    // Unused when no signing feature is enabled
+    let _ = (&options, key);
+    match alg {
+        #[cfg(feature = "crypto-sign")]
+        "ed25519" => Ok(options.with_detached_signature(Ed25519Signer::from_bytes(key)?)),
+        #[cfg(feature = "crypto-sign")]
+        "ecdsa-p256" => Ok(options.with_detached_signature(EcdsaP256Signer::from_bytes(key, SignatureFormat::Raw)?)),
+        #[cfg(feature = "crypto-sign")]
+        "ecdsa-p256-der" => Ok(options.with_detached_signature(EcdsaP256Signer::from_bytes(key, SignatureFormat::Der)?)),
+        #[cfg(feature = "crypto-rsa")]
+        "rsa-pkcs1" => Ok(options.with_detached_signature(RsaSigner::from_bytes(key, RsaScheme::Pkcs1v15)?)),
+        #[cfg(feature = "crypto-rsa")]
+        "rsa-pss" => Ok(options.with_detached_signature(RsaSigner::from_bytes(key, RsaScheme::Pss)?)),
+        alg if ALGORITHMS.contains(&alg) => Err(delbin::DelbinError::new(
+            delbin::ErrorCode::E04003,
+            format!("Signature algorithm '{alg}' is not built in"),
+        )
+        .with_hint("rebuild delbin with --features crypto-sign (ed25519, ecdsa) or crypto-rsa (rsa)")),
+        other => Err(delbin::DelbinError::new(
+            delbin::ErrorCode::E04003,
+            format!("Unknown --signature-alg '{other}'. Use {}.", ALGORITHMS.join(", ")),
+        )),
+    }
+}
+
+/// Run `write` against FILE (created or truncated), or stdout when no file
+/// is given
+fn with_output<T>(path: Option<&str>, write: impl FnOnce(&mut dyn std::io::Write) -> T) -> T {
+    use std::io::Write;
+    match path {
+        Some(path) => match std::fs::File::create(path) {
+            Ok(file) => {
+                let mut w = std::io::BufWriter::new(file);
+                let result = write(&mut w);
+                if let Err(e) = w.flush() {
+                    eprintln!("Error writing '{path}': {e}");
+                    std::process::exit(1);
+                }
+                result
+            }
+            Err(e) => {
+                eprintln!("Error writing '{path}': {e}");
+                std::process::exit(1);
+            }
+        },
+        None => {
+            let mut w = std::io::stdout().lock();
+            let result = write(&mut w);
+            let _ = w.flush();
+            result
+        }
+    }
+}
+
+/// Write to FILE, or stdout when no file is given
+fn write_output(path: Option<&str>, bytes: &[u8]) {
+    match path {
         Some(path) => {
-            if let Err(e) = std::fs::write(path, &output_bytes) {
+            if let Err(e) = std::fs::write(path, bytes) {
                 eprintln!("Error writing '{path}': {e}");
                 std::process::exit(1);
             }
         }
         None => {
             use std::io::Write;
-            if let Err(e) = std::io::stdout().write_all(&output_bytes) {
+            if let Err(e) = std::io::stdout().write_all(bytes) {
                 eprintln!("Error writing to stdout: {e}");
                 std::process::exit(1);
             }
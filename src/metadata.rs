@@ -0,0 +1,159 @@
+//! Delbin metadata record
+//!
+//! With [`GenerateOptions::with_metadata`](crate::GenerateOptions::with_metadata)
+//! the output carries a small TLV naming the format definition that produced
+//! it, so an image pulled off a device can be traced back to its DSL:
+//!
+//! | Offset | Size | Content                                              |
+//! |--------|------|------------------------------------------------------|
+//! | 0      | 1    | Tag, `0xDB`                                          |
+//! | 1      | 1    | Value length, 28                                     |
+//! | 2      | 1    | Record version, 1                                    |
+//! | 3      | 3    | delbin version: major, minor, patch                  |
+//! | 6      | 8    | Generation time, Unix seconds, little-endian         |
+//! | 14     | 16   | Layout hash: SHA-256 of the canonical DSL, truncated |
+//!
+//! The record is little-endian whatever the file's `@endian`, so tools can
+//! read it without knowing the format.
+
+use crate::ast::File;
+use crate::builtin;
+
+/// Where the metadata record goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataPlacement {
+    /// Before the output
+    Prepend,
+    /// After the output
+    Append,
+    /// Into a `[u8; N]` header field without initializer, `N` at least
+    /// [`MetadataRecord::LEN`]; the rest of the field stays zero. The
+    /// record is written before checksums are computed, so they cover it.
+    Field(String),
+}
+
+/// Decoded metadata record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataRecord {
+    /// delbin version that generated the output
+    pub delbin_version: [u8; 3],
+    /// Generation time, Unix seconds
+    pub timestamp: u64,
+    /// First 16 bytes of the SHA-256 of the canonical DSL, see [`layout_hash`]
+    pub layout_hash: [u8; 16],
+}
+
+impl MetadataRecord {
+    /// TLV tag
+    pub const TAG: u8 = 0xDB;
+    /// Record version this crate writes
+    pub const VERSION: u8 = 1;
+    /// Encoded length, tag and length byte included
+    pub const LEN: usize = 30;
+
+    /// Record for `file`, generated by this crate at `timestamp`
+    pub fn new(file: &File, timestamp: u64) -> Self {
+        let mut version = env!("CARGO_PKG_VERSION")
+            .split('.')
+            .map(|part| part.parse::<u8>().unwrap_or(0));
+        Self {
+            delbin_version: [(); 3].map(|_| version.next().unwrap_or(0)),
+            timestamp,
+            layout_hash: layout_hash(file),
+        }
+    }
+
+    /// TLV bytes
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut out = [0u8; Self::LEN];
+        out[0] = Self::TAG;
+        out[1] = (Self::LEN - 2) as u8;
+        out[2] = Self::VERSION;
+        out[3..6].copy_from_slice(&self.delbin_version);
+        out[6..14].copy_from_slice(&self.timestamp.to_le_bytes());
+        out[14..].copy_from_slice(&self.layout_hash);
+        out
+    }
+
+    /// Record at the start of `data`, if there is one
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let record = data.get(..Self::LEN)?;
+        if record[0] != Self::TAG || record[1] as usize != Self::LEN - 2 || record[2] != Self::VERSION {
+            return None;
+        }
+        Some(Self {
+            delbin_version: record[3..6].try_into().ok()?,
+            timestamp: u64::from_le_bytes(record[6..14].try_into().ok()?),
+            layout_hash: record[14..].try_into().ok()?,
+        })
+    }
+
+    /// Record anywhere in `data` and its offset
+    ///
+    /// The start and the end are tried first, as `Prepend` and `Append`
+    /// put it there; then the first match in between.
+    pub fn find(data: &[u8]) -> Option<(usize, Self)> {
+        let last = data.len().checked_sub(Self::LEN)?;
+        [0, last]
+            .into_iter()
+            .chain(1..last)
+            .find_map(|offset| Self::decode(&data[offset..]).map(|record| (offset, record)))
+    }
+}
+
+/// First 16 bytes of the SHA-256 of `file` printed canonically
+///
+/// Comments and formatting don't change it; anything that can change the
+/// output does.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::{metadata::layout_hash, parser};
+///
+/// let a = parser::parse("struct h { v: u8 = 1; }").unwrap();
+/// let b = parser::parse("// header\nstruct h {\n    v: u8 = 1; // version\n}").unwrap();
+/// assert_eq!(layout_hash(&a), layout_hash(&b));
+/// ```
+pub fn layout_hash(file: &File) -> [u8; 16] {
+    let mut file = file.clone();
+    file.prelude = None;
+    file.epilogue.clear();
+    file.trailer_gap.clear();
+    for def in std::iter::once(&mut file.struct_def).chain(file.trailer.as_mut()) {
+        def.head_source = None;
+        def.closing.clear();
+        for field in &mut def.fields {
+            field.source = None;
+            field.trivia = Default::default();
+        }
+    }
+    let hash = builtin::sha256(file.to_string().as_bytes());
+    hash[..16].try_into().expect("16 of 32 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_record_round_trip() {
+        let record = MetadataRecord {
+            delbin_version: [0, 3, 1],
+            timestamp: 0x0102_0304_0506_0708,
+            layout_hash: [0xAB; 16],
+        };
+        let bytes = record.encode();
+        assert_eq!(bytes[..6], [0xDB, 28, 1, 0, 3, 1]);
+        assert_eq!(bytes[6..14], [8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(MetadataRecord::decode(&bytes), Some(record));
+        assert_eq!(MetadataRecord::decode(&bytes[..29]), None);
+
+        let mut image = vec![0u8; 10];
+        image.extend_from_slice(&bytes);
+        image.extend_from_slice(&[0xFF; 5]);
+        assert_eq!(MetadataRecord::find(&image), Some((10, record)));
+        image[12] = 2; // unknown record version
+        assert_eq!(MetadataRecord::find(&image), None);
+    }
+}
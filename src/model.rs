@@ -0,0 +1,602 @@
+//! Delbin semantic model
+//!
+//! A resolved view of a DSL file: the layout is computed, constant fields
+//! are evaluated, and every name is classified as a field, section or env
+//! variable. Tools such as code generators and editors should build on
+//! [`Model`] rather than on [`crate::ast`], whose shape follows the grammar
+//! and changes with it.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use crate::ast::{ArrayLiteralKind, Expr, File, InputType, PolicyRule, RepeatCount, SectionDef, StructDef, Type};
+use crate::builtin::{CrcParams, DigestAlgorithm, FastHash, SimpleChecksum};
+use crate::error::{DelbinError, ErrorCode, Result};
+use crate::eval::Evaluator;
+use crate::layout::FieldLayout;
+use crate::parser;
+use crate::types::{Endian, ScalarType, Value};
+
+/// Resolved description of a DSL file
+#[derive(Debug, Clone)]
+pub struct Model {
+    /// Struct name
+    pub name: String,
+    /// Byte order in effect (after `@endian = ${VAR}` is resolved)
+    pub endian: Endian,
+    /// Header address from `@base`
+    pub base: Option<u64>,
+    /// Bytes reserved by `@size`
+    pub reserved: Option<u64>,
+    pub packed: bool,
+    pub align: Option<u32>,
+    /// Total struct size, including `@align` padding
+    pub size: usize,
+    /// Declared `@inputs`, in declaration order
+    pub inputs: Vec<Input>,
+    /// Declared `@policy` rules, in declaration order
+    pub policy: Vec<PolicyRule>,
+    /// Fields in layout order
+    pub fields: Vec<Field>,
+    /// Sections the file reads, in first-use order
+    pub sections: Vec<String>,
+    /// Env variables the file reads (including `@endian` and `@inputs`),
+    /// in first-use order
+    pub env: Vec<String>,
+}
+
+impl Model {
+    /// Look up a field by name
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+}
+
+/// Declared input
+#[derive(Debug, Clone)]
+pub struct Input {
+    pub name: String,
+    pub ty: InputType,
+    pub required: bool,
+    /// Value used when an optional input is absent
+    pub default: Option<Value>,
+}
+
+/// Resolved field
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    /// Byte offset from the start of the struct
+    pub offset: usize,
+    /// Size in bytes
+    pub size: usize,
+    /// Scalar type, or element type for arrays
+    pub elem: ScalarType,
+    /// Element count for arrays, `None` for scalars
+    pub count: Option<usize>,
+    pub value: FieldValue,
+}
+
+/// Where a field's bytes come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FieldValue {
+    /// No initializer: zero-filled
+    Zero,
+    /// Fixed by the DSL alone (magic, format version, offsets); encoded bytes
+    Constant(Vec<u8>),
+    /// Computed from env variables and/or section sizes
+    Input {
+        env: Vec<String>,
+        sections: Vec<String>,
+    },
+    /// Checksum or digest over header bytes and sections
+    Checksum {
+        /// `crc32`, a digest (`sha256`, `sha512`, `md5`, ...), the
+        /// algorithm named in `@crc()`, `crc32-<variant>` and
+        /// `crc16-<variant>` for `@crc32()` and `@crc16()` variants, the
+        /// parameters of a custom `@crc()` in RevEng notation
+        /// (`width=16 poly=0x8005 ...`), or `sum8`, `sum16`, `sum32`,
+        /// `xor8`, `checksum8_2c`, `fletcher16`, `fletcher32` and `adler32`,
+        /// or `xxh32`, `xxh64`, `xxh3` and `murmur3_32`
+        algorithm: String,
+        /// Data fed to the checksum, in order
+        over: Vec<ChecksumSource>,
+    },
+}
+
+/// Data covered by a checksum
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChecksumSource {
+    /// Byte range of the header itself
+    Header(Range<usize>),
+    /// A whole section
+    Section(String),
+}
+
+/// Inputs a generated field's value depended on
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldProvenance {
+    pub field: String,
+    /// Env variables, in first-use order (keys of `@encrypt_range` covering
+    /// the field and `@endian = ${VAR}` for multi-byte values included)
+    pub env: Vec<String>,
+    /// Sections, in first-use order; derived sections are followed by the
+    /// sections they're built from
+    pub sections: Vec<String>,
+}
+
+/// Build the model of a DSL file
+///
+/// `env` is only needed when array lengths or `@endian` depend on env
+/// variables.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::model::{self, ChecksumSource, FieldValue};
+/// use std::collections::HashMap;
+///
+/// let dsl = r#"
+///     struct h @packed {
+///         magic: [u8; 4] = @bytes("FPK");
+///         size:  u32     = @sizeof(image);
+///         crc:   u32     = @crc32(@self[..crc], image);
+///     }
+/// "#;
+/// let model = model::build(dsl, &HashMap::new()).unwrap();
+/// assert_eq!(model.field("magic").unwrap().value, FieldValue::Constant(b"FPK\0".to_vec()));
+/// assert_eq!(model.sections, ["image"]);
+/// let FieldValue::Checksum { over, .. } = &model.field("crc").unwrap().value else { panic!() };
+/// assert_eq!(over[0], ChecksumSource::Header(0..8));
+/// ```
+pub fn build(dsl: &str, env: &HashMap<String, Value>) -> Result<Model> {
+    let file = parser::parse(dsl)?;
+    from_file(&file, env)
+}
+
+/// Build the model of a parsed file
+pub fn from_file(file: &File, env: &HashMap<String, Value>) -> Result<Model> {
+    let sections = HashMap::<String, Vec<u8>>::new();
+    let mut evaluator = Evaluator::new(env, &sections);
+    let layout = evaluator.layout(file)?;
+    let mut constants: HashMap<String, Vec<u8>> =
+        evaluator.constant_fields(file)?.into_iter().collect();
+    let offsets: HashMap<&str, usize> =
+        layout.fields.iter().map(|f| (f.name.as_str(), f.offset)).collect();
+
+    let mut refs = Refs::default();
+    refs.env.extend(file.endian_var.iter().cloned());
+    refs.env.extend(file.inputs.iter().map(|i| i.name.clone()));
+
+    let mut fields = Vec::with_capacity(layout.fields.len());
+    for (def, field) in file.struct_def.fields.iter().zip(&layout.fields) {
+        if let Type::Array { len, .. } = &def.ty {
+            refs.collect(len);
+        }
+        let value = match &def.init {
+            None => FieldValue::Zero,
+            Some(init) => {
+                let mut own = Refs::default();
+                own.collect(init);
+                own.dedup();
+                refs.merge(&own);
+                match init {
+                    Expr::Call { name, args } if is_checksum(name) => {
+                        checksum_value(name, args, &offsets, layout.size)?
+                    }
+                    _ => match constants.remove(&def.name) {
+                        Some(bytes) => FieldValue::Constant(bytes),
+                        None => FieldValue::Input {
+                            env: own.env,
+                            sections: own.sections,
+                        },
+                    },
+                }
+            }
+        };
+        fields.push(Field {
+            name: field.name.clone(),
+            offset: field.offset,
+            size: field.size,
+            elem: field.elem,
+            count: field.count,
+            value,
+        });
+    }
+    refs.dedup();
+
+    Ok(Model {
+        name: layout.name,
+        endian: evaluator.endian(),
+        base: file.base,
+        reserved: file.size,
+        packed: file.struct_def.packed,
+        align: file.struct_def.align,
+        size: layout.size,
+        inputs: file.inputs.iter().map(input).collect(),
+        policy: file.policy.clone(),
+        fields,
+        sections: refs.sections,
+        env: refs.env,
+    })
+}
+
+/// Inputs each field of `struct_def` depended on, in field order
+///
+/// `fields` is the evaluated layout of `struct_def`. A field computed over
+/// header bytes (`@self[..]`, as in checksums and signatures) inherits the
+/// inputs of every field in the covered range.
+pub(crate) fn provenance(
+    file: &File,
+    struct_def: &StructDef,
+    fields: &[FieldLayout],
+) -> Vec<FieldProvenance> {
+    let span = |name: &str| {
+        fields
+            .iter()
+            .find(|f| f.name == name)
+            .map(|f| f.offset..f.offset + f.size)
+    };
+    let offset = |expr: Option<&Expr>| match expr {
+        Some(Expr::Number(n)) => *n as usize,
+        Some(Expr::SectionRef(field)) => span(field).map_or(0, |r| r.start),
+        _ => 0,
+    };
+    let end = |field: Option<&String>| field.and_then(|f| span(f)).map_or(usize::MAX, |r| r.start);
+
+    let mut refs = Vec::with_capacity(fields.len());
+    let mut covers = Vec::with_capacity(fields.len());
+    for layout in fields {
+        let mut own = Refs::default();
+        let mut ranges = Vec::new();
+        if let Some(def) = struct_def.fields.iter().find(|d| d.name == layout.name) {
+            if layout.elem.size() > 1 {
+                own.env.extend(file.endian_var.iter().cloned());
+            }
+            if let Type::Array { len, .. } = &def.ty {
+                own.collect(len);
+            }
+            if let Some(init) = &def.init {
+                own.collect(init);
+                header_ranges(init, &mut |start, stop| ranges.push(offset(start)..end(stop)));
+            }
+        }
+        let here = layout.offset..layout.offset + layout.size;
+        for enc in &struct_def.encrypt {
+            if offset(enc.start.as_deref()) < here.end && here.start < end(enc.end.as_ref()) {
+                own.collect(&enc.key);
+                own.collect(&enc.iv);
+            }
+        }
+        own.expand_derived(&file.sections);
+        refs.push(own);
+        covers.push(ranges);
+    }
+
+    // Checksums over checksums: repeat until nothing new is inherited
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, ranges) in covers.iter().enumerate() {
+            for (j, field) in fields.iter().enumerate() {
+                let overlaps = ranges
+                    .iter()
+                    .any(|r| r.start < field.offset + field.size && field.offset < r.end);
+                if i != j && overlaps {
+                    let inherited = std::mem::take(&mut refs[j]);
+                    changed |= refs[i].merge_new(&inherited);
+                    refs[j] = inherited;
+                }
+            }
+        }
+    }
+
+    fields
+        .iter()
+        .zip(refs)
+        .map(|(field, mut refs)| {
+            refs.dedup();
+            FieldProvenance {
+                field: field.name.clone(),
+                env: refs.env,
+                sections: refs.sections,
+            }
+        })
+        .collect()
+}
+
+/// Report the `@self` ranges read by `expr` as `(start, end field)`
+fn header_ranges<'e>(expr: &'e Expr, f: &mut dyn FnMut(Option<&'e Expr>, Option<&'e String>)) {
+    match expr {
+        Expr::SelfRef => f(None, None),
+        Expr::Range { start, end, .. } => f(start.as_deref(), end.as_ref()),
+        Expr::Call { name, .. } if name == "offsetof" => {}
+        Expr::Call { args, .. } => args.iter().for_each(|a| header_ranges(a, f)),
+        Expr::Named { value, .. } => header_ranges(value, f),
+        _ => {}
+    }
+}
+
+/// Env variables and sections referenced by expressions
+#[derive(Default)]
+struct Refs {
+    env: Vec<String>,
+    sections: Vec<String>,
+}
+
+impl Refs {
+    /// Record references in `expr`; identifiers inside `@offsetof()` and
+    /// `@self[..]` name fields and are skipped
+    fn collect(&mut self, expr: &Expr) {
+        match expr {
+            Expr::EnvVar(name) => self.env.push(name.clone()),
+            Expr::SectionRef(name) => self.sections.push(name.clone()),
+            Expr::BinaryOp { left, right, .. } => {
+                self.collect(left);
+                self.collect(right);
+            }
+            Expr::UnaryOp { operand, .. } | Expr::Named { value: operand, .. } => {
+                self.collect(operand)
+            }
+            Expr::Call { name, .. } if name == "offsetof" => {}
+            Expr::Call { args, .. } => args.iter().for_each(|a| self.collect(a)),
+            Expr::ArrayLiteral(ArrayLiteralKind::Repeat { value, count }) => {
+                self.collect(value);
+                if let RepeatCount::Explicit(n) = count {
+                    self.collect(n);
+                }
+            }
+            Expr::ArrayLiteral(ArrayLiteralKind::List { elements }) => {
+                elements.iter().for_each(|e| self.collect(e));
+            }
+            Expr::Number(_) | Expr::String(_) | Expr::SelfRef | Expr::Range { .. } => {}
+        }
+    }
+
+    /// Follow derived sections back to the sections and env variables
+    /// their transforms read
+    fn expand_derived(&mut self, defs: &[SectionDef]) {
+        let mut i = 0;
+        while i < self.sections.len() {
+            if let Some(def) = defs.iter().find(|d| d.name == self.sections[i]) {
+                let mut source = Refs::default();
+                source.collect(&def.transform);
+                self.merge_new(&source);
+            }
+            i += 1;
+        }
+    }
+
+    /// Merge names not already present; true if any were added
+    fn merge_new(&mut self, other: &Refs) -> bool {
+        let mut added = false;
+        for (mine, theirs) in [(&mut self.env, &other.env), (&mut self.sections, &other.sections)] {
+            for name in theirs {
+                if !mine.contains(name) {
+                    mine.push(name.clone());
+                    added = true;
+                }
+            }
+        }
+        added
+    }
+
+    fn merge(&mut self, other: &Refs) {
+        self.env.extend(other.env.iter().cloned());
+        self.sections.extend(other.sections.iter().cloned());
+    }
+
+    /// Drop repeated names, keeping the first occurrence
+    fn dedup(&mut self) {
+        for names in [&mut self.env, &mut self.sections] {
+            let mut seen = HashSet::new();
+            names.retain(|n| seen.insert(n.clone()));
+        }
+    }
+}
+
+fn is_checksum(name: &str) -> bool {
+    matches!(name, "crc32" | "crc" | "crc16")
+        || SimpleChecksum::by_name(name).is_some()
+        || FastHash::by_name(name).is_some()
+        || DigestAlgorithm::by_name(name).is_some()
+}
+
+/// Offset a `@self[start..]` range starts at: numbers, field names and
+/// `@offsetof()` of fields, with operators
+fn range_start(expr: &Expr, field_offset: &dyn Fn(&str) -> Result<usize>) -> Result<u64> {
+    match expr {
+        Expr::Number(n) => Ok(*n),
+        Expr::SectionRef(field) => field_offset(field).map(|o| o as u64),
+        Expr::Call { name, args } if name == "offsetof" => match args.as_slice() {
+            [field @ Expr::SectionRef(_)] => range_start(field, field_offset),
+            _ => Err(DelbinError::new(
+                ErrorCode::E04004,
+                "@offsetof() requires exactly 1 argument",
+            )),
+        },
+        Expr::BinaryOp { op, left, right } => {
+            let (l, r) = (range_start(left, field_offset)?, range_start(right, field_offset)?);
+            crate::eval::binary_op(*op, l, r).ok_or_else(|| {
+                DelbinError::new(ErrorCode::E04002, "Shift amount too large in range expression")
+            })
+        }
+        Expr::UnaryOp { operand, .. } => Ok(!range_start(operand, field_offset)?),
+        _ => Err(DelbinError::new(
+            ErrorCode::E04003,
+            "Expected a numeric literal or field name in range expression",
+        )),
+    }
+}
+
+/// Resolve the arguments of a checksum call
+fn checksum_value(
+    name: &str,
+    args: &[Expr],
+    offsets: &HashMap<&str, usize>,
+    struct_size: usize,
+) -> Result<FieldValue> {
+    let (algorithm, data) = match (name, args) {
+        ("crc", [Expr::String(algo), rest @ ..]) => (algo.clone(), rest),
+        ("crc", [_, ..]) => custom_crc(args)?,
+        ("crc", _) => {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                "@crc() first argument must be a string literal (algorithm name)",
+            ))
+        }
+        ("crc32", [rest @ .., Expr::String(variant)]) if variant != "iso-hdlc" => {
+            (format!("crc32-{}", variant), rest)
+        }
+        ("crc32", [rest @ .., Expr::String(_)]) => ("crc32".to_string(), rest),
+        ("crc16", [rest @ .., Expr::String(variant)]) => (format!("crc16-{}", variant), rest),
+        ("crc16", _) => {
+            return Err(DelbinError::new(
+                ErrorCode::E04004,
+                "@crc16() requires a data source and a variant string",
+            ))
+        }
+        _ => (name.to_string(), args),
+    };
+    let field_offset = |field: &str| {
+        offsets.get(field).copied().ok_or_else(|| {
+            DelbinError::new(
+                ErrorCode::E02002,
+                format!("Undefined field '{}' in range expression", field),
+            )
+        })
+    };
+
+    let mut over = Vec::with_capacity(data.len());
+    for arg in data {
+        over.push(match arg {
+            Expr::SelfRef => ChecksumSource::Header(0..struct_size),
+            Expr::Range { start, end, .. } => {
+                let start = match start.as_deref() {
+                    None => 0,
+                    Some(start) => range_start(start, &field_offset)? as usize,
+                };
+                let end = match end {
+                    Some(field) => field_offset(field)?,
+                    None => struct_size,
+                };
+                ChecksumSource::Header(start..end)
+            }
+            Expr::SectionRef(section) => ChecksumSource::Section(section.clone()),
+            _ => {
+                return Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    "Invalid argument for checksum function",
+                ))
+            }
+        });
+    }
+    Ok(FieldValue::Checksum { algorithm, over })
+}
+
+/// RevEng description and data argument of `@crc(data, width, poly, ...)`
+fn custom_crc(args: &[Expr]) -> Result<(String, &[Expr])> {
+    let bound = crate::eval::bind_args("crc", args, &["data", "width", "poly", "init", "refin", "refout", "xorout"])?;
+    let values: Option<Vec<u64>> = bound[1..].iter().map(|arg| crate::rewrite::const_value((*arg)?)).collect();
+    let (Some(data), Some([width, poly, init, refin, refout, xorout])) = (bound[0], values.as_deref()) else {
+        return Err(DelbinError::new(
+            ErrorCode::E04003,
+            "@crc() parameters must all be given as constants",
+        ));
+    };
+    let params = CrcParams {
+        width: u8::try_from(*width).unwrap_or(0),
+        poly: *poly,
+        init: *init,
+        refin: *refin != 0,
+        refout: *refout != 0,
+        xorout: *xorout,
+    };
+    params.validate()?;
+    Ok((params.to_string(), std::slice::from_ref(data)))
+}
+
+fn input(decl: &crate::ast::InputDecl) -> Input {
+    let default = match (&decl.default, decl.ty) {
+        (Some(Expr::String(s)), InputType::Bytes) => Some(Value::Bytes(s.as_bytes().to_vec())),
+        (Some(Expr::String(s)), _) => Some(Value::String(s.clone())),
+        (Some(Expr::Number(n)), _) => Some(Value::U64(*n)),
+        _ => None,
+    };
+    Input {
+        name: decl.name.clone(),
+        ty: decl.ty,
+        required: decl.required,
+        default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_resolves_fields() {
+        let dsl = r#"
+            @endian = big;
+            @inputs {
+                BUILD: u32 optional = 7;
+            }
+            struct fw @align(64) {
+                magic:   [u8; 4]  = @bytes("FW");
+                build:   u32      = ${BUILD} | (${FLAGS} << 24);
+                size:    u32      = @sizeof(app);
+                at:      u16      = @offsetof(body);
+                body:    [u8; 16];
+                digest:  [u8; 32] = @sha256(app, @self[magic..body]);
+                crc:     u16      = @crc("crc16-modbus", @self[4..crc]);
+            }
+        "#;
+        let model = build(dsl, &HashMap::new()).unwrap();
+
+        assert_eq!(model.endian, Endian::Big);
+        assert_eq!(model.size, 64);
+        assert_eq!(model.env, ["BUILD", "FLAGS"]);
+        assert_eq!(model.sections, ["app"]);
+        assert!(matches!(model.inputs[0].default, Some(Value::U64(7))));
+
+        let value = |name: &str| model.field(name).unwrap().value.clone();
+        assert_eq!(value("at"), FieldValue::Constant(vec![0, 14]));
+        assert_eq!(value("body"), FieldValue::Zero);
+        assert_eq!(
+            value("build"),
+            FieldValue::Input { env: vec!["BUILD".into(), "FLAGS".into()], sections: vec![] }
+        );
+        assert_eq!(
+            value("size"),
+            FieldValue::Input { env: vec![], sections: vec!["app".into()] }
+        );
+        assert_eq!(
+            value("digest"),
+            FieldValue::Checksum {
+                algorithm: "sha256".into(),
+                over: vec![ChecksumSource::Section("app".into()), ChecksumSource::Header(0..14)],
+            }
+        );
+        assert_eq!(
+            value("crc"),
+            FieldValue::Checksum {
+                algorithm: "crc16-modbus".into(),
+                over: vec![ChecksumSource::Header(4..62)],
+            }
+        );
+        let crc = model.field("crc").unwrap();
+        assert_eq!((crc.offset, crc.size, crc.elem, crc.count), (62, 2, ScalarType::U16, None));
+    }
+
+    #[test]
+    fn test_model_rejects_unresolved_names() {
+        let err = build("struct h { crc: u32 = @crc32(@self[..nope]); }", &HashMap::new()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::E02002);
+        let err = build("struct h { at: u32 = @offsetof(nope); }", &HashMap::new()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::E02002);
+    }
+}
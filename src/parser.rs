@@ -1,10 +1,15 @@
 //! Delbin parser
 
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
 use pest::Parser;
 use pest_derive::Parser;
 
 use crate::ast::*;
-use crate::error::{DelbinError, ErrorCode, Result};
+use crate::error::{DelbinError, ErrorCode, Result, SourceLocation};
+use crate::eval::{bind_args, is_range_based_builtin, SIGNATURE_BUILTINS};
+use crate::{printer, rewrite};
 use crate::types::{Endian, ScalarType};
 
 #[derive(Parser)]
@@ -12,23 +17,370 @@ use crate::types::{Endian, ScalarType};
 pub struct DelBinParser;
 
 /// Parse DSL text
+///
+/// `@include` needs [`parse_with_resolver`]; here it fails with E01003.
 pub fn parse(input: &str) -> Result<File> {
-    let pairs = DelBinParser::parse(Rule::file, input).map_err(|e| {
-        DelbinError::new(ErrorCode::E01003, format!("Parse error: {}", e))
-    })?;
+    parse_file(input, Expander::new(None))
+}
+
+/// Whether all of `text` is one `rule`, e.g. an identifier or an expression,
+/// for splicing outside text into generated DSL
+#[cfg(feature = "yaml")]
+pub(crate) fn is_whole(rule: Rule, text: &str) -> bool {
+    DelBinParser::parse(rule, text)
+        .is_ok_and(|mut pairs| pairs.next().is_some_and(|pair| pair.as_span().end() == text.len()))
+}
+
+/// Parse DSL text, reading `@include`d files through `resolver`
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::parser::parse_with_resolver;
+/// use delbin::Result;
+///
+/// let common = |path: &str| -> Result<String> {
+///     assert_eq!(path, "common.dbin");
+///     Ok("magic: u32 = 0x55AA55AA;\nversion: u16 = 1;".to_string())
+/// };
+/// let file = parse_with_resolver(r#"struct h { @include "common.dbin"; size: u32; }"#, &common).unwrap();
+/// let names: Vec<&str> = file.struct_def.fields.iter().map(|f| f.name.as_str()).collect();
+/// assert_eq!(names, ["magic", "version", "size"]);
+/// assert_eq!(file.includes, ["common.dbin"]);
+/// ```
+pub fn parse_with_resolver(input: &str, resolver: &dyn IncludeResolver) -> Result<File> {
+    parse_file(input, Expander::new(Some(resolver)))
+}
+
+/// Source of the files `@include` names
+pub trait IncludeResolver {
+    /// DSL text of the file `@include "path";` names
+    fn resolve(&self, path: &str) -> Result<String>;
+}
+
+impl<F: Fn(&str) -> Result<String>> IncludeResolver for F {
+    fn resolve(&self, path: &str) -> Result<String> {
+        self(path)
+    }
+}
+
+/// Resolves `@include` paths against a list of directories, the first
+/// that has the file winning
+///
+/// Paths must be relative without `..` and stay inside their directory
+/// once symlinks are resolved.
+#[derive(Debug, Clone, Default)]
+pub struct IncludePath {
+    pub dirs: Vec<PathBuf>,
+}
+
+impl IncludePath {
+    pub fn new(dirs: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        Self {
+            dirs: dirs.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Where the file `@include "path";` names is, if anywhere
+    pub fn locate(&self, path: &str) -> Result<Option<PathBuf>> {
+        let confined = Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !confined {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("@include \"{}\" is outside the include path", path),
+            )
+            .with_hint("use a path relative to an include directory"));
+        }
+        for dir in &self.dirs {
+            let full = dir.join(path);
+            if !full.is_file() {
+                continue;
+            }
+            let read_err = |e: std::io::Error| {
+                DelbinError::new(ErrorCode::E05002, format!("Cannot read @include \"{}\": {}", path, e))
+            };
+            let root = dir.canonicalize().map_err(read_err)?;
+            if !full.canonicalize().map_err(read_err)?.starts_with(&root) {
+                return Err(DelbinError::new(
+                    ErrorCode::E04003,
+                    format!("@include \"{}\" is outside the include path", path),
+                ));
+            }
+            return Ok(Some(full));
+        }
+        Ok(None)
+    }
+}
+
+impl IncludeResolver for IncludePath {
+    fn resolve(&self, path: &str) -> Result<String> {
+        let Some(full) = self.locate(path)? else {
+            let dirs: Vec<String> = self.dirs.iter().map(|d| d.display().to_string()).collect();
+            return Err(DelbinError::new(
+                ErrorCode::E05001,
+                format!("@include \"{}\" is not in the include path", path),
+            )
+            .with_hint(if dirs.is_empty() {
+                "add a directory to the include path".to_string()
+            } else {
+                format!("searched: {}", dirs.join(", "))
+            }));
+        };
+        std::fs::read_to_string(&full).map_err(|e| {
+            DelbinError::new(ErrorCode::E05002, format!("Cannot read @include \"{}\": {}", path, e))
+        })
+    }
+}
+
+/// `@include` and template state while parsing
+struct Expander<'a> {
+    resolver: Option<&'a dyn IncludeResolver>,
+    /// Files being included, outermost first, to catch cycles
+    stack: Vec<String>,
+    /// Every file read so far
+    read: Vec<String>,
+    /// Struct templates defined so far
+    templates: Vec<StructTemplate>,
+}
+
+impl<'a> Expander<'a> {
+    fn new(resolver: Option<&'a dyn IncludeResolver>) -> Self {
+        Self {
+            resolver,
+            stack: Vec::new(),
+            read: Vec::new(),
+            templates: Vec::new(),
+        }
+    }
+
+    /// Fields of the file `@include "path";` names
+    fn expand(&mut self, path: &str) -> Result<Vec<FieldDef>> {
+        let Some(resolver) = self.resolver else {
+            return Err(DelbinError::new(
+                ErrorCode::E01003,
+                format!("@include \"{}\" needs an include path", path),
+            )
+            .with_hint("parse with parser::parse_with_resolver() or GenerateOptions::with_include_path()"));
+        };
+        if self.stack.iter().any(|p| p == path) {
+            return Err(DelbinError::new(
+                ErrorCode::E01003,
+                format!("@include cycle: {} -> {}", self.stack.join(" -> "), path),
+            ));
+        }
+        let text = resolver.resolve(path)?;
+        self.stack.push(path.to_string());
+        self.read.push(path.to_string());
+        let fields = parse_fragment(&text, self).map_err(|mut e| {
+            e.message = format!("{} (in @include \"{}\")", e.message, path);
+            e
+        })?;
+        self.stack.pop();
+        if fields.is_empty() {
+            return Err(DelbinError::new(
+                ErrorCode::E01003,
+                format!("@include \"{}\" defines no fields", path),
+            ));
+        }
+        Ok(fields)
+    }
+
+    /// Fields of `instance: template<args>;`
+    fn instantiate(&self, instance: &str, template: &str, args: &[Expr]) -> Result<Vec<FieldDef>> {
+        let Some(template) = self.templates.iter().find(|t| t.name == template) else {
+            return Err(DelbinError::new(
+                ErrorCode::E01003,
+                format!("Unknown struct template '{}'", template),
+            ));
+        };
+        if args.len() != template.params.len() {
+            return Err(DelbinError::new(
+                ErrorCode::E01003,
+                format!(
+                    "Struct template '{}' takes {} parameters, {} given",
+                    template.name,
+                    template.params.len(),
+                    args.len()
+                ),
+            ));
+        }
+        let prefixed = |name: &str| format!("{}_{}", instance, name);
+        let mut fields = template.fields.clone();
+        for field in &mut fields {
+            field.name = prefixed(&field.name);
+            field.aliases = field.aliases.iter().map(|alias| prefixed(alias)).collect();
+            for own in &template.fields {
+                let renamed = prefixed(&own.name);
+                rewrite::visit_field_exprs(field, &mut |expr| rewrite::rename_reference(expr, &own.name, &renamed));
+            }
+            if let Type::Array { len, .. } = &mut field.ty {
+                substitute(len, &template.params, args);
+            }
+            if let Some(init) = &mut field.init {
+                substitute(init, &template.params, args);
+            }
+            field.source = Some(printer::canonical_source(field));
+        }
+        Ok(fields)
+    }
+}
+
+/// Replace template parameters in `expr` by their arguments
+///
+/// Arguments aren't searched again, so they may name outer sections or
+/// fields that share a parameter's name.
+fn substitute(expr: &mut Expr, params: &[String], args: &[Expr]) {
+    match expr {
+        Expr::SectionRef(name) => {
+            if let Some(i) = params.iter().position(|p| p == name) {
+                *expr = args[i].clone();
+            }
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            substitute(left, params, args);
+            substitute(right, params, args);
+        }
+        Expr::UnaryOp { operand, .. } | Expr::Named { value: operand, .. } => substitute(operand, params, args),
+        Expr::Call { args: call_args, .. } => {
+            for arg in call_args {
+                substitute(arg, params, args);
+            }
+        }
+        Expr::Range { start: Some(start), .. } => substitute(start, params, args),
+        Expr::ArrayLiteral(ArrayLiteralKind::Repeat { value, count }) => {
+            substitute(value, params, args);
+            if let RepeatCount::Explicit(n) = count {
+                substitute(n, params, args);
+            }
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::List { elements }) => {
+            for elem in elements {
+                substitute(elem, params, args);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Syntax error from pest as E01003, located in the text parsed
+fn syntax_error(e: pest::error::Error<Rule>) -> DelbinError {
+    let (line, column) = match e.line_col {
+        pest::error::LineColLocation::Pos(pos) | pest::error::LineColLocation::Span(pos, _) => pos,
+    };
+    DelbinError::new(ErrorCode::E01003, format!("Parse error: {}", e.variant.message())).with_location(SourceLocation {
+        line,
+        column,
+        context: e.line().to_string(),
+    })
+}
+
+fn parse_file(input: &str, mut expander: Expander) -> Result<File> {
+    let pairs = DelBinParser::parse(Rule::file, input).map_err(syntax_error)?;
 
     let mut endian = Endian::Little;
+    let mut endian_var = None;
+    let mut base = None;
+    let mut size = None;
+    let mut fill = None;
+    let mut slot_size = None;
+    let mut inputs = Vec::new();
+    let mut policy: Vec<PolicyRule> = Vec::new();
+    let mut layout: Vec<LayoutEntry> = Vec::new();
+    let mut bundle: Option<Bundle> = None;
+    let mut sections: Vec<SectionDef> = Vec::new();
+    let mut asserts = Vec::new();
     let mut struct_def = None;
+    let mut trailer: Option<StructDef> = None;
+    let mut trailer_gap = String::new();
+    let mut header_end = 0;
+    let mut prelude = String::new();
+    let mut epilogue = String::new();
 
     for pair in pairs {
         if pair.as_rule() == Rule::file {
             for inner in pair.into_inner() {
                 match inner.as_rule() {
-                    Rule::directive => {
-                        endian = parse_directive(inner)?;
+                    Rule::directive => match parse_directive(inner, &Settings { base, slot_size })? {
+                        Directive::Endian(e) => {
+                            endian = e;
+                            endian_var = None;
+                        }
+                        Directive::EndianVar(name) => endian_var = Some(name),
+                        Directive::Base(n) => base = Some(n),
+                        Directive::Size(n) => size = Some(n),
+                        Directive::Fill(n) => fill = Some(n),
+                        Directive::SlotSize(n) => slot_size = Some(n),
+                    },
+                    Rule::inputs_block => {
+                        for decl in inner.into_inner() {
+                            inputs.push(parse_input_decl(decl)?);
+                        }
+                    }
+                    Rule::policy_block => {
+                        for rule in inner.into_inner() {
+                            let rule = parse_policy_rule(rule)?;
+                            if policy.iter().any(|r| r.kind == rule.kind) {
+                                return Err(DelbinError::new(
+                                    ErrorCode::E01003,
+                                    format!("@policy rule '{}' is given twice", rule.kind.name()),
+                                ));
+                            }
+                            policy.push(rule);
+                        }
+                    }
+                    Rule::layout_block => {
+                        for entry in inner.into_inner() {
+                            let entry = parse_layout_entry(entry, &Settings { base, slot_size })?;
+                            if layout.iter().any(|e| e.name == entry.name) {
+                                return Err(DelbinError::new(
+                                    ErrorCode::E01003,
+                                    format!("'{}' is placed twice in @layout", entry.name),
+                                ));
+                            }
+                            layout.push(entry);
+                        }
+                    }
+                    Rule::bundle_block if bundle.is_some() => {
+                        return Err(DelbinError::new(ErrorCode::E01003, "@bundle is given twice"));
+                    }
+                    Rule::bundle_block => {
+                        bundle = Some(parse_bundle_block(inner, &Settings { base, slot_size })?);
+                    }
+                    Rule::section_def => {
+                        let def = parse_section_def(inner)?;
+                        if sections.iter().any(|d| d.name == def.name) {
+                            return Err(DelbinError::new(
+                                ErrorCode::E01003,
+                                format!("Section '{}' is defined twice", def.name),
+                            ));
+                        }
+                        sections.push(def);
+                    }
+                    Rule::assert_stmt => asserts.push(parse_assert(inner)?),
+                    Rule::template_def => {
+                        let template = parse_template_def(inner, &mut expander)?;
+                        if expander.templates.iter().any(|t| t.name == template.name) {
+                            return Err(DelbinError::new(
+                                ErrorCode::E01003,
+                                format!("Struct template '{}' is defined twice", template.name),
+                            ));
+                        }
+                        expander.templates.push(template);
+                    }
+                    Rule::struct_def if struct_def.is_none() => {
+                        let span = inner.as_span();
+                        prelude = input[..span.start()].to_string();
+                        epilogue = input[span.end()..].to_string();
+                        header_end = span.end();
+                        struct_def = Some(parse_struct_def(inner, &Settings { base, slot_size }, &mut expander)?);
                     }
                     Rule::struct_def => {
-                        struct_def = Some(parse_struct_def(inner)?);
+                        let span = inner.as_span();
+                        trailer_gap = input[header_end..span.start()].to_string();
+                        epilogue = input[span.end()..].to_string();
+                        trailer = Some(parse_struct_def(inner, &Settings { base, slot_size }, &mut expander)?);
                     }
                     Rule::EOI => {}
                     _ => {}
@@ -37,83 +389,734 @@ pub fn parse(input: &str) -> Result<File> {
         }
     }
 
+    if bundle.is_some() && !layout.is_empty() {
+        return Err(DelbinError::new(
+            ErrorCode::E01003,
+            "@bundle and @layout can't be combined",
+        ));
+    }
+
+    for def in struct_def.iter().chain(&trailer) {
+        if expander.templates.iter().any(|t| t.name == def.name) {
+            return Err(DelbinError::new(
+                ErrorCode::E01003,
+                format!("'{}' names both a struct and a struct template", def.name),
+            ));
+        }
+    }
+
+    if let (Some(header), Some(trailer)) = (&struct_def, &trailer) {
+        if header.name == trailer.name {
+            return Err(DelbinError::new(
+                ErrorCode::E01003,
+                format!("Header and trailer are both named '{}'", header.name),
+            ));
+        }
+    }
+
     Ok(File {
         endian,
+        endian_var,
+        base,
+        size,
+        fill,
+        slot_size,
+        inputs,
+        policy,
+        layout,
+        bundle,
+        sections,
+        asserts,
         struct_def: struct_def.ok_or_else(|| {
             DelbinError::new(ErrorCode::E01003, "No struct definition found")
         })?,
+        trailer,
+        trailer_gap,
+        prelude: Some(prelude),
+        epilogue,
+        templates: expander.templates,
+        includes: expander.read,
     })
 }
 
-fn parse_directive(pair: pest::iterators::Pair<Rule>) -> Result<Endian> {
+/// Parsed global directive
+enum Directive {
+    Endian(Endian),
+    EndianVar(String),
+    Base(u64),
+    Size(u64),
+    Fill(u8),
+    SlotSize(u64),
+}
+
+/// Directive values a constant expression can refer to (`@base`,
+/// `@slot_size`), as set so far
+struct Settings {
+    base: Option<u64>,
+    slot_size: Option<u64>,
+}
+
+const NO_SETTINGS: Settings = Settings { base: None, slot_size: None };
+
+/// Value of a constant expression: numbers, operators, arithmetic builtins
+/// (`@align_up()`, `@min()`, ...), and the `@base` and `@slot_size`
+/// directives; `None` for anything else, and for shifts by 64 bits or more
+fn const_value(expr: &Expr, settings: &Settings) -> Option<u64> {
+    match expr {
+        Expr::Number(n) => Some(*n),
+        Expr::BinaryOp { op, left, right } => crate::eval::binary_op(
+            *op,
+            const_value(left, settings)?,
+            const_value(right, settings)?,
+        ),
+        Expr::UnaryOp { op: UnaryOp::Not, operand } => const_value(operand, settings).map(|v| !v),
+        Expr::Call { name, args } if crate::builtin::ARITHMETIC_BUILTINS.contains(&name.as_str()) => {
+            let values = args.iter().map(|arg| const_value(arg, settings)).collect::<Option<Vec<u64>>>()?;
+            crate::builtin::arithmetic(name, &values)?.ok()
+        }
+        Expr::Call { name, args } if args.is_empty() && name == "base" => settings.base,
+        Expr::Call { name, args } if args.is_empty() && name == "slot_size" => settings.slot_size,
+        _ => None,
+    }
+}
+
+fn parse_directive(pair: pest::iterators::Pair<Rule>, settings: &Settings) -> Result<Directive> {
+    let mut inner = pair.into_inner();
+    let name = inner.next().map(|p| p.as_str()).unwrap_or_default();
+    let Some(value) = inner.next() else {
+        return Ok(Directive::Endian(Endian::Little));
+    };
+    let text = value.as_str().trim();
+    let invalid = |what: &str| {
+        DelbinError::new(
+            ErrorCode::E01003,
+            format!("Invalid {} value: {}", what, text),
+        )
+    };
+
+    match name {
+        "endian" => match text {
+            "little" => Ok(Directive::Endian(Endian::Little)),
+            "big" => Ok(Directive::Endian(Endian::Big)),
+            _ => match parse_primary_expr(value.clone())? {
+                Expr::EnvVar(var) => Ok(Directive::EndianVar(var)),
+                _ => Err(invalid("endian")),
+            },
+        },
+        _ => {
+            let number = match text {
+                "little" | "big" => None,
+                _ => const_value(&parse_primary_expr(value.clone())?, settings),
+            };
+            match (name, number) {
+                ("base", Some(n)) => Ok(Directive::Base(n)),
+                ("size", Some(n)) => Ok(Directive::Size(n)),
+                ("fill", Some(n)) if n <= 0xFF => Ok(Directive::Fill(n as u8)),
+                ("slot_size", Some(n)) => Ok(Directive::SlotSize(n)),
+                _ => Err(invalid(&format!("@{}", name))),
+            }
+        }
+    }
+}
+
+fn parse_layout_entry(pair: pest::iterators::Pair<Rule>, settings: &Settings) -> Result<LayoutEntry> {
+    let mut inner = pair.into_inner();
+    let name = inner.next().map(|p| p.as_str().to_string()).unwrap_or_default();
+    let Some(value) = inner.next() else {
+        return Err(DelbinError::new(ErrorCode::E01003, "Missing @layout offset"));
+    };
+    let offset = const_value(&parse_expr(value.clone())?, settings).ok_or_else(|| {
+        DelbinError::new(
+            ErrorCode::E01003,
+            format!("@layout offset of '{}' must be a constant expression: {}", name, value.as_str()),
+        )
+    })?;
+    Ok(LayoutEntry { name, offset })
+}
+
+fn parse_bundle_block(pair: pest::iterators::Pair<Rule>, settings: &Settings) -> Result<Bundle> {
+    let mut bundle = Bundle {
+        images: Vec::new(),
+        align: None,
+    };
     for inner in pair.into_inner() {
-        if inner.as_rule() == Rule::directive_value {
-            return match inner.as_str() {
-                "little" => Ok(Endian::Little),
-                "big" => Ok(Endian::Big),
-                _ => Err(DelbinError::new(
+        match inner.as_rule() {
+            Rule::align_attr => bundle.align = parse_align(inner, settings)?,
+            _ => {
+                let name = inner.as_str().to_string();
+                if bundle.images.contains(&name) {
+                    return Err(DelbinError::new(
+                        ErrorCode::E01003,
+                        format!("'{}' is listed twice in @bundle", name),
+                    ));
+                }
+                bundle.images.push(name);
+            }
+        }
+    }
+    if bundle.images.is_empty() {
+        return Err(DelbinError::new(ErrorCode::E01003, "@bundle lists no images"));
+    }
+    Ok(bundle)
+}
+
+/// Value of `@align(n)`: a positive constant that fits in 32 bits
+fn parse_align(pair: pest::iterators::Pair<Rule>, settings: &Settings) -> Result<Option<u32>> {
+    let mut align = None;
+    for value in pair.into_inner() {
+        let n = const_value(&parse_expr(value.clone())?, settings)
+            .and_then(|n| u32::try_from(n).ok())
+            .filter(|&n| n > 0)
+            .ok_or_else(|| {
+                DelbinError::new(
                     ErrorCode::E01003,
-                    format!("Invalid endian value: {}", inner.as_str()),
-                )),
+                    format!("Invalid @align value: {}", value.as_str()),
+                )
+                .with_hint("use a positive constant, e.g. @align(16)")
+            })?;
+        align = Some(n);
+    }
+    Ok(align)
+}
+
+/// Builtins that transform a whole section, usable in `@section` definitions
+pub(crate) const SECTION_TRANSFORMS: &[&str] = &["whiten", "merkle_tree", "encrypt", "compress"];
+
+fn parse_section_def(pair: pest::iterators::Pair<Rule>) -> Result<SectionDef> {
+    let mut inner = pair.into_inner();
+    let name = inner.next().map(|p| p.as_str().to_string()).unwrap_or_default();
+    let mut transform = match inner.next() {
+        Some(call) => parse_builtin_call(call)?,
+        None => return Err(DelbinError::new(ErrorCode::E01003, "Missing section transform")),
+    };
+    // The cipher of @encrypt(image, aes_ctr, ...) and the algorithm of
+    // @compress(image, lz4) are names, not sections
+    if let Expr::Call { name: func, args } = &mut transform {
+        let named = matches!(func.as_str(), "encrypt" | "compress");
+        if let (true, Some(Expr::SectionRef(algorithm))) = (named, args.get(1).cloned()) {
+            args[1] = Expr::String(algorithm);
+        }
+    }
+    let Expr::Call { name: func, args } = &transform else {
+        return Err(DelbinError::new(ErrorCode::E01003, "Missing section transform"));
+    };
+    // @encrypt needs an IV like @encrypt_range; other argument errors are
+    // left to the checker
+    let missing_iv = || bind_args(func, args, &["data", "cipher", "key", "iv"]).is_ok_and(|bound| bound[3].is_none());
+    if func == "encrypt" && missing_iv() {
+        return Err(DelbinError::new(
+            ErrorCode::E01003,
+            format!("@section {}: @encrypt() requires iv = ${{IV}}", name),
+        )
+        .with_hint("pass a unique 16-byte IV per image, e.g. a bytes env variable"));
+    }
+    if !SECTION_TRANSFORMS.contains(&func.as_str()) {
+        return Err(DelbinError::new(
+            ErrorCode::E01003,
+            format!(
+                "@{}() is not a section transform. Supported: {}",
+                func,
+                SECTION_TRANSFORMS.join(", ")
+            ),
+        ));
+    }
+    if !matches!(args.first(), Some(Expr::SectionRef(_))) {
+        return Err(DelbinError::new(
+            ErrorCode::E01003,
+            format!("@section {}: the first argument of @{}() must be a section name", name, func),
+        ));
+    }
+    Ok(SectionDef { name, transform })
+}
+
+fn parse_assert(pair: pest::iterators::Pair<Rule>) -> Result<Assert> {
+    let mut inner = pair.into_inner();
+    let cond = match inner.next() {
+        Some(expr) => parse_expr(expr)?,
+        None => return Err(DelbinError::new(ErrorCode::E01003, "Missing @assert condition")),
+    };
+    let message = match inner.next() {
+        Some(string) => {
+            let s = string.as_str();
+            Some(unescape_string(&s[1..s.len() - 1])?)
+        }
+        None => None,
+    };
+    Ok(Assert { cond, message })
+}
+
+/// Value of a `hex_number`, `bin_number`, `size_number` or `dec_number` pair
+fn parse_number(pair: &pest::iterators::Pair<Rule>) -> Result<u64> {
+    let s = pair.as_str();
+    let value = match pair.as_rule() {
+        Rule::hex_number => u64::from_str_radix(&s[2..], 16).ok(),
+        Rule::bin_number => u64::from_str_radix(&s[2..], 2).ok(),
+        Rule::size_number => {
+            let (digits, unit) = s.split_at(s.len() - 1);
+            let shift = match unit {
+                "K" => 10,
+                "M" => 20,
+                _ => 30,
             };
+            digits
+                .parse::<u64>()
+                .ok()
+                .and_then(|n| n.checked_mul(1 << shift))
+        }
+        _ => s.parse::<u64>().ok(),
+    };
+    value.ok_or_else(|| DelbinError::new(ErrorCode::E01004, format!("Invalid number: {}", s)))
+}
+
+/// `kind: required;` or `kind: algorithm;`, the algorithm a builtin of the
+/// kind
+fn parse_policy_rule(pair: pest::iterators::Pair<Rule>) -> Result<PolicyRule> {
+    let mut inner = pair.into_inner();
+    let name = inner.next().map(|p| p.as_str()).unwrap_or_default();
+    let value = inner.next().map(|p| p.as_str()).unwrap_or_default();
+    let kind = PolicyKind::from_name(name).ok_or_else(|| {
+        let names: Vec<&str> = PolicyKind::ALL.iter().map(PolicyKind::name).collect();
+        DelbinError::new(
+            ErrorCode::E01003,
+            format!("Unknown @policy rule: '{}'. Supported: {}", name, names.join(", ")),
+        )
+    })?;
+    if value == "required" {
+        return Ok(PolicyRule { kind, algorithm: None });
+    }
+    let signature = SIGNATURE_BUILTINS.contains(&value);
+    let valid = match kind {
+        PolicyKind::Signature => signature,
+        _ => is_range_based_builtin(value) && !signature,
+    };
+    if !valid {
+        return Err(DelbinError::new(
+            ErrorCode::E01003,
+            format!("Invalid value for @policy rule '{}': {}", name, value),
+        )
+        .with_hint(match kind {
+            PolicyKind::Signature => "use `required` or a signature builtin, e.g. `signature: ed25519;`",
+            _ => "use `required` or a checksum builtin, e.g. `payload_hash: sha256;`",
+        }));
+    }
+    Ok(PolicyRule {
+        kind,
+        algorithm: Some(value.to_string()),
+    })
+}
+
+fn parse_input_decl(pair: pest::iterators::Pair<Rule>) -> Result<InputDecl> {
+    let mut name = String::new();
+    let mut ty = InputType::Str;
+    let mut required = true;
+    let mut default = None;
+
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::ident => name = inner.as_str().to_string(),
+            Rule::input_type => {
+                ty = match inner.as_str() {
+                    "str" => InputType::Str,
+                    "bytes" => InputType::Bytes,
+                    other => InputType::Int(ScalarType::from_str(other).ok_or_else(|| {
+                        DelbinError::new(ErrorCode::E01003, format!("Unknown type: {}", other))
+                    })?),
+                }
+            }
+            Rule::input_presence => required = inner.as_str() == "required",
+            Rule::input_default => {
+                // A string, or a constant expression folded to its value
+                let text = inner.as_str();
+                default = Some(match parse_primary_expr(inner)? {
+                    Expr::String(s) => Expr::String(s),
+                    expr => Expr::Number(const_value(&expr, &NO_SETTINGS).ok_or_else(|| {
+                        DelbinError::new(
+                            ErrorCode::E01003,
+                            format!("Default of input '{}' must be a constant: {}", name, text),
+                        )
+                    })?),
+                });
+            }
+            _ => {}
         }
     }
-    Ok(Endian::Little)
+
+    if required && default.is_some() {
+        return Err(DelbinError::new(
+            ErrorCode::E01003,
+            format!("Required input '{}' cannot have a default", name),
+        ));
+    }
+    Ok(InputDecl {
+        name,
+        ty,
+        required,
+        default,
+    })
 }
 
-fn parse_struct_def(pair: pest::iterators::Pair<Rule>) -> Result<StructDef> {
+fn parse_struct_def(pair: pest::iterators::Pair<Rule>, settings: &Settings, expander: &mut Expander) -> Result<StructDef> {
     let mut name = String::new();
     let mut packed = false;
     let mut align = None;
-    let mut fields = Vec::new();
+    let mut fields: Vec<FieldDef> = Vec::new();
+    let mut encrypt = Vec::new();
+    let mut expect_encrypted = Vec::new();
+
+    // Offsets below are relative to the struct text
+    let text = pair.as_str();
+    let base = pair.as_span().start();
+    // Attributes may contain braces (`${KEY}`), so search after the last one
+    let head_end = pair
+        .clone()
+        .into_inner()
+        .filter(|p| matches!(p.as_rule(), Rule::ident | Rule::struct_attr))
+        .last()
+        .map_or(0, |p| p.as_span().end() - base);
+    let body_start = head_end + find_open_brace(&text[head_end..]) + 1;
+    let mut prev_end = body_start;
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
             Rule::ident => {
                 name = inner.as_str().to_string();
             }
-            Rule::struct_attr => {
-                let attr_str = inner.as_str();
-                if attr_str.contains("packed") {
-                    packed = true;
-                } else if attr_str.contains("align") {
-                    // Parse @align(n)
-                    for attr_inner in inner.into_inner() {
-                        if attr_inner.as_rule() == Rule::align_attr {
-                            for num in attr_inner.into_inner() {
-                                if num.as_rule() == Rule::dec_number {
-                                    align = Some(num.as_str().parse().unwrap_or(1));
-                                }
-                            }
-                        }
-                    }
+            Rule::struct_attr => match inner.into_inner().next() {
+                // Parse @align(n)
+                Some(attr_inner) if attr_inner.as_rule() == Rule::align_attr => {
+                    align = parse_align(attr_inner, settings)?;
                 }
-            }
-            Rule::field_def => {
-                fields.push(parse_field_def(inner)?);
+                Some(attr_inner) if attr_inner.as_rule() == Rule::encrypt_attr => {
+                    encrypt.push(parse_encrypt_attr(attr_inner)?);
+                }
+                Some(attr_inner) if attr_inner.as_rule() == Rule::expect_attr => {
+                    expect_encrypted.extend(attr_inner.into_inner().map(|p| p.as_str().to_string()));
+                }
+                _ => packed = true,
+            },
+            Rule::field_def | Rule::instance_def | Rule::tlv_def | Rule::include_stmt | Rule::for_block => {
+                parse_member(inner, text, base, &mut prev_end, &mut fields, expander)?;
             }
             _ => {}
         }
     }
 
+    check_names(&name, &fields)?;
+
+    // Everything between the last field and the closing brace
+    let closing_gap = &text[prev_end..text.len() - 1];
+    let closing = if fields.is_empty() {
+        closing_gap.to_string()
+    } else {
+        attach_trailing(&mut fields, closing_gap)
+    };
+
     Ok(StructDef {
         name,
         packed,
         align,
         fields,
+        encrypt,
+        expect_encrypted,
+        head_source: Some(text[..body_start].to_string()),
+        closing,
     })
 }
 
+/// Fields of an included file
+fn parse_fragment(text: &str, expander: &mut Expander) -> Result<Vec<FieldDef>> {
+    let pairs = DelBinParser::parse(Rule::fragment, text).map_err(syntax_error)?;
+    let mut fields = Vec::new();
+    let mut prev_end = 0;
+    for pair in pairs {
+        for inner in pair.into_inner() {
+            if matches!(inner.as_rule(), Rule::field_def | Rule::instance_def | Rule::tlv_def | Rule::include_stmt) {
+                parse_member(inner, text, 0, &mut prev_end, &mut fields, expander)?;
+            }
+        }
+    }
+    Ok(fields)
+}
+
+/// `struct name<params> { ... }`
+fn parse_template_def(pair: pest::iterators::Pair<Rule>, expander: &mut Expander) -> Result<StructTemplate> {
+    let text = pair.as_str();
+    let base = pair.as_span().start();
+    let mut name = String::new();
+    let mut params: Vec<String> = Vec::new();
+    let mut fields = Vec::new();
+    let mut prev_end = text.find('>').map_or(0, |end| end + find_open_brace(&text[end..]) + 1);
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::ident if name.is_empty() => name = inner.as_str().to_string(),
+            Rule::ident => {
+                let param = inner.as_str().to_string();
+                if params.contains(&param) {
+                    return Err(DelbinError::new(
+                        ErrorCode::E01003,
+                        format!("Struct template '{}' has parameter '{}' twice", name, param),
+                    ));
+                }
+                params.push(param);
+            }
+            _ => parse_member(inner, text, base, &mut prev_end, &mut fields, expander)?,
+        }
+    }
+    if let Some(field) = fields.iter().find(|f| params.contains(&f.name)) {
+        return Err(DelbinError::new(
+            ErrorCode::E01003,
+            format!("Struct template '{}' has a field and a parameter named '{}'", name, field.name),
+        ));
+    }
+    Ok(StructTemplate { name, params, fields })
+}
+
+/// Reject a field or `@alias` that names another field or alias of struct
+/// `name`, e.g. a template instance's `rec0_tag` next to a declared
+/// `rec0_tag`; fields of an `@for` block only meet the others in the same
+/// loop, as unrolling prefixes them all alike
+fn check_names(name: &str, fields: &[FieldDef]) -> Result<()> {
+    let located = |err: DelbinError, field: &FieldDef| match &field.location {
+        Some(location) => err.with_location(location.clone()),
+        None => err,
+    };
+    let mut names = HashSet::new();
+    for field in fields {
+        if !names.insert((field.for_each.as_ref(), field.name.as_str())) {
+            let err = DelbinError::new(
+                ErrorCode::E01003,
+                format!("Duplicate field '{}' in struct '{}'", field.name, name),
+            );
+            return Err(located(err, field));
+        }
+    }
+    for field in fields {
+        for alias in &field.aliases {
+            if !names.insert((field.for_each.as_ref(), alias.as_str())) {
+                let err = DelbinError::new(
+                    ErrorCode::E01003,
+                    format!("Alias '{}' of field '{}' is already a name in struct '{}'", alias, field.name, name),
+                );
+                return Err(located(err, field));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fields of `name: tlv<T, L> { tlv { ... } ... }`: `name_<i>_tag`,
+/// `name_<i>_len` and `name_<i>_value` for record `i`
+fn parse_tlv_def(pair: pest::iterators::Pair<Rule>) -> Result<Vec<FieldDef>> {
+    let mut inner = pair.into_inner();
+    let name = inner.next().map(|p| p.as_str().to_string()).unwrap_or_default();
+    let mut scalar = || {
+        let s = inner.next().map(|p| p.as_str()).unwrap_or_default();
+        ScalarType::from_str(s).ok_or_else(|| DelbinError::new(ErrorCode::E01003, format!("Unknown type: {}", s)))
+    };
+    let (tag_ty, len_ty) = (scalar()?, scalar()?);
+    let mut fields = Vec::new();
+    for (i, record) in inner.enumerate() {
+        let mut parts = record.into_inner();
+        let (Some(tag), Some(value)) = (parts.next(), parts.next()) else {
+            return Err(DelbinError::new(ErrorCode::E01003, "Invalid TLV record"));
+        };
+        let mut value = value.into_inner();
+        let (ty, init) = match (value.next(), value.next()) {
+            (Some(ty), Some(init)) => {
+                let init = match init.as_rule() {
+                    Rule::array_literal => parse_array_literal(init)?,
+                    _ => parse_expr(init)?,
+                };
+                (parse_type_spec(ty)?, init)
+            }
+            (Some(init), None) => (Type::Inferred, parse_expr(init)?),
+            _ => return Err(DelbinError::new(ErrorCode::E01003, "Missing TLV value")),
+        };
+        // Typed values have their type's size, the others their own length
+        let len = match &ty {
+            Type::Scalar(s) => Expr::Number(s.size() as u64),
+            Type::Array { elem, len } => Expr::BinaryOp {
+                op: BinOp::Mul,
+                left: Box::new(Expr::Number(elem.size() as u64)),
+                right: len.clone(),
+            },
+            Type::Inferred => Expr::Call {
+                name: "sizeof".to_string(),
+                args: vec![init.clone()],
+            },
+        };
+        let prefix = format!("{}_{}", name, i);
+        fields.push(FieldDef::new(format!("{}_tag", prefix), Type::Scalar(tag_ty), Some(parse_expr(tag)?)));
+        let mut len = FieldDef::new(format!("{}_len", prefix), Type::Scalar(len_ty), Some(len));
+        len.tlv_len = true;
+        fields.push(len);
+        fields.push(FieldDef::new(format!("{}_value", prefix), ty, Some(init)));
+    }
+    if fields.is_empty() {
+        return Err(DelbinError::new(ErrorCode::E01003, format!("TLV list '{}' has no records", name)));
+    }
+    for field in &mut fields {
+        field.source = Some(printer::canonical_source(field));
+    }
+    Ok(fields)
+}
+
+/// Append a field, or the fields an `@include`, template instance or TLV
+/// list brings in, to `fields`; `text` is the source from offset `base`,
+/// parsed up to `prev_end`
+fn parse_member(
+    pair: pest::iterators::Pair<Rule>,
+    text: &str,
+    base: usize,
+    prev_end: &mut usize,
+    fields: &mut Vec<FieldDef>,
+    expander: &mut Expander,
+) -> Result<()> {
+    let span = pair.as_span();
+    let (start, end) = (span.start() - base, span.end() - base);
+    let leading = attach_trailing(fields, &text[*prev_end..start]);
+    let location = location_of(span.start_pos());
+    let expanded = match pair.as_rule() {
+        Rule::include_stmt => {
+            let s = pair.into_inner().next().map(|p| p.as_str()).unwrap_or("\"\"");
+            expander.expand(&unescape_string(&s[1..s.len() - 1])?)
+        }
+        Rule::instance_def => {
+            let mut inner = pair.into_inner();
+            let instance = inner.next().map(|p| p.as_str().to_string()).unwrap_or_default();
+            let template = inner.next().map(|p| p.as_str().to_string()).unwrap_or_default();
+            let args = inner.map(parse_expr).collect::<Result<Vec<_>>>()?;
+            expander.instantiate(&instance, &template, &args)
+        }
+        Rule::tlv_def => parse_tlv_def(pair),
+        Rule::for_block => {
+            let mut body = parse_for_block(pair, text, base, expander)?;
+            for field in &mut body {
+                field.expanded_from = Some(text[start..end].to_string());
+            }
+            body[0].trivia.leading = leading;
+            fields.extend(body);
+            *prev_end = end;
+            return Ok(());
+        }
+        _ => {
+            let mut field = parse_field_def(pair)?;
+            field.trivia.leading = leading;
+            field.source = Some(text[start..end].to_string());
+            field.location = Some(location);
+            fields.push(field);
+            *prev_end = end;
+            return Ok(());
+        }
+    };
+    let mut expanded = expanded.map_err(|e| match e.location {
+        Some(_) => e,
+        None => e.with_location(location.clone()),
+    })?;
+    // Errors in expanded fields point at the @include or instance
+    for field in &mut expanded {
+        field.expanded_from = Some(text[start..end].to_string());
+        field.location = Some(location.clone());
+    }
+    if let Some(first) = expanded.first_mut() {
+        first.trivia.leading = leading;
+    }
+    fields.extend(expanded);
+    *prev_end = end;
+    Ok(())
+}
+
+/// Fields of an `@for` block, marked with its loop; they keep their own
+/// locations
+fn parse_for_block(
+    pair: pest::iterators::Pair<Rule>,
+    text: &str,
+    base: usize,
+    expander: &mut Expander,
+) -> Result<Vec<FieldDef>> {
+    let location = location_of(pair.as_span().start_pos());
+    let mut inner = pair.into_inner();
+    let var = inner.next().map(|p| p.as_str().to_string()).unwrap_or_default();
+    let list_pair = inner.next().ok_or_else(|| DelbinError::new(ErrorCode::E01003, "Invalid @for block"))?;
+    let list_end = list_pair.as_span().end() - base;
+    let Expr::EnvVar(list) = parse_env_var(list_pair)? else {
+        return Err(DelbinError::new(ErrorCode::E01003, "Invalid @for block"));
+    };
+
+    let mut body = Vec::new();
+    let mut prev_end = list_end + text[list_end..].find('{').unwrap_or(0) + 1;
+    for member in inner {
+        parse_member(member, text, base, &mut prev_end, &mut body, expander)?;
+    }
+    if body.is_empty() {
+        return Err(DelbinError::new(
+            ErrorCode::E01003,
+            format!("@for {} in ${{{}}} has no fields", var, list),
+        )
+        .with_location(location));
+    }
+    let for_each = ForEach { var, list };
+    for field in &mut body {
+        field.for_each = Some(for_each.clone());
+    }
+    Ok(body)
+}
+
+/// 1-based line and column of `pos`, with its line as context
+fn location_of(pos: pest::Position) -> SourceLocation {
+    let (line, column) = pos.line_col();
+    SourceLocation {
+        line,
+        column,
+        context: pos.line_of().trim_end().to_string(),
+    }
+}
+
+/// Offset of the struct body's `{`, skipping comments in the struct head
+fn find_open_brace(text: &str) -> usize {
+    let mut i = 0;
+    while i < text.len() {
+        if text[i..].starts_with("//") {
+            i += text[i..].find('\n').unwrap_or(text.len() - i);
+        } else if text.as_bytes()[i] == b'{' {
+            return i;
+        } else {
+            i += 1;
+        }
+    }
+    text.len().saturating_sub(1)
+}
+
+/// Split the gap after the last parsed field: a comment on the same line
+/// becomes that field's trailing trivia; the rest is returned
+fn attach_trailing(fields: &mut [FieldDef], gap: &str) -> String {
+    let Some(prev) = fields.last_mut() else {
+        return gap.to_string();
+    };
+    match gap.find('\n') {
+        Some(nl) if !gap[..nl].trim().is_empty() => {
+            prev.trivia.trailing = gap[..nl].to_string();
+            gap[nl..].to_string()
+        }
+        _ => gap.to_string(),
+    }
+}
+
 fn parse_field_def(pair: pest::iterators::Pair<Rule>) -> Result<FieldDef> {
     let mut name = String::new();
+    let mut aliases = Vec::new();
     let mut ty = None;
     let mut init = None;
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
-            Rule::ident => {
-                if name.is_empty() {
-                    name = inner.as_str().to_string();
+            Rule::ident if name.is_empty() => {
+                name = inner.as_str().to_string();
+            }
+            Rule::field_attr => {
+                for alias in inner.into_inner() {
+                    let s = alias.as_str();
+                    aliases.push(unescape_string(&s[1..s.len() - 1])?);
                 }
             }
             Rule::type_spec => {
@@ -129,11 +1132,13 @@ fn parse_field_def(pair: pest::iterators::Pair<Rule>) -> Result<FieldDef> {
         }
     }
 
-    Ok(FieldDef {
+    let mut field = FieldDef::new(
         name,
-        ty: ty.ok_or_else(|| DelbinError::new(ErrorCode::E01003, "Missing type"))?,
+        ty.ok_or_else(|| DelbinError::new(ErrorCode::E01003, "Missing type"))?,
         init,
-    })
+    );
+    field.aliases = aliases;
+    Ok(field)
 }
 
 fn parse_type_spec(pair: pest::iterators::Pair<Rule>) -> Result<Type> {
@@ -157,6 +1162,7 @@ fn parse_type_spec(pair: pest::iterators::Pair<Rule>) -> Result<Type> {
 fn parse_array_type(pair: pest::iterators::Pair<Rule>) -> Result<Type> {
     let mut elem = None;
     let mut len = None;
+    let mut inferred = false;
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
@@ -166,10 +1172,20 @@ fn parse_array_type(pair: pest::iterators::Pair<Rule>) -> Result<Type> {
             Rule::expr => {
                 len = Some(parse_expr(inner)?);
             }
+            Rule::infer_marker => inferred = true,
             _ => {}
         }
     }
 
+    if inferred {
+        return match elem {
+            Some(ScalarType::U8) => Ok(Type::Inferred),
+            _ => Err(DelbinError::new(
+                ErrorCode::E01003,
+                "Only [u8; _] arrays can take their length from the initializer",
+            )),
+        };
+    }
     Ok(Type::Array {
         elem: elem.ok_or_else(|| DelbinError::new(ErrorCode::E01003, "Missing array element type"))?,
         len: Box::new(len.ok_or_else(|| DelbinError::new(ErrorCode::E01003, "Missing array length"))?),
@@ -177,14 +1193,39 @@ fn parse_array_type(pair: pest::iterators::Pair<Rule>) -> Result<Type> {
 }
 
 fn parse_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
-    // Handle the case where we might receive an expr node or directly an or_expr node
+    // Handle the case where we might receive an expr node or directly a cmp_expr node
     let actual_pair = if pair.as_rule() == Rule::expr {
-        // Unwrap expr to get or_expr
+        // Unwrap expr to get cmp_expr
         pair.into_inner().next().ok_or_else(|| DelbinError::new(ErrorCode::E01003, "Empty expr"))?
     } else {
         pair
     };
-    parse_or_expr(actual_pair)
+    parse_cmp_expr(actual_pair)
+}
+
+fn parse_cmp_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
+    let mut inner_pairs = pair.into_inner();
+    let left = match inner_pairs.next() {
+        Some(left) => parse_or_expr(left)?,
+        None => return Err(DelbinError::new(ErrorCode::E01003, "Empty expression")),
+    };
+    let (Some(op_pair), Some(right)) = (inner_pairs.next(), inner_pairs.next()) else {
+        return Ok(left);
+    };
+    let op = match op_pair.as_str() {
+        "<" => BinOp::Lt,
+        "<=" => BinOp::Le,
+        ">" => BinOp::Gt,
+        ">=" => BinOp::Ge,
+        "==" => BinOp::Eq,
+        "!=" => BinOp::Ne,
+        _ => return Err(DelbinError::new(ErrorCode::E01003, "Invalid comparison operator")),
+    };
+    Ok(Expr::BinaryOp {
+        op,
+        left: Box::new(left),
+        right: Box::new(parse_or_expr(right)?),
+    })
 }
 
 fn parse_or_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
@@ -291,7 +1332,7 @@ fn parse_add_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
         return Err(DelbinError::new(ErrorCode::E01003, "Empty expression"));
     }
 
-    let mut left = parse_unary_expr(inner_pairs.remove(0))?;
+    let mut left = parse_mul_expr(inner_pairs.remove(0))?;
 
     while inner_pairs.len() >= 2 {
         let op_pair = inner_pairs.remove(0);
@@ -300,6 +1341,32 @@ fn parse_add_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
             "-" => BinOp::Sub,
             _ => return Err(DelbinError::new(ErrorCode::E01003, "Invalid add operator")),
         };
+        let right = parse_mul_expr(inner_pairs.remove(0))?;
+        left = Expr::BinaryOp {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+    }
+
+    Ok(left)
+}
+
+fn parse_mul_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
+    let mut inner_pairs: Vec<_> = pair.into_inner().collect();
+
+    if inner_pairs.is_empty() {
+        return Err(DelbinError::new(ErrorCode::E01003, "Empty expression"));
+    }
+
+    let mut left = parse_unary_expr(inner_pairs.remove(0))?;
+
+    while inner_pairs.len() >= 2 {
+        let op_pair = inner_pairs.remove(0);
+        let op = match op_pair.as_str() {
+            "*" => BinOp::Mul,
+            _ => return Err(DelbinError::new(ErrorCode::E01003, "Invalid multiply operator")),
+        };
         let right = parse_unary_expr(inner_pairs.remove(0))?;
         left = Expr::BinaryOp {
             op,
@@ -378,6 +1445,15 @@ fn parse_primary_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
                 })?;
                 return Ok(Expr::Number(value));
             }
+            Rule::size_number => {
+                return parse_number(&inner).map(Expr::Number);
+            }
+            Rule::setting_ref => {
+                return Ok(Expr::Call {
+                    name: inner.as_str()[1..].to_string(),
+                    args: Vec::new(),
+                });
+            }
             Rule::string => {
                 let s = inner.as_str();
                 let content = &s[1..s.len() - 1];
@@ -434,6 +1510,18 @@ fn parse_arg(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
             Rule::range_expr => {
                 return parse_range_expr(inner);
             }
+            Rule::named_arg => {
+                let mut parts = inner.into_inner();
+                let name = parts.next().map(|p| p.as_str().to_string()).unwrap_or_default();
+                let value = match parts.next() {
+                    Some(value) => parse_expr(value)?,
+                    None => return Err(DelbinError::new(ErrorCode::E01003, "Invalid argument")),
+                };
+                return Ok(Expr::Named {
+                    name,
+                    value: Box::new(value),
+                });
+            }
             Rule::expr => {
                 return parse_expr(inner);
             }
@@ -451,42 +1539,7 @@ fn parse_range_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
     for inner in pair.into_inner() {
         if inner.as_rule() == Rule::range_spec {
             has_range_spec = true;
-            for spec_inner in inner.into_inner() {
-                match spec_inner.as_rule() {
-                    Rule::range_start => {
-                        for child in spec_inner.into_inner() {
-                            let expr = match child.as_rule() {
-                                Rule::ident => Expr::SectionRef(child.as_str().to_string()),
-                                Rule::hex_number => {
-                                    let s = child.as_str();
-                                    Expr::Number(u64::from_str_radix(&s[2..], 16).map_err(|_| {
-                                        DelbinError::new(ErrorCode::E01004, format!("Invalid hex: {}", s))
-                                    })?)
-                                }
-                                Rule::bin_number => {
-                                    let s = child.as_str();
-                                    Expr::Number(u64::from_str_radix(&s[2..], 2).map_err(|_| {
-                                        DelbinError::new(ErrorCode::E01004, format!("Invalid binary: {}", s))
-                                    })?)
-                                }
-                                Rule::dec_number => {
-                                    Expr::Number(child.as_str().parse::<u64>().map_err(|_| {
-                                        DelbinError::new(ErrorCode::E01004, format!("Invalid number: {}", child.as_str()))
-                                    })?)
-                                }
-                                _ => return Err(DelbinError::new(ErrorCode::E01003, "Invalid range start")),
-                            };
-                            start = Some(Box::new(expr));
-                        }
-                    }
-                    Rule::range_end => {
-                        for ident in spec_inner.into_inner() {
-                            end = Some(ident.as_str().to_string());
-                        }
-                    }
-                    _ => {}
-                }
-            }
+            (start, end) = parse_range_spec(inner)?;
         }
     }
 
@@ -501,13 +1554,59 @@ fn parse_range_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
     }
 }
 
+/// Start and end of a `range_spec` (`start..end`, both optional)
+fn parse_range_spec(pair: pest::iterators::Pair<Rule>) -> Result<(Option<Box<Expr>>, Option<String>)> {
+    let mut start = None;
+    let mut end = None;
+    for spec_inner in pair.into_inner() {
+        match spec_inner.as_rule() {
+            Rule::range_start => {
+                for child in spec_inner.into_inner() {
+                    start = Some(Box::new(parse_expr(child)?));
+                }
+            }
+            Rule::range_end => {
+                for ident in spec_inner.into_inner() {
+                    end = Some(ident.as_str().to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((start, end))
+}
+
+fn parse_encrypt_attr(pair: pest::iterators::Pair<Rule>) -> Result<EncryptRange> {
+    let mut inner = pair.into_inner();
+    let (start, end) = match inner.next() {
+        Some(spec) => parse_range_spec(spec)?,
+        None => (None, None),
+    };
+    let key = match inner.next() {
+        Some(key) => parse_expr(key)?,
+        None => return Err(DelbinError::new(ErrorCode::E01003, "@encrypt_range() needs a key")),
+    };
+    let iv = match inner.next() {
+        Some(iv) => parse_expr(iv)?,
+        None => return Err(DelbinError::new(ErrorCode::E01003, "@encrypt_range() needs an IV")),
+    };
+    Ok(EncryptRange { start, end, key, iv })
+}
+
+/// `${NAME}`, or `${NAME[1].size}` as `EnvVar("NAME[1].size")`
 fn parse_env_var(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
+    let mut name = String::new();
     for inner in pair.into_inner() {
-        if inner.as_rule() == Rule::ident {
-            return Ok(Expr::EnvVar(inner.as_str().to_string()));
+        match inner.as_rule() {
+            Rule::ident => name.push_str(inner.as_str()),
+            Rule::env_index => name.push_str(&inner.as_str().split_whitespace().collect::<String>()),
+            _ => {}
         }
     }
-    Err(DelbinError::new(ErrorCode::E01003, "Invalid environment variable"))
+    if name.is_empty() {
+        return Err(DelbinError::new(ErrorCode::E01003, "Invalid environment variable"));
+    }
+    Ok(Expr::EnvVar(name))
 }
 
 fn parse_array_literal(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
@@ -544,11 +1643,8 @@ fn parse_repeat_form(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
             Rule::array_elem => {
                 value = Some(parse_array_elem(inner)?);
             }
-            Rule::dec_number => {
-                let n = inner.as_str().parse::<u64>().map_err(|_| {
-                    DelbinError::new(ErrorCode::E01004, format!("Invalid number: {}", inner.as_str()))
-                })?;
-                count = Some(Expr::Number(n));
+            Rule::expr => {
+                count = Some(parse_expr(inner)?);
             }
             Rule::infer_marker => {
                 is_infer = true;
@@ -586,35 +1682,10 @@ fn parse_list_form(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
 }
 
 fn parse_array_elem(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
-    for inner in pair.into_inner() {
-        match inner.as_rule() {
-            Rule::env_var => {
-                return parse_env_var(inner);
-            }
-            Rule::hex_number => {
-                let s = inner.as_str();
-                let value = u64::from_str_radix(&s[2..], 16).map_err(|_| {
-                    DelbinError::new(ErrorCode::E01004, format!("Invalid hex number: {}", s))
-                })?;
-                return Ok(Expr::Number(value));
-            }
-            Rule::bin_number => {
-                let s = inner.as_str();
-                let value = u64::from_str_radix(&s[2..], 2).map_err(|_| {
-                    DelbinError::new(ErrorCode::E01004, format!("Invalid binary number: {}", s))
-                })?;
-                return Ok(Expr::Number(value));
-            }
-            Rule::dec_number => {
-                let value = inner.as_str().parse::<u64>().map_err(|_| {
-                    DelbinError::new(ErrorCode::E01004, format!("Invalid number: {}", inner.as_str()))
-                })?;
-                return Ok(Expr::Number(value));
-            }
-            _ => {}
-        }
+    match pair.into_inner().next() {
+        Some(expr) => parse_expr(expr),
+        None => Err(DelbinError::new(ErrorCode::E01004, "Invalid array element")),
     }
-    Err(DelbinError::new(ErrorCode::E01004, "Invalid array element"))
 }
 
 /// Handle string escape sequences
@@ -686,6 +1757,118 @@ mod tests {
         assert_eq!(file.struct_def.fields.len(), 2);
     }
 
+    #[test]
+    fn test_endian_from_env_var() {
+        let file = parse("@endian = ${TARGET_ENDIAN}; struct h { a: u8; }").unwrap();
+        assert_eq!(file.endian_var.as_deref(), Some("TARGET_ENDIAN"));
+
+        let file = parse("@endian = ${E}; @endian = big; struct h { a: u8; }").unwrap();
+        assert_eq!(file.endian_var, None);
+        assert_eq!(file.endian, Endian::Big);
+    }
+
+    #[test]
+    fn test_base_and_size_directives() {
+        let file = parse("@base = 0x08000000; @size = 512; struct h { a: u8; }").unwrap();
+        assert_eq!(file.base, Some(0x0800_0000));
+        assert_eq!(file.size, Some(512));
+
+        assert!(parse("@base = big; struct h { a: u8; }").is_err());
+        assert!(parse("@size = ${S}; struct h { a: u8; }").is_err());
+        assert!(parse("@endian = 1; struct h { a: u8; }").is_err());
+    }
+
+    #[test]
+    fn test_constant_expressions() {
+        let file = parse(
+            "@base = 0x08000000; @slot_size = 4 * 64; @size = @slot_size - 16;\n\
+             @layout { app @ @base + 1K; }\n\
+             @inputs { N: u32 optional = 1 << 4; }\n\
+             struct h @align(1 << 3) {\n\
+                 a: [u8; 4] = [1 + 1; 2 * 2];\n\
+                 b: [u8; _] = [~0xF0 & 0xFF, ${N} + 1];\n\
+                 c: u32 = @crc32(@self[@offsetof(a) + 1..c]);\n\
+             }",
+        )
+        .unwrap();
+        assert_eq!((file.slot_size, file.size), (Some(256), Some(240)));
+        assert_eq!(file.layout[0].offset, 0x0800_0400);
+        assert!(matches!(file.inputs[0].default, Some(Expr::Number(16))));
+        assert_eq!(file.struct_def.align, Some(8));
+        let fields: Vec<_> = file.struct_def.fields.iter().map(|f| f.to_string().trim().to_string()).collect();
+        assert_eq!(fields[0], "a: [u8; 4] = [1 + 1; 2 * 2];");
+        assert_eq!(fields[1], "b: [u8; _] = [~0xF0 & 0xFF, ${N} + 1];");
+        assert_eq!(fields[2], "c: u32 = @crc32(@self[@offsetof(a) + 1..c]);");
+
+        // Values must still be constants
+        assert!(parse("@size = ${S} * 2; struct h { a: u8; }").is_err());
+        assert!(parse("@size = @base; struct h { a: u8; }").is_err());
+        assert!(parse("@fill = 0x80 << 1; struct h { a: u8; }").is_err());
+        assert!(parse("struct h @align(4 - 4) { a: u8; }").is_err());
+        assert!(parse("@inputs { N: u8 optional = ${M}; } struct h { a: u8; }").is_err());
+
+        let file = parse("@size = @align_up(100, 64); @fill = @align_down(0x1FF, 0x100) - 1; struct h { a: u8; }").unwrap();
+        assert_eq!((file.size, file.fill), (Some(128), Some(0xFF)));
+        assert!(parse("@size = @align_up(100, 0); struct h { a: u8; }").is_err());
+        let file = parse("@size = @min(@align_up(5000, 4K), 6K); struct h { a: u8; }").unwrap();
+        assert_eq!(file.size, Some(6144));
+    }
+
+    #[test]
+    fn test_parse_inputs_block() {
+        let file = parse(
+            r#"
+            @inputs {
+                VERSION_MAJOR: u8 required;
+                VERSION_STRING: str optional = "dev";
+                BUILD: u32 optional = 0x10;
+                KEY: bytes;
+            }
+            struct h { a: u8; }
+            "#,
+        )
+        .unwrap();
+        let summary: Vec<_> = file
+            .inputs
+            .iter()
+            .map(|i| (i.name.as_str(), i.ty, i.required, i.default.is_some()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("VERSION_MAJOR", InputType::Int(ScalarType::U8), true, false),
+                ("VERSION_STRING", InputType::Str, false, true),
+                ("BUILD", InputType::Int(ScalarType::U32), false, true),
+                ("KEY", InputType::Bytes, true, false),
+            ]
+        );
+
+        assert!(parse("@inputs { A: u8 required = 1; } struct h { a: u8; }").is_err());
+    }
+
+    #[test]
+    fn test_parse_policy_block() {
+        let file = parse("@policy { header_crc: required; payload_hash: sha256; } struct h { a: u8; }").unwrap();
+        assert_eq!(
+            file.policy,
+            [
+                PolicyRule { kind: PolicyKind::HeaderCrc, algorithm: None },
+                PolicyRule { kind: PolicyKind::PayloadHash, algorithm: Some("sha256".to_string()) },
+            ]
+        );
+
+        for bad in [
+            "@policy { header_size: required; }",
+            "@policy { payload_hash: sha257; }",
+            "@policy { payload_hash: ed25519; }",
+            "@policy { signature: crc32; }",
+            "@policy { signature: required; signature: ed25519; }",
+        ] {
+            let err = parse(&format!("{} struct h {{ a: u8; }}", bad)).unwrap_err();
+            assert_eq!(err.code, ErrorCode::E01003, "{bad}");
+        }
+    }
+
     #[test]
     fn test_array_literal_repeat_explicit() {
         let input = r#"
@@ -740,4 +1923,104 @@ mod tests {
         let result = parse(input);
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_include_expands_and_prints_back() {
+        let resolver = |path: &str| -> Result<String> {
+            match path {
+                "common.dbin" => Ok("// shared prefix\nmagic: u32 = 0x55AA55AA;\n@include \"ver.dbin\";\n".to_string()),
+                "ver.dbin" => Ok("version: u16 = 1;".to_string()),
+                "loop.dbin" => Ok("@include \"loop2.dbin\";".to_string()),
+                "loop2.dbin" => Ok("@include \"loop.dbin\";".to_string()),
+                "empty.dbin" => Ok("// nothing\n".to_string()),
+                "broken.dbin" => Ok("x: u8 = ;".to_string()),
+                _ => Err(DelbinError::new(ErrorCode::E05001, format!("no {}", path))),
+            }
+        };
+        let input = "struct h @packed {\n    @include \"common.dbin\";  // header prefix\n    size: u32;\n}\n";
+        let file = parse_with_resolver(input, &resolver).unwrap();
+        let names: Vec<&str> = file.struct_def.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["magic", "version", "size"]);
+        assert_eq!(file.includes, ["common.dbin", "ver.dbin"]);
+        assert_eq!(file.struct_def.fields[1].expanded_from.as_deref(), Some("@include \"common.dbin\";"));
+        assert_eq!(file.struct_def.fields[1].location.as_ref().unwrap().line, 2);
+        assert_eq!(file.to_string(), input);
+
+        let err = parse(input).unwrap_err();
+        assert_eq!(err.code, ErrorCode::E01003);
+        assert!(err.message.contains("needs an include path"), "{}", err.message);
+
+        let err = parse_with_resolver("struct h { @include \"loop.dbin\"; }", &resolver).unwrap_err();
+        assert!(err.message.contains("cycle: loop.dbin -> loop2.dbin -> loop.dbin"), "{}", err.message);
+        let err = parse_with_resolver("struct h { @include \"empty.dbin\"; }", &resolver).unwrap_err();
+        assert!(err.message.contains("defines no fields"), "{}", err.message);
+        let err = parse_with_resolver("struct h { @include \"broken.dbin\"; }", &resolver).unwrap_err();
+        assert!(err.message.ends_with("(in @include \"broken.dbin\")"), "{}", err.message);
+        let err = parse_with_resolver("struct h { @include \"nope.dbin\"; }", &resolver).unwrap_err();
+        assert_eq!(err.code, ErrorCode::E05001);
+    }
+
+    #[test]
+    fn test_include_path_is_searched_in_order_and_confined() {
+        let dir = std::env::temp_dir().join(format!("delbin-include-{}", std::process::id()));
+        let (first, second) = (dir.join("a"), dir.join("b"));
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::create_dir_all(&second).unwrap();
+        std::fs::write(second.join("common.dbin"), "magic: u8 = 2;").unwrap();
+        std::fs::write(dir.join("secret.dbin"), "magic: u8 = 3;").unwrap();
+
+        let includes = IncludePath::new([&first, &second]);
+        let file = parse_with_resolver("struct h { @include \"common.dbin\"; }", &includes).unwrap();
+        assert!(matches!(file.struct_def.fields[0].init, Some(Expr::Number(2))));
+
+        std::fs::write(first.join("common.dbin"), "magic: u8 = 1;").unwrap();
+        let file = parse_with_resolver("struct h { @include \"common.dbin\"; }", &includes).unwrap();
+        assert!(matches!(file.struct_def.fields[0].init, Some(Expr::Number(1))));
+
+        let err = parse_with_resolver("struct h { @include \"../secret.dbin\"; }", &includes).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(err.code, ErrorCode::E04003);
+    }
+
+    #[test]
+    fn test_struct_template_instances() {
+        let input = r#"struct tlv<T, LEN> {
+    tag: u16 = T;
+    len: u16 = LEN;
+    value: [u8; LEN];
+    end: u32 = @offsetof(value) + LEN;
+}
+
+struct h @packed {
+    a: tlv<0x01, 4>;  // first record
+    b: tlv<${TAG}, 2>;
+}
+"#;
+        let file = parse(input).unwrap();
+        let names: Vec<&str> = file.struct_def.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["a_tag", "a_len", "a_value", "a_end", "b_tag", "b_len", "b_value", "b_end"]);
+        assert_eq!(file.templates[0].params, ["T", "LEN"]);
+        assert_eq!(file.struct_def.fields[3].source.as_deref(), Some("a_end: u32 = @offsetof(a_value) + 4;"));
+        assert_eq!(file.struct_def.fields[4].source.as_deref(), Some("b_tag: u16 = ${TAG};"));
+        assert_eq!(file.to_string(), input);
+
+        let mut edited = file.clone();
+        edited.struct_def.fields[0].source = None;
+        assert!(edited.to_string().contains("\n    a_tag: u16 = 1;\n    a_len: u16 = 4;"), "{}", edited);
+
+        for (dsl, message) in [
+            ("struct h { a: tlv<1>; }", "Unknown struct template 'tlv'"),
+            ("struct t<A> { x: u8 = A; } struct h { a: t<1, 2>; }", "takes 1 parameters, 2 given"),
+            ("struct t<A, A> { x: u8; } struct h { x: u8; }", "parameter 'A' twice"),
+            ("struct t<x> { x: u8; } struct h { y: u8; }", "a field and a parameter named 'x'"),
+            ("struct t<A> { x: u8; } struct t<B> { y: u8; } struct h { y: u8; }", "defined twice"),
+            ("struct h<A> { x: u8; } struct h { y: u8; }", "names both a struct and a struct template"),
+            ("struct r<N> { tag: u8 = N; } struct h { a_tag: u8 = 1; a: r<2>; }", "Duplicate field 'a_tag'"),
+            ("struct r<N> { tag: u8 = N; } struct h { a: r<2>; a_tag: u8 = 1; }", "Duplicate field 'a_tag'"),
+        ] {
+            let err = parse(dsl).unwrap_err();
+            assert_eq!(err.code, ErrorCode::E01003);
+            assert!(err.message.contains(message), "{}: {}", dsl, err.message);
+        }
+    }
 }
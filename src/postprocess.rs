@@ -0,0 +1,148 @@
+//! Delbin output post-processors
+//!
+//! Last-mile transforms run over the finished bytes (header or assembled
+//! image) before they are returned: byte swapping for 16-bit programmers,
+//! padding to the flash size, vendor-specific scrambling. Register them on
+//! [`GenerateOptions`](crate::GenerateOptions).
+
+use crate::builtin;
+use crate::error::{DelbinError, ErrorCode, Result};
+
+/// Transform applied to generated output
+pub trait PostProcessor: Send + Sync {
+    /// Short name for diagnostics
+    fn name(&self) -> &str;
+
+    /// Transform `data` in place
+    fn process(&self, data: &mut Vec<u8>) -> Result<()>;
+}
+
+/// Swap the bytes of every 16-bit word (`01 02 03 04` → `02 01 04 03`)
+///
+/// The output length must be even.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByteSwap16;
+
+impl PostProcessor for ByteSwap16 {
+    fn name(&self) -> &str {
+        "byteswap16"
+    }
+
+    fn process(&self, data: &mut Vec<u8>) -> Result<()> {
+        swap_words(data, 2)
+    }
+}
+
+/// Reverse the bytes of every 32-bit word (`01 02 03 04` → `04 03 02 01`)
+///
+/// The output length must be a multiple of 4.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByteSwap32;
+
+impl PostProcessor for ByteSwap32 {
+    fn name(&self) -> &str {
+        "byteswap32"
+    }
+
+    fn process(&self, data: &mut Vec<u8>) -> Result<()> {
+        swap_words(data, 4)
+    }
+}
+
+/// Pad the output with `fill` up to `size` bytes (e.g. the flash size)
+///
+/// Output longer than `size` is an error.
+#[derive(Debug, Clone, Copy)]
+pub struct PadTo {
+    pub size: usize,
+    pub fill: u8,
+}
+
+impl PostProcessor for PadTo {
+    fn name(&self) -> &str {
+        "pad"
+    }
+
+    fn process(&self, data: &mut Vec<u8>) -> Result<()> {
+        if data.len() > self.size {
+            return Err(DelbinError::new(
+                ErrorCode::E04002,
+                format!("Output is {} bytes, larger than pad size {}", data.len(), self.size),
+            ));
+        }
+        data.resize(self.size, self.fill);
+        Ok(())
+    }
+}
+
+/// XOR the whole output with an LFSR sequence (see [`builtin::Lfsr`])
+///
+/// For boot ROMs that descramble the entire image, header included; use a
+/// `@section` transform to whiten only the payload.
+#[derive(Debug, Clone, Copy)]
+pub struct Whiten {
+    pub poly: u64,
+    pub seed: u64,
+}
+
+impl PostProcessor for Whiten {
+    fn name(&self) -> &str {
+        "whiten"
+    }
+
+    fn process(&self, data: &mut Vec<u8>) -> Result<()> {
+        builtin::whiten(data, self.poly, self.seed)
+    }
+}
+
+fn swap_words(data: &mut [u8], width: usize) -> Result<()> {
+    if !data.len().is_multiple_of(width) {
+        return Err(DelbinError::new(
+            ErrorCode::E04003,
+            format!(
+                "Cannot byte-swap {} bytes in {}-bit words; pad the output first",
+                data.len(),
+                width * 8
+            ),
+        ));
+    }
+    for word in data.chunks_exact_mut(width) {
+        word.reverse();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_swaps() {
+        let mut data = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        ByteSwap16.process(&mut data).unwrap();
+        assert_eq!(data, [2, 1, 4, 3, 6, 5, 8, 7]);
+        ByteSwap32.process(&mut data).unwrap();
+        assert_eq!(data, [3, 4, 1, 2, 7, 8, 5, 6]);
+
+        let err = ByteSwap32.process(&mut vec![0; 6]).unwrap_err();
+        assert_eq!(err.code, ErrorCode::E04003);
+    }
+
+    #[test]
+    fn test_pad_to() {
+        let mut data = vec![1, 2];
+        PadTo { size: 4, fill: 0xFF }.process(&mut data).unwrap();
+        assert_eq!(data, [1, 2, 0xFF, 0xFF]);
+        assert!(PadTo { size: 3, fill: 0 }.process(&mut data).is_err());
+    }
+
+    #[test]
+    fn test_whiten_round_trips() {
+        let pn9 = Whiten { poly: 0x221, seed: 0x1FF };
+        let mut data = b"payload".to_vec();
+        pn9.process(&mut data).unwrap();
+        assert_eq!(data[0], b'p' ^ 0xFF);
+        pn9.process(&mut data).unwrap();
+        assert_eq!(data, b"payload");
+    }
+}
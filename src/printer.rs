@@ -0,0 +1,534 @@
+//! Delbin DSL printer
+//!
+//! Turns an AST back into DSL text. Nodes that still carry their source text
+//! (see [`FieldDef::source`]) are printed verbatim together with their
+//! trivia, so `print(parse(text)) == text`; new or edited nodes are printed
+//! in canonical form.
+
+use std::fmt::{self, Display, Formatter, Write};
+
+use crate::ast::*;
+use crate::types::Endian;
+
+/// Print a file as DSL text
+pub fn print(file: &File) -> String {
+    file.to_string()
+}
+
+impl Display for File {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.prelude {
+            Some(prelude) => f.write_str(prelude)?,
+            None => {
+                let endian = match (&self.endian_var, self.endian) {
+                    (Some(var), _) => format!("${{{}}}", var),
+                    (None, Endian::Little) => "little".to_string(),
+                    (None, Endian::Big) => "big".to_string(),
+                };
+                writeln!(f, "@endian = {};", endian)?;
+                if let Some(base) = self.base {
+                    writeln!(f, "@base = 0x{:08X};", base)?;
+                }
+                if let Some(size) = self.size {
+                    writeln!(f, "@size = 0x{:X};", size)?;
+                }
+                if let Some(fill) = self.fill {
+                    writeln!(f, "@fill = 0x{:02X};", fill)?;
+                }
+                if let Some(slot_size) = self.slot_size {
+                    writeln!(f, "@slot_size = 0x{:X};", slot_size)?;
+                }
+                f.write_char('\n')?;
+                if !self.inputs.is_empty() {
+                    f.write_str("@inputs {\n")?;
+                    for input in &self.inputs {
+                        let presence = if input.required { "required" } else { "optional" };
+                        write!(f, "    {}: {} {}", input.name, input.ty.name(), presence)?;
+                        if let Some(default) = &input.default {
+                            write!(f, " = {}", default)?;
+                        }
+                        f.write_str(";\n")?;
+                    }
+                    f.write_str("}\n\n")?;
+                }
+                if !self.policy.is_empty() {
+                    f.write_str("@policy {\n")?;
+                    for rule in &self.policy {
+                        let value = rule.algorithm.as_deref().unwrap_or("required");
+                        writeln!(f, "    {}: {};", rule.kind.name(), value)?;
+                    }
+                    f.write_str("}\n\n")?;
+                }
+                if !self.layout.is_empty() {
+                    f.write_str("@layout {\n")?;
+                    for entry in &self.layout {
+                        writeln!(f, "    {} @ 0x{:X};", entry.name, entry.offset)?;
+                    }
+                    f.write_str("}\n\n")?;
+                }
+                if let Some(bundle) = &self.bundle {
+                    f.write_str("@bundle ")?;
+                    if let Some(align) = bundle.align {
+                        write!(f, "@align({}) ", align)?;
+                    }
+                    f.write_str("{\n")?;
+                    for image in &bundle.images {
+                        writeln!(f, "    {};", image)?;
+                    }
+                    f.write_str("}\n\n")?;
+                }
+                for def in &self.sections {
+                    writeln!(f, "@section {} = {};", def.name, def.transform)?;
+                }
+                if !self.sections.is_empty() {
+                    f.write_char('\n')?;
+                }
+                for assert in &self.asserts {
+                    write!(f, "@assert({}", assert.cond)?;
+                    if let Some(message) = &assert.message {
+                        f.write_str(", ")?;
+                        write_string(f, message)?;
+                    }
+                    f.write_str(");\n")?;
+                }
+                if !self.asserts.is_empty() {
+                    f.write_char('\n')?;
+                }
+                for template in &self.templates {
+                    write!(f, "struct {}<{}> {{", template.name, template.params.join(", "))?;
+                    write_fields(f, &template.fields)?;
+                    f.write_str("\n}\n\n")?;
+                }
+            }
+        }
+        write!(f, "{}", self.struct_def)?;
+        if let Some(trailer) = &self.trailer {
+            let gap = if self.trailer_gap.is_empty() { "\n\n" } else { &self.trailer_gap };
+            write!(f, "{}{}", gap, trailer)?;
+        }
+        f.write_str(&self.epilogue)
+    }
+}
+
+impl Display for StructDef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.head_source {
+            Some(head) => f.write_str(head)?,
+            None => {
+                write!(f, "struct {}", self.name)?;
+                if self.packed {
+                    f.write_str(" @packed")?;
+                }
+                if let Some(align) = self.align {
+                    write!(f, " @align({})", align)?;
+                }
+                for enc in &self.encrypt {
+                    f.write_str(" @encrypt_range(")?;
+                    if let Some(start) = &enc.start {
+                        write!(f, "{}", start)?;
+                    }
+                    write!(f, "..{}, {}, {})", enc.end.as_deref().unwrap_or(""), enc.key, enc.iv)?;
+                }
+                if !self.expect_encrypted.is_empty() {
+                    write!(f, " @expect_encrypted({})", self.expect_encrypted.join(", "))?;
+                }
+                f.write_str(" {")?;
+            }
+        }
+        write_fields(f, &self.fields)?;
+        let closing = if self.closing.is_empty() { "\n" } else { &self.closing };
+        write!(f, "{}}}", closing)
+    }
+}
+
+/// Print a struct body's fields; fields expanded from an `@include`,
+/// template instance or `@for` block print as it, unless one of them was
+/// edited
+fn write_fields(f: &mut Formatter<'_>, fields: &[FieldDef]) -> fmt::Result {
+    let mut i = 0;
+    while i < fields.len() {
+        let Some(from) = &fields[i].expanded_from else {
+            write!(f, "{}", fields[i])?;
+            i += 1;
+            continue;
+        };
+        let run = &fields[i..i + fields[i..].iter().take_while(|field| field.expanded_from.as_ref() == Some(from)).count()];
+        if run.iter().all(|field| field.source.is_some()) {
+            write!(f, "{}{}{}", run[0].trivia.leading, from, run[run.len() - 1].trivia.trailing)?;
+        } else if let Some(for_each) = &run[0].for_each {
+            write_for_block(f, for_each, run)?;
+        } else {
+            for field in run {
+                write!(f, "{}", field)?;
+            }
+        }
+        i += run.len();
+    }
+    Ok(())
+}
+
+/// An edited `@for` block, its fields one level deeper than the block
+fn write_for_block(f: &mut Formatter<'_>, for_each: &ForEach, body: &[FieldDef]) -> fmt::Result {
+    let leading = match body[0].trivia.leading.as_str() {
+        "" => "\n    ",
+        leading => leading,
+    };
+    let indent = leading.rsplit('\n').next().unwrap_or_default();
+    write!(f, "{}@for {} in ${{{}}} {{", leading, for_each.var, for_each.list)?;
+    let mut first = body[0].clone();
+    first.trivia.leading = format!("\n{}    ", indent);
+    write!(f, "{}", first)?;
+    for field in &body[1..] {
+        write!(f, "{}", field)?;
+    }
+    write!(f, "\n{}}}", indent)
+}
+
+/// Canonical source of a field: `name @alias(...): type = init;`
+pub(crate) fn canonical_source(field: &FieldDef) -> String {
+    Canonical(field).to_string()
+}
+
+struct Canonical<'a>(&'a FieldDef);
+
+impl Display for Canonical<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let field = self.0;
+        f.write_str(&field.name)?;
+        if !field.aliases.is_empty() {
+            f.write_str(" @alias(")?;
+            for (i, alias) in field.aliases.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write_string(f, alias)?;
+            }
+            f.write_char(')')?;
+        }
+        write!(f, ": {}", field.ty)?;
+        if let Some(init) = &field.init {
+            write!(f, " = {}", init)?;
+        }
+        f.write_char(';')
+    }
+}
+
+/// Prints the field with its trivia (leading whitespace included)
+impl Display for FieldDef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}{}", self.trivia.leading, source)?,
+            None => {
+                let leading = if self.trivia.leading.is_empty() {
+                    "\n    "
+                } else {
+                    &self.trivia.leading
+                };
+                write!(f, "{}{}", leading, Canonical(self))?;
+            }
+        }
+        f.write_str(&self.trivia.trailing)
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Scalar(s) => f.write_str(s.name()),
+            Type::Array { elem, len } => write!(f, "[{}; {}]", elem.name(), len),
+            Type::Inferred => f.write_str("[u8; _]"),
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_expr(f, self, 0)
+    }
+}
+
+/// Binding strength of a binary operator (higher binds tighter)
+fn precedence(op: BinOp) -> u8 {
+    match op {
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::Eq | BinOp::Ne => 0,
+        BinOp::Or => 1,
+        BinOp::And => 2,
+        BinOp::Shl | BinOp::Shr => 3,
+        BinOp::Add | BinOp::Sub => 4,
+        BinOp::Mul => 5,
+    }
+}
+
+fn op_str(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Or => "|",
+        BinOp::And => "&",
+        BinOp::Shl => "<<",
+        BinOp::Shr => ">>",
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Lt => "<",
+        BinOp::Le => "<=",
+        BinOp::Gt => ">",
+        BinOp::Ge => ">=",
+        BinOp::Eq => "==",
+        BinOp::Ne => "!=",
+    }
+}
+
+/// Write `expr`, parenthesized if it binds looser than `min_prec`
+fn write_expr(f: &mut Formatter<'_>, expr: &Expr, min_prec: u8) -> fmt::Result {
+    match expr {
+        Expr::Number(n) if *n > 0xFF => write!(f, "0x{:X}", n),
+        Expr::Number(n) => write!(f, "{}", n),
+        Expr::String(s) => write_string(f, s),
+        Expr::EnvVar(name) => write!(f, "${{{}}}", name),
+        Expr::Named { name, value } => write!(f, "{} = {}", name, value),
+        Expr::BinaryOp { op, left, right } => {
+            let prec = precedence(*op);
+            let paren = prec < min_prec;
+            if paren {
+                f.write_char('(')?;
+            }
+            // Operators are left-associative: a right operand of equal
+            // precedence needs parentheses. Comparisons don't chain, so
+            // either operand does.
+            write_expr(f, left, if prec == 0 { 1 } else { prec })?;
+            write!(f, " {} ", op_str(*op))?;
+            write_expr(f, right, prec + 1)?;
+            if paren {
+                f.write_char(')')?;
+            }
+            Ok(())
+        }
+        Expr::UnaryOp { op: UnaryOp::Not, operand } => {
+            f.write_char('~')?;
+            write_expr(f, operand, u8::MAX)
+        }
+        Expr::Call { name, args } if args.is_empty() && matches!(name.as_str(), "slot_size" | "base" | "index") => {
+            write!(f, "@{}", name)
+        }
+        Expr::Call { name, args } => {
+            write!(f, "@{}(", name)?;
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write_expr(f, arg, 0)?;
+            }
+            f.write_char(')')
+        }
+        Expr::SectionRef(name) => f.write_str(name),
+        Expr::SelfRef => f.write_str("@self"),
+        Expr::Range { start, end, .. } => {
+            f.write_str("@self[")?;
+            if let Some(start) = start {
+                write_expr(f, start, 0)?;
+            }
+            f.write_str("..")?;
+            if let Some(end) = end {
+                f.write_str(end)?;
+            }
+            f.write_char(']')
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::Repeat { value, count }) => {
+            write!(f, "[{}; ", value)?;
+            match count {
+                RepeatCount::Explicit(n) => write!(f, "{}]", n),
+                RepeatCount::Infer => f.write_str("_]"),
+            }
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::List { elements }) => {
+            f.write_char('[')?;
+            for (i, elem) in elements.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}", elem)?;
+            }
+            f.write_char(']')
+        }
+    }
+}
+
+/// Write a string literal with DSL escapes
+fn write_string(f: &mut Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\0' => f.write_str("\\0")?,
+            c if (c as u32) < 0x20 => write!(f, "\\x{:02X}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::types::ScalarType;
+
+    const SRC: &str = r#"// Firmware header
+@endian = big;   // network order
+
+struct fw_header @packed @align(16) {
+    // identification
+    magic:   [u8; 4] = @bytes("FPK\0");  // file magic
+    version: u32     = (${MAJOR} << 8) | ${MINOR};
+
+    crc:     u32     = @crc32(@self[..crc]);
+    // more fields go here
+}
+// end
+"#;
+
+    #[test]
+    fn test_print_is_lossless() {
+        let file = parse(SRC).unwrap();
+        assert_eq!(print(&file), SRC);
+    }
+
+    #[test]
+    fn test_trivia_attached_to_fields() {
+        let file = parse(SRC).unwrap();
+        let fields = &file.struct_def.fields;
+        assert_eq!(fields[0].trivia.comments().collect::<Vec<_>>(), ["// identification", "// file magic"]);
+        assert_eq!(fields[0].trivia.trailing, "  // file magic");
+        assert!(fields[2].trivia.leading.starts_with("\n\n"));
+        assert!(file.struct_def.closing.contains("// more fields go here"));
+    }
+
+    #[test]
+    fn test_edited_field_prints_canonically() {
+        let mut file = parse(SRC).unwrap();
+        file.struct_def.fields[1].source = None;
+        file.struct_def.fields.push(FieldDef::new(
+            "flags",
+            Type::Scalar(ScalarType::U16),
+            Some(Expr::BinaryOp {
+                op: BinOp::Sub,
+                left: Box::new(Expr::Number(0x1000)),
+                right: Box::new(Expr::BinaryOp {
+                    op: BinOp::Add,
+                    left: Box::new(Expr::Number(1)),
+                    right: Box::new(Expr::EnvVar("N".into())),
+                }),
+            }),
+        ));
+
+        let text = print(&file);
+        assert!(text.contains("\n    version: u32 = ${MAJOR} << 8 | ${MINOR};\n"), "{text}");
+        assert!(text.contains("\n    flags: u16 = 0x1000 - (1 + ${N});"), "{text}");
+        assert!(text.contains("  // file magic\n"));
+
+        // Canonical output re-parses to the same structure
+        let reparsed = parse(&text).unwrap();
+        assert_eq!(reparsed.struct_def.fields.len(), 4);
+    }
+
+    #[test]
+    fn test_aliases_print_canonically() {
+        let mut file = parse("struct h {\n  size   @alias( \"len\",\"img_len\" ):u32=1;\n}").unwrap();
+        assert_eq!(file.struct_def.fields[0].aliases, ["len", "img_len"]);
+        file.struct_def.fields[0].source = None;
+        let text = print(&file);
+        assert!(text.contains("\n  size @alias(\"len\", \"img_len\"): u32 = 1;\n"), "{text}");
+        assert_eq!(parse(&text).unwrap().struct_def.fields[0].aliases.len(), 2);
+    }
+
+    #[test]
+    fn test_canonical_prelude_includes_inputs() {
+        let mut file = parse(
+            "@inputs { V: u8 required; S: str optional = \"dev\"; } struct h { a: u8 = ${V}; }",
+        )
+        .unwrap();
+        file.prelude = None;
+        let text = print(&file);
+        assert!(text.starts_with(
+            "@endian = little;\n\n@inputs {\n    V: u8 required;\n    S: str optional = \"dev\";\n}\n\nstruct h {"
+        ), "{text}");
+        assert_eq!(parse(&text).unwrap().inputs.len(), 2);
+    }
+
+    #[test]
+    fn test_canonical_prelude_includes_policy() {
+        let mut file = parse("@policy { signature: ed25519; header_crc: required; } struct h { a: u8; }").unwrap();
+        file.prelude = None;
+        let text = print(&file);
+        assert!(text.starts_with(
+            "@endian = little;\n\n@policy {\n    signature: ed25519;\n    header_crc: required;\n}\n\nstruct h {"
+        ), "{text}");
+        assert_eq!(parse(&text).unwrap().policy, file.policy);
+    }
+
+    #[test]
+    fn test_canonical_prelude_includes_layout() {
+        let mut file = parse("@fill = 0; @layout { h @ 0; app @ 0x1000; } struct h { a: u8; }").unwrap();
+        file.prelude = None;
+        let text = print(&file);
+        assert!(text.starts_with(
+            "@endian = little;\n@fill = 0x00;\n\n@layout {\n    h @ 0x0;\n    app @ 0x1000;\n}\n\nstruct h {"
+        ), "{text}");
+        let reparsed = parse(&text).unwrap();
+        assert_eq!((reparsed.fill, reparsed.layout.len()), (Some(0), 2));
+        assert!(parse("@layout { a @ 0; a @ 1; } struct h { a: u8; }").is_err());
+        assert!(parse("@fill = 0x100; struct h { a: u8; }").is_err());
+    }
+
+    #[test]
+    fn test_canonical_prelude_includes_bundle() {
+        let mut file = parse("@bundle @align(2 * 8) { boot; app; } struct h { a: u8; } struct e { b: u8; }").unwrap();
+        file.prelude = None;
+        let text = print(&file);
+        assert!(text.starts_with(
+            "@endian = little;\n\n@bundle @align(16) {\n    boot;\n    app;\n}\n\nstruct h {"
+        ), "{text}");
+        let reparsed = parse(&text).unwrap().bundle.unwrap();
+        assert_eq!((reparsed.images.len(), reparsed.align), (2, Some(16)));
+        assert!(parse("@bundle { a; a; } struct h { a: u8; }").is_err());
+        assert!(parse("@bundle { } struct h { a: u8; }").is_err());
+        assert!(parse("@bundle { a; } @layout { h @ 0; } struct h { a: u8; }").is_err());
+    }
+
+    #[test]
+    fn test_canonical_prelude_includes_section_defs() {
+        let mut file = parse("@section w = @whiten(app, 0x221, ${SEED}); struct h { a: u8; }").unwrap();
+        file.prelude = None;
+        let text = print(&file);
+        assert!(text.contains("\n@section w = @whiten(app, 0x221, ${SEED});\n\nstruct h {"), "{text}");
+        assert_eq!(parse(&text).unwrap().sections[0].name, "w");
+        assert!(parse("@section w = @whiten(a, 3, 1); @section w = @whiten(b, 3, 1); struct h { a: u8; }").is_err());
+    }
+
+    #[test]
+    fn test_canonical_prelude_includes_templates() {
+        let mut file = parse("struct pair<A, B> { a: u8 = A; b: u8 = B; } struct h { p: pair<1, 2>; }").unwrap();
+        file.prelude = None;
+        let text = print(&file);
+        assert!(text.contains("\nstruct pair<A, B> { a: u8 = A; b: u8 = B;\n}\n\nstruct h { p: pair<1, 2>; }"), "{text}");
+        assert_eq!(parse(&text).unwrap().struct_def.fields.len(), 2);
+    }
+
+    #[test]
+    fn test_encrypt_attr_round_trips() {
+        let src = "struct h @packed @encrypt_range(a.., ${KEY}, ${IV}) @expect_encrypted(app, data) {\n    a: u8;\n}";
+        let mut file = parse(src).unwrap();
+        assert_eq!(print(&file), src);
+        file.struct_def.head_source = None;
+        assert_eq!(file.struct_def.to_string(), src);
+    }
+
+    #[test]
+    fn test_print_string_escapes() {
+        let expr = Expr::String("a\"b\\\n\0\x01".into());
+        assert_eq!(expr.to_string(), r#""a\"b\\\n\0\x01""#);
+    }
+}
@@ -0,0 +1,470 @@
+//! Delbin section data providers
+//!
+//! Sections don't have to live in a `Vec<u8>`: anything implementing
+//! [`SectionProvider`] can back them, e.g. files read on demand.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use sha2::Digest;
+
+use crate::builtin::{CrcDigest, Sha256};
+use crate::error::{DelbinError, ErrorCode, Result};
+
+/// Chunk size used when streaming section data
+pub const SECTION_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Digest algorithms a provider may compute over a whole section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    /// CRC32-ISO-HDLC, returned as 4 big-endian bytes
+    Crc32,
+    /// SHA256, returned as 32 bytes
+    Sha256,
+}
+
+/// Source of named section data
+pub trait SectionProvider {
+    /// Size of a section in bytes, or `None` if the section doesn't exist
+    fn len(&self, name: &str) -> Option<u64>;
+
+    /// Read a byte range of a section
+    fn read(&self, name: &str, range: Range<u64>) -> Result<Cow<'_, [u8]>>;
+
+    /// Feed a byte range of a section to `f` in bounded-size chunks
+    ///
+    /// The default implementation issues `read()` calls of at most
+    /// [`SECTION_CHUNK_SIZE`] bytes, so memory use stays constant.
+    fn read_chunks(
+        &self,
+        name: &str,
+        range: Range<u64>,
+        f: &mut dyn FnMut(&[u8]),
+    ) -> Result<()> {
+        let mut pos = range.start;
+        while pos < range.end {
+            let end = range.end.min(pos + SECTION_CHUNK_SIZE);
+            f(&self.read(name, pos..end)?);
+            pos = end;
+        }
+        Ok(())
+    }
+
+    /// Digest of a whole section
+    ///
+    /// The default implementation streams the section through the digest;
+    /// providers that already know the digest (or can compute it more
+    /// cheaply) may override it.
+    fn hash(&self, name: &str, algo: HashAlgorithm) -> Result<Vec<u8>> {
+        let len = self.len(name).ok_or_else(|| undefined_section(name))?;
+        Ok(match algo {
+            HashAlgorithm::Crc32 => {
+                let mut digest = CrcDigest::crc32();
+                self.read_chunks(name, 0..len, &mut |chunk| digest.update(chunk))?;
+                (digest.finalize() as u32).to_be_bytes().to_vec()
+            }
+            HashAlgorithm::Sha256 => {
+                let mut hasher = Sha256::new();
+                self.read_chunks(name, 0..len, &mut |chunk| hasher.update(chunk))?;
+                hasher.finalize().to_vec()
+            }
+        })
+    }
+}
+
+/// Error for a missing section
+pub(crate) fn undefined_section(name: &str) -> DelbinError {
+    DelbinError::new(ErrorCode::E02003, format!("Undefined section: {}", name))
+}
+
+/// Slice `data` by `range`, checking bounds
+fn slice_range<'d>(name: &str, data: &'d [u8], range: Range<u64>) -> Result<&'d [u8]> {
+    if range.start <= range.end && range.end <= data.len() as u64 {
+        Ok(&data[range.start as usize..range.end as usize])
+    } else {
+        Err(DelbinError::new(
+            ErrorCode::E04002,
+            format!(
+                "Invalid range {}..{} for section '{}' ({} bytes)",
+                range.start,
+                range.end,
+                name,
+                data.len()
+            ),
+        ))
+    }
+}
+
+/// Provider whose `top` sections shadow those of `inner`
+pub(crate) struct Overlay<'a> {
+    top: &'a dyn SectionProvider,
+    inner: &'a dyn SectionProvider,
+}
+
+impl<'a> Overlay<'a> {
+    pub(crate) fn new(top: &'a dyn SectionProvider, inner: &'a dyn SectionProvider) -> Self {
+        Self { top, inner }
+    }
+
+    fn pick(&self, name: &str) -> &'a dyn SectionProvider {
+        if self.top.len(name).is_some() {
+            self.top
+        } else {
+            self.inner
+        }
+    }
+}
+
+impl SectionProvider for Overlay<'_> {
+    fn len(&self, name: &str) -> Option<u64> {
+        self.pick(name).len(name)
+    }
+
+    fn read(&self, name: &str, range: Range<u64>) -> Result<Cow<'_, [u8]>> {
+        self.pick(name).read(name, range)
+    }
+
+    fn read_chunks(&self, name: &str, range: Range<u64>, f: &mut dyn FnMut(&[u8])) -> Result<()> {
+        self.pick(name).read_chunks(name, range, f)
+    }
+
+    fn hash(&self, name: &str, algo: HashAlgorithm) -> Result<Vec<u8>> {
+        self.pick(name).hash(name, algo)
+    }
+}
+
+/// Provider exposing section `target` of `inner` under the name `alias` too
+pub(crate) struct Alias<'a> {
+    alias: &'a str,
+    target: &'a str,
+    inner: &'a dyn SectionProvider,
+}
+
+impl<'a> Alias<'a> {
+    pub(crate) fn new(alias: &'a str, target: &'a str, inner: &'a dyn SectionProvider) -> Self {
+        Self { alias, target, inner }
+    }
+
+    fn resolve<'n>(&'n self, name: &'n str) -> &'n str {
+        if name == self.alias {
+            self.target
+        } else {
+            name
+        }
+    }
+}
+
+impl SectionProvider for Alias<'_> {
+    fn len(&self, name: &str) -> Option<u64> {
+        self.inner.len(self.resolve(name))
+    }
+
+    fn read(&self, name: &str, range: Range<u64>) -> Result<Cow<'_, [u8]>> {
+        self.inner.read(self.resolve(name), range)
+    }
+
+    fn read_chunks(&self, name: &str, range: Range<u64>, f: &mut dyn FnMut(&[u8])) -> Result<()> {
+        self.inner.read_chunks(self.resolve(name), range, f)
+    }
+
+    fn hash(&self, name: &str, algo: HashAlgorithm) -> Result<Vec<u8>> {
+        self.inner.hash(self.resolve(name), algo)
+    }
+}
+
+/// Provider that remembers the whole-section digests of `inner`, so
+/// repeated evaluations over the same sections hash each one only once
+pub(crate) struct DigestCache<'a> {
+    inner: &'a dyn SectionProvider,
+    digests: RefCell<HashMap<(String, HashAlgorithm), Vec<u8>>>,
+}
+
+impl<'a> DigestCache<'a> {
+    pub(crate) fn new(inner: &'a dyn SectionProvider) -> Self {
+        Self {
+            inner,
+            digests: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl SectionProvider for DigestCache<'_> {
+    fn len(&self, name: &str) -> Option<u64> {
+        self.inner.len(name)
+    }
+
+    fn read(&self, name: &str, range: Range<u64>) -> Result<Cow<'_, [u8]>> {
+        self.inner.read(name, range)
+    }
+
+    fn read_chunks(&self, name: &str, range: Range<u64>, f: &mut dyn FnMut(&[u8])) -> Result<()> {
+        self.inner.read_chunks(name, range, f)
+    }
+
+    fn hash(&self, name: &str, algo: HashAlgorithm) -> Result<Vec<u8>> {
+        let key = (name.to_string(), algo);
+        if let Some(digest) = self.digests.borrow().get(&key) {
+            return Ok(digest.clone());
+        }
+        let digest = self.inner.hash(name, algo)?;
+        self.digests.borrow_mut().insert(key, digest.clone());
+        Ok(digest)
+    }
+}
+
+impl SectionProvider for HashMap<String, Vec<u8>> {
+    fn len(&self, name: &str) -> Option<u64> {
+        self.get(name).map(|d| d.len() as u64)
+    }
+
+    fn read(&self, name: &str, range: Range<u64>) -> Result<Cow<'_, [u8]>> {
+        let data = self.get(name).ok_or_else(|| undefined_section(name))?;
+        slice_range(name, data, range).map(Cow::Borrowed)
+    }
+}
+
+impl SectionProvider for HashMap<&str, &[u8]> {
+    fn len(&self, name: &str) -> Option<u64> {
+        self.get(name).map(|d| d.len() as u64)
+    }
+
+    fn read(&self, name: &str, range: Range<u64>) -> Result<Cow<'_, [u8]>> {
+        let data = self.get(name).ok_or_else(|| undefined_section(name))?;
+        slice_range(name, data, range).map(Cow::Borrowed)
+    }
+}
+
+/// Sections backed by files, read on demand
+///
+/// Only the byte ranges the DSL actually needs are read; `@sizeof()` only
+/// touches file metadata.
+#[derive(Debug, Clone, Default)]
+pub struct FileSections {
+    paths: HashMap<String, PathBuf>,
+}
+
+impl FileSections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a section backed by the file at `path`
+    pub fn insert(&mut self, name: impl Into<String>, path: impl Into<PathBuf>) {
+        self.paths.insert(name.into(), path.into());
+    }
+}
+
+impl SectionProvider for FileSections {
+    fn len(&self, name: &str) -> Option<u64> {
+        let path = self.paths.get(name)?;
+        fs::metadata(path).ok().map(|m| m.len())
+    }
+
+    fn read(&self, name: &str, range: Range<u64>) -> Result<Cow<'_, [u8]>> {
+        let path = self.paths.get(name).ok_or_else(|| undefined_section(name))?;
+        let len = self.len(name).ok_or_else(|| {
+            DelbinError::new(
+                ErrorCode::E05001,
+                format!("Section file not found: {}", path.display()),
+            )
+        })?;
+        if range.start > range.end || range.end > len {
+            return Err(DelbinError::new(
+                ErrorCode::E04002,
+                format!(
+                    "Invalid range {}..{} for section '{}' ({} bytes)",
+                    range.start, range.end, name, len
+                ),
+            ));
+        }
+
+        let read_err = |e: std::io::Error| {
+            DelbinError::new(
+                ErrorCode::E05002,
+                format!("Error reading section '{}' from '{}': {}", name, path.display(), e),
+            )
+        };
+        let mut file = fs::File::open(path).map_err(read_err)?;
+        file.seek(SeekFrom::Start(range.start)).map_err(read_err)?;
+        let mut buf = vec![0u8; (range.end - range.start) as usize];
+        file.read_exact(&mut buf).map_err(read_err)?;
+        Ok(Cow::Owned(buf))
+    }
+}
+
+/// Section file mapped into memory
+///
+/// Dereferences to the file contents. Built with
+/// [`section_from_file`](crate::utils::section_from_file).
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MappedSection {
+    // memmap2 refuses zero-length mappings, so empty files carry no map
+    map: Option<memmap2::Mmap>,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedSection {
+    /// Map the file at `path` read-only
+    ///
+    /// The file must not be truncated or modified while the mapping is alive.
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = fs::File::open(path).map_err(|e| {
+            DelbinError::new(
+                ErrorCode::E05001,
+                format!("Cannot open section file '{}': {}", path.display(), e),
+            )
+        })?;
+        let len = file
+            .metadata()
+            .map_err(|e| {
+                DelbinError::new(
+                    ErrorCode::E05002,
+                    format!("Cannot stat section file '{}': {}", path.display(), e),
+                )
+            })?
+            .len();
+        if len == 0 {
+            return Ok(Self { map: None });
+        }
+        // SAFETY: the mapping is read-only; callers are told not to modify
+        // the file while it is mapped.
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| {
+            DelbinError::new(
+                ErrorCode::E05002,
+                format!("Cannot map section file '{}': {}", path.display(), e),
+            )
+        })?;
+        Ok(Self { map: Some(map) })
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl std::ops::Deref for MappedSection {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.map.as_deref().unwrap_or(&[])
+    }
+}
+
+/// Named memory-mapped sections
+///
+/// Reads borrow straight from the mappings, so neither `@sizeof()` nor a
+/// checksum over a large image copies it into the heap.
+#[cfg(feature = "mmap")]
+#[derive(Debug, Default)]
+pub struct MappedSections {
+    maps: HashMap<String, MappedSection>,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedSections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a mapped section
+    pub fn insert(&mut self, name: impl Into<String>, section: MappedSection) {
+        self.maps.insert(name.into(), section);
+    }
+
+    /// Section names, in arbitrary order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.maps.keys().map(String::as_str)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl SectionProvider for MappedSections {
+    fn len(&self, name: &str) -> Option<u64> {
+        self.maps.get(name).map(|m| m.len() as u64)
+    }
+
+    fn read(&self, name: &str, range: Range<u64>) -> Result<Cow<'_, [u8]>> {
+        let data = self.maps.get(name).ok_or_else(|| undefined_section(name))?;
+        slice_range(name, data, range).map(Cow::Borrowed)
+    }
+
+    fn read_chunks(
+        &self,
+        name: &str,
+        range: Range<u64>,
+        f: &mut dyn FnMut(&[u8]),
+    ) -> Result<()> {
+        // The whole range is already addressable; no need to chunk
+        f(&self.read(name, range)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashmap_provider_read_range() {
+        let mut sections = HashMap::new();
+        sections.insert("image".to_string(), b"hello world".to_vec());
+        assert_eq!(SectionProvider::len(&sections, "image"), Some(11));
+        assert_eq!(&*sections.read("image", 6..11).unwrap(), b"world");
+        assert!(sections.read("image", 6..12).is_err());
+        assert_eq!(sections.read("nope", 0..1).unwrap_err().code, ErrorCode::E02003);
+    }
+
+    #[test]
+    fn test_default_hash_uses_read() {
+        let mut sections = HashMap::new();
+        sections.insert("image".to_string(), b"hello world".to_vec());
+        let crc = sections.hash("image", HashAlgorithm::Crc32).unwrap();
+        assert_eq!(crc, vec![0x0D, 0x4A, 0x11, 0x85]);
+    }
+
+    #[test]
+    fn test_read_chunks_covers_range() {
+        let mut sections = HashMap::new();
+        sections.insert("big".to_string(), vec![7u8; (SECTION_CHUNK_SIZE * 2 + 10) as usize]);
+        let mut sizes = Vec::new();
+        sections
+            .read_chunks("big", 5..SECTION_CHUNK_SIZE * 2 + 10, &mut |c| sizes.push(c.len()))
+            .unwrap();
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(sizes.iter().sum::<usize>() as u64, SECTION_CHUNK_SIZE * 2 + 5);
+    }
+
+    #[test]
+    fn test_file_sections_reads_lazily() {
+        let path = std::env::temp_dir().join(format!("delbin-provider-{}.bin", std::process::id()));
+        fs::write(&path, b"0123456789").unwrap();
+
+        let mut sections = FileSections::new();
+        sections.insert("image", &path);
+        assert_eq!(sections.len("image"), Some(10));
+        assert_eq!(&*sections.read("image", 2..5).unwrap(), b"234");
+        assert_eq!(sections.read("image", 8..11).unwrap_err().code, ErrorCode::E04002);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mapped_sections_borrow_file_data() {
+        let path = std::env::temp_dir().join(format!("delbin-mmap-{}.bin", std::process::id()));
+        fs::write(&path, b"hello world").unwrap();
+
+        let mut sections = MappedSections::new();
+        sections.insert("image", MappedSection::open(&path).unwrap());
+        assert_eq!(sections.len("image"), Some(11));
+        assert!(matches!(sections.read("image", 6..11).unwrap(), Cow::Borrowed(b"world")));
+        let crc = sections.hash("image", HashAlgorithm::Crc32).unwrap();
+        assert_eq!(crc, vec![0x0D, 0x4A, 0x11, 0x85]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,443 @@
+//! Delbin AST rewriting
+//!
+//! Small refactoring operations for format evolution scripts. References to
+//! the edited fields (`@offsetof(field)`, `@self[field..end]`,
+//! `@encrypt_range(field..end, ...)`) follow renames, and numeric range
+//! starts (`@self[16..crc]`) behind an inserted or resized field are renumbered when the layout up to that point has a
+//! constant size. Fields that a rewrite touches lose their verbatim source
+//! and print canonically; everything else keeps its formatting.
+
+use crate::ast::*;
+use crate::error::{DelbinError, ErrorCode, Result};
+
+/// Rename a field and every reference to it
+///
+/// A field of the trailer struct is renamed there when the header has no
+/// field of that name.
+pub fn rename_field(file: &mut File, old: &str, new: &str) -> Result<()> {
+    if !file.struct_def.fields.iter().any(|f| f.name == old) {
+        if let Some(trailer) = file.trailer.as_mut().filter(|t| t.fields.iter().any(|f| f.name == old)) {
+            return rename_struct_field(trailer, old, new);
+        }
+    }
+    rename_struct_field(&mut file.struct_def, old, new)?;
+
+    // `@assert`s are evaluated against the header
+    let mut changed = false;
+    for assert in &mut file.asserts {
+        changed |= visit_expr(&mut assert.cond, &mut |expr| rename_reference(expr, old, new));
+    }
+    if changed {
+        file.prelude = None;
+    }
+    Ok(())
+}
+
+fn rename_struct_field(def: &mut StructDef, old: &str, new: &str) -> Result<()> {
+    let index = field_index(def, old)?;
+    ensure_unique(def, new)?;
+
+    let field = &mut def.fields[index];
+    field.name = new.to_string();
+    // The source starts with the field name; an expanded field's text is
+    // in its `@include`, template or `@for` block, which no longer fits
+    if field.expanded_from.is_some() {
+        field.source = None;
+    } else if let Some(source) = &mut field.source {
+        source.replace_range(..old.len(), new);
+    }
+
+    for field in &mut def.fields {
+        if visit_field_exprs(field, &mut |expr| rename_reference(expr, old, new)) {
+            field.source = None;
+        }
+    }
+    let mut changed = false;
+    for range in &mut def.encrypt {
+        if let Some(Expr::SectionRef(start)) = range.start.as_deref_mut() {
+            if start == old {
+                *start = new.to_string();
+                changed = true;
+            }
+        }
+        if range.end.as_deref() == Some(old) {
+            range.end = Some(new.to_string());
+            changed = true;
+        }
+    }
+    if changed {
+        def.head_source = None;
+    }
+    Ok(())
+}
+
+/// Insert `field` directly after the field named `after`
+///
+/// A field without leading trivia gets the indentation of `after`.
+pub fn insert_field_after(file: &mut File, after: &str, mut field: FieldDef) -> Result<()> {
+    let index = field_index(&file.struct_def, after)?;
+    ensure_unique(&file.struct_def, &field.name)?;
+
+    let offsets = static_offsets(&file.struct_def.fields);
+    let shift = offsets[index + 1].zip(static_size(&field.ty));
+
+    if field.trivia.leading.is_empty() {
+        let leading = &file.struct_def.fields[index].trivia.leading;
+        let indent = leading.rsplit('\n').next().unwrap_or("");
+        field.trivia.leading = format!("\n{}", indent);
+    }
+    file.struct_def.fields.insert(index + 1, field);
+
+    if let Some((from, size)) = shift {
+        renumber_offsets(file, from, size as i64);
+    }
+    Ok(())
+}
+
+/// Change the length of an array field
+///
+/// A repeat initializer whose count equals the old length (`[0xFF; 16]`)
+/// is resized with it.
+pub fn resize_array(file: &mut File, name: &str, new_len: u64) -> Result<()> {
+    let index = field_index(&file.struct_def, name)?;
+    let (elem, old_len) = match &file.struct_def.fields[index].ty {
+        Type::Array { elem, len } => (*elem, const_value(len)),
+        Type::Scalar(_) => {
+            return Err(DelbinError::new(
+                ErrorCode::E03001,
+                format!("Field '{}' is not an array", name),
+            ))
+        }
+        Type::Inferred => {
+            return Err(DelbinError::new(
+                ErrorCode::E03001,
+                format!("Field '{}' takes its length from its initializer", name),
+            ))
+        }
+    };
+
+    let field = &mut file.struct_def.fields[index];
+    if let Some(Expr::ArrayLiteral(ArrayLiteralKind::Repeat {
+        count: RepeatCount::Explicit(count),
+        ..
+    })) = &mut field.init
+    {
+        if old_len.is_some() && const_value(count) == old_len {
+            **count = Expr::Number(new_len);
+        }
+    }
+    change_type(
+        file,
+        name,
+        Type::Array {
+            elem,
+            len: Box::new(Expr::Number(new_len)),
+        },
+    )
+}
+
+/// Change the type of a field
+pub fn change_type(file: &mut File, name: &str, ty: Type) -> Result<()> {
+    let index = field_index(&file.struct_def, name)?;
+
+    let offsets = static_offsets(&file.struct_def.fields);
+    let old_size = static_size(&file.struct_def.fields[index].ty);
+    let new_size = static_size(&ty);
+
+    let field = &mut file.struct_def.fields[index];
+    field.ty = ty;
+    field.source = None;
+
+    if let (Some(from), Some(old), Some(new)) = (offsets[index + 1], old_size, new_size) {
+        renumber_offsets(file, from, new as i64 - old as i64);
+    }
+    Ok(())
+}
+
+fn field_index(def: &StructDef, name: &str) -> Result<usize> {
+    def.fields
+        .iter()
+        .position(|f| f.name == name)
+        .ok_or_else(|| DelbinError::new(ErrorCode::E02002, format!("Undefined field: {}", name)))
+}
+
+fn ensure_unique(def: &StructDef, name: &str) -> Result<()> {
+    if def.fields.iter().any(|f| f.name == name) {
+        return Err(DelbinError::new(
+            ErrorCode::E02002,
+            format!("Field '{}' already exists", name),
+        ));
+    }
+    Ok(())
+}
+
+/// Rename a field reference in `expr` (not in its children)
+pub(crate) fn rename_reference(expr: &mut Expr, old: &str, new: &str) -> bool {
+    let mut changed = false;
+    match expr {
+        Expr::Call { name, args } if name == "offsetof" => {
+            if let [Expr::SectionRef(arg)] = args.as_mut_slice() {
+                if arg == old {
+                    *arg = new.to_string();
+                    changed = true;
+                }
+            }
+        }
+        Expr::Range { start, end, .. } => {
+            if let Some(start) = start {
+                if let Expr::SectionRef(arg) = start.as_mut() {
+                    if arg == old {
+                        *arg = new.to_string();
+                        changed = true;
+                    }
+                }
+            }
+            if end.as_deref() == Some(old) {
+                *end = Some(new.to_string());
+                changed = true;
+            }
+        }
+        _ => {}
+    }
+    changed
+}
+
+/// Add `delta` to numeric range starts at or after `from`
+fn renumber_offsets(file: &mut File, from: usize, delta: i64) {
+    if delta == 0 {
+        return;
+    }
+    for field in &mut file.struct_def.fields {
+        let changed = visit_field_exprs(field, &mut |expr| {
+            if let Expr::Range { start: Some(start), .. } = expr {
+                if let Expr::Number(n) = start.as_mut() {
+                    if *n >= from as u64 {
+                        *n = n.saturating_add_signed(delta);
+                        return true;
+                    }
+                }
+            }
+            false
+        });
+        if changed {
+            field.source = None;
+        }
+    }
+    let def = &mut file.struct_def;
+    for range in &mut def.encrypt {
+        if let Some(Expr::Number(n)) = range.start.as_deref_mut() {
+            if *n >= from as u64 {
+                *n = n.saturating_add_signed(delta);
+                def.head_source = None;
+            }
+        }
+    }
+}
+
+/// Apply `f` to every expression of a field; true if any call returned true
+pub(crate) fn visit_field_exprs(field: &mut FieldDef, f: &mut dyn FnMut(&mut Expr) -> bool) -> bool {
+    let mut changed = false;
+    if let Type::Array { len, .. } = &mut field.ty {
+        changed |= visit_expr(len, f);
+    }
+    if let Some(init) = &mut field.init {
+        changed |= visit_expr(init, f);
+    }
+    changed
+}
+
+fn visit_expr(expr: &mut Expr, f: &mut dyn FnMut(&mut Expr) -> bool) -> bool {
+    let mut changed = f(expr);
+    match expr {
+        Expr::BinaryOp { left, right, .. } => {
+            changed |= visit_expr(left, f);
+            changed |= visit_expr(right, f);
+        }
+        Expr::UnaryOp { operand, .. } | Expr::Named { value: operand, .. } => {
+            changed |= visit_expr(operand, f)
+        }
+        Expr::Call { args, .. } => {
+            for arg in args {
+                changed |= visit_expr(arg, f);
+            }
+        }
+        Expr::Range { base, .. } => changed |= visit_expr(base, f),
+        Expr::ArrayLiteral(ArrayLiteralKind::Repeat { value, count }) => {
+            changed |= visit_expr(value, f);
+            if let RepeatCount::Explicit(n) = count {
+                changed |= visit_expr(n, f);
+            }
+        }
+        Expr::ArrayLiteral(ArrayLiteralKind::List { elements }) => {
+            for elem in elements {
+                changed |= visit_expr(elem, f);
+            }
+        }
+        _ => {}
+    }
+    changed
+}
+
+/// Offsets of each field plus the end offset, while the layout is constant
+fn static_offsets(fields: &[FieldDef]) -> Vec<Option<usize>> {
+    let mut offsets = Vec::with_capacity(fields.len() + 1);
+    let mut offset = Some(0usize);
+    offsets.push(offset);
+    for field in fields {
+        offset = offset.zip(static_size(&field.ty)).map(|(o, s)| o + s);
+        offsets.push(offset);
+    }
+    offsets
+}
+
+/// Size of a type whose length (if any) is a constant expression
+fn static_size(ty: &Type) -> Option<usize> {
+    match ty {
+        Type::Scalar(s) => Some(s.size()),
+        Type::Array { elem, len } => Some(elem.size() * const_value(len)? as usize),
+        Type::Inferred => None,
+    }
+}
+
+/// Value of an expression made only of literals
+pub(crate) fn const_value(expr: &Expr) -> Option<u64> {
+    match expr {
+        Expr::Number(n) => Some(*n),
+        Expr::UnaryOp { op: UnaryOp::Not, operand } => Some(!const_value(operand)?),
+        Expr::BinaryOp { op, left, right } => {
+            let (l, r) = (const_value(left)?, const_value(right)?);
+            match op {
+                BinOp::Or => Some(l | r),
+                BinOp::And => Some(l & r),
+                BinOp::Shl => l.checked_shl(r as u32),
+                BinOp::Shr => l.checked_shr(r as u32),
+                BinOp::Add => l.checked_add(r),
+                BinOp::Sub => l.checked_sub(r),
+                BinOp::Mul => l.checked_mul(r),
+                BinOp::Lt => Some((l < r) as u64),
+                BinOp::Le => Some((l <= r) as u64),
+                BinOp::Gt => Some((l > r) as u64),
+                BinOp::Ge => Some((l >= r) as u64),
+                BinOp::Eq => Some((l == r) as u64),
+                BinOp::Ne => Some((l != r) as u64),
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::printer::print;
+    use crate::types::ScalarType;
+    use std::collections::HashMap;
+
+    const SRC: &str = r#"@endian = little;
+
+struct h @packed {
+    magic:   [u8; 4]  = @bytes("FPK");  // magic
+    body:    [u8; 8]  = [0xFF; 8];
+    body_at: u32      = @offsetof(body);
+    crc:     u32      = @crc32(@self[4..crc]);
+    tail:    u32      = @crc32(@self[body..crc]);
+}
+"#;
+
+    fn generate(file: &File) -> Vec<u8> {
+        crate::generate(&print(file), &HashMap::new(), &HashMap::new())
+            .unwrap()
+            .data
+    }
+
+    #[test]
+    fn test_rename_field_updates_references() {
+        let mut file = parse(SRC).unwrap();
+        let before = generate(&file);
+        rename_field(&mut file, "body", "payload").unwrap();
+
+        let text = print(&file);
+        assert!(text.contains("\n    payload:    [u8; 8]  = [0xFF; 8];\n"), "{text}");
+        assert!(text.contains("body_at: u32 = @offsetof(payload);"), "{text}");
+        assert!(text.contains("tail: u32 = @crc32(@self[payload..crc]);"), "{text}");
+        assert!(text.contains("  // magic\n"), "{text}");
+        assert_eq!(generate(&file), before);
+
+        assert!(rename_field(&mut file, "nope", "x").is_err());
+        assert!(rename_field(&mut file, "crc", "magic").is_err());
+    }
+
+    #[test]
+    fn test_rename_field_updates_asserts_and_trailer() {
+        let src = r#"@assert(@offsetof(crc) == 4, "crc moved");
+
+struct h @packed {
+    magic: u32 = 1;
+    crc: u32 = @crc32(@self[..crc]);
+}
+
+struct t @packed {
+    tag: u8 = 7;
+    sum: u32 = @crc32(@self[tag..sum]);
+}
+"#;
+        let mut file = parse(src).unwrap();
+        let before = crate::merge(src, &HashMap::new(), b"app").unwrap().data;
+        rename_field(&mut file, "crc", "checksum").unwrap();
+        rename_field(&mut file, "tag", "kind").unwrap();
+
+        let text = print(&file);
+        assert!(text.contains("@assert(@offsetof(checksum) == 4, \"crc moved\");"), "{text}");
+        assert!(text.contains("sum: u32 = @crc32(@self[kind..sum]);"), "{text}");
+        assert_eq!(crate::merge(&text, &HashMap::new(), b"app").unwrap().data, before);
+    }
+
+    #[test]
+    fn test_rewrites_update_encrypt_ranges() {
+        let src = "struct h @packed @encrypt_range(body..crc, ${KEY}, ${IV}) @encrypt_range(4.., ${KEY}, ${IV}) {\n    magic: u32;\n    body: [u8; 8];\n    crc: u32;\n}";
+        let mut file = parse(src).unwrap();
+        rename_field(&mut file, "body", "payload").unwrap();
+        rename_field(&mut file, "crc", "checksum").unwrap();
+        let text = print(&file);
+        assert!(text.contains("@encrypt_range(payload..checksum, ${KEY}, ${IV})"), "{text}");
+
+        resize_array(&mut file, "payload", 4).unwrap();
+        assert!(print(&file).contains("@encrypt_range(4.., ${KEY}, ${IV})"));
+        change_type(&mut file, "magic", Type::Scalar(ScalarType::U16)).unwrap();
+        let text = print(&file);
+        assert!(text.contains("@encrypt_range(2.., ${KEY}, ${IV})"), "{text}");
+        assert!(parse(&text).is_ok());
+    }
+
+    #[test]
+    fn test_insert_field_after_renumbers_offsets() {
+        let mut file = parse(SRC).unwrap();
+        let flags = FieldDef::new("flags", Type::Scalar(ScalarType::U16), Some(Expr::Number(3)));
+        insert_field_after(&mut file, "magic", flags).unwrap();
+
+        let text = print(&file);
+        assert!(text.contains("  // magic\n    flags: u16 = 3;\n"), "{text}");
+        assert!(text.contains("@crc32(@self[6..crc])"), "{text}");
+
+        let data = generate(&file);
+        assert_eq!(&data[4..6], &[3, 0]);
+        assert_eq!(&data[14..18], &[6, 0, 0, 0]); // body_at = @offsetof(body)
+    }
+
+    #[test]
+    fn test_resize_array_updates_repeat_count_and_offsets() {
+        let mut file = parse(SRC).unwrap();
+        resize_array(&mut file, "magic", 8).unwrap();
+
+        let text = print(&file);
+        assert!(text.contains("magic: [u8; 8] = @bytes(\"FPK\");  // magic"), "{text}");
+        assert!(text.contains("@crc32(@self[8..crc])"), "{text}");
+
+        resize_array(&mut file, "body", 4).unwrap();
+        assert!(print(&file).contains("body: [u8; 4] = [255; 4];"));
+        assert_eq!(generate(&file).len(), 8 + 4 + 4 + 4 + 4);
+
+        assert_eq!(resize_array(&mut file, "crc", 2).unwrap_err().code, ErrorCode::E03001);
+    }
+}
@@ -0,0 +1,94 @@
+//! Delbin runtime inputs: randomness and the clock
+//!
+//! `@random()`, `@uuid()` and `@timestamp()` (and the metadata record's
+//! generation time) are the only values that change from run to run. They
+//! come from a [`RandomSource`] and a [`Clock`], the OS and the system time
+//! by default; register others on [`GenerateOptions`](crate::GenerateOptions)
+//! to pin them in tests or reproducible pipelines.
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::builtin;
+use crate::error::Result;
+
+/// Source of the bytes random builtins produce
+pub trait RandomSource: Send + Sync {
+    /// Fill `buf` for the field named `field`
+    ///
+    /// Deterministic sources can key on `field` so a value doesn't depend
+    /// on the order fields are evaluated in.
+    fn fill(&self, field: &str, buf: &mut [u8]) -> Result<()>;
+}
+
+/// Source of the current time
+pub trait Clock: Send + Sync {
+    /// Current time, Unix seconds
+    fn now(&self) -> u64;
+}
+
+/// The operating system's random source (needs the `random` feature)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsRandom;
+
+impl RandomSource for OsRandom {
+    fn fill(&self, _field: &str, buf: &mut [u8]) -> Result<()> {
+        buf.copy_from_slice(&builtin::random_bytes("Random data", buf.len())?);
+        Ok(())
+    }
+}
+
+/// Bytes derived from a seed and the field name with
+/// [`builtin::seeded_bytes`]: the same on every run and platform
+#[derive(Debug, Clone, Copy)]
+pub struct SeededRandom {
+    pub seed: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn fill(&self, field: &str, buf: &mut [u8]) -> Result<()> {
+        buf.copy_from_slice(&builtin::seeded_bytes(self.seed, field, buf.len()));
+        Ok(())
+    }
+}
+
+/// The system time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+}
+
+/// A clock stopped at a given Unix time
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+/// `inner` with field names qualified by `prefix`, so the entries of a
+/// bundle don't draw the same values from a deterministic source
+pub(crate) struct Prefixed {
+    pub prefix: String,
+    pub inner: Arc<dyn RandomSource>,
+}
+
+impl RandomSource for Prefixed {
+    fn fill(&self, field: &str, buf: &mut [u8]) -> Result<()> {
+        self.inner.fill(&format!("{}.{}", self.prefix, field), buf)
+    }
+}
@@ -0,0 +1,266 @@
+//! Delbin signature backends
+//!
+//! `@sign(...)` hands the bytes it covers to a [`Signer`] attached to the
+//! evaluator or to [`GenerateOptions`](crate::GenerateOptions), so keys can
+//! stay in an HSM or cloud KMS and the crate never sees them.
+//!
+//! With the `crypto-sign` feature (and `crypto-rsa` for RSA) the built-in
+//! algorithms are available as signers too, for keys held locally.
+
+use crate::error::{DelbinError, ErrorCode, Result};
+
+/// Produces the signature bytes for `@sign(...)`
+pub trait Signer: Send + Sync {
+    /// Signature over `data`; its length must match the `@sign` field
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Encoding of an ECDSA signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureFormat {
+    /// `r || s`, each a fixed-width big-endian integer
+    #[default]
+    Raw,
+    /// ASN.1 DER `SEQUENCE { r INTEGER, s INTEGER }`, variable length
+    Der,
+}
+
+impl SignatureFormat {
+    /// Format by DSL name (`"raw"` or `"der"`)
+    pub fn by_name(name: &str) -> Result<Self> {
+        match name {
+            "raw" => Ok(SignatureFormat::Raw),
+            "der" => Ok(SignatureFormat::Der),
+            other => Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("Unknown signature format: '{}'. Supported: raw, der", other),
+            )),
+        }
+    }
+}
+
+/// RSA signature padding scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RsaScheme {
+    /// RSASSA-PKCS1-v1_5
+    #[default]
+    Pkcs1v15,
+    /// RSASSA-PSS with MGF1 and a 32-byte salt
+    Pss,
+}
+
+impl RsaScheme {
+    /// Scheme by DSL name (`"pkcs1"` or `"pss"`)
+    pub fn by_name(name: &str) -> Result<Self> {
+        match name {
+            "pkcs1" => Ok(RsaScheme::Pkcs1v15),
+            "pss" => Ok(RsaScheme::Pss),
+            other => Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("Unknown RSA scheme: '{}'. Supported: pkcs1, pss", other),
+            )),
+        }
+    }
+}
+
+/// Ed25519 signer, as used by `@ed25519(...)`
+#[cfg(feature = "crypto-sign")]
+pub struct Ed25519Signer {
+    key: ed25519_dalek::SigningKey,
+}
+
+#[cfg(feature = "crypto-sign")]
+impl Ed25519Signer {
+    /// Key from a 32-byte seed or a 64-byte seed + public key pair
+    pub fn from_bytes(key: &[u8]) -> Result<Self> {
+        let invalid = |what: &str| {
+            DelbinError::new(ErrorCode::E04003, format!("Invalid Ed25519 key: {}", what))
+        };
+        let key = match key.len() {
+            32 => ed25519_dalek::SigningKey::from_bytes(key.try_into().expect("length checked")),
+            64 => ed25519_dalek::SigningKey::from_keypair_bytes(key.try_into().expect("length checked"))
+                .map_err(|_| invalid("public half doesn't match the seed"))?,
+            n => return Err(invalid(&format!("expected 32 or 64 bytes, got {}", n))),
+        };
+        Ok(Self { key })
+    }
+
+    /// 32-byte public key
+    pub fn public_key(&self) -> [u8; 32] {
+        self.key.verifying_key().to_bytes()
+    }
+}
+
+#[cfg(feature = "crypto-sign")]
+impl Signer for Ed25519Signer {
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        use ed25519_dalek::Signer as _;
+        Ok(self.key.sign(data).to_bytes().to_vec())
+    }
+}
+
+/// ECDSA P-256 signer with SHA-256 and RFC 6979 nonces, as used by
+/// `@ecdsa_p256(...)`
+#[cfg(feature = "crypto-sign")]
+pub struct EcdsaP256Signer {
+    key: p256::ecdsa::SigningKey,
+    format: SignatureFormat,
+}
+
+#[cfg(feature = "crypto-sign")]
+impl EcdsaP256Signer {
+    /// Key from its 32-byte big-endian private scalar
+    pub fn from_bytes(key: &[u8], format: SignatureFormat) -> Result<Self> {
+        let invalid = || {
+            DelbinError::new(
+                ErrorCode::E04003,
+                format!("Invalid P-256 key: expected a 32-byte private scalar, got {} bytes", key.len()),
+            )
+        };
+        if key.len() != 32 {
+            return Err(invalid());
+        }
+        let key = p256::ecdsa::SigningKey::from_slice(key).map_err(|_| {
+            DelbinError::new(ErrorCode::E04003, "Invalid P-256 key: scalar is zero or out of range")
+        })?;
+        Ok(Self { key, format })
+    }
+
+    /// Uncompressed SEC1 public key (`04 || x || y`, 65 bytes)
+    pub fn public_key(&self) -> Vec<u8> {
+        self.key.verifying_key().to_encoded_point(false).as_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "crypto-sign")]
+impl Signer for EcdsaP256Signer {
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        use p256::ecdsa::signature::Signer as _;
+        let signature: p256::ecdsa::Signature = self.key.sign(data);
+        Ok(match self.format {
+            SignatureFormat::Raw => signature.to_bytes().to_vec(),
+            SignatureFormat::Der => signature.to_der().as_bytes().to_vec(),
+        })
+    }
+}
+
+/// RSA signer with SHA-256, as used by `@rsa_sign(...)`
+///
+/// Signatures are as long as the modulus: 256 bytes for RSA-2048, 384 for
+/// RSA-3072.
+#[cfg(feature = "crypto-rsa")]
+pub struct RsaSigner {
+    key: rsa::RsaPrivateKey,
+    scheme: RsaScheme,
+}
+
+#[cfg(feature = "crypto-rsa")]
+impl RsaSigner {
+    /// Key in PKCS#8 or PKCS#1 form, DER or PEM; 2048, 3072 or 4096 bits
+    pub fn from_bytes(key: &[u8], scheme: RsaScheme) -> Result<Self> {
+        use rsa::pkcs1::DecodeRsaPrivateKey;
+        use rsa::pkcs8::DecodePrivateKey;
+
+        let pem = std::str::from_utf8(key).ok();
+        let key = rsa::RsaPrivateKey::from_pkcs8_der(key)
+            .or_else(|_| rsa::RsaPrivateKey::from_pkcs1_der(key))
+            .ok()
+            .or_else(|| {
+                let pem = pem?;
+                rsa::RsaPrivateKey::from_pkcs8_pem(pem)
+                    .or_else(|_| rsa::RsaPrivateKey::from_pkcs1_pem(pem))
+                    .ok()
+            })
+            .ok_or_else(|| {
+                DelbinError::new(
+                    ErrorCode::E04003,
+                    "Invalid RSA key: expected a PKCS#8 or PKCS#1 private key (DER or PEM)",
+                )
+            })?;
+        let bits = rsa::traits::PublicKeyParts::size(&key) * 8;
+        if !matches!(bits, 2048 | 3072 | 4096) {
+            return Err(DelbinError::new(
+                ErrorCode::E04003,
+                format!("Unsupported RSA key size: {} bits. Supported: 2048, 3072, 4096", bits),
+            ));
+        }
+        Ok(Self { key, scheme })
+    }
+
+    /// Signature length in bytes
+    pub fn size(&self) -> usize {
+        rsa::traits::PublicKeyParts::size(&self.key)
+    }
+
+    /// Public key as PKCS#1 `RSAPublicKey` DER
+    pub fn public_key(&self) -> Vec<u8> {
+        use rsa::pkcs1::EncodeRsaPublicKey;
+        self.key
+            .to_public_key()
+            .to_pkcs1_der()
+            .expect("RSA public keys always encode")
+            .into_vec()
+    }
+}
+
+#[cfg(feature = "crypto-rsa")]
+impl Signer for RsaSigner {
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let digest = crate::builtin::sha256(data);
+        // The RNG blinds the private-key operation for both schemes and
+        // draws the PSS salt
+        let mut rng = rsa::rand_core::OsRng;
+        let signature = match self.scheme {
+            RsaScheme::Pkcs1v15 => {
+                self.key
+                    .sign_with_rng(&mut rng, rsa::Pkcs1v15Sign::new::<sha2::Sha256>(), &digest)
+            }
+            RsaScheme::Pss => self.key.sign_with_rng(&mut rng, rsa::Pss::new::<sha2::Sha256>(), &digest),
+        };
+        signature.map_err(|e| DelbinError::new(ErrorCode::E04003, format!("RSA signing failed: {}", e)))
+    }
+}
+
+#[cfg(all(test, feature = "crypto-sign"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ed25519_rfc8032_vector() {
+        // RFC 8032 section 7.1, TEST 1 (empty message)
+        let seed = hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").unwrap();
+        let signer = Ed25519Signer::from_bytes(&seed).unwrap();
+        assert_eq!(
+            hex::encode(signer.public_key()),
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        assert_eq!(
+            hex::encode(signer.sign(b"").unwrap()),
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+             5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        );
+
+        let pair = [seed.as_slice(), &signer.public_key()].concat();
+        assert!(Ed25519Signer::from_bytes(&pair).is_ok());
+        assert!(Ed25519Signer::from_bytes(&pair[..40]).is_err());
+    }
+
+    #[test]
+    fn test_ecdsa_p256_rfc6979_vector() {
+        // RFC 6979 appendix A.2.5, SHA-256, message "sample"
+        let key = hex::decode("C9AFA9D845BA75166B5C215767B1D6934E50C3DB36E89B127B8A622B120F6721").unwrap();
+        let raw = EcdsaP256Signer::from_bytes(&key, SignatureFormat::Raw).unwrap();
+        assert_eq!(
+            hex::encode_upper(raw.sign(b"sample").unwrap()),
+            "EFD48B2AACB6A8FD1140DD9CD45E81D69D2C877B56AAF991C34D0EA84EAF3716\
+             F7CB1C942D657C41D436C7A1B6E29F65F3E900DBB9AFF4064DC4AB2F843ACDA8"
+        );
+        assert_eq!(&raw.public_key()[..3], [0x04, 0x60, 0xFE]);
+
+        let der = EcdsaP256Signer::from_bytes(&key, SignatureFormat::Der).unwrap();
+        let der_sig = der.sign(b"sample").unwrap();
+        assert_eq!(der_sig[0], 0x30);
+        assert_eq!(der_sig.len(), 2 + der_sig[1] as usize);
+        assert!(EcdsaP256Signer::from_bytes(&key[..31], SignatureFormat::Raw).is_err());
+    }
+}
@@ -1,7 +1,14 @@
 //! Delbin type definitions
 
+use std::collections::HashMap;
+
+use zeroize::Zeroize;
+
+use crate::error::{DelbinError, ErrorCode};
+
 /// Endianness
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Endian {
     #[default]
     Little,
@@ -10,6 +17,7 @@ pub enum Endian {
 
 /// Scalar type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ScalarType {
     U8,
     U16,
@@ -42,6 +50,31 @@ impl ScalarType {
         }
     }
 
+    /// Value range of a signed type, `None` for unsigned types
+    pub(crate) fn signed_range(&self) -> Option<std::ops::RangeInclusive<i128>> {
+        match self {
+            ScalarType::I8 => Some(i8::MIN as i128..=i8::MAX as i128),
+            ScalarType::I16 => Some(i16::MIN as i128..=i16::MAX as i128),
+            ScalarType::I32 => Some(i32::MIN as i128..=i32::MAX as i128),
+            ScalarType::I64 => Some(i64::MIN as i128..=i64::MAX as i128),
+            _ => None,
+        }
+    }
+
+    /// DSL type name (`u8`, `i32`, ...)
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScalarType::U8 => "u8",
+            ScalarType::U16 => "u16",
+            ScalarType::U32 => "u32",
+            ScalarType::U64 => "u64",
+            ScalarType::I8 => "i8",
+            ScalarType::I16 => "i16",
+            ScalarType::I32 => "i32",
+            ScalarType::I64 => "i64",
+        }
+    }
+
     /// Parse from string
     #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {match s {
@@ -58,8 +91,84 @@ impl ScalarType {
     }
 }
 
+/// Secret byte string (keys, MAC secrets)
+///
+/// The contents are zeroized on drop and never printed by `Debug`.
+#[derive(Clone, Default)]
+pub struct Secret(Vec<u8>);
+
+impl Secret {
+    pub fn new(bytes: impl Into<Vec<u8>>) -> Self {
+        Secret(bytes.into())
+    }
+
+    /// Access the secret bytes
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret([REDACTED; {} bytes])", self.0.len())
+    }
+}
+
+/// Evaluated argument of a custom builtin (see `Evaluator::register_fn()`)
+#[derive(Debug, Clone)]
+pub enum ArgValue {
+    /// Numeric expression
+    Int(u64),
+    /// String literal or string variable
+    Str(String),
+    /// Section contents, an `@self` range, or a bytes variable
+    Bytes(Vec<u8>),
+    /// Secret variable
+    Secret(Secret),
+}
+
+impl ArgValue {
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            ArgValue::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ArgValue::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Data of a bytes or secret argument
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            ArgValue::Bytes(b) => Some(b),
+            ArgValue::Secret(s) => Some(s.expose()),
+            _ => None,
+        }
+    }
+}
+
 /// Runtime value
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     U8(u8),
     U16(u16),
@@ -71,6 +180,13 @@ pub enum Value {
     I64(i64),
     Bytes(Vec<u8>),
     String(String),
+    /// Secret bytes, zeroized on drop; never serialized
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Secret(Secret),
+    /// List of values, for `@for` blocks and `${LIST[i]}`
+    List(Vec<Value>),
+    /// Values by name, read as `${MAP.key}`
+    Map(HashMap<String, Value>),
 }
 
 impl Value {
@@ -104,4 +220,236 @@ impl Value {
             _ => None,
         }
     }
+
+    /// Access secret bytes
+    pub fn as_secret(&self) -> Option<&[u8]> {
+        match self {
+            Value::Secret(s) => Some(s.expose()),
+            _ => None,
+        }
+    }
+
+    /// Elements of a list
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Entries of a map
+    pub fn as_map(&self) -> Option<&HashMap<String, Value>> {
+        match self {
+            Value::Map(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /// Element `index` of a list
+    ///
+    /// ```rust
+    /// use delbin::Value;
+    /// use std::collections::HashMap;
+    ///
+    /// let part = Value::Map(HashMap::from([("size".to_string(), Value::U32(4096))]));
+    /// let parts = Value::List(vec![part]);
+    /// assert_eq!(parts.at(0).and_then(|p| p.get("size")).and_then(Value::as_u64), Some(4096));
+    /// assert!(parts.at(1).is_none());
+    /// ```
+    pub fn at(&self, index: usize) -> Option<&Value> {
+        self.as_list()?.get(index)
+    }
+
+    /// Entry `key` of a map
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_map()?.get(key)
+    }
+
+    /// Numeric value, sign included
+    pub(crate) fn as_i128(&self) -> Option<i128> {
+        match self {
+            Value::U8(v) => Some(*v as i128),
+            Value::U16(v) => Some(*v as i128),
+            Value::U32(v) => Some(*v as i128),
+            Value::U64(v) => Some(*v as i128),
+            Value::I8(v) => Some(*v as i128),
+            Value::I16(v) => Some(*v as i128),
+            Value::I32(v) => Some(*v as i128),
+            Value::I64(v) => Some(*v as i128),
+            _ => None,
+        }
+    }
+
+    /// Hex width of a numeric value's type, in digits
+    fn hex_width(&self) -> Option<usize> {
+        match self {
+            Value::U8(_) | Value::I8(_) => Some(2),
+            Value::U16(_) | Value::I16(_) => Some(4),
+            Value::U32(_) | Value::I32(_) => Some(8),
+            Value::U64(_) | Value::I64(_) => Some(16),
+            _ => None,
+        }
+    }
+
+    /// Longer form for reports: numbers in hex at their type's width with
+    /// the decimal value, bytes spaced with their length
+    ///
+    /// ```rust
+    /// use delbin::Value;
+    ///
+    /// assert_eq!(Value::U16(4660).to_string_pretty(), "0x1234 (4660)");
+    /// assert_eq!(Value::I8(-1).to_string_pretty(), "0xFF (-1)");
+    /// assert_eq!(Value::Bytes(vec![0xDE, 0xAD]).to_string_pretty(), "DE AD (2 bytes)");
+    /// assert_eq!(Value::String("fw".into()).to_string_pretty(), "\"fw\"");
+    /// ```
+    pub fn to_string_pretty(&self) -> String {
+        match (self.as_i128(), self.hex_width()) {
+            (Some(v), Some(width)) => {
+                let bits = (v as u64) & (u64::MAX >> (64 - width * 4));
+                format!("0x{:0width$X} ({})", bits, v, width = width)
+            }
+            _ => match self {
+                Value::Bytes(b) => {
+                    let hex: Vec<String> = b.iter().map(|b| format!("{:02X}", b)).collect();
+                    let unit = if b.len() == 1 { "byte" } else { "bytes" };
+                    format!("{} ({} {})", hex.join(" "), b.len(), unit).trim_start().to_string()
+                }
+                other => other.to_string(),
+            },
+        }
+    }
+
+    /// What the value is, for conversion errors
+    fn kind(&self) -> String {
+        match self {
+            Value::Bytes(b) => format!("{} bytes", b.len()),
+            Value::String(_) => "a string".to_string(),
+            Value::Secret(_) => "a secret".to_string(),
+            Value::List(items) => format!("a list of {} values", items.len()),
+            Value::Map(entries) => format!("a map of {} values", entries.len()),
+            number => format!("the number {}", number),
+        }
+    }
+}
+
+/// Decimal numbers, uppercase hex bytes, quoted and escaped strings,
+/// lists in brackets, maps in braces with sorted keys; secrets are redacted
+///
+/// ```rust
+/// use delbin::Value;
+/// use std::collections::HashMap;
+///
+/// assert_eq!(Value::I32(-5).to_string(), "-5");
+/// assert_eq!(Value::Bytes(vec![0xDE, 0xAD]).to_string(), "DEAD");
+/// assert_eq!(Value::String("a\"b".into()).to_string(), r#""a\"b""#);
+/// assert_eq!(Value::List(vec![Value::U8(1), Value::String("x".into())]).to_string(), r#"[1, "x"]"#);
+///
+/// let map = HashMap::from([("b".to_string(), Value::U8(2)), ("a".to_string(), Value::U8(1))]);
+/// assert_eq!(Value::Map(map).to_string(), "{a: 1, b: 2}");
+/// ```
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Bytes(b) => f.write_str(&crate::utils::to_hex_string(b)),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Secret(s) => write!(f, "[REDACTED; {} bytes]", s.len()),
+            Value::List(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Value::Map(entries) => {
+                let mut keys: Vec<&String> = entries.keys().collect();
+                keys.sort();
+                f.write_str("{")?;
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}: {}", key, entries[key])?;
+                }
+                f.write_str("}")
+            }
+            number => write!(f, "{}", number.as_i128().expect("numeric value")),
+        }
+    }
+}
+
+/// `TryFrom<Value>` and `TryFrom<&Value>` for integer types: any numeric
+/// value that fits, E03001 otherwise
+macro_rules! impl_try_from_value {
+    ($($ty:ty),*) => {$(
+        impl TryFrom<&Value> for $ty {
+            type Error = DelbinError;
+
+            fn try_from(value: &Value) -> Result<Self, Self::Error> {
+                value
+                    .as_i128()
+                    .and_then(|v| <$ty>::try_from(v).ok())
+                    .ok_or_else(|| conversion_error(value, stringify!($ty)))
+            }
+        }
+
+        impl TryFrom<Value> for $ty {
+            type Error = DelbinError;
+
+            fn try_from(value: Value) -> Result<Self, Self::Error> {
+                <$ty>::try_from(&value)
+            }
+        }
+    )*};
+}
+
+impl_try_from_value!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+/// Bytes of a `Value::Bytes`
+impl TryFrom<Value> for Vec<u8> {
+    type Error = DelbinError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bytes(b) => Ok(b),
+            other => Err(conversion_error(&other, "bytes")),
+        }
+    }
+}
+
+impl TryFrom<&Value> for Vec<u8> {
+    type Error = DelbinError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        value.as_bytes().map(<[u8]>::to_vec).ok_or_else(|| conversion_error(value, "bytes"))
+    }
+}
+
+/// Text of a `Value::String`
+impl TryFrom<Value> for String {
+    type Error = DelbinError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            other => Err(conversion_error(&other, "a string")),
+        }
+    }
+}
+
+impl TryFrom<&Value> for String {
+    type Error = DelbinError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        value.as_string().map(str::to_string).ok_or_else(|| conversion_error(value, "a string"))
+    }
+}
+
+fn conversion_error(value: &Value, target: &str) -> DelbinError {
+    DelbinError::new(
+        ErrorCode::E03001,
+        format!("Cannot convert {} to {}", value.kind(), target),
+    )
 }
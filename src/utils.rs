@@ -1,7 +1,16 @@
 //! Delbin utility functions
 
-use crate::types::Value;
+use crate::error::{DelbinError, DelbinWarning, ErrorCode, Result, WarningCode};
+use crate::layout::FieldLayout;
+use crate::types::{Secret, Value};
 use std::collections::HashMap;
+use std::ops::Range;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+
+#[cfg(feature = "mmap")]
+use crate::provider::{MappedSection, MappedSections, SectionProvider};
+use subtle::ConstantTimeEq;
 
 /// Create environment variable mapping from common types
 pub fn create_env() -> HashMap<String, Value> {
@@ -18,11 +27,355 @@ pub fn env_insert_str(env: &mut HashMap<String, Value>, key: &str, value: &str)
     env.insert(key.to_string(), Value::String(value.to_string()));
 }
 
+/// Add secret bytes (keys, MAC secrets) to environment variables
+pub fn env_insert_secret(env: &mut HashMap<String, Value>, key: &str, value: &[u8]) {
+    env.insert(key.to_string(), Value::Secret(Secret::new(value)));
+}
+
+/// Infer the value of an env variable given as text (command line, process
+/// environment): decimal, `0x` hex or `0b` binary integers become `U64`,
+/// negative decimals `I64`, anything else a string
+///
+/// ```rust
+/// use delbin::infer_env_value;
+///
+/// assert_eq!(infer_env_value("0x0800_0000").as_u64(), Some(0x0800_0000));
+/// assert_eq!(infer_env_value("-3").to_string(), "-3");
+/// assert_eq!(infer_env_value("1.2.3").as_string(), Some("1.2.3"));
+/// ```
+pub fn infer_env_value(text: &str) -> Value {
+    if let Some(n) = parse_prefixed_int(text) {
+        return Value::U64(n);
+    }
+    if let Ok(n) = text.parse::<u64>() {
+        return Value::U64(n);
+    }
+    if text.starts_with('-') {
+        if let Ok(n) = text.parse::<i64>() {
+            return Value::I64(n);
+        }
+    }
+    Value::String(text.to_string())
+}
+
+/// `0x`/`0b` integer literal, `_` separators allowed as in the DSL
+fn parse_prefixed_int(text: &str) -> Option<u64> {
+    let (digits, radix) = if let Some(d) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        (d, 16)
+    } else if let Some(d) = text.strip_prefix("0b").or(text.strip_prefix("0B")) {
+        (d, 2)
+    } else {
+        return None;
+    };
+    let digits = digits.replace('_', "");
+    if digits.is_empty() {
+        return None;
+    }
+    u64::from_str_radix(&digits, radix).ok()
+}
+
+/// Env variables from the process environment whose names start with
+/// `prefix`, keyed without it: with `DELBIN_`, `DELBIN_VERSION=3` becomes
+/// `VERSION = 3`. Values are inferred by [`infer_env_value`]; variables
+/// whose name or value isn't valid UTF-8 are skipped.
+pub fn env_from_process(prefix: &str) -> HashMap<String, Value> {
+    std::env::vars_os()
+        .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+        .filter_map(|(k, v)| {
+            let name = k.strip_prefix(prefix)?;
+            (!name.is_empty()).then(|| (name.to_string(), infer_env_value(&v)))
+        })
+        .collect()
+}
+
+/// Env variables from a JSON object
+///
+/// Numbers become `U64` (`I64` when negative), booleans `U8` 0/1, arrays
+/// lists and objects maps. Strings stay strings unless they are `0x`/`0b`
+/// literals, which JSON can't write as numbers. Floats and `null` are
+/// rejected with E03001.
+///
+/// ```rust
+/// let env = delbin::env_from_json(r#"{"VERSION": 3, "BASE": "0x08000000", "TAG": "rc1"}"#).unwrap();
+/// assert_eq!(env["VERSION"].as_u64(), Some(3));
+/// assert_eq!(env["BASE"].as_u64(), Some(0x0800_0000));
+/// assert_eq!(env["TAG"].as_string(), Some("rc1"));
+/// ```
+#[cfg(feature = "json")]
+pub fn env_from_json(json: &str) -> Result<HashMap<String, Value>> {
+    use serde_json::Value as Json;
+
+    fn convert(key: &str, value: &Json) -> Result<Value> {
+        Ok(match value {
+            Json::Bool(b) => Value::U8(*b as u8),
+            Json::Number(n) => match (n.as_u64(), n.as_i64()) {
+                (Some(n), _) => Value::U64(n),
+                (None, Some(n)) => Value::I64(n),
+                _ => return Err(env_type_error(key, "a float", "JSON")),
+            },
+            Json::String(s) => string_env_value(s),
+            Json::Array(items) => Value::List(
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| convert(&format!("{}[{}]", key, i), v))
+                    .collect::<Result<_>>()?,
+            ),
+            Json::Object(map) => Value::Map(
+                map.iter()
+                    .map(|(k, v)| Ok((k.clone(), convert(&format!("{}.{}", key, k), v)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            Json::Null => return Err(env_type_error(key, "null", "JSON")),
+        })
+    }
+
+    let doc: Json = serde_json::from_str(json)
+        .map_err(|e| DelbinError::new(ErrorCode::E01003, format!("invalid JSON env: {}", e)))?;
+    let Json::Object(map) = doc else {
+        return Err(DelbinError::new(
+            ErrorCode::E01003,
+            "JSON env must be an object of variables".to_string(),
+        ));
+    };
+    map.iter()
+        .map(|(k, v)| Ok((k.clone(), convert(k, v)?)))
+        .collect()
+}
+
+/// Env variables from a TOML document
+///
+/// Converted like [`env_from_json`]: integers become `U64` (`I64` when
+/// negative), booleans `U8` 0/1, arrays lists and tables maps; `0x`/`0b`
+/// strings become integers, for addresses beyond TOML's `i64`. Floats and
+/// dates are rejected with E03001.
+///
+/// ```rust
+/// let env = delbin::env_from_toml("VERSION = 3\nTAG = \"rc1\"\n[image]\nslot = 1\n").unwrap();
+/// assert_eq!(env["VERSION"].as_u64(), Some(3));
+/// assert_eq!(env["image"].get("slot").and_then(|v| v.as_u64()), Some(1));
+/// ```
+#[cfg(feature = "toml")]
+pub fn env_from_toml(text: &str) -> Result<HashMap<String, Value>> {
+    use toml::Value as Toml;
+
+    fn convert(key: &str, value: &Toml) -> Result<Value> {
+        Ok(match value {
+            Toml::Boolean(b) => Value::U8(*b as u8),
+            Toml::Integer(n) if *n < 0 => Value::I64(*n),
+            Toml::Integer(n) => Value::U64(*n as u64),
+            Toml::String(s) => string_env_value(s),
+            Toml::Array(items) => Value::List(
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| convert(&format!("{}[{}]", key, i), v))
+                    .collect::<Result<_>>()?,
+            ),
+            Toml::Table(table) => Value::Map(
+                table
+                    .iter()
+                    .map(|(k, v)| Ok((k.clone(), convert(&format!("{}.{}", key, k), v)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            Toml::Float(_) => return Err(env_type_error(key, "a float", "TOML")),
+            Toml::Datetime(_) => return Err(env_type_error(key, "a date", "TOML")),
+        })
+    }
+
+    let table: toml::Table = toml::from_str(text)
+        .map_err(|e| DelbinError::new(ErrorCode::E01003, format!("invalid TOML env: {}", e)))?;
+    table
+        .iter()
+        .map(|(k, v)| Ok((k.clone(), convert(k, v)?)))
+        .collect()
+}
+
+/// A string from a JSON/TOML env: `0x`/`0b` literals are integers
+#[cfg(any(feature = "json", feature = "toml"))]
+fn string_env_value(s: &str) -> Value {
+    match parse_prefixed_int(s) {
+        Some(n) => Value::U64(n),
+        None => Value::String(s.to_string()),
+    }
+}
+
+#[cfg(any(feature = "json", feature = "toml"))]
+fn env_type_error(key: &str, what: &str, format: &str) -> DelbinError {
+    DelbinError::new(
+        ErrorCode::E03001,
+        format!("{} env variable '{}' is {}", format, key, what),
+    )
+    .with_hint("env values are integers, strings, lists or maps".to_string())
+}
+
+/// Compare two byte strings in constant time
+///
+/// The running time depends only on the lengths, never on the contents,
+/// so MAC and digest comparisons don't leak how many bytes matched.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
 /// Create sections mapping
 pub fn create_sections() -> HashMap<String, Vec<u8>> {
     HashMap::new()
 }
 
+/// What to do when two sources set the same env variable or section
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Fail with E02006 naming both sources
+    #[default]
+    Error,
+    /// Keep the later value and record a W02001 warning
+    LastWins,
+}
+
+/// Env variables or sections assembled from several sources (a file, the
+/// command line, the process environment) in increasing precedence
+///
+/// Every insert names its source, so a conflict is resolved by the
+/// [`DuplicatePolicy`] instead of whichever `HashMap::insert` ran last.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::{DuplicatePolicy, InputSet, Value, WarningCode};
+///
+/// let mut env = InputSet::env(DuplicatePolicy::LastWins);
+/// env.insert("build.toml", "VERSION", Value::U32(1)).unwrap();
+/// env.insert("--env", "VERSION", Value::U32(2)).unwrap();
+/// assert_eq!(env.source_of("VERSION"), Some("--env"));
+/// assert_eq!(env.warnings()[0].code, WarningCode::W02001);
+///
+/// let mut strict = InputSet::env(DuplicatePolicy::Error);
+/// strict.insert("build.toml", "VERSION", Value::U32(1)).unwrap();
+/// assert!(strict.insert("--env", "VERSION", Value::U32(2)).is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct InputSet<V> {
+    kind: &'static str,
+    policy: DuplicatePolicy,
+    entries: HashMap<String, (V, String)>,
+    warnings: Vec<DelbinWarning>,
+}
+
+impl InputSet<Value> {
+    /// Env variables under `policy`
+    pub fn env(policy: DuplicatePolicy) -> Self {
+        Self::new("Env variable", policy)
+    }
+}
+
+impl<V> InputSet<V> {
+    /// Sections (bytes, file paths, ...) under `policy`
+    pub fn sections(policy: DuplicatePolicy) -> Self {
+        Self::new("Section", policy)
+    }
+
+    fn new(kind: &'static str, policy: DuplicatePolicy) -> Self {
+        Self {
+            kind,
+            policy,
+            entries: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Set `key` from `source`; E02006 if another source set it and the
+    /// policy is [`DuplicatePolicy::Error`]
+    pub fn insert(&mut self, source: &str, key: impl Into<String>, value: V) -> Result<()> {
+        let key = key.into();
+        if let Some((_, previous)) = self.entries.get(&key) {
+            let message = format!("{} '{}' is set by both {} and {}", self.kind, key, previous, source);
+            match self.policy {
+                DuplicatePolicy::Error => {
+                    return Err(DelbinError::new(ErrorCode::E02006, message)
+                        .with_hint("set it in one place, or use DuplicatePolicy::LastWins"));
+                }
+                DuplicatePolicy::LastWins => self.warnings.push(DelbinWarning {
+                    code: WarningCode::W02001,
+                    message: format!("{}; using the value from {}", message, source),
+                    location: None,
+                }),
+            }
+        }
+        self.entries.insert(key, (value, source.to_string()));
+        Ok(())
+    }
+
+    /// Insert every entry of one source, e.g. a parsed env file
+    pub fn extend<K: Into<String>>(&mut self, source: &str, entries: impl IntoIterator<Item = (K, V)>) -> Result<()> {
+        entries.into_iter().try_for_each(|(key, value)| self.insert(source, key, value))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    /// Source the current value of `key` came from
+    pub fn source_of(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(|(_, source)| source.as_str())
+    }
+
+    /// One W02001 per value replaced under [`DuplicatePolicy::LastWins`]
+    pub fn warnings(&self) -> &[DelbinWarning] {
+        &self.warnings
+    }
+
+    /// The assembled map and the override warnings
+    pub fn into_parts(self) -> (HashMap<String, V>, Vec<DelbinWarning>) {
+        let map = self.entries.into_iter().map(|(key, (value, _))| (key, value)).collect();
+        (map, self.warnings)
+    }
+
+    /// The assembled map, for `generate()` and friends
+    pub fn into_map(self) -> HashMap<String, V> {
+        self.into_parts().0
+    }
+}
+
+/// Memory-map a file for use as section data
+#[cfg(feature = "mmap")]
+pub fn section_from_file(path: impl AsRef<Path>) -> Result<MappedSection> {
+    MappedSection::open(path)
+}
+
+/// Memory-map every regular file in `dir` as a section named after its file stem
+///
+/// `image.bin` becomes section `image`. Subdirectories are skipped; two files
+/// with the same stem are an error.
+#[cfg(feature = "mmap")]
+pub fn sections_from_dir(dir: impl AsRef<Path>) -> Result<MappedSections> {
+    let dir = dir.as_ref();
+    let read_err = |e: std::io::Error| {
+        DelbinError::new(
+            ErrorCode::E05002,
+            format!("Cannot read section directory '{}': {}", dir.display(), e),
+        )
+    };
+
+    let mut sections = MappedSections::new();
+    for entry in std::fs::read_dir(dir).map_err(read_err)? {
+        let path = entry.map_err(read_err)?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if sections.len(name).is_some() {
+            return Err(DelbinError::new(
+                ErrorCode::E05002,
+                format!("Duplicate section '{}' in '{}'", name, dir.display()),
+            ));
+        }
+        sections.insert(name, MappedSection::open(&path)?);
+    }
+    Ok(sections)
+}
+
 /// Format byte array as hexadecimal string
 pub fn to_hex_string(data: &[u8]) -> String {
     data.iter()
@@ -47,35 +400,194 @@ pub fn from_hex_string(hex: &str) -> Option<Vec<u8>> {
 /// Print byte array as formatted hexadecimal dump
 pub fn hex_dump(data: &[u8], bytes_per_line: usize) -> String {
     let mut result = String::new();
+    dump_lines(&mut result, data, 0, bytes_per_line, |_| Default::default());
+    result
+}
 
-    for (i, chunk) in data.chunks(bytes_per_line).enumerate() {
-        // Address
-        result.push_str(&format!("{:08X}: ", i * bytes_per_line));
+/// Hex dump with each line labeled by the names of the fields it overlaps
+///
+/// Lines outside every field (alignment padding, a merged image) carry no label.
+pub fn annotated_dump(data: &[u8], fields: &[FieldLayout], bytes_per_line: usize) -> String {
+    let spans = field_spans(data, fields);
+    let mut result = String::new();
+    dump_lines(&mut result, data, 0, bytes_per_line, |line| {
+        let labels: Vec<&str> = spans
+            .iter()
+            .filter(|(span, _)| span.start < line.end && line.start < span.end)
+            .map(|&(_, name)| name)
+            .collect();
+        if labels.is_empty() {
+            Default::default()
+        } else {
+            (String::new(), format!(" {}", labels.join(", ")))
+        }
+    });
+    result
+}
 
-        // Hexadecimal
-        for byte in chunk {
-            result.push_str(&format!("{:02X} ", byte));
+/// Hex dump split at field boundaries, each field starting on its own line
+///
+/// The field name goes in a column before the address, on the first line
+/// of the field only. Bytes not covered by any field (alignment padding, a
+/// merged image) are shown as `..`.
+///
+/// ```text
+/// magic    00000000: 46 50 4B 00                                      |FPK.|
+/// version  00000004: 02 01                                            |..|
+/// ..       00000006: 00 00                                            |..|
+/// ```
+pub fn field_map_dump(data: &[u8], fields: &[FieldLayout], bytes_per_line: usize) -> String {
+    // Fill the gaps so every byte is printed exactly once
+    let mut regions = Vec::new();
+    let mut pos = 0;
+    for (span, name) in field_spans(data, fields) {
+        if span.end <= pos {
+            continue;
         }
+        if span.start > pos {
+            regions.push((pos..span.start, ".."));
+        }
+        regions.push((span.start.max(pos)..span.end, name));
+        pos = span.end;
+    }
+    if pos < data.len() {
+        regions.push((pos..data.len(), ".."));
+    }
+
+    let width = regions.iter().map(|(_, name)| name.len()).max().unwrap_or(0);
+    let mut result = String::new();
+    for (region, name) in regions {
+        let first = region.start;
+        dump_lines(&mut result, &data[region.clone()], first, bytes_per_line, |line| {
+            let label = if line.start == first { name } else { "" };
+            (format!("{:<width$}  ", label, width = width), String::new())
+        });
+    }
+    result
+}
+
+/// Byte ranges of the non-empty `fields` within `data`, by offset
+fn field_spans<'f>(data: &[u8], fields: &'f [FieldLayout]) -> Vec<(Range<usize>, &'f str)> {
+    let mut spans: Vec<_> = fields
+        .iter()
+        .filter(|f| f.size > 0 && f.offset < data.len())
+        .map(|f| (f.offset..(f.offset + f.size).min(data.len()), f.name.as_str()))
+        .collect();
+    spans.sort_by_key(|(span, _)| span.start);
+    spans
+}
+
+/// Dump `data`, which sits at `address`, `bytes_per_line` bytes per line;
+/// `annotate` gives the text before and after each line from its address range
+fn dump_lines(
+    result: &mut String,
+    data: &[u8],
+    address: usize,
+    bytes_per_line: usize,
+    mut annotate: impl FnMut(Range<usize>) -> (String, String),
+) {
+    for (i, chunk) in data.chunks(bytes_per_line).enumerate() {
+        let start = address + i * bytes_per_line;
+        let (before, after) = annotate(start..start + chunk.len());
+        result.push_str(&before);
+        dump_line(result, start, chunk, bytes_per_line);
+        result.push_str(&after);
+        result.push('\n');
+    }
+}
+
+/// Byte statistics of a data stream, fed chunk by chunk
+///
+/// Used to spot sections that should be encrypted but look like plaintext:
+/// ciphertext is close to 8 bits of entropy per byte and has no long runs
+/// of zeros.
+#[derive(Debug, Clone)]
+pub struct ByteStats {
+    counts: [u64; 256],
+    len: u64,
+    zero_run: u64,
+    max_zero_run: u64,
+}
 
-        // Padding
-        for _ in 0..(bytes_per_line - chunk.len()) {
-            result.push_str("   ");
+impl Default for ByteStats {
+    fn default() -> Self {
+        Self {
+            counts: [0; 256],
+            len: 0,
+            zero_run: 0,
+            max_zero_run: 0,
         }
+    }
+}
 
-        // ASCII
-        result.push_str(" |");
-        for byte in chunk {
-            let c = if *byte >= 0x20 && *byte < 0x7F {
-                *byte as char
+impl ByteStats {
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.counts[byte as usize] += 1;
+            if byte == 0 {
+                self.zero_run += 1;
+                self.max_zero_run = self.max_zero_run.max(self.zero_run);
             } else {
-                '.'
-            };
-            result.push(c);
+                self.zero_run = 0;
+            }
         }
-        result.push_str("|\n");
+        self.len += data.len() as u64;
     }
 
-    result
+    /// Bytes seen so far
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Shannon entropy in bits per byte (0.0 to 8.0)
+    pub fn entropy(&self) -> f64 {
+        let len = self.len as f64;
+        self.counts
+            .iter()
+            .filter(|&&c| c > 0)
+            .map(|&c| {
+                let p = c as f64 / len;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// Longest run of zero bytes
+    pub fn max_zero_run(&self) -> u64 {
+        self.max_zero_run
+    }
+}
+
+/// One dump line without the trailing newline: address, hex bytes, ASCII
+fn dump_line(result: &mut String, address: usize, chunk: &[u8], bytes_per_line: usize) {
+    // Address
+    result.push_str(&format!("{:08X}: ", address));
+
+    // Hexadecimal
+    for byte in chunk {
+        result.push_str(&format!("{:02X} ", byte));
+    }
+
+    // Padding
+    for _ in 0..(bytes_per_line - chunk.len()) {
+        result.push_str("   ");
+    }
+
+    // ASCII
+    result.push_str(" |");
+    for byte in chunk {
+        let c = if *byte >= 0x20 && *byte < 0x7F {
+            *byte as char
+        } else {
+            '.'
+        };
+        result.push(c);
+    }
+    result.push('|');
 }
 
 #[cfg(test)]
@@ -97,6 +609,166 @@ mod tests {
         assert_eq!(from_hex_string("123"), None); // Odd length
     }
 
+    #[test]
+    fn test_byte_stats() {
+        let mut stats = ByteStats::default();
+        assert_eq!(stats.entropy(), 0.0);
+        stats.update(&[0, 0, 1]);
+        stats.update(&[0, 0, 0, 2]);
+        assert_eq!(stats.len(), 7);
+        assert_eq!(stats.max_zero_run(), 3);
+
+        let mut stats = ByteStats::default();
+        stats.update(&(0..=255).collect::<Vec<u8>>());
+        assert!((stats.entropy() - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(b"abcd", b"abcd"));
+        assert!(!ct_eq(b"abcd", b"abce"));
+        assert!(!ct_eq(b"abcd", b"abc"));
+        assert!(ct_eq(b"", b""));
+    }
+
+    #[test]
+    fn test_infer_env_value() {
+        assert_eq!(infer_env_value("42").as_u64(), Some(42));
+        assert_eq!(infer_env_value("0xFF").as_u64(), Some(0xFF));
+        assert_eq!(infer_env_value("0b1010").as_u64(), Some(10));
+        assert!(matches!(infer_env_value("-7"), Value::I64(-7)));
+        assert_eq!(infer_env_value("0x").as_string(), Some("0x"));
+        assert_eq!(infer_env_value("v1.0").as_string(), Some("v1.0"));
+    }
+
+    #[test]
+    fn test_env_from_process_strips_prefix() {
+        std::env::set_var("DELBIN_UTILS_TEST_VERSION", "0x10");
+        std::env::set_var("DELBIN_UTILS_TEST_NAME", "app");
+        let env = env_from_process("DELBIN_UTILS_TEST_");
+        assert_eq!(env["VERSION"].as_u64(), Some(0x10));
+        assert_eq!(env["NAME"].as_string(), Some("app"));
+        assert!(!env.contains_key("PATH"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_env_from_json() {
+        let env = env_from_json(
+            r#"{"N": -2, "ON": true, "V": "42", "SLOTS": [{"size": 16}], "BIG": 18446744073709551615}"#,
+        )
+        .unwrap();
+        assert!(matches!(env["N"], Value::I64(-2)));
+        assert_eq!(env["ON"].as_u64(), Some(1));
+        assert_eq!(env["V"].as_string(), Some("42"));
+        assert_eq!(env["SLOTS"].at(0).and_then(|s| s.get("size")).and_then(|v| v.as_u64()), Some(16));
+        assert_eq!(env["BIG"].as_u64(), Some(u64::MAX));
+
+        let err = env_from_json(r#"{"S": {"ratio": 1.5}}"#).unwrap_err();
+        assert_eq!(err.code, ErrorCode::E03001);
+        assert!(err.message.contains("S.ratio"), "{}", err.message);
+        assert_eq!(env_from_json("[1]").unwrap_err().code, ErrorCode::E01003);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_env_from_toml() {
+        let env = env_from_toml("BASE = \"0xFFFF_0000_0000_0000\"\nN = -1\nL = [1, 2]\n").unwrap();
+        assert_eq!(env["BASE"].as_u64(), Some(0xFFFF_0000_0000_0000));
+        assert!(matches!(env["N"], Value::I64(-1)));
+        assert_eq!(env["L"].as_list().map(|l| l.len()), Some(2));
+        assert_eq!(env_from_toml("T = 1979-05-27").unwrap_err().code, ErrorCode::E03001);
+    }
+
+    #[test]
+    fn test_secret_is_redacted_in_debug() {
+        let mut env = create_env();
+        env_insert_secret(&mut env, "KEY", b"topsecret");
+        let debug = format!("{:?}", env["KEY"]);
+        assert!(!debug.contains("topsecret"));
+        assert_eq!(env["KEY"].as_secret().unwrap(), b"topsecret");
+    }
+
+    #[test]
+    fn test_input_set_duplicate_policy() {
+        let mut sections = InputSet::sections(DuplicatePolicy::LastWins);
+        sections.extend("config", [("app", vec![1u8]), ("boot", vec![2])]).unwrap();
+        sections.insert("--section", "app", vec![3]).unwrap();
+        assert_eq!(sections.get("app"), Some(&vec![3]));
+        assert_eq!(sections.source_of("boot"), Some("config"));
+        let (map, warnings) = sections.into_parts();
+        assert_eq!(map.len(), 2);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, WarningCode::W02001);
+        assert!(warnings[0].message.contains("config and --section"));
+
+        let mut env = InputSet::env(DuplicatePolicy::default());
+        env.insert("process env", "VERSION", Value::U32(1)).unwrap();
+        let err = env.extend("--env", [("BUILD", Value::U32(7)), ("VERSION", Value::U32(2))]).unwrap_err();
+        assert_eq!(err.code, ErrorCode::E02006);
+        assert_eq!(err.message, "Env variable 'VERSION' is set by both process env and --env");
+        assert!(matches!(env.get("VERSION"), Some(Value::U32(1))));
+        assert!(env.warnings().is_empty());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_sections_from_dir_maps_by_stem() {
+        let dir = std::env::temp_dir().join(format!("delbin-secdir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("image.bin"), b"0123456789").unwrap();
+        std::fs::write(dir.join("empty.bin"), b"").unwrap();
+
+        let sections = sections_from_dir(&dir).unwrap();
+        assert_eq!(sections.len("image"), Some(10));
+        assert_eq!(sections.len("empty"), Some(0));
+        assert_eq!(sections.len("nested"), None);
+        assert_eq!(&*section_from_file(dir.join("image.bin")).unwrap(), b"0123456789");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_annotated_dump_labels_fields() {
+        let field = |name: &str, offset, size| FieldLayout {
+            name: name.to_string(),
+            offset,
+            size,
+            elem: crate::ScalarType::U8,
+            count: Some(size),
+        };
+        let fields = [field("magic", 0, 4), field("body", 4, 20)];
+        let dump = annotated_dump(&[0x41; 40], &fields, 16);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("|AAAAAAAAAAAAAAAA| magic, body"));
+        assert!(lines[1].ends_with("|AAAAAAAAAAAAAAAA| body"));
+        assert!(lines[2].ends_with("|AAAAAAAA|"));
+    }
+
+    #[test]
+    fn test_field_map_dump_splits_at_boundaries() {
+        let field = |name: &str, offset, size| FieldLayout {
+            name: name.to_string(),
+            offset,
+            size,
+            elem: crate::ScalarType::U8,
+            count: Some(size),
+        };
+        let fields = [field("version", 4, 2), field("magic", 0, 4), field("body", 8, 20)];
+        let dump = field_map_dump(&[0x41; 30], &fields, 16);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with("magic    00000000: 41 41 41 41 "));
+        assert!(lines[0].ends_with("|AAAA|"));
+        assert!(lines[1].starts_with("version  00000004: 41 41 "));
+        assert!(lines[2].starts_with("..       00000006: 41 41 "));
+        assert!(lines[3].starts_with("body     00000008: "));
+        assert!(lines[3].ends_with("|AAAAAAAAAAAAAAAA|"));
+        assert!(lines[4].starts_with("         00000018: 41 41 41 41 "));
+        assert!(lines[5].starts_with("..       0000001C: 41 41 "));
+    }
+
     #[test]
     fn test_hex_dump() {
         let data = b"Hello, World!";
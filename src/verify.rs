@@ -0,0 +1,53 @@
+//! Delbin verification result types
+
+/// What a verified field was checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CheckKind {
+    /// `@crc32`/`@crc`/`@sha256` recomputed over the header and sections
+    Checksum,
+    /// Constant initializer (magic string, format version, offset)
+    Constant,
+    /// Value derived from the env passed to `verify_with_env()` (version,
+    /// build number, partition name)
+    Input,
+}
+
+/// Outcome of checking one field
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldCheck {
+    pub field: String,
+    pub kind: CheckKind,
+    /// Bytes the DSL produces for the field
+    pub expected: Vec<u8>,
+    /// Bytes found in the header
+    pub actual: Vec<u8>,
+}
+
+impl FieldCheck {
+    /// True when the header holds the expected bytes
+    pub fn passed(&self) -> bool {
+        crate::utils::ct_eq(&self.expected, &self.actual)
+    }
+}
+
+/// Result of verifying a header against its DSL
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VerifyReport {
+    /// Every checked field, in evaluation order (checksums over `@self` last)
+    pub checks: Vec<FieldCheck>,
+}
+
+impl VerifyReport {
+    /// True when every check passed
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(FieldCheck::passed)
+    }
+
+    /// Checks that failed
+    pub fn failures(&self) -> impl Iterator<Item = &FieldCheck> {
+        self.checks.iter().filter(|c| !c.passed())
+    }
+}
@@ -0,0 +1,350 @@
+//! Delbin YAML layout interchange
+//!
+//! Many formats start life as a Kaitai-style YAML field list. These
+//! functions convert such a list to and from the DSL, so a team can migrate
+//! without retyping its layouts:
+//!
+//! ```yaml
+//! name: fw_header
+//! endian: little        # optional, little by default
+//! packed: true          # optional
+//! align: 256            # optional
+//! fields:               # `seq` is accepted too
+//!   - name: magic       # `id` is accepted too
+//!     type: u8
+//!     size: 4           # element count; makes the field an array
+//!     value: '@bytes("FPK")'
+//!     doc: Format magic # becomes a `//` comment
+//!   - name: version
+//!     type: u32
+//!     value: 0x0102     # integer, or DSL expression text
+//! ```
+
+use std::fmt::Write;
+
+use serde_yaml::{Mapping, Value as Yaml};
+
+use crate::ast::{File, Type};
+use crate::error::{DelbinError, ErrorCode, Result};
+use crate::parser::{self, Rule};
+use crate::types::{Endian, ScalarType};
+
+/// Convert a YAML field list into a parsed DSL file
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::import_yaml_layout;
+///
+/// let yaml = "name: hdr\npacked: true\nfields:\n  - { name: magic, type: u32, value: 0x4B5046 }\n";
+/// let file = import_yaml_layout(yaml).unwrap();
+/// assert_eq!(file.struct_def.name, "hdr");
+/// assert_eq!(file.struct_def.fields[0].name, "magic");
+/// ```
+pub fn import_yaml_layout(yaml: &str) -> Result<File> {
+    let doc: Yaml = serde_yaml::from_str(yaml)
+        .map_err(|e| yaml_error(format!("invalid YAML: {}", e)))?;
+    let root = doc
+        .as_mapping()
+        .ok_or_else(|| yaml_error("expected a mapping at the top level".to_string()))?;
+
+    let name = str_key(root, &["name"], "layout")?
+        .ok_or_else(|| yaml_error("layout needs a `name`".to_string()))?;
+    ident(name, "layout")?;
+    let endian = match str_key(root, &["endian"], "layout")? {
+        None | Some("little") | Some("le") => "little",
+        Some("big") | Some("be") => "big",
+        Some(other) => return Err(yaml_error(format!("unknown endian '{}'", other))),
+    };
+
+    let mut dsl = format!("@endian = {};\nstruct {}", endian, name);
+    if root.get("packed").map(|v| v.as_bool()) == Some(Some(true)) {
+        dsl.push_str(" @packed");
+    }
+    if let Some(align) = root.get("align") {
+        let align = align
+            .as_u64()
+            .ok_or_else(|| yaml_error("`align` must be an integer".to_string()))?;
+        let _ = write!(dsl, " @align({})", align);
+    }
+    dsl.push_str(" {\n");
+
+    let fields = ["fields", "seq"]
+        .iter()
+        .find_map(|key| root.get(*key))
+        .and_then(Yaml::as_sequence)
+        .ok_or_else(|| yaml_error("layout needs a `fields` list".to_string()))?;
+    for (index, field) in fields.iter().enumerate() {
+        let field = field
+            .as_mapping()
+            .ok_or_else(|| yaml_error(format!("field #{} is not a mapping", index + 1)))?;
+        let what = format!("field #{}", index + 1);
+        let name = str_key(field, &["name", "id"], &what)?
+            .ok_or_else(|| yaml_error(format!("{} needs a `name`", what)))?;
+        ident(name, &what)?;
+        let what = format!("field '{}'", name);
+        let ty = str_key(field, &["type"], &what)?
+            .ok_or_else(|| yaml_error(format!("{} needs a `type`", what)))?;
+        if ScalarType::from_str(ty).is_none() {
+            return Err(yaml_error(format!("{} has unknown type '{}'", what, ty)));
+        }
+
+        if let Some(doc) = str_key(field, &["doc"], &what)? {
+            for line in doc.lines() {
+                let _ = writeln!(dsl, "    // {}", line);
+            }
+        }
+        let ty = match field.get("size") {
+            None => ty.to_string(),
+            Some(size) => format!("[{}; {}]", ty, expr_text(size, &what, "size", &[Rule::expr, Rule::infer_marker])?),
+        };
+        let _ = write!(dsl, "    {}: {}", name, ty);
+        if let Some(value) = field.get("value") {
+            let _ = write!(dsl, " = {}", expr_text(value, &what, "value", &[Rule::expr, Rule::array_literal])?);
+        }
+        dsl.push_str(";\n");
+    }
+    dsl.push_str("}\n");
+
+    parser::parse(&dsl).map_err(|e| e.with_hint("in the DSL generated from the YAML layout"))
+}
+
+/// Convert a DSL file into a YAML field list
+///
+/// Only the header struct's name, byte order, `@packed`, `@align` and fields
+/// have a YAML form; files using anything else fail with E04003 rather than
+/// losing it.
+///
+/// # Example
+///
+/// ```rust
+/// use delbin::{export_yaml_layout, import_yaml_layout, parser};
+///
+/// let file = parser::parse("struct hdr @packed { magic: [u8; 4] = @bytes(\"FPK\"); }").unwrap();
+/// let yaml = export_yaml_layout(&file).unwrap();
+/// assert!(yaml.contains("value: '@bytes(\"FPK\")'"));
+/// assert_eq!(import_yaml_layout(&yaml).unwrap().struct_def.fields.len(), 1);
+/// ```
+pub fn export_yaml_layout(file: &File) -> Result<String> {
+    let def = &file.struct_def;
+    let unsupported: Vec<&str> = [
+        (file.endian_var.is_some(), "@endian = ${VAR}"),
+        (file.base.is_some(), "@base"),
+        (file.size.is_some(), "@size"),
+        (file.fill.is_some(), "@fill"),
+        (file.slot_size.is_some(), "@slot_size"),
+        (!file.asserts.is_empty(), "@assert"),
+        (!file.inputs.is_empty(), "@inputs"),
+        (!file.policy.is_empty(), "@policy"),
+        (!file.layout.is_empty(), "@layout"),
+        (!file.sections.is_empty(), "@section"),
+        (file.bundle.is_some(), "@bundle"),
+        (!file.includes.is_empty(), "@include"),
+        (!file.templates.is_empty(), "struct templates"),
+        (file.trailer.is_some(), "a trailer struct"),
+        (!def.encrypt.is_empty(), "@encrypt_range"),
+        (!def.expect_encrypted.is_empty(), "@expect_encrypted"),
+        (def.fields.iter().any(|f| !f.aliases.is_empty()), "@alias"),
+        (def.fields.iter().any(|f| f.for_each.is_some()), "@for"),
+    ]
+    .into_iter()
+    .filter_map(|(used, what)| used.then_some(what))
+    .collect();
+    if !unsupported.is_empty() {
+        return Err(DelbinError::new(
+            ErrorCode::E04003,
+            format!("YAML layouts can't express {}", unsupported.join(", ")),
+        ));
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "name: {}", def.name);
+    let endian = match file.endian {
+        Endian::Little => "little",
+        Endian::Big => "big",
+    };
+    let _ = writeln!(out, "endian: {}", endian);
+    if def.packed {
+        let _ = writeln!(out, "packed: true");
+    }
+    if let Some(align) = def.align {
+        let _ = writeln!(out, "align: {}", align);
+    }
+    let _ = writeln!(out, "fields:");
+    for field in &def.fields {
+        let _ = writeln!(out, "  - name: {}", field.name);
+        let comments: Vec<&str> = field
+            .trivia
+            .comments()
+            .map(|c| c.trim_start_matches('/').trim())
+            .collect();
+        if !comments.is_empty() {
+            let _ = writeln!(out, "    doc: {}", yaml_quote(&comments.join("\n")));
+        }
+        match &field.ty {
+            Type::Scalar(scalar) => {
+                let _ = writeln!(out, "    type: {}", Type::Scalar(*scalar));
+            }
+            Type::Array { elem, len } => {
+                let _ = writeln!(out, "    type: {}", Type::Scalar(*elem));
+                let _ = writeln!(out, "    size: {}", yaml_quote(&len.to_string()));
+            }
+            Type::Inferred => {
+                let _ = writeln!(out, "    type: u8\n    size: _");
+            }
+        }
+        if let Some(init) = &field.init {
+            let _ = writeln!(out, "    value: {}", yaml_quote(&init.to_string()));
+        }
+    }
+    Ok(out)
+}
+
+/// String value of the first of `keys` present in `map`
+fn str_key<'y>(map: &'y Mapping, keys: &[&str], what: &str) -> Result<Option<&'y str>> {
+    match keys.iter().find_map(|key| map.get(*key)) {
+        None => Ok(None),
+        Some(value) => value
+            .as_str()
+            .map(Some)
+            .ok_or_else(|| yaml_error(format!("{} `{}` must be a string", what, keys[0]))),
+    }
+}
+
+/// DSL text for an integer or expression-string YAML value; strings must be
+/// exactly one of `rules`, so they can't reach past their place in the DSL
+fn expr_text(value: &Yaml, what: &str, key: &str, rules: &[Rule]) -> Result<String> {
+    match value {
+        Yaml::Number(n) if n.is_u64() => Ok(n.to_string()),
+        Yaml::String(s) if rules.iter().any(|&rule| parser::is_whole(rule, s.trim())) => Ok(s.trim().to_string()),
+        Yaml::String(s) => Err(yaml_error(format!("{} `{}` is not a DSL expression: '{}'", what, key, s))),
+        _ => Err(yaml_error(format!(
+            "{} `{}` must be a non-negative integer or a DSL expression string",
+            what, key
+        ))),
+    }
+}
+
+/// Check that a name from the YAML is a DSL identifier
+fn ident(name: &str, what: &str) -> Result<()> {
+    if parser::is_whole(Rule::ident, name) {
+        Ok(())
+    } else {
+        Err(yaml_error(format!("{} name '{}' is not an identifier", what, name)))
+    }
+}
+
+/// Single-quoted YAML scalar (quotes doubled); plain if it's a bare integer
+fn yaml_quote(s: &str) -> String {
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        s.to_string()
+    } else if s.contains('\n') {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+    } else {
+        format!("'{}'", s.replace('\'', "''"))
+    }
+}
+
+fn yaml_error(message: String) -> DelbinError {
+    DelbinError::new(ErrorCode::E01003, format!("YAML layout: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_yaml_import_generates_same_bytes() {
+        let yaml = r#"
+name: fw_header
+endian: big
+packed: true
+seq:
+  - id: magic
+    type: u8
+    size: 4
+    value: '@bytes("FPK")'
+    doc: Format magic
+  - name: version
+    type: u16
+    value: 0x0102
+  - name: reserved
+    type: u8
+    size: 2
+  - name: crc
+    type: u32
+    value: '@crc32(@self[..crc])'
+"#;
+        let file = import_yaml_layout(yaml).unwrap();
+        assert_eq!(file.struct_def.fields[0].trivia.comments().collect::<Vec<_>>(), ["// Format magic"]);
+
+        let dsl = r#"
+            @endian = big;
+            struct fw_header @packed {
+                magic:    [u8; 4] = @bytes("FPK");
+                version:  u16     = 0x0102;
+                reserved: [u8; 2];
+                crc:      u32     = @crc32(@self[..crc]);
+            }
+        "#;
+        let sections = HashMap::<String, Vec<u8>>::new();
+        let expected = crate::generate(dsl, &HashMap::new(), &sections).unwrap().data;
+        let printed = crate::printer::print(&file);
+        assert_eq!(crate::generate(&printed, &HashMap::new(), &sections).unwrap().data, expected);
+
+        // And back: export, re-import, same struct
+        let exported = export_yaml_layout(&file).unwrap();
+        assert!(exported.contains("  - name: magic\n    doc: 'Format magic'\n    type: u8\n    size: 4\n"), "{exported}");
+        let again = crate::printer::print(&import_yaml_layout(&exported).unwrap());
+        assert_eq!(crate::generate(&again, &HashMap::new(), &sections).unwrap().data, expected);
+    }
+
+    #[test]
+    fn test_yaml_import_errors() {
+        for (yaml, needle) in [
+            ("- a\n", "top level"),
+            ("fields: []\n", "needs a `name`"),
+            ("name: h\nfields:\n  - { name: a }\n", "needs a `type`"),
+            ("name: h\nfields:\n  - { name: a, type: u8, value: true }\n", "`value` must be"),
+            ("name: h\nendian: middle\nfields: []\n", "unknown endian"),
+        ] {
+            let err = import_yaml_layout(yaml).unwrap_err();
+            assert_eq!(err.code, ErrorCode::E01003);
+            assert!(err.message.contains(needle), "{}: {}", needle, err.message);
+        }
+
+        // Text that isn't exactly a name, type or expression is rejected
+        // rather than spliced into the DSL
+        for (yaml, needle) in [
+            ("name: h\nfields:\n  - { name: a, type: u8, value: '1 +' }\n", "not a DSL expression"),
+            ("name: h\nfields:\n  - { name: a, type: u8, value: '1; b: u32 = 2' }\n", "not a DSL expression"),
+            ("name: h\nfields:\n  - { name: a, type: u8, size: '4]; b: [u8' }\n", "not a DSL expression"),
+            ("name: h\nfields:\n  - { name: 'a: u8; b', type: u8 }\n", "not an identifier"),
+            ("name: 'h { a: u8; } struct g'\nfields: []\n", "not an identifier"),
+            ("name: h\nfields:\n  - { name: a, type: 'u8; b: u32' }\n", "unknown type"),
+        ] {
+            let err = import_yaml_layout(yaml).unwrap_err();
+            assert_eq!(err.code, ErrorCode::E01003);
+            assert!(err.message.contains(needle), "{}: {}", needle, err.message);
+        }
+
+        let include = |path: &str| -> Result<String> {
+            assert_eq!(path, "common.dbin");
+            Ok("magic: u32 = 1;".to_string())
+        };
+        for (dsl, needle) in [
+            ("@base = 0x1000; struct h { a: u8; }", "@base"),
+            ("@bundle { h; } struct h { a: u8; }", "@bundle"),
+            ("struct h { @include \"common.dbin\"; }", "@include"),
+            ("struct t<N> { a: u8 = N; } struct h { x: t<1>; }", "struct templates"),
+            ("struct h { a @alias(\"b\"): u8; }", "@alias"),
+            ("struct h { @for p in ${PARTS} { a: u8; } }", "@for"),
+        ] {
+            let file = parser::parse_with_resolver(dsl, &include).unwrap();
+            let err = export_yaml_layout(&file).unwrap_err();
+            assert_eq!(err.code, ErrorCode::E04003);
+            assert!(err.message.contains(needle), "{}: {}", needle, err.message);
+        }
+    }
+}
@@ -78,6 +78,19 @@ fn test_cli_verbose_prints_warnings_to_stderr() {
     );
 }
 
+#[test]
+fn test_cli_duplicate_env_policy() {
+    let dsl = "@endian = little; struct h @packed { val: u8 = ${VER}; }";
+    let (code, stdout, stderr) = run_delbin(dsl, &["--env", "VER=1", "--env", "VER=2", "--verbose"]);
+    assert_eq!(code, 0, "stderr: {stderr}");
+    assert_eq!(stdout.trim(), "02");
+    assert!(stderr.contains("W02001"), "{stderr}");
+
+    let (code, _, stderr) = run_delbin(dsl, &["--env", "VER=1", "--env", "VER=2", "--on-duplicate", "error"]);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("E02006"), "{stderr}");
+}
+
 #[test]
 fn test_cli_bin_format_writes_binary() {
     let dsl = "@endian = little; struct h @packed { val: u8 = 0xAB; }";
@@ -85,3 +98,370 @@ fn test_cli_bin_format_writes_binary() {
     assert_eq!(code, 0);
     assert_eq!(stdout_bytes, b"\xAB", "binary output should be raw byte 0xAB");
 }
+
+#[test]
+fn test_cli_map_format_labels_fields() {
+    let dsl = "@endian = little; struct h { magic: [u8; 4] = @bytes(\"FPK\"); version: u16 = 0x0102; }";
+    let (code, stdout, _) = run_delbin(dsl, &["--format", "map"]);
+    assert_eq!(code, 0);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines[0].starts_with("magic    00000000: 46 50 4B 00 "), "{stdout}");
+    assert!(lines[1].starts_with("version  00000004: 02 01 "), "{stdout}");
+}
+
+#[test]
+fn test_cli_infer_prints_draft_dsl() {
+    let dir = std::env::temp_dir();
+    let a = dir.join(format!("delbin-infer-a-{}.bin", std::process::id()));
+    let b = dir.join(format!("delbin-infer-b-{}.bin", std::process::id()));
+    std::fs::write(&a, b"HDR\x00\x01\x00\x00\x00").unwrap();
+    std::fs::write(&b, b"HDR\x00\x02\x00\x00\x00").unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_delbin"))
+        .arg("infer")
+        .arg(&a)
+        .arg(&b)
+        .output()
+        .expect("Failed to spawn delbin binary");
+    std::fs::remove_file(&a).unwrap();
+    std::fs::remove_file(&b).unwrap();
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert_eq!(out.status.code(), Some(0), "stderr: {}", String::from_utf8_lossy(&out.stderr));
+    assert!(stdout.contains(r#"@bytes("HDR")"#), "got: {stdout}");
+    assert!(stdout.contains("field_0004: u32;"), "got: {stdout}");
+}
+
+#[test]
+fn test_cli_depfile_lists_section_files() {
+    let dir = std::env::temp_dir();
+    let image = dir.join(format!("delbin-dep-image-{}.bin", std::process::id()));
+    let out = dir.join(format!("delbin-dep-out-{}.bin", std::process::id()));
+    let dep = dir.join(format!("delbin-dep-{}.d", std::process::id()));
+    std::fs::write(&image, b"payload").unwrap();
+
+    let dsl = "struct h @packed { size: u32 = @sizeof(image); }";
+    let (code, _, stderr) = run_delbin(
+        dsl,
+        &[
+            "--section",
+            &format!("image={}", image.display()),
+            "--section",
+            "unused=/dev/null",
+            "-o",
+            out.to_str().unwrap(),
+            "--depfile",
+            dep.to_str().unwrap(),
+        ],
+    );
+    let rule = std::fs::read_to_string(&dep).unwrap();
+    for path in [&image, &out, &dep] {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    assert_eq!(code, 0, "stderr: {stderr}");
+    assert_eq!(rule, format!("{}: {}\n", out.display(), image.display()));
+}
+
+#[test]
+fn test_cli_sarif_log() {
+    let sarif = std::env::temp_dir().join(format!("delbin-{}.sarif", std::process::id()));
+    let dsl = "struct h @packed {\n    val: u8 = 0x1FF;\n}";
+    let (code, _, stderr) = run_delbin(dsl, &["--sarif", sarif.to_str().unwrap()]);
+    let log = std::fs::read_to_string(&sarif).unwrap();
+    assert_eq!(code, 0, "stderr: {stderr}");
+    assert!(log.contains(r#""ruleId": "W03002""#), "got: {log}");
+    assert!(log.contains(r#""startLine": 2"#), "got: {log}");
+
+    let (code, _, _) = run_delbin("struct h { val: u8 = @sizeof(app); }", &["--sarif", sarif.to_str().unwrap()]);
+    let log = std::fs::read_to_string(&sarif).unwrap();
+    std::fs::remove_file(&sarif).unwrap();
+    assert_ne!(code, 0);
+    assert!(log.contains(r#""level": "error""#), "got: {log}");
+}
+
+#[test]
+fn test_cli_detached_signature() {
+    let dir = std::env::temp_dir();
+    let key = dir.join(format!("delbin-sig-key-{}.bin", std::process::id()));
+    let sig = dir.join(format!("delbin-sig-{}.bin", std::process::id()));
+    std::fs::write(&key, [7u8; 32]).unwrap();
+
+    let dsl = "struct h @packed { val: u8 = 0xAB; }";
+    let args = ["--signature", sig.to_str().unwrap(), "--signing-key", key.to_str().unwrap()];
+    let (code, stdout, stderr) = run_delbin(dsl, &args);
+
+    #[cfg(feature = "crypto-sign")]
+    {
+        use delbin::Signer;
+        assert_eq!(code, 0, "stderr: {stderr}");
+        assert_eq!(stdout.trim(), "AB");
+        let expected = delbin::sign::Ed25519Signer::from_bytes(&[7; 32]).unwrap().sign(&[0xAB]).unwrap();
+        assert_eq!(std::fs::read(&sig).unwrap(), expected);
+        std::fs::remove_file(&sig).unwrap();
+    }
+    #[cfg(not(feature = "crypto-sign"))]
+    {
+        assert_eq!(code, 1);
+        assert!(stdout.is_empty());
+        assert!(stderr.contains("'ed25519' is not built in"), "{stderr}");
+    }
+
+    let (code, _, stderr) = run_delbin(dsl, &[&args[..], &["--signature-alg", "dsa"]].concat());
+    assert_eq!(code, 1);
+    assert!(stderr.contains("Unknown --signature-alg 'dsa'"), "{stderr}");
+    std::fs::remove_file(&key).unwrap();
+}
+
+#[test]
+fn test_cli_rng_seed_is_reproducible() {
+    let dsl = "struct h @packed { id: [u8; 16] = @uuid(); }";
+    let (code, first, stderr) = run_delbin(dsl, &["--rng-seed", "42"]);
+    assert_eq!(code, 0, "stderr: {stderr}");
+    assert_eq!(first.trim().len(), 32);
+    assert_eq!(run_delbin(dsl, &["--rng-seed", "42"]).1, first);
+    assert_ne!(run_delbin(dsl, &["--rng-seed", "43"]).1, first);
+}
+
+#[test]
+fn test_cli_include_path() {
+    let dir = std::env::temp_dir().join(format!("delbin-cli-include-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let common = dir.join("common.dbin");
+    std::fs::write(&common, "magic: u16 = 0xA55A;\n").unwrap();
+    let out = dir.join("out.bin");
+    let dep = dir.join("out.d");
+
+    let dsl = r#"struct h @packed { @include "common.dbin"; v: u8 = 1; }"#;
+    let (code, stdout, stderr) = run_delbin(dsl, &["-I", dir.to_str().unwrap()]);
+    assert_eq!(code, 0, "stderr: {stderr}");
+    assert_eq!(stdout.trim(), "5AA501");
+
+    let (code, _, stderr) = run_delbin(
+        dsl,
+        &["--include-path", dir.to_str().unwrap(), "-o", out.to_str().unwrap(), "--depfile", dep.to_str().unwrap()],
+    );
+    let rule = std::fs::read_to_string(&dep).unwrap_or_default();
+    let (missing, _, _) = run_delbin(dsl, &[]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(code, 0, "stderr: {stderr}");
+    assert_eq!(rule, format!("{}: {}\n", out.display(), common.display()));
+    assert_ne!(missing, 0);
+}
+
+#[test]
+fn test_cli_gen_subcommand() {
+    let dir = std::env::temp_dir().join(format!("delbin-cli-gen-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dsl = dir.join("header.dbin");
+    let image = dir.join("app.bin");
+    let out = dir.join("header.bin");
+    std::fs::write(
+        &dsl,
+        "struct h @packed { major: u8 = ${VERSION_MAJOR}; ver: [u8; 6] = @bytes(${VERSION_STRING}); size: u16 = @sizeof(image); }",
+    )
+    .unwrap();
+    std::fs::write(&image, [0u8; 0x102]).unwrap();
+
+    let gen = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_delbin"))
+            .args(["gen", dsl.to_str().unwrap(), "-D", "VERSION_MAJOR=1", "-D", "VERSION_STRING=1.2.3"])
+            .arg("--section")
+            .arg(format!("image={}", image.display()))
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+    let written = gen(&["-o", out.to_str().unwrap()]);
+    let header = std::fs::read(&out).unwrap_or_default();
+    let printed = gen(&[]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(written.status.code(), Some(0), "stderr: {}", String::from_utf8_lossy(&written.stderr));
+    // --output defaults to raw bytes, stdout to hex
+    assert_eq!(header, b"\x011.2.3\x00\x02\x01");
+    assert_eq!(String::from_utf8_lossy(&printed.stdout).trim(), "01312E322E33000201");
+}
+
+#[test]
+fn test_cli_merge_and_assemble() {
+    let dir = std::env::temp_dir().join(format!("delbin-cli-merge-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dsl = dir.join("header.dbin");
+    let app = dir.join("app.bin");
+    let out = dir.join("out.bin");
+    std::fs::write(&dsl, "@layout { hdr @ 0x0; image @ 0x8; }\nstruct hdr @packed { size: u32 = @sizeof(image); }").unwrap();
+    std::fs::write(&app, b"code").unwrap();
+    let delbin = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_delbin")).args(args).output().unwrap();
+    let (dsl, app, out) = (dsl.to_str().unwrap(), app.to_str().unwrap(), out.to_str().unwrap());
+
+    let merged = delbin(&["merge", dsl, app, "-o", out, "--pad-to", "0x6", "--fill", "0xEE"]);
+    let merged_data = std::fs::read(out).unwrap_or_default();
+    let footer = delbin(&["merge", dsl, app, "--append"]);
+    let too_small = delbin(&["merge", dsl, app, "-o", out, "--pad-to", "2"]);
+    let left_behind = std::path::Path::new(out).exists();
+    let assembled = delbin(&["assemble", dsl, "--section", &format!("image={app}")]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(merged.status.code(), Some(0), "stderr: {}", String::from_utf8_lossy(&merged.stderr));
+    assert_eq!(merged_data, b"\x04\0\0\0\xEE\xEEcode");
+    assert_eq!(footer.stdout, b"code\x04\0\0\0");
+    assert_eq!(too_small.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&too_small.stderr).contains("pad_to reserves only 2"));
+    assert!(!left_behind);
+    assert_eq!(assembled.stdout, b"\x04\0\0\0\xFF\xFF\xFF\xFFcode");
+}
+
+#[test]
+fn test_cli_inspect_decodes_image() {
+    let dir = std::env::temp_dir().join(format!("delbin-cli-inspect-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dsl = dir.join("header.dbin");
+    let image = dir.join("firmware.bin");
+    let short = dir.join("short.bin");
+    std::fs::write(&dsl, r#"struct h @packed { magic: [u8; 4] = @bytes("FPK"); version: u16 = 0x0203; size: u32; }"#).unwrap();
+    std::fs::write(&image, b"FPK\0\x03\x02\x10\0\0\0payload").unwrap();
+    std::fs::write(&short, b"FPK\0\x03").unwrap();
+    let inspect = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_delbin")).arg("inspect").arg(&dsl).args(args).output().unwrap();
+        (out.status.code(), String::from_utf8_lossy(&out.stdout).into_owned())
+    };
+    let (code, table) = inspect(&[image.to_str().unwrap()]);
+    let (_, json) = inspect(&[image.to_str().unwrap(), "--json"]);
+    let (_, truncated) = inspect(&[short.to_str().unwrap()]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(code, Some(0));
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines[1].split_whitespace().collect::<Vec<_>>(), ["magic", "0x0", "46504B00", r#""FPK""#]);
+    assert_eq!(lines[2].split_whitespace().collect::<Vec<_>>(), ["version", "0x4", "0302", "515", "(0x203)"]);
+    assert!(json.contains(r#"{"name": "size", "offset": 6, "size": 4, "raw": "10000000", "value": 16}"#), "{json}");
+    assert!(truncated.contains("<truncated>"), "{truncated}");
+    assert!(truncated.contains("(5 of 10 header bytes present)"), "{truncated}");
+}
+
+#[test]
+fn test_cli_verify_exit_code() {
+    let dir = std::env::temp_dir().join(format!("delbin-cli-verify-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dsl = dir.join("header.dbin");
+    let app = dir.join("app.bin");
+    let image = dir.join("firmware.bin");
+    std::fs::write(
+        &dsl,
+        r#"struct h @packed { magic: [u8; 4] = @bytes("FPK"); ver: u16 = ${VER}; icrc: u32 = @crc32(image); }"#,
+    )
+    .unwrap();
+    std::fs::write(&app, b"payload").unwrap();
+    let delbin = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_delbin")).args(args).output().unwrap();
+        (out.status.code(), String::from_utf8_lossy(&out.stdout).into_owned())
+    };
+    let (dsl, app, image) = (dsl.to_str().unwrap(), app.to_str().unwrap(), image.to_str().unwrap());
+
+    delbin(&["merge", dsl, app, "-D", "VER=3", "-o", image]);
+    let good = delbin(&["verify", dsl, image, "-D", "VER=3"]);
+    let wrong_version = delbin(&["verify", dsl, image, "-D", "VER=4"]);
+    let mut data = std::fs::read(image).unwrap();
+    *data.last_mut().unwrap() ^= 1;
+    std::fs::write(image, &data).unwrap();
+    let corrupted = delbin(&["verify", dsl, image]);
+    std::fs::remove_dir_all(std::path::Path::new(dsl).parent().unwrap()).unwrap();
+
+    assert_eq!(good.0, Some(0), "{}", good.1);
+    assert!(good.1.ends_with("3 checks passed\n"), "{}", good.1);
+    assert_eq!(wrong_version.0, Some(1));
+    assert!(wrong_version.1.contains("FAIL  ver    input     expected 0400, found 0300"), "{}", wrong_version.1);
+    assert_eq!(corrupted.0, Some(1));
+    assert!(corrupted.1.contains("FAIL  icrc"), "{}", corrupted.1);
+    assert!(corrupted.1.ends_with("1 of 2 checks failed\n"), "{}", corrupted.1);
+}
+
+#[test]
+fn test_cli_config_file() {
+    let dir = std::env::temp_dir().join(format!("delbin-cli-config-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("build")).unwrap();
+    std::fs::write(
+        dir.join("header.dbin"),
+        "struct h @packed { ver: u16 = ${VERSION}; base: u32 = ${BASE}; size: u8 = @sizeof(image); }",
+    )
+    .unwrap();
+    std::fs::write(dir.join("build/app.bin"), b"code").unwrap();
+    std::fs::write(
+        dir.join("delbin.toml"),
+        "input = \"header.dbin\"\noutput = \"build/header.bin\"\n\n[env]\nVERSION = 0x102\nBASE = \"0x08000000\"\n\n[sections]\nimage = \"build/app.bin\"\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("typo.toml"), "outptu = \"x.bin\"\n").unwrap();
+    let delbin = |args: &[&str], cwd: &std::path::Path| {
+        Command::new(env!("CARGO_BIN_EXE_delbin")).args(args).current_dir(cwd).output().unwrap()
+    };
+
+    // ./delbin.toml is picked up; paths in it are relative to its directory
+    let implicit = delbin(&["gen"], &dir);
+    let header = std::fs::read(dir.join("build/header.bin")).unwrap_or_default();
+    // The command line overrides the config
+    let config = dir.join("delbin.toml");
+    let v3 = dir.join("v3.bin");
+    let overridden = delbin(&["gen", "-c", config.to_str().unwrap(), "-D", "VERSION=3", "-o", v3.to_str().unwrap()], &std::env::temp_dir());
+    let v3_header = std::fs::read(&v3).unwrap_or_default();
+    let typo = delbin(&["gen", "-c", dir.join("typo.toml").to_str().unwrap()], &dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(implicit.status.code(), Some(0), "stderr: {}", String::from_utf8_lossy(&implicit.stderr));
+    assert_eq!(header, b"\x02\x01\0\0\0\x08\x04");
+    assert_eq!(overridden.status.code(), Some(0), "stderr: {}", String::from_utf8_lossy(&overridden.stderr));
+    assert_eq!(v3_header, b"\x03\0\0\0\0\x08\x04");
+    assert_eq!(typo.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&typo.stderr).contains("unknown setting `outptu`"));
+}
+
+#[test]
+fn test_cli_merge_and_verify_include_path() {
+    let dir = std::env::temp_dir().join(format!("delbin-cli-merge-include-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("shared")).unwrap();
+    let dsl = dir.join("header.dbin");
+    let app = dir.join("app.bin");
+    let image = dir.join("firmware.bin");
+    std::fs::write(dir.join("shared/common.dbin"), "magic: u16 = 0xA55A;\n").unwrap();
+    std::fs::write(&dsl, r#"struct h @packed { @include "common.dbin"; icrc: u32 = @crc32(image); }"#).unwrap();
+    std::fs::write(&app, b"payload").unwrap();
+    let delbin = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_delbin")).args(args).output().unwrap();
+    let shared = dir.join("shared");
+    let (dsl, app, image, shared) =
+        (dsl.to_str().unwrap(), app.to_str().unwrap(), image.to_str().unwrap(), shared.to_str().unwrap());
+
+    let merged = delbin(&["merge", dsl, app, "-I", shared, "-o", image]);
+    let data = std::fs::read(image).unwrap_or_default();
+    let verified = delbin(&["verify", dsl, image, "-I", shared]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(merged.status.code(), Some(0), "stderr: {}", String::from_utf8_lossy(&merged.stderr));
+    assert_eq!(&data[..2], b"\x5A\xA5");
+    assert_eq!(&data[6..], b"payload");
+    assert_eq!(verified.status.code(), Some(0), "stderr: {}", String::from_utf8_lossy(&verified.stderr));
+    assert!(String::from_utf8_lossy(&verified.stdout).ends_with("2 checks passed\n"));
+}
+
+#[test]
+fn test_cli_config_include_path() {
+    let dir = std::env::temp_dir().join(format!("delbin-cli-config-include-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("shared")).unwrap();
+    std::fs::write(dir.join("shared/common.dbin"), "magic: u16 = 0xA55A;\n").unwrap();
+    std::fs::write(dir.join("header.dbin"), r#"struct h @packed { @include "common.dbin"; size: u8 = @sizeof(image); }"#)
+        .unwrap();
+    std::fs::write(dir.join("app.bin"), b"code").unwrap();
+    std::fs::write(dir.join("delbin.toml"), "include_path = [\"shared\"]\n").unwrap();
+    let delbin = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_delbin")).args(args).current_dir(&dir).output().unwrap();
+
+    // The config's include path serves every command, not just gen
+    let merged = delbin(&["merge", "header.dbin", "app.bin", "-o", "firmware.bin"]);
+    let data = std::fs::read(dir.join("firmware.bin")).unwrap_or_default();
+    let verified = delbin(&["verify", "header.dbin", "firmware.bin"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(merged.status.code(), Some(0), "stderr: {}", String::from_utf8_lossy(&merged.stderr));
+    assert_eq!(data, b"\x5A\xA5\x04code");
+    assert_eq!(verified.status.code(), Some(0), "stderr: {}", String::from_utf8_lossy(&verified.stderr));
+}